@@ -0,0 +1,34 @@
+// build.rs
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Output of a git command, None if git or the repository is not available
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Only the gRPC API needs generated code
+    if std::env::var_os("CARGO_FEATURE_GRPC").is_some() {
+        tonic_build::compile_protos("proto/gateway.proto")?;
+    }
+
+    // Build info served by the version registers and the HTTP /version endpoint
+    let mut git_hash = git(&["rev-parse", "--short=8", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    if git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty()) {
+        git_hash.push_str("-dirty");
+    }
+    let build_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    println!("cargo:rustc-env=GATEWAY_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=GATEWAY_BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+    Ok(())
+}
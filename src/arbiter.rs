@@ -0,0 +1,789 @@
+// src/arbiter.rs
+use crate::{
+    config::{CommandHistoryConfig, FlagsConfig, LockoutConfig, Role},
+    connections::ConnectionRegistry,
+    control_mode::ControlMode,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    fault::{FaultEvent, FaultSender},
+    flags,
+    history::{self, CommandRecord},
+    SystemCommand,
+};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// How often a running dispatch looks for an OFF that interrupts it
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// --- Command Sources ---
+/// Interface a command was received from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandSource {
+    Gpio,
+    Modbus,
+    OpcUa,
+    Grpc,
+    Scheduler,
+    /// Trip contact of the GPIO inputs (fire alarm, e-stop)
+    ExternalTrip,
+    /// Error signal of a BMS (emergency OFF)
+    Fault,
+    /// ON after a transient fault (see AutoRestartConfig)
+    AutoRestart,
+}
+
+impl CommandSource {
+    /// Value of the source in REG_LAST_COMMAND_SOURCE.
+    pub fn code(self) -> u16 {
+        match self {
+            CommandSource::Gpio => 1,
+            CommandSource::Modbus => 2,
+            CommandSource::OpcUa => 3,
+            CommandSource::Grpc => 4,
+            CommandSource::Scheduler => 5,
+            CommandSource::ExternalTrip => 6,
+            CommandSource::Fault => 7,
+            CommandSource::AutoRestart => 8,
+        }
+    }
+
+    /// Sources that send commands without an operator, refused in manual control mode.
+    pub fn is_automatic(self) -> bool {
+        matches!(self, CommandSource::Scheduler | CommandSource::AutoRestart)
+    }
+}
+
+/// Value of a command in REG_LAST_COMMAND.
+pub fn command_code(command: &SystemCommand) -> u16 {
+    match command {
+        SystemCommand::Off => 1,
+        SystemCommand::On => 2,
+        SystemCommand::Quit => 3,
+    }
+}
+
+/// A system command together with the interface it came from.
+#[derive(Debug, Clone)]
+pub struct SourcedCommand {
+    pub source: CommandSource,
+    pub command: SystemCommand,
+    /// Bypasses the lockout window and the source priority (e.g. long-pressed OFF button)
+    pub forced: bool,
+    /// Role of the client that sent it, None for local and automatic sources
+    pub role: Option<Role>,
+}
+
+impl SourcedCommand {
+    pub fn new(source: CommandSource, command: SystemCommand) -> Self {
+        Self { source, command, forced: false, role: None }
+    }
+
+    pub fn forced(source: CommandSource, command: SystemCommand) -> Self {
+        Self { source, command, forced: true, role: None }
+    }
+
+    pub fn with_role(self, role: Role) -> Self {
+        Self { role: Some(role), ..self }
+    }
+
+    /// Source and role of the client, as written to the audit log.
+    pub fn origin(&self) -> String {
+        match self.role {
+            Some(role) => format!("{:?} ({})", self.source, role.name()),
+            None => format!("{:?}", self.source),
+        }
+    }
+}
+
+// --- Arbitration Policy ---
+/// Decides about commands arriving while the controls are frozen after a previous
+/// command (the lockout window).
+pub struct ArbitrationPolicy {
+    /// Highest priority first, unlisted sources rank below all listed ones
+    pub priority: Vec<CommandSource>,
+    /// OFF is accepted from every source, regardless of priority
+    pub off_always_wins: bool,
+    /// ON is refused while a BMS reports errors or has stale data
+    pub inhibit_on_fault: bool,
+    /// Length of the lockout window per command type
+    pub lockout: LockoutConfig,
+    /// Texts of the BMS errors in the interlock reasons
+    pub flag_labels: FlagsConfig,
+}
+
+impl ArbitrationPolicy {
+    fn rank(&self, source: CommandSource) -> usize {
+        self.priority.iter().position(|s| *s == source).unwrap_or(self.priority.len())
+    }
+
+    // Err holds the reason for rejecting the request
+    fn decide(&self, last: Option<&SourcedCommand>, request: &SourcedCommand) -> Result<&'static str, String> {
+        let Some(last) = last else {
+            return Ok("no previous command");
+        };
+        if last.command == request.command {
+            return Err(format!("duplicate of {:?} from {:?}", last.command, last.source));
+        }
+        if request.command == SystemCommand::Off && self.off_always_wins {
+            return Ok("OFF always wins");
+        }
+        if self.rank(request.source) < self.rank(last.source) {
+            Ok("higher priority source")
+        } else {
+            Err(format!(
+                "conflicts with {:?} from {:?}, which has equal or higher priority",
+                last.command, last.source
+            ))
+        }
+    }
+}
+
+// --- Command Results ---
+/// Outcome of a command on a single output, reported back to the arbiter.
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub output: String,
+    pub command: SystemCommand,
+    pub success: bool,
+}
+
+/// Overall outcome of the last dispatched command, exposed via REG_COMMAND_STATUS.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommandStatus {
+    #[default]
+    None = 0,
+    Ok = 1,
+    PartialFailure = 2,
+    Failed = 3,
+}
+
+/// Command forwarded to the LED output together with how it went.
+#[derive(Debug, Clone)]
+pub struct CommandReport {
+    pub command: SystemCommand,
+    pub status: CommandStatus,
+}
+
+// --- Command Outputs ---
+/// A named consumer of system commands (inverter client, CAN transmitter).
+pub struct OutputTarget {
+    pub name: String,
+    pub tx: Sender<SystemCommand>,
+}
+
+/// Fan-out targets of the command arbiter.
+/// Every consumer has its own channel, so each command reaches all of them
+/// (a cloned crossbeam receiver would only hand the command to one consumer).
+pub struct CommandOutputs {
+    pub targets: Vec<OutputTarget>,
+    pub led_tx: Sender<CommandReport>,
+    pub result_rx: Receiver<CommandResult>,
+    pub result_timeout: Duration,
+    pub max_retries: u32,
+    pub connections: Arc<ConnectionRegistry>,
+    /// Refuse ON while an inverter is down
+    pub block_on_while_down: bool,
+    /// Start the outputs one after another on ON, None starts all at once
+    pub soft_start: Option<SoftStart>,
+    /// Deadline of the emergency OFF on BMS error signals, None if disabled
+    pub emergency_deadline: Option<Duration>,
+}
+
+// Lets a long running dispatch give way to a command that must not wait (an OFF during
+// the soft start). `check` is polled while waiting, once it returned true the dispatch
+// stops sending and waiting.
+struct Interrupt<'a> {
+    check: &'a mut dyn FnMut() -> bool,
+    hit: bool,
+}
+
+impl<'a> Interrupt<'a> {
+    fn new(check: &'a mut dyn FnMut() -> bool) -> Self {
+        Self { check, hit: false }
+    }
+
+    fn poll(&mut self) -> bool {
+        self.hit = self.hit || (self.check)();
+        self.hit
+    }
+
+    // Sleeps for `duration` unless interrupted, returns true if it was
+    fn sleep(&mut self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while !self.poll() {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            std::thread::sleep(remaining.min(INTERRUPT_POLL_INTERVAL));
+        }
+        true
+    }
+}
+
+/// Timing of the staggered ON sequence.
+pub struct SoftStart {
+    /// Pause between the confirmation of one output and the start of the next
+    pub delay: Duration,
+    /// How long to wait for a single output to confirm ON
+    pub result_timeout: Duration,
+}
+
+impl CommandOutputs {
+    /// Forwards a command to all outputs, retrying outputs that failed or did not answer.
+    /// The LEDs are updated once the final outcome is known.
+    pub fn dispatch(&self, msg: &SystemCommand) -> CommandStatus {
+        self.dispatch_interruptible(msg, &mut || false)
+    }
+
+    /// Like `dispatch`, but gives up as soon as `interrupted` returns true: no further
+    /// outputs are started, the command counts as failed. `interrupted` is polled every
+    /// few milliseconds while the dispatch waits for results or the soft start delay.
+    pub fn dispatch_interruptible(&self, msg: &SystemCommand, interrupted: &mut dyn FnMut() -> bool) -> CommandStatus {
+        let mut interrupt = Interrupt::new(interrupted);
+        if *msg == SystemCommand::On && self.block_on_while_down {
+            let down = self.connections.down();
+            if !down.is_empty() {
+                log::error!("Refusing {:?}, inverters {:?} are down.", msg, down);
+                return self.report(msg, CommandStatus::Failed);
+            }
+        }
+
+        // Drop results left over from a previous command that timed out
+        for stale in self.result_rx.try_iter() {
+            log::debug!("Discarding stale command result: {:?}", stale);
+        }
+
+        let pending = match &self.soft_start {
+            Some(soft_start) if *msg == SystemCommand::On => self.send_staggered(msg, soft_start, &mut interrupt),
+            _ => self.send_with_retries(msg, self.targets.iter().collect(), self.result_timeout, &mut interrupt),
+        };
+
+        let status = if interrupt.hit {
+            log::warn!(target: "audit", "{:?} interrupted, outputs {:?} not confirmed.", msg, pending.iter().map(|t| &t.name).collect::<Vec<_>>());
+            CommandStatus::Failed
+        } else if pending.is_empty() {
+            log::info!("{:?} confirmed by all {} outputs.", msg, self.targets.len());
+            CommandStatus::Ok
+        } else if pending.len() < self.targets.len() {
+            log::error!(
+                "{:?} failed on {:?} after {} retries.",
+                msg,
+                pending.iter().map(|t| &t.name).collect::<Vec<_>>(),
+                self.max_retries
+            );
+            CommandStatus::PartialFailure
+        } else {
+            log::error!("{:?} failed on all outputs.", msg);
+            CommandStatus::Failed
+        };
+
+        self.report(msg, status)
+    }
+
+    /// Sends OFF to all outputs at once and reports whether every one confirmed it within
+    /// `deadline`. Outputs that missed the deadline are retried as usual afterwards.
+    pub fn emergency_off(&self, deadline: Duration) -> CommandStatus {
+        let msg = SystemCommand::Off;
+        for stale in self.result_rx.try_iter() {
+            log::debug!("Discarding stale command result: {:?}", stale);
+        }
+
+        let started = Instant::now();
+        let targets: Vec<&OutputTarget> = self.targets.iter().collect();
+        for target in &targets {
+            if let Err(e) = target.tx.send(msg.clone()) {
+                log::error!("Error when sending {:#?} to {}: {:?}", msg, target.name, e);
+            }
+        }
+        // Nothing interrupts an OFF
+        let mut never = || false;
+        let mut interrupt = Interrupt::new(&mut never);
+        let failed = self.collect_results(&msg, &targets, deadline, &mut interrupt);
+        if failed.is_empty() {
+            log::warn!(
+                target: "audit",
+                "Emergency OFF confirmed by all {} outputs after {:?} (deadline {:?}).",
+                targets.len(),
+                started.elapsed(),
+                deadline
+            );
+            return self.report(&msg, CommandStatus::Ok);
+        }
+        log::error!(
+            target: "audit",
+            "Emergency OFF not confirmed by {:?} within the deadline of {:?}.",
+            failed,
+            deadline
+        );
+
+        let late: Vec<&OutputTarget> = targets.into_iter().filter(|t| failed.contains(&t.name)).collect();
+        let pending = self.send_with_retries(&msg, late, self.result_timeout, &mut interrupt);
+        let status = if pending.len() == self.targets.len() {
+            CommandStatus::Failed
+        } else {
+            CommandStatus::PartialFailure
+        };
+        if !pending.is_empty() {
+            log::error!(
+                "{:?} failed on {:?} after {} retries.",
+                msg,
+                pending.iter().map(|t| &t.name).collect::<Vec<_>>(),
+                self.max_retries
+            );
+        }
+        self.report(&msg, status)
+    }
+
+    // Sends `msg` to `pending` until all confirmed it, the retries are used up or the
+    // dispatch is interrupted. Returns the outputs that still failed.
+    fn send_with_retries<'a>(
+        &self,
+        msg: &SystemCommand,
+        mut pending: Vec<&'a OutputTarget>,
+        timeout: Duration,
+        interrupt: &mut Interrupt,
+    ) -> Vec<&'a OutputTarget> {
+        for attempt in 0..=self.max_retries {
+            if interrupt.poll() {
+                break;
+            }
+            if attempt > 0 {
+                log::warn!(
+                    "Retrying {:?} on {:?} (attempt {}/{})",
+                    msg,
+                    pending.iter().map(|t| &t.name).collect::<Vec<_>>(),
+                    attempt,
+                    self.max_retries
+                );
+            }
+            for target in &pending {
+                if let Err(e) = target.tx.send(msg.clone()) {
+                    log::error!("Error when sending {:#?} to {}: {:?}", msg, target.name, e);
+                } else {
+                    log::debug!("{:#?} sent to {}.", msg, target.name);
+                }
+            }
+            let failed = self.collect_results(msg, &pending, timeout, interrupt);
+            pending.retain(|t| failed.contains(&t.name));
+            if pending.is_empty() {
+                break;
+            }
+        }
+        pending
+    }
+
+    // Soft start: starts one output at a time and waits for its confirmation,
+    // then pauses before the next one. Outputs that fail do not stop the sequence, an
+    // interrupt does: the outputs not started yet are returned as failed.
+    fn send_staggered(&self, msg: &SystemCommand, soft_start: &SoftStart, interrupt: &mut Interrupt) -> Vec<&OutputTarget> {
+        let mut failed = Vec::new();
+        for (position, target) in self.targets.iter().enumerate() {
+            if position > 0 {
+                log::info!("Soft start: Waiting {:?} before starting {}.", soft_start.delay, target.name);
+            }
+            if (position > 0 && interrupt.sleep(soft_start.delay)) || interrupt.poll() {
+                log::warn!("Soft start: Interrupted before starting {}.", target.name);
+                failed.extend(&self.targets[position..]);
+                break;
+            }
+            log::info!("Soft start: Starting {} ({}/{}).", target.name, position + 1, self.targets.len());
+            failed.extend(self.send_with_retries(msg, vec![target], soft_start.result_timeout, interrupt));
+        }
+        failed
+    }
+
+    // Forwards the final outcome of a command to the LEDs
+    fn report(&self, msg: &SystemCommand, status: CommandStatus) -> CommandStatus {
+        let report = CommandReport { command: msg.clone(), status };
+        if let Err(e) = self.led_tx.send(report) {
+            log::error!("Error when sending command report to LEDs: {:?}", e);
+        }
+        status
+    }
+
+    /// Waits for the results of `msg` from `targets`, until the dispatch is interrupted.
+    /// Returns the names of outputs that failed or did not answer in time.
+    fn collect_results(
+        &self,
+        msg: &SystemCommand,
+        targets: &[&OutputTarget],
+        timeout: Duration,
+        interrupt: &mut Interrupt,
+    ) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut outstanding: Vec<String> = targets.iter().map(|t| t.name.clone()).collect();
+        let mut failed = Vec::new();
+
+        while !outstanding.is_empty() && !interrupt.poll() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.result_rx.recv_timeout(remaining.min(INTERRUPT_POLL_INTERVAL)) {
+                Ok(result) if result.command != *msg || !outstanding.contains(&result.output) => {
+                    log::debug!("Ignoring unexpected command result: {:?}", result);
+                }
+                Ok(result) => {
+                    outstanding.retain(|name| *name != result.output);
+                    if result.success {
+                        log::info!("{} acknowledged {:?}.", result.output, msg);
+                    } else {
+                        log::error!("{} failed to execute {:?}.", result.output, msg);
+                        failed.push(result.output);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    log::error!(
+                        "Timeout after {:?} waiting for {:?} results from {:?}.",
+                        timeout,
+                        msg,
+                        outstanding
+                    );
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    log::error!("Command result channel closed while waiting for {:?}.", msg);
+                    break;
+                }
+            }
+        }
+
+        failed.extend(outstanding);
+        failed
+    }
+}
+
+// Stores the outcome of the last command in the status register of a BMS dataset
+fn set_command_status(bms_data: &SharedBmsData, status: CommandStatus) {
+    bms_data.modify(|data| data.command_status = Some(status as u16));
+}
+
+// Reason to refuse ON because of the state of a BMS, None if it is healthy or in maintenance
+fn on_inhibit_reason(bms_id: u8, bms_data: &SharedBmsData, flag_labels: &FlagsConfig) -> Option<String> {
+    bms_data.read(|data| {
+        if data.in_maintenance() {
+            return None;
+        }
+        let (error1, error2) = (data.error1.unwrap_or(0), data.error2.unwrap_or(0));
+        if error1 != 0 || error2 != 0 {
+            return Some(format!(
+                "BMS {} reports errors: {} (error1 {:#04X}, error2 {:#04X})",
+                bms_id,
+                flags::error_summary(data, flag_labels),
+                error1,
+                error2
+            ));
+        }
+        // Never confirmed by CAN counts as stale as well
+        if data.stale.unwrap_or(true) {
+            return Some(format!("data of BMS {} is stale", bms_id));
+        }
+        None
+    })
+}
+
+// Publishes the last accepted command in the registers of a BMS dataset
+fn set_last_command(bms_data: &SharedBmsData, record: &CommandRecord) {
+    bms_data.modify(|data| {
+        data.last_command = Some(command_code(&record.command));
+        data.last_command_source = Some(record.source.code());
+        data.last_command_time = Some(record.timestamp as u32);
+    });
+}
+
+// Clears the frozen flag after `window`, unless another command was accepted meanwhile
+// (its own reset takes over then)
+fn reset_control_frozen(bms: Vec<(u8, SharedBmsData)>, window: Duration, generation: Arc<AtomicU64>, accepted: u64) {
+    std::thread::sleep(window);
+    if generation.load(Ordering::SeqCst) != accepted {
+        return;
+    }
+    for (_, bms_data) in &bms {
+        bms_data.modify(|data| data.control_frozen = Some(false));
+    }
+    log::debug!("Control frozen reset after {:?}.", window);
+}
+
+/// Emergency-off coordinator, the only consumer of the BMS fault events while the
+/// emergency OFF is enabled: forwards every event to the LEDs and requests the emergency
+/// OFF from the arbiter for each raised fault. Faults raised within `hold_off` of the last
+/// request are covered by it.
+pub fn emergency_off_coordinator(
+    error_rx: Receiver<FaultEvent>,
+    led_error_tx: FaultSender,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    hold_off: Duration,
+    control: SharedBmsData,
+) {
+    let mut last_request: Option<Instant> = None;
+    for event in error_rx.iter() {
+        led_error_tx.send(event);
+        let FaultEvent::Raised(condition) = event else {
+            continue;
+        };
+        if control.read(BmsData::control_mode) == ControlMode::Maintenance {
+            log::warn!(target: "audit", "Fault {:?} raised in maintenance mode, not switching off.", condition);
+            continue;
+        }
+        if last_request.is_some_and(|at| at.elapsed() < hold_off) {
+            continue;
+        }
+        log::warn!("Fault {:?} raised, requesting emergency OFF.", condition);
+        last_request = Some(Instant::now());
+        if input_tx.send(SourcedCommand::forced(CommandSource::Fault, SystemCommand::Off)).is_err() {
+            log::error!("Arbiter gone, emergency-off coordinator stopping.");
+            return;
+        }
+    }
+}
+
+/// The arbiter. It blocks on the command channel and on the output results, so it runs
+/// on a thread of its own instead of taking a worker of the runtime (see main.rs). `bms`
+/// are the data slots with the ID of the BMS they serve, the first one holds the control mode.
+pub fn input_flag_manager_task(
+    bms: Vec<(u8, SharedBmsData)>,
+    input_rx: std::sync::mpsc::Receiver<SourcedCommand>,
+    outputs: CommandOutputs,
+    policy: ArbitrationPolicy,
+    history_config: CommandHistoryConfig,
+    shutdown_tx: tokio::sync::oneshot::Sender<CommandSource>,
+)  -> Result<(), AppError> {
+    // Last accepted command, the reference for conflicts within the lockout window
+    let mut last: Option<SourcedCommand> = None;
+    let mut last_accepted_at: Option<Instant> = None;
+    // Counts the accepted commands, so a pending reset of the frozen flag can tell it is outdated
+    let generation = Arc::new(AtomicU64::new(0));
+    // Most recent command held back by the lockout, dispatched when its window ends
+    let mut pending: Option<SourcedCommand> = None;
+    let set_command_status_all = |status: CommandStatus| {
+        for (_, bms_data) in &bms {
+            set_command_status(bms_data, status);
+        }
+    };
+    let set_last_command_all = |record: &CommandRecord| {
+        for (_, bms_data) in &bms {
+            set_last_command(bms_data, record);
+        }
+    };
+
+    // Restore the state commanded before the restart. Only OFF is sent again, an ON
+    // replayed after a reboot could start a plant that was stopped in the meantime.
+    if let Some(record) = history_config.enabled.then(|| history::load(&history_config)).flatten() {
+        log::info!(
+            target: "audit",
+            "Restored last command {:?} from {:?} (Unix time {}).",
+            record.command, record.source, record.timestamp
+        );
+        set_last_command_all(&record);
+        if record.command == SystemCommand::Off && history_config.restore_off {
+            log::warn!(target: "audit", "Plant was commanded OFF before the restart, sending OFF again.");
+            set_command_status_all(outputs.dispatch(&SystemCommand::Off));
+        }
+    }
+
+    // Requests received while a dispatch was running, an OFF among them is taken first
+    let mut backlog: VecDeque<SourcedCommand> = VecDeque::new();
+
+    loop {
+        // Wait for the next request, or until the window of the pending command ends
+        let pending_until = pending
+            .as_ref()
+            .zip(last_accepted_at)
+            .map(|(p, at)| at + policy.lockout.window(&p.command));
+        let off_first = backlog.iter().position(|request| request.command == SystemCommand::Off);
+        let next = off_first.or((!backlog.is_empty()).then_some(0)).and_then(|index| backlog.remove(index));
+        let request = match (next, pending_until) {
+            (Some(request), _) => request,
+            (None, Some(until)) => match input_rx.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                Ok(request) => request,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => match pending.take() {
+                    Some(request) => {
+                        log::info!(target: "audit", "Lockout over, dispatching pending {:?} from {}.", request.command, request.origin());
+                        request
+                    }
+                    None => continue,
+                },
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            (None, None) => match input_rx.recv() {
+                Ok(request) => request,
+                Err(_) => break,
+            },
+        };
+        let msg = request.command.clone();
+        // The window depends on the incoming command, so OFF is not held back by a debounced ON
+        let window = policy.lockout.window(&msg);
+        let control_frozen = last_accepted_at.is_some_and(|at| at.elapsed() < window);
+        // Outside the lockout window every command is accepted
+        let decision = if request.forced {
+            Ok("forced")
+        } else if control_frozen {
+            policy.decide(last.as_ref(), &request)
+        } else {
+            Ok("controls not frozen")
+        };
+        // The interlock holds regardless of source and priority
+        let inhibit = if decision.is_ok() && msg == SystemCommand::On && policy.inhibit_on_fault {
+            bms.iter()
+                .find_map(|(bms_id, bms_data)| on_inhibit_reason(*bms_id, bms_data, &policy.flag_labels))
+        } else {
+            None
+        };
+        let inhibited = inhibit.is_some();
+        // Automatic commands are refused while the operator has taken manual control
+        let manual = decision.is_ok()
+            && request.source.is_automatic()
+            && bms.first().is_some_and(|(_, bms_data)| bms_data.read(BmsData::control_mode) == ControlMode::Manual);
+        let decision = match inhibit {
+            Some(reason) => Err(format!("interlock, {}", reason)),
+            None if manual => Err("manual control mode".to_string()),
+            None => decision,
+        };
+        // Held back by the lockout only: keep it instead of dropping it. The slot holds one
+        // command, a newer one replaces it, and a repetition of the last accepted one is dropped.
+        if decision.is_err()
+            && control_frozen
+            && !inhibited
+            && !manual
+            && policy.lockout.queue
+            && last.as_ref().is_some_and(|last| last.command != msg)
+        {
+            match pending.replace(request.clone()) {
+                Some(replaced) => log::info!(
+                    target: "audit",
+                    "{:?} from {} deferred until the lockout ends, replacing pending {:?} from {}.",
+                    msg, request.origin(), replaced.command, replaced.origin()
+                ),
+                None => log::info!(target: "audit", "{:?} from {} deferred until the lockout ends.", msg, request.origin()),
+            }
+            continue;
+        }
+        match &decision {
+            Ok(reason) => log::info!(target: "audit", "{:?} from {} accepted ({}).", msg, request.origin(), reason),
+            Err(reason) => log::warn!(target: "audit", "{:?} from {} rejected: {}.", msg, request.origin(), reason),
+        }
+        if inhibited {
+            // Let the operator see that ON was refused (red LED blinks until the next command)
+            set_command_status_all(outputs.report(&msg, CommandStatus::Failed));
+        }
+        if decision.is_ok() {
+            for (bms_id, bms_data) in &bms {
+                bms_data.modify(|data| data.control_frozen = Some(true));
+                log::debug!("Control for BMS {} frozen.", bms_id);
+            }
+
+            last_accepted_at = Some(Instant::now());
+            if let Some(superseded) = pending.take() {
+                log::info!(
+                    target: "audit",
+                    "Pending {:?} from {} superseded by {:?} from {}.",
+                    superseded.command, superseded.origin(), msg, request.origin()
+                );
+            }
+            let accepted = generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let (bms_clone, generation_clone) = (bms.clone(), generation.clone());
+            let longest = policy.lockout.longest();
+            std::thread::spawn(move || reset_control_frozen(bms_clone, longest, generation_clone, accepted));
+            let record = CommandRecord::now(msg.clone(), request.source);
+            set_last_command_all(&record);
+            if history_config.enabled {
+                if let Err(e) = history::save(&history_config, &record) {
+                    log::error!("Failed to persist command history: {}", e);
+                }
+            }
+            let status = match outputs.emergency_deadline {
+                Some(deadline) if request.source == CommandSource::Fault => outputs.emergency_off(deadline),
+                // An OFF must not wait for the soft start or the retries of an ON
+                _ if msg == SystemCommand::On => outputs.dispatch_interruptible(&msg, &mut || {
+                    backlog.extend(input_rx.try_iter());
+                    backlog.iter().any(|request| request.command == SystemCommand::Off)
+                }),
+                _ => outputs.dispatch(&msg),
+            };
+            set_command_status_all(status);
+            // QUIT stops the gateway once its frame is out, later commands are not accepted
+            if msg == SystemCommand::Quit {
+                log::warn!(target: "audit", "QUIT from {} ({:?}), shutting the gateway down.", request.origin(), status);
+                if shutdown_tx.send(request.source).is_err() {
+                    log::error!("Cannot request the shutdown, the main task is gone.");
+                }
+                return Ok(());
+            }
+            last = Some(request);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn off_interrupts_a_soft_start() {
+        let (tx1, rx1) = crossbeam_channel::unbounded();
+        let (tx2, rx2) = crossbeam_channel::unbounded();
+        let (result_tx, result_rx) = crossbeam_channel::unbounded();
+        let (led_tx, _led_rx) = crossbeam_channel::unbounded();
+        let outputs = CommandOutputs {
+            targets: vec![
+                OutputTarget { name: "inverter1".to_string(), tx: tx1 },
+                OutputTarget { name: "inverter2".to_string(), tx: tx2 },
+            ],
+            led_tx,
+            result_rx,
+            result_timeout: Duration::from_secs(1),
+            max_retries: 0,
+            connections: Arc::new(ConnectionRegistry::new(&[], Vec::new())),
+            block_on_while_down: false,
+            soft_start: Some(SoftStart { delay: Duration::from_secs(30), result_timeout: Duration::from_secs(1) }),
+            emergency_deadline: None,
+        };
+        let policy = ArbitrationPolicy {
+            priority: Vec::new(),
+            off_always_wins: true,
+            inhibit_on_fault: false,
+            lockout: LockoutConfig::default(),
+            flag_labels: FlagsConfig::default(),
+        };
+        let history_config = CommandHistoryConfig { enabled: false, ..CommandHistoryConfig::default() };
+        let (input_tx, input_rx) = std::sync::mpsc::channel();
+        let (shutdown_tx, _shutdown_rx) = tokio::sync::oneshot::channel();
+        let arbiter = std::thread::spawn(move || {
+            input_flag_manager_task(
+                vec![(1, SharedBmsData::default())],
+                input_rx,
+                outputs,
+                policy,
+                history_config,
+                shutdown_tx,
+            )
+        });
+
+        input_tx.send(SourcedCommand::new(CommandSource::Modbus, SystemCommand::On)).unwrap();
+        assert_eq!(rx1.recv_timeout(Duration::from_secs(1)), Ok(SystemCommand::On));
+        result_tx
+            .send(CommandResult { output: "inverter1".to_string(), command: SystemCommand::On, success: true })
+            .unwrap();
+
+        // The soft start now waits 30 s before the second inverter, the OFF must not
+        input_tx.send(SourcedCommand::new(CommandSource::Gpio, SystemCommand::Off)).unwrap();
+        assert_eq!(rx1.recv_timeout(Duration::from_secs(1)), Ok(SystemCommand::Off));
+        assert_eq!(rx2.recv_timeout(Duration::from_secs(1)), Ok(SystemCommand::Off));
+        assert!(rx2.try_recv().is_err(), "the second inverter must not be started");
+
+        drop(input_tx);
+        assert!(arbiter.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+// src/archive.rs
+use crate::{config::StorageAreaConfig, persist};
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Extension appended to the name of a compressed file.
+pub const EXTENSION: &str = "zst";
+
+// Temporary files of write_atomic, they are never archived
+fn is_temporary(name: &str) -> bool {
+    name.ends_with(".tmp")
+}
+
+fn is_compressed(name: &str) -> bool {
+    name.strip_suffix(EXTENSION).is_some_and(|rest| rest.ends_with('.'))
+}
+
+// Names of the files of an area, oldest first
+fn file_names(area: &StorageAreaConfig) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = match fs::read_dir(&area.dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(&area.prefix) && !is_temporary(name))
+            .collect(),
+        // Nothing written yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    names.sort();
+    Ok(names)
+}
+
+// --- Compression ---
+// Replaces `path` by its zstd-compressed copy `<name>.zst`
+fn compress_file(path: &Path, level: i32) -> io::Result<PathBuf> {
+    let compressed = zstd::stream::encode_all(fs::File::open(path)?, level)?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(EXTENSION);
+    let archive_path = path.with_file_name(name);
+    persist::write_atomic(&archive_path, &compressed)?;
+    fs::remove_file(path)?;
+    Ok(archive_path)
+}
+
+/// Compresses the rotated files of an area, i.e. all uncompressed files but the newest
+/// one, which may still be written to. Returns the number of compressed files.
+pub fn compress_rotated(area: &StorageAreaConfig, level: i32) -> io::Result<usize> {
+    let names = file_names(area)?;
+    let mut compressed = 0;
+    for name in names.iter().take(names.len().saturating_sub(1)).filter(|name| !is_compressed(name)) {
+        let path = area.dir.join(name);
+        let size = fs::metadata(&path)?.len();
+        let archive_path = compress_file(&path, level)?;
+        log::info!(
+            "Storage: Compressed {} ({} -> {} bytes)",
+            path.display(),
+            size,
+            fs::metadata(&archive_path).map(|metadata| metadata.len()).unwrap_or(0)
+        );
+        compressed += 1;
+    }
+    Ok(compressed)
+}
+
+// --- Listing ---
+/// A file of a storage area, as listed by the HTTP API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveEntry {
+    pub dir: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub compressed: bool,
+}
+
+/// The files of all areas, oldest first within each area.
+pub fn list(areas: &[StorageAreaConfig]) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    for area in areas {
+        for name in file_names(area)? {
+            let size = fs::metadata(area.dir.join(&name))?.len();
+            entries.push(ArchiveEntry { dir: area.dir.clone(), compressed: is_compressed(&name), name, size });
+        }
+    }
+    Ok(entries)
+}
+
+/// Content of the file `name` of one of the areas, None if no area has such a file.
+/// Only plain names of listed files are accepted, so no path outside the areas is read.
+pub fn read(areas: &[StorageAreaConfig], name: &str) -> io::Result<Option<Vec<u8>>> {
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return Ok(None);
+    }
+    for area in areas {
+        if file_names(area)?.iter().any(|listed| listed == name) {
+            return fs::read(area.dir.join(name)).map(Some);
+        }
+    }
+    Ok(None)
+}
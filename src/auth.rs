@@ -0,0 +1,198 @@
+// src/auth.rs
+use crate::{
+    config::{ApiAuthConfig, ApiClientConfig, Role},
+    error::AppError,
+    SystemCommand,
+};
+use std::io;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+// --- Roles ---
+/// Role needed to send `command` on any interface.
+pub fn command_role(command: &SystemCommand) -> Role {
+    match command {
+        SystemCommand::On | SystemCommand::Off => Role::Operator,
+        // Shuts the gateway down
+        SystemCommand::Quit => Role::Maintainer,
+    }
+}
+
+// --- Authorization ---
+/// Reason a request is refused.
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("no valid client certificate or token")]
+    Unauthenticated,
+    #[error("client {client} is a {role}, this needs the {needed} role")]
+    Forbidden { client: String, role: &'static str, needed: &'static str },
+}
+
+/// An authenticated API client.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+// Compares in constant time, so the response time does not reveal a token prefix
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Decides about the requests to the HTTP and gRPC APIs (see ApiAuthConfig).
+#[derive(Debug)]
+pub struct Authorizer {
+    config: ApiAuthConfig,
+}
+
+impl Authorizer {
+    pub fn new(config: ApiAuthConfig) -> Self {
+        Self { config }
+    }
+
+    // The certificate identifies the client first, the token is the fallback
+    fn client(&self, certificate_name: Option<&str>, token: Option<&str>) -> Option<&ApiClientConfig> {
+        let by_certificate = certificate_name.and_then(|name| {
+            self.config.clients.iter().find(|client| client.certificate_name.as_deref() == Some(name))
+        });
+        by_certificate.or_else(|| {
+            let token = token?;
+            self.config.clients.iter().find(|client| client.token.as_deref().is_some_and(|known| same_token(known, token)))
+        })
+    }
+
+    /// The client if its role includes `needed`. `certificate_name` is the common name
+    /// of the verified client certificate, `token` the bearer token. Without
+    /// authentication every client is an anonymous maintainer.
+    pub fn check(&self, certificate_name: Option<&str>, token: Option<&str>, needed: Role) -> Result<Caller, AuthError> {
+        if !self.config.enabled {
+            return Ok(Caller { name: "anonymous".to_string(), role: Role::Maintainer });
+        }
+        let client = self.client(certificate_name, token).ok_or(AuthError::Unauthenticated)?;
+        if client.role < needed {
+            return Err(AuthError::Forbidden {
+                client: client.name.clone(),
+                role: client.role.name(),
+                needed: needed.name(),
+            });
+        }
+        Ok(Caller { name: client.name.clone(), role: client.role })
+    }
+}
+
+/// Token of an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(value: &str) -> Option<&str> {
+    value.trim().strip_prefix("Bearer ").map(str::trim)
+}
+
+// --- Connections ---
+/// A connection to an API server, TLS if configured.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Accepts the connections of the HTTP API, with the TLS handshake if configured.
+#[derive(Clone)]
+pub struct Acceptor {
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+impl Acceptor {
+    pub fn new(config: &ApiAuthConfig) -> Result<Self, AppError> {
+        #[cfg(feature = "tls")]
+        return Ok(Self { tls: (config.enabled && config.tls()).then(|| tls::acceptor(config)).transpose()? });
+        #[cfg(not(feature = "tls"))]
+        {
+            if config.enabled && config.tls() {
+                return Err(AppError::Config("api_auth: TLS needs a build with the tls feature".to_string()));
+            }
+            Ok(Self {})
+        }
+    }
+
+    /// The connection and the common name of the verified client certificate, if any.
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<(Box<dyn Connection>, Option<String>)> {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = &self.tls {
+            let stream = acceptor.accept(stream).await?;
+            let name = stream.get_ref().1.peer_certificates().and_then(|certs| certs.first()).and_then(|cert| certificate_name(cert));
+            return Ok((Box::new(stream), name));
+        }
+        Ok((Box::new(stream), None))
+    }
+}
+
+/// Common name of the subject of a DER-encoded certificate.
+#[cfg(feature = "tls")]
+pub fn certificate_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let name = cert.subject().iter_common_name().next()?.as_str().ok()?.to_string();
+    Some(name)
+}
+
+// --- TLS ---
+#[cfg(feature = "tls")]
+pub mod tls {
+    use crate::{config::ApiAuthConfig, error::AppError};
+    use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+    use tokio_rustls::rustls::{
+        RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+    };
+
+    fn error(path: &Path, e: impl std::fmt::Display) -> AppError {
+        AppError::Config(format!("api_auth: {}: {}", path.display(), e))
+    }
+
+    fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, AppError> {
+        let mut reader = BufReader::new(File::open(path).map_err(|e| error(path, e))?);
+        rustls_pemfile::certs(&mut reader).collect::<Result<_, _>>().map_err(|e| error(path, e))
+    }
+
+    fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, AppError> {
+        let mut reader = BufReader::new(File::open(path).map_err(|e| error(path, e))?);
+        rustls_pemfile::private_key(&mut reader)
+            .map_err(|e| error(path, e))?
+            .ok_or_else(|| error(path, "no private key"))
+    }
+
+    /// PEM files of the configuration: certificate chain, key and client CA.
+    pub fn pem_files(config: &ApiAuthConfig) -> Result<(Vec<u8>, Vec<u8>, Option<Vec<u8>>), AppError> {
+        let read = |path: &Path| std::fs::read(path).map_err(|e| error(path, e));
+        let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+            return Err(AppError::Config("api_auth: tls_cert and tls_key have to be set together".to_string()));
+        };
+        Ok((read(cert)?, read(key)?, config.client_ca.as_deref().map(read).transpose()?))
+    }
+
+    /// TLS acceptor of the HTTP API. Client certificates are asked for with `client_ca`,
+    /// but not required, so clients without one can still use a token.
+    pub fn acceptor(config: &ApiAuthConfig) -> Result<tokio_rustls::TlsAcceptor, AppError> {
+        let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+            return Err(AppError::Config("api_auth: tls_cert and tls_key have to be set together".to_string()));
+        };
+        let builder = ServerConfig::builder();
+        let builder = match &config.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in certificates(ca)? {
+                    roots.add(certificate).map_err(|e| error(ca, e))?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()
+                    .map_err(|e| error(ca, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_config = builder
+            .with_single_cert(certificates(cert)?, private_key(key)?)
+            .map_err(|e| error(cert, e))?;
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(server_config)))
+    }
+}
@@ -0,0 +1,126 @@
+// src/auto_restart.rs
+use crate::{
+    SystemCommand,
+    arbiter::{self, CommandSource, SourcedCommand},
+    config::AutoRestartConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    flags::StatusByte,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+// Error bytes of a BMS that are not zero, a BMS in maintenance reports none
+fn error_codes(data: &BmsData) -> Vec<(StatusByte, u8)> {
+    if data.in_maintenance() {
+        return Vec::new();
+    }
+    [StatusByte::Error1, StatusByte::Error2]
+        .into_iter()
+        .filter_map(|byte| byte.value(data).filter(|value| *value != 0).map(|value| (byte, value)))
+        .collect()
+}
+
+// The system was switched on, or off by the fault signal only: an OFF of an operator or
+// the scheduler since then is respected
+fn was_running(data: &BmsData) -> bool {
+    let on = arbiter::command_code(&SystemCommand::On);
+    let off = arbiter::command_code(&SystemCommand::Off);
+    match data.last_command {
+        Some(command) if command == on => true,
+        Some(command) if command == off => data.last_command_source == Some(CommandSource::Fault.code()),
+        _ => false,
+    }
+}
+
+// --- Auto Restart Task ---
+/// Watches the error bytes of the BMS and sends ON to the arbiter after a fault that only
+/// showed transient codes (see AutoRestartConfig). The arbiter still applies its interlock.
+pub async fn task(
+    config: AutoRestartConfig,
+    bms: Vec<SharedBmsData>,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+) -> Result<(), AppError> {
+    log::info!(
+        "Automatic restart after transient faults {:?}: ON {:?} after the errors cleared, at most {} per day",
+        config.codes,
+        config.delay(),
+        config.max_attempts_per_day
+    );
+    // A transient fault is waiting for its restart, with the time the errors are clear since
+    let mut pending = false;
+    let mut clear_since: Option<Instant> = None;
+    let mut attempts: VecDeque<Instant> = VecDeque::new();
+
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let datasets: Vec<BmsData> = bms.iter().map(SharedBmsData::get).collect();
+        let codes: Vec<(StatusByte, u8)> = datasets.iter().flat_map(error_codes).collect();
+        let running = datasets.first().is_some_and(was_running);
+
+        if let Some(&(byte, value)) = codes.iter().find(|(byte, value)| !config.is_transient(*byte, *value)) {
+            if pending {
+                log::warn!(
+                    target: "audit",
+                    "Automatic restart cancelled: {} = {:#04X} is not a transient code.",
+                    byte.name(), value
+                );
+                pending = false;
+            }
+            clear_since = None;
+            continue;
+        }
+        if !codes.is_empty() {
+            if !pending && running {
+                log::warn!(
+                    target: "audit",
+                    "Transient fault {:?}, automatic restart {:?} after the errors clear.",
+                    codes, config.delay()
+                );
+                pending = true;
+            }
+            clear_since = None;
+            continue;
+        }
+        if !pending {
+            continue;
+        }
+        if !running {
+            log::info!(target: "audit", "Automatic restart cancelled: switched off after the fault.");
+            pending = false;
+            continue;
+        }
+        if clear_since.get_or_insert_with(Instant::now).elapsed() < config.delay() {
+            continue;
+        }
+
+        pending = false;
+        clear_since = None;
+        while attempts.front().is_some_and(|at| at.elapsed() >= ATTEMPT_WINDOW) {
+            attempts.pop_front();
+        }
+        if attempts.len() >= config.max_attempts_per_day as usize {
+            log::error!(
+                target: "audit",
+                "Automatic restart skipped: {} attempts within 24 h, waiting for an operator.",
+                attempts.len()
+            );
+            continue;
+        }
+        attempts.push_back(Instant::now());
+        log::warn!(
+            target: "audit",
+            "Automatic restart attempt {}/{}: errors clear for {:?}, sending ON.",
+            attempts.len(), config.max_attempts_per_day, config.delay()
+        );
+        input_tx
+            .send(SourcedCommand::new(CommandSource::AutoRestart, SystemCommand::On))
+            .map_err(|e| AppError::SendError(format!("Failed to send automatic restart: {}", e)))?;
+    }
+}
@@ -0,0 +1,245 @@
+// src/blackbox.rs
+use crate::{
+    can_sys::{CanFrame, EmbeddedFrame, Frame},
+    clock::{self, ClockStatus},
+    config::{BlackBoxConfig, FlagsConfig},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    flags, persist,
+    rules::Severity,
+    storage,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+
+// How often the decoded data is sampled and the fault state is checked
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Dump files are named blackbox_<unix seconds>_bms<id>.json
+pub const FILE_PREFIX: &str = "blackbox_";
+
+// --- Records ---
+/// A raw CAN frame as received by one of the RX tasks.
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameRecord {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    /// Monotonic time in milliseconds (see clock::monotonic_millis)
+    pub monotonic_ms: u128,
+    pub bms_id: u8,
+    pub can_id: u32,
+    pub data: Vec<u8>,
+}
+
+/// A decoded BMS dataset.
+#[derive(Debug, Clone, Serialize)]
+pub struct DataRecord {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    /// Monotonic time in milliseconds (see clock::monotonic_millis)
+    pub monotonic_ms: u128,
+    pub bms_id: u8,
+    pub data: BmsData,
+}
+
+// Content of a dump file
+#[derive(Serialize)]
+struct Dump<'a> {
+    bms_id: u8,
+    reason: &'a str,
+    trigger_timestamp_ms: u128,
+    // Whether the wall-clock timestamps can be trusted, None if not monitored
+    clock: Option<ClockStatus>,
+    frames: Vec<FrameRecord>,
+    data: Vec<DataRecord>,
+}
+
+// --- Frame Buffer ---
+/// Raw frames of the last `pre_trigger + post_trigger` seconds, fed by the CAN RX tasks.
+#[derive(Debug)]
+pub struct BlackBox {
+    retention: Duration,
+    max_frames: usize,
+    frames: Mutex<VecDeque<(Instant, FrameRecord)>>,
+}
+
+impl BlackBox {
+    pub fn new(config: &BlackBoxConfig) -> Self {
+        Self {
+            retention: config.pre_trigger() + config.post_trigger(),
+            max_frames: config.max_frames.max(1),
+            frames: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Appends a received frame and drops the frames that fell out of the retention.
+    pub fn record_frame(&self, bms_id: u8, frame: &CanFrame) {
+        let now = Instant::now();
+        let record = FrameRecord {
+            timestamp_ms: clock::unix_millis(),
+            monotonic_ms: clock::monotonic_millis(),
+            bms_id,
+            can_id: frame.raw_id(),
+            data: frame.data().to_vec(),
+        };
+        let Ok(mut frames) = self.frames.lock() else {
+            log::error!("Black box lock poisoned, dropping frame.");
+            return;
+        };
+        while let Some((at, _)) = frames.front() {
+            if frames.len() < self.max_frames && now.duration_since(*at) <= self.retention {
+                break;
+            }
+            frames.pop_front();
+        }
+        frames.push_back((now, record));
+    }
+
+    // Frames received since `since`
+    fn frames_since(&self, since: Instant) -> Vec<FrameRecord> {
+        self.frames
+            .lock()
+            .map(|frames| {
+                frames
+                    .iter()
+                    .filter(|(at, _)| *at >= since)
+                    .map(|(_, record)| record.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// --- Fault Detection ---
+// Why the dataset counts as faulted, None if it is healthy or the BMS is in maintenance
+fn fault_reason(data: &BmsData, flag_labels: &FlagsConfig) -> Option<String> {
+    if data.in_maintenance() {
+        return None;
+    }
+    let (error1, error2) = (data.error1.unwrap_or(0), data.error2.unwrap_or(0));
+    if error1 != 0 || error2 != 0 {
+        Some(format!(
+            "{} (error1 = {:#04X}, error2 = {:#04X})",
+            flags::error_summary(data, flag_labels),
+            error1,
+            error2
+        ))
+    } else if data.rule_severity == Some(Severity::Trip as u16) {
+        Some("threshold rule tripped".to_string())
+    } else if data.stale == Some(true) {
+        Some("data stale".to_string())
+    } else {
+        None
+    }
+}
+
+// A dump waiting for the end of the post-trigger recording
+struct Capture {
+    bms_id: u8,
+    reason: String,
+    triggered_at: Instant,
+    trigger_timestamp_ms: u128,
+}
+
+// --- Dump Files ---
+fn write_dump(config: &BlackBoxConfig, dump: &Dump<'_>) -> Result<PathBuf, AppError> {
+    fs::create_dir_all(&config.dir).map_err(|e| AppError::Persist(e.to_string()))?;
+    let path = config.dir.join(format!(
+        "{}{}_bms{}.json",
+        FILE_PREFIX,
+        dump.trigger_timestamp_ms / 1000,
+        dump.bms_id
+    ));
+    let content = serde_json::to_vec(dump).map_err(|e| AppError::Persist(e.to_string()))?;
+    persist::write_atomic(&path, &content).map_err(|e| AppError::Persist(e.to_string()))?;
+    // The names start with the Unix time, so they sort oldest first
+    if let Err(e) = storage::prune(&config.dir, FILE_PREFIX, config.quota_bytes, 0) {
+        log::error!("Black box: Failed to rotate dumps in {}: {}", config.dir.display(), e);
+    }
+    Ok(path)
+}
+
+// --- Black Box Task ---
+/// Samples the decoded data and watches for fault events (errors, tripped rules, stale data).
+/// When a BMS becomes faulted, the frames and data from `pre_trigger` before until
+/// `post_trigger` after the event are dumped. Events during a running capture are
+/// covered by it and do not start another one.
+pub async fn task(
+    config: BlackBoxConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    black_box: Arc<BlackBox>,
+    flag_labels: FlagsConfig,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting black box recorder ({:?} before / {:?} after events, dumps in {})",
+        config.pre_trigger(),
+        config.post_trigger(),
+        config.dir.display()
+    );
+    let retention = config.pre_trigger() + config.post_trigger();
+    let mut samples: VecDeque<(Instant, DataRecord)> = VecDeque::new();
+    let mut faulted = vec![false; bms.len()];
+    let mut capture: Option<Capture> = None;
+    let mut ticker = interval(SAMPLE_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+
+        for (index, (bms_id, bms_data)) in bms.iter().enumerate() {
+            let data = bms_data.get();
+            let reason = fault_reason(&data, &flag_labels);
+            if let (Some(reason), None, false) = (&reason, &capture, faulted[index]) {
+                log::warn!("Black box: BMS {} fault event ({}), recording.", bms_id, reason);
+                capture = Some(Capture {
+                    bms_id: *bms_id,
+                    reason: reason.clone(),
+                    triggered_at: now,
+                    trigger_timestamp_ms: clock::unix_millis(),
+                });
+            }
+            faulted[index] = reason.is_some();
+            let record = DataRecord {
+                timestamp_ms: clock::unix_millis(),
+                monotonic_ms: clock::monotonic_millis(),
+                bms_id: *bms_id,
+                data,
+            };
+            samples.push_back((now, record));
+        }
+        while samples.front().is_some_and(|(at, _)| now.duration_since(*at) > retention) {
+            samples.pop_front();
+        }
+
+        if let Some(capture) = capture.take_if(|c| now.duration_since(c.triggered_at) >= config.post_trigger()) {
+            let since = capture.triggered_at.checked_sub(config.pre_trigger()).unwrap_or(capture.triggered_at);
+            let dump = Dump {
+                bms_id: capture.bms_id,
+                reason: &capture.reason,
+                trigger_timestamp_ms: capture.trigger_timestamp_ms,
+                clock: clock::latest(),
+                frames: black_box.frames_since(since),
+                data: samples
+                    .iter()
+                    .filter(|(at, _)| *at >= since)
+                    .map(|(_, record)| record.clone())
+                    .collect(),
+            };
+            match write_dump(&config, &dump) {
+                Ok(path) => log::info!(
+                    "Black box: Wrote {} frames and {} samples to {}",
+                    dump.frames.len(),
+                    dump.data.len(),
+                    path.display()
+                ),
+                Err(e) => log::error!("Black box: Failed to write dump: {}", e),
+            }
+        }
+    }
+}
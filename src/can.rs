@@ -1,130 +1,994 @@
 // src/can.rs
-use crate::{data::BmsData, error::AppError, SystemCommand};
-use socketcan::{frame::AsPtr, EmbeddedFrame, StandardId, CanFrame, CanFilter, CanSocket, Frame, Socket, SocketOptions};
-use std::{sync::{Arc, RwLock}, time::Duration};
+use crate::{
+    arbiter::CommandResult,
+    blackbox::BlackBox,
+    can_stats::{self, CanRxStats},
+    config::{BmsField, CanConfig, CanMode, CanopenConfig, CommandAckConfig, FlagsConfig, NativeMessage, PdoMapping, PlausibilityConfig, RegisterTunnelConfig, RulesConfig, SdoRead, TxConfirmConfig},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    fault::{FaultCondition, FaultEvent, FaultSender, FaultSignal},
+    flags,
+    can_tx::{TxLane, TxPriority},
+    rules::{RuleEngine, Severity, SeverityMap},
+    SystemCommand,
+};
+use crate::can_sys::{AsPtr, EmbeddedFrame, ExtendedId, StandardId, CanFrame, CanFilter, CanSocket, Frame, Socket, SocketOptions};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, ErrorKind},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::time::sleep; // Use tokio's sleep
 
 // --- CAN Receiver Task ---
-pub async fn rx_task(can_if: &str, bms_id: u8, bms_data: Arc<RwLock<Option<BmsData>>>, error_tx: crossbeam_channel::Sender<()>) -> Result<(), AppError> {
+// Poll interval of the (non-blocking) CAN sockets while no frames arrive
+const RX_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Applies a received frame to the BMS data and checks errors and threshold rules
+fn process_frame(
+    bms_id: u8,
+    frame: &CanFrame,
+    bms_data: &SharedBmsData,
+    faults: &mut FaultSignal,
+    rule_engine: &mut RuleEngine,
+    severity_tx: &tokio::sync::watch::Sender<SeverityMap>,
+    flag_labels: &FlagsConfig,
+    can: &CanConfig,
+) -> Result<(), AppError> {
+    log::trace!("BMS {}: Received CAN frame: {:?}", bms_id, frame); // Use trace for verbose logging
+    let Some(message) = can.ids.message(bms_id, frame.raw_id()) else {
+        // Received through a filter added at runtime, there is no decoder for it
+        log::debug!("BMS {}: {}", bms_id, AppError::UnsupportedCanId(frame.raw_id()));
+        return Ok(());
+    };
+
+    bms_data.modify(|data_ref| {
+        let before = data_ref.clone();
+        // Update data from the frame
+        if let Err(e) = data_ref.update_from_frame(frame, message, &can.frame_check) {
+            log::error!("BMS {}: Failed to update data from CAN frame: {}", bms_id, e);
+            return;
+        }
+        flags::log_changes(bms_id, &before, data_ref, flag_labels);
+        log::debug!("BMS {}: Successfully updated data for CAN ID {:#X}", bms_id, frame.raw_id());
+        check_plausibility(bms_id, data_ref, &can.plausibility);
+
+        if message == NativeMessage::Status {
+            let data = frame.as_bytes(); // Use data() method
+            signal_fault(bms_id, data_ref, faults, FaultCondition::ErrorBits(bms_id), data[6] != 0 || data[7] != 0);
+        }
+
+        // Evaluate gateway-side threshold rules
+        evaluate_rules(bms_id, data_ref, rule_engine, faults, severity_tx);
+    });
+    Ok(())
+}
+
+// Counts and flags data that fails the plausibility checks, a hint at a wrong decoder
+fn check_plausibility(bms_id: u8, data_ref: &mut BmsData, config: &PlausibilityConfig) {
+    if !config.enabled {
+        return;
+    }
+    let reasons = data_ref.implausibilities(config);
+    let implausible = !reasons.is_empty();
+    if implausible {
+        data_ref.decoder_suspicion = Some(data_ref.decoder_suspicion.unwrap_or(0).wrapping_add(1));
+    }
+    if implausible != data_ref.implausible.unwrap_or(false) {
+        if implausible {
+            log::warn!("BMS {}: Implausible data, check the frame decoding: {}", bms_id, reasons.join(", "));
+        } else {
+            log::info!("BMS {}: Data is plausible again.", bms_id);
+        }
+    }
+    data_ref.implausible = Some(implausible);
+}
+
+// --- Runtime CAN Filters ---
+/// CAN IDs the native RX task of one BMS receives. Changes are applied to its sockets
+/// right away, e.g. after a reload of the frame definitions.
+#[derive(Clone)]
+pub struct CanFilters {
+    ids: Arc<tokio::sync::watch::Sender<BTreeSet<u32>>>,
+}
+
+impl CanFilters {
+    pub fn new(ids: impl IntoIterator<Item = u32>) -> Self {
+        Self { ids: Arc::new(tokio::sync::watch::Sender::new(ids.into_iter().collect())) }
+    }
+
+    /// Adds a filter, returns false if the ID was already received.
+    pub fn add(&self, can_id: u32) -> bool {
+        self.ids.send_if_modified(|ids| ids.insert(can_id))
+    }
+
+    /// Removes a filter, returns false if the ID was not received.
+    pub fn remove(&self, can_id: u32) -> bool {
+        self.ids.send_if_modified(|ids| ids.remove(&can_id))
+    }
+
+    pub fn ids(&self) -> Vec<u32> {
+        self.ids.borrow().iter().copied().collect()
+    }
+}
+
+// Applies the filter IDs to all sockets, without filters nothing is received
+fn apply_filters(bms_id: u8, sockets: &[CanSocket], ids: &BTreeSet<u32>, mask: u32) -> Result<(), AppError> {
+    let filters: Vec<CanFilter> = ids.iter().map(|&id| CanFilter::new(id, mask)).collect();
+    for socket in sockets {
+        socket.set_filters(&filters)?;
+    }
+    log::info!("BMS {}: Set CAN filters for IDs {:X?}", bms_id, ids);
+    Ok(())
+}
+
+// --- Bus Discovery ---
+// BMS a received ID belongs to, CANopen nodes are mapped via the configured node IDs
+fn discovered_bms(can: &CanConfig, can_id: u32) -> Option<u8> {
+    match can.mode {
+        CanMode::Native => (0..=u8::MAX).find(|&bms_id| can.ids.message(bms_id, can_id).is_some()),
+        CanMode::Canopen => {
+            let is_node_traffic = COB_TPDO.iter().chain([&COB_HEARTBEAT]).any(|base| can_id & 0x780 == u32::from(*base));
+            let node_id = (can_id & 0x7F) as u8;
+            if !is_node_traffic || node_id == 0 {
+                return None;
+            }
+            (1..=u8::MAX).find(|&bms_id| can.canopen.node_id(bms_id) == Some(node_id))
+        }
+    }
+}
+
+/// Opens a raw CAN socket on `can_if`, failing with `AppError::CanInit`.
+pub fn open_socket(can_if: &str) -> Result<CanSocket, AppError> {
+    CanSocket::open(can_if).map_err(|source| AppError::CanInit { interface: can_if.to_string(), source })
+}
+
+/// Listens unfiltered on every configured interface for `discovery.listen_ms` and returns
+/// the BMS that sent frames, with the CAN IDs received from each. Blocks while listening.
+pub fn discover(can: &CanConfig) -> Result<BTreeMap<u8, BTreeSet<u32>>, AppError> {
+    let mut interfaces: BTreeSet<&str> = BTreeSet::from([can.interface.as_str()]);
+    for bus in &can.buses {
+        interfaces.insert(&bus.primary);
+        interfaces.extend(bus.secondary.as_deref());
+    }
+    log::info!("Discovering BMS on {:?} for {:?}...", interfaces, can.discovery.listen());
+    let sockets = interfaces
+        .iter()
+        .map(|can_if| {
+            let socket = open_socket(can_if)?;
+            socket.set_nonblocking(true)?;
+            Ok((*can_if, socket))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let mut discovered: BTreeMap<u8, BTreeSet<u32>> = BTreeMap::new();
+    let mut unknown: BTreeSet<u32> = BTreeSet::new();
+    let deadline = Instant::now() + can.discovery.listen();
+    while Instant::now() < deadline {
+        let mut received = false;
+        for (can_if, socket) in &sockets {
+            match socket.read_frame() {
+                Ok(frame) => {
+                    received = true;
+                    let can_id = frame.raw_id();
+                    match discovered_bms(can, can_id) {
+                        Some(bms_id) => {
+                            if discovered.entry(bms_id).or_default().insert(can_id) {
+                                log::debug!("Discovery: {:#X} on {} belongs to BMS {}", can_id, can_if, bms_id);
+                            }
+                        }
+                        None => {
+                            unknown.insert(can_id);
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(AppError::CanSocket(e)),
+            }
+        }
+        if !received {
+            std::thread::sleep(RX_POLL_INTERVAL);
+        }
+    }
+
+    if discovered.is_empty() {
+        log::warn!("Discovery: No BMS found on the bus.");
+    }
+    for (bms_id, ids) in &discovered {
+        log::info!("Discovery: BMS {} present (IDs {:X?})", bms_id, ids);
+    }
+    if !unknown.is_empty() {
+        log::info!("Discovery: Frames with foreign IDs {:X?}", unknown);
+    }
+    Ok(discovered)
+}
+
+// Notices a BMS that stopped sending: its data is marked stale and the fault signal
+// raised once, until the next frame clears it again
+struct DataWatchdog {
+    timeout: Duration,
+    last_frame: Instant,
+    expired: bool,
+}
+
+impl DataWatchdog {
+    fn new(timeout: Duration) -> Self {
+        Self { timeout, last_frame: Instant::now(), expired: false }
+    }
+
+    fn frame_received(&mut self, bms_id: u8, now: Instant, faults: &mut FaultSignal) {
+        self.last_frame = now;
+        if self.expired {
+            self.expired = false;
+            log::info!("BMS {}: Receiving frames again.", bms_id);
+            faults.set(FaultCondition::DataTimeout(bms_id), false);
+        }
+    }
+
+    fn check(&mut self, bms_id: u8, now: Instant, bms_data: &SharedBmsData, faults: &mut FaultSignal) {
+        if self.expired || now.duration_since(self.last_frame) <= self.timeout {
+            return;
+        }
+        self.expired = true;
+        log::error!("BMS {}: No frames for {:?}, the data is stale. Signalling error.", bms_id, self.timeout);
+        bms_data.modify(|data_ref| {
+            data_ref.stale = Some(true);
+            signal_fault(bms_id, data_ref, faults, FaultCondition::DataTimeout(bms_id), true);
+        });
+    }
+}
+
+/// Receives the native BMS frames. With a secondary interface configured, both buses are
+/// monitored and reception switches to the secondary while the primary delivers no frames.
+/// Without frames for `data_timeout_ms` the data of the BMS is marked stale.
+pub async fn rx_task(
+    can: CanConfig,
+    bms_id: u8,
+    bms_data: SharedBmsData,
+    error_tx: FaultSender,
+    rules: RulesConfig,
+    flag_labels: FlagsConfig,
+    filters: CanFilters,
+    severity_tx: tokio::sync::watch::Sender<SeverityMap>,
+    black_box: Option<Arc<BlackBox>>,
+    rx_stats: Arc<CanRxStats>,
+) -> Result<(), AppError> {
     log::info!("Starting CAN RX task for BMS ID {}", bms_id);
+    let mut rule_engine = RuleEngine::new(rules);
+    let mut faults = FaultSignal::new(error_tx);
+    let bus = can.bus(bms_id);
+    let failover_timeout = can.failover_timeout();
+
+    // Open the CAN sockets, the primary first
+    let interfaces: Vec<&str> = std::iter::once(bus.primary.as_str()).chain(bus.secondary.as_deref()).collect();
+    let mut sockets = Vec::with_capacity(interfaces.len());
+    for can_if in &interfaces {
+        let socket = open_socket(can_if)?;
+        // Both buses are polled from this task, so reads must not block
+        socket.set_nonblocking(true)?;
+        log::info!("Opened CAN socket on {} for BMS ID {}", can_if, bms_id);
+        sockets.push(socket);
+    }
+    let mut filter_rx = filters.ids.subscribe();
+    apply_filters(bms_id, &sockets, &filter_rx.borrow_and_update(), can.ids.mask)?;
+    let redundant = sockets.len() > 1;
+
+    // Index of the bus whose frames are processed, last frame and error state per bus
+    let mut active = 0;
+    let mut last_frame = vec![Instant::now(); sockets.len()];
+    let mut read_failed = vec![false; sockets.len()];
+    let mut redundancy_lost = false;
+    let mut last_sample = Instant::now();
+    let mut watchdog = DataWatchdog::new(can.data_timeout());
+
+    loop {
+        faults.follow_control_mode(bms_data.read(BmsData::control_mode));
+        if filter_rx.has_changed().unwrap_or(false) {
+            apply_filters(bms_id, &sockets, &filter_rx.borrow_and_update(), can.ids.mask)?;
+        }
+        if last_sample.elapsed() >= can_stats::SAMPLE_INTERVAL {
+            last_sample = Instant::now();
+            for (can_if, socket) in interfaces.iter().zip(&sockets) {
+                rx_stats.sample(bms_id, can_if, socket);
+            }
+        }
+
+        let mut received = false;
+        for (index, socket) in sockets.iter().enumerate() {
+            loop {
+                match socket.read_frame() {
+                    Ok(frame) => {
+                        received = true;
+                        last_frame[index] = Instant::now();
+                        read_failed[index] = false;
+                        // Frames of the standby bus only prove that it is alive
+                        if index == active {
+                            watchdog.frame_received(bms_id, last_frame[index], &mut faults);
+                            if let Some(black_box) = &black_box {
+                                black_box.record_frame(bms_id, &frame);
+                            }
+                            process_frame(
+                                bms_id,
+                                &frame,
+                                &bms_data,
+                                &mut faults,
+                                &mut rule_engine,
+                                &severity_tx,
+                                &flag_labels,
+                                &can,
+                            )?;
+                        }
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) if !redundant => {
+                        // Handle other read errors (e.g., device unplugged)
+                        log::error!("BMS {}: Error reading from CAN socket: {}", bms_id, e);
+                        // Optional: add a delay before retrying or attempting to reopen
+                        sleep(Duration::from_secs(1)).await;
+                        // Potentially return the error to stop the task
+                        return Err(AppError::CanSocket(e));
+                    }
+                    Err(e) => {
+                        // With a redundant bus the failover takes care of a failed interface
+                        if !read_failed[index] {
+                            log::error!(
+                                "BMS {}: Error reading from CAN socket on {}: {}",
+                                bms_id, interfaces[index], e
+                            );
+                            read_failed[index] = true;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
 
-    // Open the CAN socket
-    let socket = CanSocket::open(can_if)?;
+        if redundant {
+            let primary_ok = last_frame[0].elapsed() < failover_timeout;
+            let secondary_ok = last_frame[1].elapsed() < failover_timeout;
+
+            let wanted = if !primary_ok && secondary_ok { 1 } else { 0 };
+            if wanted != active {
+                if wanted == 1 {
+                    log::warn!(
+                        "BMS {}: No frames on primary CAN {} for {:?}, switching to secondary {}.",
+                        bms_id, interfaces[0], failover_timeout, interfaces[1]
+                    );
+                } else {
+                    log::info!("BMS {}: Primary CAN {} is back, switching back to it.", bms_id, interfaces[0]);
+                }
+                active = wanted;
+            }
+
+            let lost = !(primary_ok && secondary_ok);
+            if lost != redundancy_lost {
+                redundancy_lost = lost;
+                if lost {
+                    log::warn!(
+                        "BMS {}: CAN redundancy lost (primary {} {}, secondary {} {}).",
+                        bms_id,
+                        interfaces[0],
+                        if primary_ok { "ok" } else { "silent" },
+                        interfaces[1],
+                        if secondary_ok { "ok" } else { "silent" }
+                    );
+                } else {
+                    log::info!("BMS {}: CAN redundancy restored.", bms_id);
+                }
+                bms_data.modify(|data| data.redundancy_lost = Some(lost));
+            }
+        }
+        watchdog.check(bms_id, Instant::now(), &bms_data, &mut faults);
+
+        if received {
+            // Yield to prevent a tight loop if many frames arrive quickly
+            tokio::task::yield_now().await;
+        } else {
+            sleep(RX_POLL_INTERVAL).await;
+        }
+    }
+    // Note: This loop currently runs forever. Add exit conditions if needed.
+}
+
+
+// Forwards the level of a fault condition to the inverters and LEDs, which only see its
+// edges. A BMS in maintenance raises nothing, a condition still active afterwards is
+// raised with the next update.
+fn signal_fault(bms_id: u8, data_ref: &BmsData, faults: &mut FaultSignal, condition: FaultCondition, active: bool) {
+    if active && data_ref.in_maintenance() {
+        log::debug!("BMS {}: In maintenance, not signalling {:?}.", bms_id, condition);
+        faults.clear_bms(bms_id);
+        return;
+    }
+    faults.set(condition, active);
+}
+
+// Evaluates the threshold rules after an update and publishes the highest severity
+fn evaluate_rules(
+    bms_id: u8,
+    data_ref: &mut BmsData,
+    rule_engine: &mut RuleEngine,
+    faults: &mut FaultSignal,
+    severity_tx: &tokio::sync::watch::Sender<SeverityMap>,
+) {
+    let events = rule_engine.evaluate(data_ref);
+    if !events.is_empty() {
+        handle_rule_events(bms_id, data_ref, &events, faults);
+        let highest = rule_engine.highest();
+        data_ref.rule_severity = Some(highest as u16);
+        severity_tx.send_modify(|map| {
+            map.insert(bms_id, highest);
+        });
+    }
+}
+
+// Logs rule severity changes and forwards trips into the error path (inverter OFF, LEDs)
+fn handle_rule_events(
+    bms_id: u8,
+    data_ref: &BmsData,
+    events: &[crate::rules::RuleEvent],
+    faults: &mut FaultSignal,
+) {
+    for event in events {
+        match event.severity {
+            Severity::Normal => log::info!(
+                "BMS {}: {:?} back to normal (value {}, was {:?})",
+                bms_id, event.rule, event.value, event.previous
+            ),
+            Severity::Warning | Severity::Derate => log::warn!(
+                "BMS {}: {:?} at {:?} level (value {})",
+                bms_id, event.rule, event.severity, event.value
+            ),
+            Severity::Trip => {
+                log::error!(
+                    "BMS {}: {:?} tripped (value {}). Signalling error.",
+                    bms_id, event.rule, event.value
+                );
+            }
+        }
+        let condition = FaultCondition::RuleTrip(bms_id, event.rule);
+        signal_fault(bms_id, data_ref, faults, condition, event.severity == Severity::Trip);
+    }
+}
+
+
+// --- CANopen Receiver Task ---
+// Function code bases of the CANopen predefined connection set (COB-ID = base + node ID)
+const COB_NMT: u16 = 0x000;
+const COB_TPDO: [u16; 4] = [0x180, 0x280, 0x380, 0x480];
+const COB_SDO_TX: u16 = 0x580; // Node -> gateway
+const COB_SDO_RX: u16 = 0x600; // Gateway -> node
+const COB_HEARTBEAT: u16 = 0x700;
+
+const NMT_START_REMOTE_NODE: u8 = 0x01;
+const NMT_STATE_BOOTUP: u8 = 0x00;
+const SDO_UPLOAD_REQUEST: u8 = 0x40;
+const SDO_ABORT: u8 = 0x80;
+
+// How often the heartbeat timeout is checked while no frames arrive
+const CANOPEN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// TPDO mapping matching the layout of the native frames:
+/// TPDO1 like message 1, TPDO2 like message 2 and TPDO3 like message 3.
+pub fn default_tpdo_mapping() -> Vec<PdoMapping> {
+    let entry = |pdo, offset, length, field| PdoMapping { pdo, offset, length, field };
+    vec![
+        entry(1, 0, 2, BmsField::MinCellVoltage),
+        entry(1, 2, 2, BmsField::MaxCellVoltage),
+        entry(1, 4, 1, BmsField::MinTemperature),
+        entry(1, 5, 1, BmsField::MaxTemperature),
+        entry(1, 6, 1, BmsField::Info),
+        entry(1, 7, 1, BmsField::Soc),
+        entry(2, 0, 2, BmsField::Current),
+        entry(2, 2, 2, BmsField::TotalVoltage),
+        entry(2, 4, 1, BmsField::Warning1),
+        entry(2, 5, 1, BmsField::Warning2),
+        entry(2, 6, 1, BmsField::Error1),
+        entry(2, 7, 1, BmsField::Error2),
+        entry(3, 0, 4, BmsField::Current32),
+        entry(3, 4, 4, BmsField::TotalVoltage32),
+    ]
+}
+
+fn standard_frame(cob_id: u16, data: &[u8]) -> io::Result<CanFrame> {
+    StandardId::new(cob_id)
+        .and_then(|id| CanFrame::new(id, data))
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid 11-bit CAN ID {:#X}", cob_id)))
+}
+
+// CANopen values are little endian
+fn le_value(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |acc, byte| (acc << 8) | u32::from(*byte))
+}
+
+fn send_nmt_start(socket: &CanSocket, tx: &TxLane, node_id: u8) {
+    let result = standard_frame(COB_NMT, &[NMT_START_REMOTE_NODE, node_id]).and_then(|frame| {
+        tx.acquire_blocking(TxPriority::Normal, &frame);
+        socket.write_frame(&frame)
+    });
+    match result {
+        Ok(()) => log::info!("CANopen: Sent NMT start to node {}", node_id),
+        Err(e) => log::error!("CANopen: Failed to send NMT start to node {}: {}", node_id, e),
+    }
+}
+
+// Expedited SDO upload of one object dictionary entry. Other frames are skipped while
+// waiting, which loses nothing as the node does not send PDOs before it is started.
+fn sdo_read(socket: &CanSocket, tx: &TxLane, node_id: u8, read: &SdoRead, timeout: Duration) -> Result<u32, String> {
+    let [index_lo, index_hi] = read.index.to_le_bytes();
+    let request = [SDO_UPLOAD_REQUEST, index_lo, index_hi, read.subindex, 0, 0, 0, 0];
+    let frame = standard_frame(COB_SDO_RX + u16::from(node_id), &request).map_err(|e| e.to_string())?;
+    tx.acquire_blocking(TxPriority::Normal, &frame);
+    socket.write_frame(&frame).map_err(|e| e.to_string())?;
+
+    let response_id = u32::from(COB_SDO_TX + u16::from(node_id));
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timeout".to_string());
+        }
+        let frame = match socket.read_frame_timeout(remaining) {
+            Ok(frame) => frame,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err("timeout".to_string());
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let data = frame.data();
+        // Skip everything but the response to this index/subindex
+        if frame.raw_id() != response_id || data.len() != 8 || data[1..4] != request[1..4] {
+            continue;
+        }
+
+        let command = data[0];
+        if command == SDO_ABORT {
+            return Err(format!("aborted with code {:#010X}", le_value(&data[4..8])));
+        }
+        // Expedited upload response: 010x nnes, n = unused bytes if the size is indicated (s)
+        if command & 0xE2 != 0x42 {
+            return Err(format!("unsupported response {:#04X} (segmented transfer?)", command));
+        }
+        let unused = if command & 0x01 != 0 { usize::from((command >> 2) & 0x03) } else { 0 };
+        return Ok(le_value(&data[4..8 - unused]));
+    }
+}
+
+// --- Register Tunnel ---
+const SDO_DOWNLOAD_2_BYTES: u8 = 0x2B;
+const SDO_DOWNLOAD_RESPONSE: u8 = 0x60;
+
+/// Why a tunneled parameter access failed.
+#[derive(Debug, thiserror::Error)]
+pub enum TunnelError {
+    #[error("no response from the BMS")]
+    Timeout,
+    #[error("aborted by the BMS with code {0:#010X}")]
+    Aborted(u32),
+    #[error("unexpected response {0:#04X}")]
+    UnexpectedResponse(u8),
+    #[error("CAN error: {0}")]
+    Can(#[from] std::io::Error),
+}
+
+/// SDO-style parameter access of the BMS behind the register window of a Modbus
+/// server (see `RegisterTunnelConfig`). Accesses are serialized, one request is
+/// outstanding at a time.
+#[derive(Debug)]
+pub struct RegisterTunnel {
+    config: RegisterTunnelConfig,
+    socket: Mutex<CanSocket>,
+    tx: Arc<TxLane>,
+}
+
+impl RegisterTunnel {
+    pub fn open(can_if: &str, config: RegisterTunnelConfig, tx: Arc<TxLane>) -> Result<Self, AppError> {
+        let socket = open_socket(can_if)?;
+        socket.set_filters(&[CanFilter::new(u32::from(config.response_id), 0x7FF)])?;
+        log::info!(
+            "Register tunnel {}..{} on {} (request {:#X}, response {:#X})",
+            config.start,
+            u32::from(config.start) + u32::from(config.count),
+            can_if,
+            config.request_id,
+            config.response_id
+        );
+        Ok(Self { config, socket: Mutex::new(socket) })
+    }
+
+    /// Parameter index of a register, None outside of the window.
+    pub fn parameter(&self, register: u16) -> Option<u16> {
+        self.config.parameter(register)
+    }
+
+    pub fn read(&self, parameter: u16) -> Result<u16, TunnelError> {
+        let [index_lo, index_hi] = parameter.to_le_bytes();
+        let response = self.transfer([SDO_UPLOAD_REQUEST, index_lo, index_hi, 0, 0, 0, 0, 0])?;
+        // Expedited upload response, the size indication is not required
+        if response[0] & 0xE2 != 0x42 {
+            return Err(TunnelError::UnexpectedResponse(response[0]));
+        }
+        Ok(u16::from_le_bytes([response[4], response[5]]))
+    }
+
+    pub fn write(&self, parameter: u16, value: u16) -> Result<(), TunnelError> {
+        let [index_lo, index_hi] = parameter.to_le_bytes();
+        let [value_lo, value_hi] = value.to_le_bytes();
+        let response = self.transfer([SDO_DOWNLOAD_2_BYTES, index_lo, index_hi, 0, value_lo, value_hi, 0, 0])?;
+        if response[0] != SDO_DOWNLOAD_RESPONSE {
+            return Err(TunnelError::UnexpectedResponse(response[0]));
+        }
+        Ok(())
+    }
+
+    // Sends the request and waits for the response to the same index/subindex
+    fn transfer(&self, request: [u8; 8]) -> Result<[u8; 8], TunnelError> {
+        let socket = self.socket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Late responses of timed out requests must not be taken for this one
+        while socket.read_frame_timeout(Duration::ZERO).is_ok() {}
+        let frame = standard_frame(self.config.request_id, &request)?;
+        self.tx.acquire_blocking(TxPriority::Normal, &frame);
+        socket.write_frame(&frame)?;
+
+        let deadline = Instant::now() + self.config.timeout();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(TunnelError::Timeout);
+            }
+            let frame = match socket.read_frame_timeout(remaining) {
+                Ok(frame) => frame,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(TunnelError::Timeout);
+                }
+                Err(e) => return Err(TunnelError::Can(e)),
+            };
+            let Ok(response) = <[u8; 8]>::try_from(frame.data()) else {
+                continue;
+            };
+            if response[1..4] != request[1..4] {
+                continue;
+            }
+            if response[0] == SDO_ABORT {
+                return Err(TunnelError::Aborted(le_value(&response[4..8])));
+            }
+            return Ok(response);
+        }
+    }
+}
+
+// Decodes the mapped values of TPDO `pdo`, returns true if an error byte is mapped to it
+fn apply_tpdo(data_ref: &mut BmsData, mapping: &[PdoMapping], pdo: u8, payload: &[u8]) -> bool {
+    let mut maps_errors = false;
+    for entry in mapping.iter().filter(|entry| entry.pdo == pdo) {
+        match payload.get(entry.offset..entry.offset + entry.length) {
+            Some(bytes) if (1..=4).contains(&entry.length) => {
+                data_ref.set_field(entry.field, le_value(bytes));
+                maps_errors |= matches!(entry.field, BmsField::Error1 | BmsField::Error2);
+            }
+            _ => log::warn!(
+                "CANopen: Mapping of {:?} does not fit TPDO{} with {} bytes",
+                entry.field, pdo, payload.len()
+            ),
+        }
+    }
+    data_ref.stale = Some(false);
+    maps_errors
+}
+
+/// Receives the data of a CANopen BMS: reads the configured SDOs, starts the node via NMT,
+/// maps its TPDOs into BmsData and signals an error if its heartbeat is missing.
+pub async fn canopen_rx_task(
+    can_if: &str,
+    bms_id: u8,
+    config: CanopenConfig,
+    plausibility: PlausibilityConfig,
+    bms_data: SharedBmsData,
+    error_tx: FaultSender,
+    rules: RulesConfig,
+    flag_labels: FlagsConfig,
+    severity_tx: tokio::sync::watch::Sender<SeverityMap>,
+    black_box: Option<Arc<BlackBox>>,
+    tx: Arc<TxLane>,
+    rx_stats: Arc<CanRxStats>,
+) -> Result<(), AppError> {
+    let node_id = config
+        .node_id(bms_id)
+        .ok_or_else(|| AppError::Config(format!("No CANopen node ID configured for BMS {}", bms_id)))?;
+    log::info!("Starting CANopen RX task for BMS ID {} (node {})", bms_id, node_id);
+    let mut rule_engine = RuleEngine::new(rules);
+    let mut faults = FaultSignal::new(error_tx);
+
+    let socket = open_socket(can_if)?;
     log::info!("Opened CAN socket on {} for BMS ID {}", can_if, bms_id);
 
-    // Define CAN IDs to filter for based on bms_id
-    let can_id1: u32 = if bms_id == 1 { 0xB101 } else { 0xB102 };
-    let can_id2: u32 = if bms_id == 1 { 0xB201 } else { 0xB202 };
-
-    // Set CAN filters
-    // Standard Frame ID Mask (0x7FF for 11-bit IDs)
-    // Use 0x1FFFFFFF for standard or extended frames if unsure
-    let filters = [
-        CanFilter::new(can_id1, 0x1FFFFFFF),
-        CanFilter::new(can_id2, 0x1FFFFFFF),
-    ];
+    // Standard frame ID Mask (0x7FF for 11-bit IDs)
+    let filters: Vec<CanFilter> = COB_TPDO
+        .iter()
+        .chain([&COB_SDO_TX, &COB_HEARTBEAT])
+        .map(|base| CanFilter::new(u32::from(base + u16::from(node_id)), 0x7FF))
+        .collect();
     socket.set_filters(&filters)?;
-    log::info!("Set CAN filters for IDs {:#X} and {:#X}", can_id1, can_id2);
 
-    // Set non-blocking mode might be beneficial with async, but read_frame can block
-    // socket.set_nonblocking(true)?;
+    // The node is still pre-operational here, so the SDO transfers see no PDO traffic
+    if !config.sdo_reads.is_empty() {
+        let mut metadata = BTreeMap::new();
+        for read in &config.sdo_reads {
+            match sdo_read(&socket, &tx, node_id, read, config.sdo_timeout()) {
+                Ok(value) => {
+                    log::info!(
+                        "BMS {}: {} ({:#06X}sub{}) = {}",
+                        bms_id, read.name, read.index, read.subindex, value
+                    );
+                    metadata.insert(read.name.clone(), value);
+                }
+                Err(e) => log::warn!(
+                    "BMS {}: SDO read of {} ({:#06X}sub{}) failed: {}",
+                    bms_id, read.name, read.index, read.subindex, e
+                ),
+            }
+        }
+        bms_data.modify(|data| data.pack_metadata = metadata);
+    }
+
+    if config.start_node {
+        send_nmt_start(&socket, &tx, node_id);
+    }
+
+    let heartbeat_id = COB_HEARTBEAT + u16::from(node_id);
+    let heartbeat_timeout = config.heartbeat_timeout();
+    let mut last_heartbeat = Instant::now();
+    let mut node_lost = false;
+    let mut last_sample = Instant::now();
 
     loop {
-        match socket.read_frame() {
+        faults.follow_control_mode(bms_data.read(BmsData::control_mode));
+        if last_sample.elapsed() >= can_stats::SAMPLE_INTERVAL {
+            last_sample = Instant::now();
+            rx_stats.sample(bms_id, can_if, &socket);
+        }
+        match socket.read_frame_timeout(CANOPEN_POLL_INTERVAL) {
             Ok(frame) => {
-                log::trace!("BMS {}: Received CAN frame: {:?}", bms_id, frame); // Use trace for verbose logging
-
-                // Acquire write lock to update data
-                match bms_data.write() {
-                    Ok(mut data_guard) => {
-                        // Get mutable reference, initializing if None
-                        let data_ref = data_guard.get_or_insert_with(BmsData::default);
-                        // Update data from the frame
-                        if let Err(e) = data_ref.update_from_frame(&frame) {
-                            log::error!("BMS {}: Failed to update data from CAN frame: {}", bms_id, e);
-                        } else {
-                             log::debug!("BMS {}: Successfully updated data for CAN ID {:#X}", bms_id, frame.raw_id());
-
-                             let can_id = frame.raw_id(); // Use id() method
-                            
-                             match can_id {
-                                0xB201 | 0xB202 => {
-                                    let data = frame.as_bytes(); // Use data() method
-                                    if data[6] != 0 || data[7] != 0 {
-                                        let _ = error_tx.send(());
-                                    }
-                                },
-                                _ => {}
-                             };
-                        }
+                log::trace!("BMS {}: Received CAN frame: {:?}", bms_id, frame);
+                if let Some(black_box) = &black_box {
+                    black_box.record_frame(bms_id, &frame);
+                }
+                // Only standard IDs pass the filters
+                let cob_id = frame.raw_id() as u16;
+                let payload = frame.data();
+
+                if cob_id == heartbeat_id {
+                    last_heartbeat = Instant::now();
+                    if node_lost {
+                        log::info!("BMS {}: Heartbeat of CANopen node {} is back.", bms_id, node_id);
+                        node_lost = false;
+                        faults.set(FaultCondition::HeartbeatLost(bms_id), false);
                     }
-                    Err(e) => {
-                        log::error!("BMS {}: Failed to acquire write lock: {}", bms_id, e);
-                        // Consider breaking or specific error handling for poisoned lock
-                        return Err(AppError::LockPoisoned);
+                    // A node that (re)booted waits in pre-operational until it is started again
+                    if payload.first() == Some(&NMT_STATE_BOOTUP) && config.start_node {
+                        log::warn!("BMS {}: CANopen node {} booted, starting it.", bms_id, node_id);
+                        send_nmt_start(&socket, &tx, node_id);
                     }
+                } else if let Some(index) = COB_TPDO.iter().position(|base| base + u16::from(node_id) == cob_id) {
+                    let pdo = index as u8 + 1;
+                    bms_data.modify(|data_ref| {
+                        let before = data_ref.clone();
+                        if apply_tpdo(data_ref, &config.tpdo_mapping, pdo, payload) {
+                            flags::log_changes(bms_id, &before, data_ref, &flag_labels);
+                            check_plausibility(bms_id, data_ref, &plausibility);
+                            let error = data_ref.error1.unwrap_or(0) != 0 || data_ref.error2.unwrap_or(0) != 0;
+                            signal_fault(bms_id, data_ref, &mut faults, FaultCondition::ErrorBits(bms_id), error);
+                        }
+                        evaluate_rules(bms_id, data_ref, &mut rule_engine, &mut faults, &severity_tx);
+                    });
                 }
+                // Late SDO responses are ignored
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No frame available right now (only relevant in non-blocking mode)
-                // Yield control to the Tokio runtime
+            Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
                 tokio::task::yield_now().await;
-                continue;
             }
             Err(e) => {
-                // Handle other read errors (e.g., device unplugged)
                 log::error!("BMS {}: Error reading from CAN socket: {}", bms_id, e);
-                // Optional: add a delay before retrying or attempting to reopen
                 sleep(Duration::from_secs(1)).await;
-                // Potentially return the error to stop the task
                 return Err(AppError::CanSocket(e));
             }
         }
-        // Optional: yield to prevent tight loop if many frames arrive quickly
-        // tokio::task::yield_now().await;
+
+        // Node guarding via the heartbeat consumer time
+        if let Some(timeout) = heartbeat_timeout {
+            if !node_lost && last_heartbeat.elapsed() > timeout {
+                node_lost = true;
+                log::error!(
+                    "BMS {}: No heartbeat from CANopen node {} for {:?}. Signalling error.",
+                    bms_id, node_id, timeout
+                );
+                bms_data.modify(|data_ref| {
+                    data_ref.stale = Some(true);
+                    signal_fault(bms_id, data_ref, &mut faults, FaultCondition::HeartbeatLost(bms_id), true);
+                });
+            }
+        }
     }
-    // Note: This loop currently runs forever. Add exit conditions if needed.
 }
 
 
 // --- CAN Transmitter Task  ---
+/// Name under which the CAN transmitter reports command results to the arbiter.
+pub const TX_OUTPUT_NAME: &str = "can_tx";
+
+// Builds the command frame sent to the BMS for a system command. The IDs need 29 bits.
+fn command_frame(command: &SystemCommand) -> Result<CanFrame, AppError> {
+    let (raw_id, data): (u32, [u8; 8]) = match command {
+        SystemCommand::Off => (0xA300, [0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]),
+        SystemCommand::On => (0xA300, [0x20, 0x20, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]),
+        SystemCommand::Quit => (0xA100, [0x20, 0x20, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]),
+    };
+    ExtendedId::new(raw_id)
+        .and_then(|id| CanFrame::new(id, &data))
+        .ok_or(AppError::UnsupportedCanId(raw_id))
+}
+
+// Waits for the acknowledgement of `command`, other frames are skipped
+fn wait_for_ack(socket: &CanSocket, command: &CanFrame, ack: &CommandAckConfig) -> Result<(), String> {
+    let deadline = Instant::now() + ack.timeout();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timeout".to_string());
+        }
+        let frame = match socket.read_frame_timeout(remaining) {
+            Ok(frame) => frame,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err("timeout".to_string());
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        if frame.raw_id() != ack.can_id {
+            continue;
+        }
+        if ack.match_command && frame.data().first() != command.data().first() {
+            log::debug!("CAN TX: Ignoring acknowledgement {:02X?} of another command", frame.data());
+            continue;
+        }
+        return Ok(());
+    }
+}
+
+// Reads the frames left over from earlier commands (late echoes and acknowledgements),
+// so they are not taken for those of the next frame
+fn drain(socket: &CanSocket) -> std::io::Result<()> {
+    socket.set_nonblocking(true)?;
+    while socket.read_frame().is_ok() {}
+    socket.set_nonblocking(false)
+}
+
+// Waits for the echo of `frame`, SocketCAN loops it back once the controller has sent it
+fn wait_for_echo(socket: &CanSocket, frame: &CanFrame, confirm: &TxConfirmConfig) -> Result<(), String> {
+    let deadline = Instant::now() + confirm.timeout();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("no TX confirmation".to_string());
+        }
+        let received = match socket.read_frame_timeout(remaining) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err("no TX confirmation".to_string());
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        if let CanFrame::Error(error) = received {
+            return Err(format!("error frame on the bus: {:?}", error));
+        }
+        if received.raw_id() == frame.raw_id() && received.data() == frame.data() {
+            return Ok(());
+        }
+    }
+}
+
+// Writes a command frame and, if configured, waits until it went out on the bus
+// Commands are safety frames, they are never held back by the transmit limits
+fn transmit(socket: &CanSocket, tx: &TxLane, frame: &CanFrame, confirm: &TxConfirmConfig) -> Result<(), String> {
+    tx.acquire_blocking(TxPriority::Safety, frame);
+    if confirm.enabled {
+        drain(socket).map_err(|e| e.to_string())?;
+    }
+    socket.write_frame(frame).map_err(|e| e.to_string())?;
+    if confirm.enabled {
+        wait_for_echo(socket, frame, confirm)?;
+    }
+    Ok(())
+}
+
+// Sends the command frame, re-sending it until the BMS acknowledges it if configured
+fn send_command(
+    socket: &CanSocket,
+    tx: &TxLane,
+    command: &SystemCommand,
+    ack: &CommandAckConfig,
+    confirm: &TxConfirmConfig,
+) -> Result<(), String> {
+    let frame = command_frame(command).map_err(|e| e.to_string())?;
+    // QUIT is not part of the acknowledged 0xA300 protocol
+    if !ack.enabled || *command == SystemCommand::Quit {
+        return transmit(socket, tx, &frame, confirm);
+    }
+    for attempt in 0..=ack.retries {
+        if attempt > 0 {
+            log::warn!("CAN TX: Re-sending {:?} frame (attempt {}/{})", command, attempt, ack.retries);
+        }
+        transmit(socket, tx, &frame, confirm)?;
+        match wait_for_ack(socket, &frame, ack) {
+            Ok(()) => {
+                log::debug!("CAN TX: {:?} acknowledged by the BMS", command);
+                return Ok(());
+            }
+            Err(e) => log::warn!("CAN TX: No acknowledgement for {:?}: {}", command, e),
+        }
+    }
+    Err(format!("not acknowledged after {} attempts", ack.retries + 1))
+}
+
+/// Sends the commands to the BMS and reports the results to the arbiter. An OFF frame
+/// that did not go out on the bus (see TxConfirmConfig) raises the fault signal on `error_tx`.
 pub async fn tx_task(
     can_if: &str,
+    ack: CommandAckConfig,
+    confirm: TxConfirmConfig,
     output_rx: crossbeam_channel::Receiver<SystemCommand>,
+    result_tx: crossbeam_channel::Sender<CommandResult>,
+    error_tx: FaultSender,
+    tx: Arc<TxLane>,
 ) -> Result<(), AppError> {
     log::info!("Starting CAN TX task");
-    let socket = CanSocket::open(can_if)?;
+    let socket = open_socket(can_if)?;
+    // Only the acknowledgements and the echoes are of interest on this socket
+    let mut filter_ids = BTreeSet::new();
+    if ack.enabled {
+        filter_ids.insert(ack.can_id);
+        log::info!("CAN TX: Expecting command acknowledgements on {:#X}", ack.can_id);
+    }
+    if confirm.enabled {
+        socket.set_recv_own_msgs(true)?;
+        socket.set_error_filter_accept_all()?;
+        for command in [SystemCommand::Off, SystemCommand::On, SystemCommand::Quit] {
+            filter_ids.insert(command_frame(&command)?.raw_id());
+        }
+        log::info!("CAN TX: Confirming the command frames by their echo within {:?}", confirm.timeout());
+    }
+    if !filter_ids.is_empty() {
+        let filters: Vec<CanFilter> = filter_ids.into_iter().map(|id| CanFilter::new(id, 0x1FFF_FFFF)).collect();
+        socket.set_filters(&filters)?;
+    }
 
     loop {
         match output_rx.recv() {
             Ok(command) => {
-                match command {
-                    SystemCommand::Off => {
-                        let id: StandardId = StandardId::new(0xA300)
-                            .expect("Invalid CAN id for StandardId");
-                        let frame = CanFrame::new(
-                            id, 
-                            &[0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]).unwrap();
-                        socket.write_frame(&frame)?;
-                    }
-                    SystemCommand::On => {
-                        let id: StandardId = StandardId::new(0xA300)
-                        .expect("Invalid CAN id for StandardId");
-                        let frame = CanFrame::new(
-                            id, 
-                            &[0x20, 0x20, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]).unwrap();
-                        socket.write_frame(&frame)?;
-                    }
-                    SystemCommand::Quit => {
-                        let id: StandardId = StandardId::new(0xA100)
-                        .expect("Invalid CAN id for StandardId");
-                        let frame = CanFrame::new(
-                            id, 
-                            &[0x20, 0x20, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B, 0x0B]).unwrap();
-                        socket.write_frame(&frame)?;
-                        log::info!("CAN TX task received Quit command, exiting.");
-                        break;
+                let success = match send_command(&socket, &tx, &command, &ack, &confirm) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::error!("CAN TX: Failed to send {:?} frame: {}", command, e);
+                        false
                     }
+                };
+                // The BMS may still be on, fall back to switching the inverters off
+                if !success && confirm.enabled && command == SystemCommand::Off {
+                    log::error!("CAN TX: OFF not confirmed on the bus, raising the fault signal.");
+                    // Raised for every failed OFF, each one leaves the BMS on
+                    error_tx.send(FaultEvent::Raised(FaultCondition::OffNotSent));
+                }
+                let result = CommandResult {
+                    output: TX_OUTPUT_NAME.to_string(),
+                    command: command.clone(),
+                    success,
+                };
+                if let Err(e) = result_tx.send(result) {
+                    log::warn!("CAN TX: Failed to send command result: {}", e);
+                }
+                if command == SystemCommand::Quit && success {
+                    log::info!("CAN TX task received Quit command, exiting.");
+                    break;
                 }
             }
             Err(_) => {
@@ -134,4 +998,34 @@ pub async fn tx_task(
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault;
+
+    #[test]
+    fn silent_bms_is_reported_stale() {
+        let (tx, rxs) = fault::channel(1);
+        let mut faults = FaultSignal::new(tx);
+        let bms_data = SharedBmsData::new(BmsData { stale: Some(false), ..BmsData::default() });
+        let mut watchdog = DataWatchdog::new(Duration::from_secs(5));
+        let start = Instant::now();
+
+        watchdog.frame_received(1, start, &mut faults);
+        watchdog.check(1, start + Duration::from_secs(4), &bms_data, &mut faults);
+        assert_eq!(bms_data.read(|data| data.stale), Some(false));
+        assert!(rxs[0].try_recv().is_err());
+
+        // The BMS stops sending
+        watchdog.check(1, start + Duration::from_secs(6), &bms_data, &mut faults);
+        watchdog.check(1, start + Duration::from_secs(7), &bms_data, &mut faults);
+        assert_eq!(bms_data.read(|data| data.stale), Some(true));
+        let events: Vec<FaultEvent> = rxs[0].try_iter().collect();
+        assert_eq!(events, vec![FaultEvent::Raised(FaultCondition::DataTimeout(1))]);
+
+        watchdog.frame_received(1, start + Duration::from_secs(8), &mut faults);
+        assert_eq!(rxs[0].try_recv(), Ok(FaultEvent::Cleared(FaultCondition::DataTimeout(1))));
+    }
+}
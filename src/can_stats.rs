@@ -0,0 +1,130 @@
+// src/can_stats.rs
+use crate::can_sys::CanSocket;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, sync::Mutex, time::Duration};
+
+/// How often the RX tasks sample the counters of their sockets.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// --- Kernel Counters ---
+// Indices of the SK_MEMINFO_* values (linux/sock_diag.h), drops is the last one used
+const MEMINFO_RMEM_ALLOC: usize = 0;
+const MEMINFO_RCVBUF: usize = 1;
+const MEMINFO_DROPS: usize = 8;
+const MEMINFO_LEN: usize = MEMINFO_DROPS + 1;
+
+// Memory info of the socket (SO_MEMINFO)
+#[cfg(feature = "socketcan")]
+fn socket_meminfo(socket: &CanSocket) -> std::io::Result<[u32; MEMINFO_LEN]> {
+    use std::os::fd::AsRawFd;
+
+    let mut meminfo = [0u32; MEMINFO_LEN];
+    let mut len = std::mem::size_of_val(&meminfo) as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MEMINFO,
+            meminfo.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(meminfo)
+}
+
+#[cfg(not(feature = "socketcan"))]
+fn socket_meminfo(_socket: &CanSocket) -> std::io::Result<[u32; MEMINFO_LEN]> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+// Counter of the network interface, None if the driver does not provide it
+fn interface_counter(can_if: &str, name: &str) -> Option<u64> {
+    fs::read_to_string(format!("/sys/class/net/{}/statistics/{}", can_if, name))
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+// --- RX Statistics ---
+/// Receive buffer and drop counters of one CAN socket of an RX task.
+#[derive(Debug, Clone, Serialize)]
+pub struct RxCounters {
+    pub bms_id: u8,
+    pub interface: String,
+    /// Bytes queued in the socket receive buffer
+    pub buffer_used: u32,
+    /// Size of the socket receive buffer in bytes
+    pub buffer_size: u32,
+    /// Frames the kernel dropped because the socket buffer was full, since the socket was opened
+    pub socket_drops: u32,
+    /// Frames the interface dropped, shared by all sockets on it
+    pub interface_dropped: Option<u64>,
+    /// Controller FIFO overruns of the interface
+    pub interface_overruns: Option<u64>,
+    /// Frames lost since startup, by any of the counters above
+    pub lost_total: u64,
+}
+
+/// Receive statistics of all CAN RX sockets, served by the HTTP API as `/can/stats`.
+/// Frames dropped between two samples are logged as warning, bursts of the BMS would
+/// otherwise be lost silently.
+#[derive(Debug, Default)]
+pub struct CanRxStats {
+    sockets: Mutex<BTreeMap<(u8, String), RxCounters>>,
+}
+
+impl CanRxStats {
+    /// Samples the counters of `socket`, which receives the frames of `bms_id` on `can_if`.
+    pub fn sample(&self, bms_id: u8, can_if: &str, socket: &CanSocket) {
+        let meminfo = match socket_meminfo(socket) {
+            Ok(meminfo) => meminfo,
+            Err(e) => {
+                log::debug!("BMS {}: Cannot read the socket counters on {}: {}", bms_id, can_if, e);
+                return;
+            }
+        };
+        let mut current = RxCounters {
+            bms_id,
+            interface: can_if.to_string(),
+            buffer_used: meminfo[MEMINFO_RMEM_ALLOC],
+            buffer_size: meminfo[MEMINFO_RCVBUF],
+            socket_drops: meminfo[MEMINFO_DROPS],
+            interface_dropped: interface_counter(can_if, "rx_dropped"),
+            interface_overruns: interface_counter(can_if, "rx_fifo_errors"),
+            lost_total: 0,
+        };
+        let Ok(mut sockets) = self.sockets.lock() else {
+            log::error!("CAN RX statistics lock poisoned, dropping sample.");
+            return;
+        };
+        let key = (bms_id, can_if.to_string());
+        if let Some(previous) = sockets.get(&key) {
+            // A reopened socket (task restart) or interface starts counting at zero again
+            let increase = |now: Option<u64>, before: Option<u64>| match (now, before) {
+                (Some(now), Some(before)) => now.saturating_sub(before),
+                _ => 0,
+            };
+            let socket_lost = u64::from(current.socket_drops.saturating_sub(previous.socket_drops));
+            let dropped = increase(current.interface_dropped, previous.interface_dropped);
+            let overruns = increase(current.interface_overruns, previous.interface_overruns);
+            current.lost_total = previous.lost_total + socket_lost + dropped + overruns;
+            if socket_lost + dropped + overruns > 0 {
+                log::warn!(
+                    "BMS {}: CAN frames lost on {} (socket buffer full: {}, interface dropped: {}, overruns: {}, buffer {}/{} bytes)",
+                    bms_id, can_if, socket_lost, dropped, overruns, current.buffer_used, current.buffer_size
+                );
+            }
+        }
+        sockets.insert(key, current);
+    }
+
+    /// Copy of the counters of all sockets.
+    pub fn snapshot(&self) -> Vec<RxCounters> {
+        self.sockets
+            .lock()
+            .map(|sockets| sockets.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
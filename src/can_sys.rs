@@ -0,0 +1,238 @@
+// src/can_sys.rs
+// CAN socket layer. With the `socketcan` feature these are the SocketCAN types, without
+// it (macOS, Windows) a stand-in with the same API whose sockets cannot be opened, so
+// the gateway still builds and runs without the CAN tasks.
+
+#[cfg(feature = "socketcan")]
+pub use socketcan::{
+    CanFilter, CanFrame, CanInterface, CanSocket, EmbeddedFrame, ExtendedId, Frame, Socket, SocketOptions, StandardId,
+    frame::AsPtr,
+};
+
+#[cfg(not(feature = "socketcan"))]
+pub use stub::*;
+
+#[cfg(not(feature = "socketcan"))]
+mod stub {
+    use std::{io, time::Duration};
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "the gateway was built without the socketcan feature")
+    }
+
+    // Size of a struct can_frame: ID, DLC, padding and 8 data bytes
+    const RAW_FRAME_LEN: usize = 16;
+    // Marks a 29-bit ID in the ID word of struct can_frame
+    const CAN_EFF_FLAG: u32 = 0x8000_0000;
+
+    // --- Frames ---
+    /// Standard 11-bit CAN identifier.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct StandardId(u16);
+
+    impl StandardId {
+        pub fn new(raw: u16) -> Option<Self> {
+            (raw <= 0x7FF).then_some(Self(raw))
+        }
+    }
+
+    /// Extended 29-bit CAN identifier.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ExtendedId(u32);
+
+    impl ExtendedId {
+        pub fn new(raw: u32) -> Option<Self> {
+            (raw <= 0x1FFF_FFFF).then_some(Self(raw))
+        }
+    }
+
+    /// Standard or extended CAN identifier.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Id {
+        Standard(StandardId),
+        Extended(ExtendedId),
+    }
+
+    impl From<StandardId> for Id {
+        fn from(id: StandardId) -> Self {
+            Id::Standard(id)
+        }
+    }
+
+    impl From<ExtendedId> for Id {
+        fn from(id: ExtendedId) -> Self {
+            Id::Extended(id)
+        }
+    }
+
+    /// Data frame in the memory layout of the kernel (struct can_frame).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CanDataFrame {
+        raw: [u8; RAW_FRAME_LEN],
+    }
+
+    /// Error frame reported by the controller.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CanErrorFrame {
+        raw: [u8; RAW_FRAME_LEN],
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CanFrame {
+        Data(CanDataFrame),
+        Remote(CanDataFrame),
+        Error(CanErrorFrame),
+    }
+
+    impl CanFrame {
+        fn raw(&self) -> &[u8; RAW_FRAME_LEN] {
+            match self {
+                CanFrame::Data(frame) | CanFrame::Remote(frame) => &frame.raw,
+                CanFrame::Error(frame) => &frame.raw,
+            }
+        }
+    }
+
+    /// Construction of frames, `embedded_can::Frame` in SocketCAN.
+    pub trait EmbeddedFrame: Sized {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self>;
+        fn is_extended(&self) -> bool;
+        fn data(&self) -> &[u8];
+    }
+
+    /// Access to the raw CAN ID.
+    pub trait Frame {
+        fn raw_id(&self) -> u32;
+    }
+
+    /// The frame as bytes in the kernel layout.
+    pub trait AsPtr {
+        fn as_bytes(&self) -> &[u8];
+    }
+
+    impl EmbeddedFrame for CanFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            if data.len() > 8 {
+                return None;
+            }
+            let id_word = match id.into() {
+                Id::Standard(id) => u32::from(id.0),
+                Id::Extended(id) => id.0 | CAN_EFF_FLAG,
+            };
+            let mut raw = [0u8; RAW_FRAME_LEN];
+            raw[..4].copy_from_slice(&id_word.to_ne_bytes());
+            raw[4] = data.len() as u8;
+            raw[8..8 + data.len()].copy_from_slice(data);
+            Some(CanFrame::Data(CanDataFrame { raw }))
+        }
+
+        fn is_extended(&self) -> bool {
+            let raw = self.raw();
+            u32::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]]) & CAN_EFF_FLAG != 0
+        }
+
+        fn data(&self) -> &[u8] {
+            let raw = self.raw();
+            &raw[8..8 + usize::from(raw[4]).min(8)]
+        }
+    }
+
+    impl Frame for CanFrame {
+        fn raw_id(&self) -> u32 {
+            let raw = self.raw();
+            u32::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]]) & 0x1FFF_FFFF
+        }
+    }
+
+    impl AsPtr for CanFrame {
+        fn as_bytes(&self) -> &[u8] {
+            self.raw()
+        }
+    }
+
+    // --- Sockets ---
+    /// Acceptance filter of a socket.
+    #[derive(Debug, Clone, Copy)]
+    pub struct CanFilter {
+        _id: u32,
+        _mask: u32,
+    }
+
+    impl CanFilter {
+        pub fn new(id: u32, mask: u32) -> Self {
+            Self { _id: id, _mask: mask }
+        }
+    }
+
+    /// Raw CAN socket, cannot be opened without SocketCAN.
+    #[derive(Debug)]
+    pub struct CanSocket {
+        _private: (),
+    }
+
+    pub trait Socket: Sized {
+        fn open(ifname: &str) -> io::Result<Self>;
+        fn read_frame(&self) -> io::Result<CanFrame>;
+        fn read_frame_timeout(&self, timeout: Duration) -> io::Result<CanFrame>;
+        fn write_frame(&self, frame: &CanFrame) -> io::Result<()>;
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    }
+
+    pub trait SocketOptions {
+        fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()>;
+        fn set_recv_own_msgs(&self, enabled: bool) -> io::Result<()>;
+        fn set_error_filter_accept_all(&self) -> io::Result<()>;
+    }
+
+    impl Socket for CanSocket {
+        fn open(_ifname: &str) -> io::Result<Self> {
+            Err(unsupported())
+        }
+
+        fn read_frame(&self) -> io::Result<CanFrame> {
+            Err(unsupported())
+        }
+
+        fn read_frame_timeout(&self, _timeout: Duration) -> io::Result<CanFrame> {
+            Err(unsupported())
+        }
+
+        fn write_frame(&self, _frame: &CanFrame) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Err(unsupported())
+        }
+    }
+
+    impl SocketOptions for CanSocket {
+        fn set_filters(&self, _filters: &[CanFilter]) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        fn set_recv_own_msgs(&self, _enabled: bool) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        fn set_error_filter_accept_all(&self) -> io::Result<()> {
+            Err(unsupported())
+        }
+    }
+
+    /// Network interface of a CAN bus.
+    #[derive(Debug)]
+    pub struct CanInterface {
+        _private: (),
+    }
+
+    impl CanInterface {
+        pub fn open(_ifname: &str) -> io::Result<Self> {
+            Err(unsupported())
+        }
+
+        pub fn bit_rate(&self) -> io::Result<Option<u32>> {
+            Err(unsupported())
+        }
+    }
+}
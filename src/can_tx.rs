@@ -0,0 +1,154 @@
+// src/can_tx.rs
+use crate::{
+    can_sys::{CanFrame, EmbeddedFrame, Frame},
+    config::CanTxConfig,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Bits on the wire without data: SOF, arbitration, control, CRC, ACK, EOF and intermission
+const STANDARD_FRAME_OVERHEAD_BITS: u32 = 47;
+const EXTENDED_FRAME_OVERHEAD_BITS: u32 = 67;
+// Worst case of the stuff bits, in percent of the frame
+const STUFFING_PERCENT: u32 = 20;
+// Extended frame with 8 data bytes
+const MAX_FRAME_BITS: u32 = (EXTENDED_FRAME_OVERHEAD_BITS + 64) * (100 + STUFFING_PERCENT) / 100;
+
+// Bus time of `frame` in bits, including worst case bit stuffing
+fn frame_bits(frame: &CanFrame) -> f64 {
+    let overhead = if frame.is_extended() { EXTENDED_FRAME_OVERHEAD_BITS } else { STANDARD_FRAME_OVERHEAD_BITS };
+    let bits = overhead + 8 * frame.data().len() as u32;
+    f64::from(bits + bits * STUFFING_PERCENT / 100)
+}
+
+// --- Priorities ---
+/// Class of a transmitted frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxPriority {
+    /// ON/OFF/QUIT commands, never held back. They count against the budget, so the
+    /// other traffic backs off after them.
+    Safety,
+    /// Periodic messages and parameter access, sent within the gap and load limits
+    Normal,
+}
+
+// --- Bus Lane ---
+#[derive(Debug)]
+struct LaneState {
+    last_frame: Option<Instant>,
+    // Bits that may be sent right away, refilled at the budgeted rate
+    budget: f64,
+    refilled_at: Instant,
+}
+
+/// Transmit budget of one CAN interface, shared by everything sending on it.
+#[derive(Debug)]
+pub struct TxLane {
+    interface: String,
+    min_gap: Duration,
+    // Budgeted bits per second, 0 for no load limit
+    rate: f64,
+    capacity: f64,
+    state: Mutex<LaneState>,
+}
+
+impl TxLane {
+    fn new(interface: &str, config: &CanTxConfig) -> Self {
+        let rate = f64::from(config.bitrate) * config.max_bus_load_percent / 100.0;
+        // Room for at least one frame of the maximum size, or nothing could ever be sent
+        let capacity = (rate * config.window().as_secs_f64()).max(f64::from(MAX_FRAME_BITS));
+        Self {
+            interface: interface.to_string(),
+            min_gap: config.min_frame_gap(),
+            rate,
+            capacity,
+            state: Mutex::new(LaneState { last_frame: None, budget: capacity, refilled_at: Instant::now() }),
+        }
+    }
+
+    // How long `frame` has to wait, zero if it may be sent now (it is then accounted for)
+    fn reserve(&self, priority: TxPriority, frame: &CanFrame) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let bits = frame_bits(frame);
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+        state.budget = (state.budget + elapsed * self.rate).min(self.capacity);
+        state.refilled_at = now;
+
+        if priority == TxPriority::Normal {
+            let gap_wait = state
+                .last_frame
+                .map(|at| (at + self.min_gap).saturating_duration_since(now))
+                .unwrap_or_default();
+            if !gap_wait.is_zero() {
+                return gap_wait;
+            }
+            if self.rate > 0.0 && state.budget < bits {
+                return Duration::from_secs_f64((bits - state.budget) / self.rate);
+            }
+        }
+        if self.rate > 0.0 {
+            state.budget -= bits;
+        }
+        state.last_frame = Some(now);
+        Duration::ZERO
+    }
+
+    /// Blocks until `frame` may be sent.
+    pub fn acquire_blocking(&self, priority: TxPriority, frame: &CanFrame) {
+        loop {
+            let wait = self.reserve(priority, frame);
+            if wait.is_zero() {
+                return;
+            }
+            log::trace!("CAN TX {}: Holding back {:#X} for {:?}", self.interface, frame.raw_id(), wait);
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Waits until `frame` may be sent.
+    pub async fn acquire(&self, priority: TxPriority, frame: &CanFrame) {
+        loop {
+            let wait = self.reserve(priority, frame);
+            if wait.is_zero() {
+                return;
+            }
+            log::trace!("CAN TX {}: Holding back {:#X} for {:?}", self.interface, frame.raw_id(), wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// --- Scheduler ---
+/// Keeps the frames sent by the gateway within a minimum inter-frame gap and a share of
+/// the bus load per interface, so the buses shared with the BMS traffic are not flooded.
+#[derive(Debug)]
+pub struct TxScheduler {
+    config: CanTxConfig,
+    lanes: Mutex<BTreeMap<String, Arc<TxLane>>>,
+}
+
+impl TxScheduler {
+    pub fn new(config: CanTxConfig) -> Self {
+        log::info!(
+            "CAN TX: At most {} % of {} bit/s per bus, at least {:?} between frames",
+            config.max_bus_load_percent,
+            config.bitrate,
+            config.min_frame_gap()
+        );
+        Self { config, lanes: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Budget of the interface `can_if`, shared by all its senders.
+    pub fn lane(&self, can_if: &str) -> Arc<TxLane> {
+        let mut lanes = self.lanes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(
+            lanes
+                .entry(can_if.to_string())
+                .or_insert_with(|| Arc::new(TxLane::new(can_if, &self.config))),
+        )
+    }
+}
@@ -0,0 +1,104 @@
+// src/clock.rs
+use crate::{config::ClockConfig, error::AppError};
+use serde::Serialize;
+use std::{
+    sync::{OnceLock, RwLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::interval;
+
+// --- Timestamps ---
+// Reference of the monotonic timestamps, set on first use
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Wall-clock time in milliseconds since the Unix epoch. Only meaningful while the
+/// clock is synchronized, see `latest`.
+pub fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Milliseconds on a monotonic clock starting at the first timestamp taken, unaffected
+/// by steps of the wall clock.
+pub fn monotonic_millis() -> u128 {
+    START.get_or_init(Instant::now).elapsed().as_millis()
+}
+
+// --- Synchronization Status ---
+/// Synchronization state of the system clock as kept by the kernel, which is updated by
+/// whichever NTP client runs (chrony, ntpd, systemd-timesyncd).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClockStatus {
+    pub synchronized: bool,
+    /// Maximum error in microseconds
+    pub max_error_us: i64,
+    /// Estimated error in microseconds
+    pub estimated_error_us: i64,
+}
+
+static STATUS: RwLock<Option<ClockStatus>> = RwLock::new(None);
+
+/// Reads the synchronization state from the kernel.
+#[cfg(target_os = "linux")]
+pub fn read_status() -> Result<ClockStatus, AppError> {
+    // SAFETY: timex is plain data, zeroed means modes = 0, which only reads the state
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: `timex` outlives the call
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state == -1 {
+        return Err(AppError::Runtime(std::io::Error::last_os_error()));
+    }
+    Ok(ClockStatus {
+        synchronized: state != libc::TIME_ERROR && timex.status & libc::STA_UNSYNC == 0,
+        max_error_us: i64::from(timex.maxerror),
+        estimated_error_us: i64::from(timex.esterror),
+    })
+}
+
+// Only Linux reports the state via adjtimex
+#[cfg(not(target_os = "linux"))]
+pub fn read_status() -> Result<ClockStatus, AppError> {
+    Err(AppError::Runtime(std::io::Error::from(std::io::ErrorKind::Unsupported)))
+}
+
+/// Last state checked by the clock task, None before the first check or if it is disabled.
+pub fn latest() -> Option<ClockStatus> {
+    STATUS.read().ok().and_then(|status| *status)
+}
+
+// --- Clock Task ---
+/// Checks the synchronization state periodically and warns while the clock is not
+/// synchronized, as the wall-clock timestamps of the recorded data are wrong then.
+pub async fn task(config: ClockConfig) -> Result<(), AppError> {
+    log::info!("Starting clock monitor (every {:?})", config.check_interval());
+    let mut ticker = interval(config.check_interval());
+    let mut last: Option<bool> = None;
+
+    loop {
+        ticker.tick().await;
+        let status = match read_status() {
+            Ok(status) => status,
+            Err(e) => {
+                log::error!("Clock: Cannot read the synchronization state: {}", e);
+                continue;
+            }
+        };
+        if last != Some(status.synchronized) {
+            if status.synchronized {
+                log::info!(
+                    "Clock: Synchronized (estimated error {} us, maximum {} us).",
+                    status.estimated_error_us,
+                    status.max_error_us
+                );
+            } else {
+                log::warn!("Clock: Not synchronized, wall-clock timestamps may be wrong.");
+            }
+            last = Some(status.synchronized);
+        }
+        if let Ok(mut current) = STATUS.write() {
+            *current = Some(status);
+        }
+    }
+}
@@ -0,0 +1,3019 @@
+// src/config.rs
+use crate::arbiter::CommandSource;
+use crate::data;
+use crate::error::AppError;
+use crate::flags::StatusByte;
+use crate::flow::FlowState;
+use crate::site;
+use crate::SystemCommand;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Default location of the configuration file if none is given on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "gateway.toml";
+
+// --- Root Configuration ---
+/// Gateway configuration, loaded from a TOML file.
+/// Every field has a default matching the original hard-coded setup,
+/// so a missing file or missing keys keep the gateway behaving as before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Log inverter register writes instead of sending them (for all inverters)
+    pub dry_run: bool,
+    /// BMS IDs that start in maintenance mode (faults and stale data are not signalled)
+    pub maintenance: Vec<u8>,
+    /// Language of the operator-facing texts (display, fault texts)
+    pub language: Language,
+    pub can: CanConfig,
+    pub modbus_servers: Vec<ModbusServerConfig>,
+    pub site_map: SiteMapConfig,
+    pub inverters: Vec<InverterConfig>,
+    pub arbiter: ArbiterConfig,
+    pub rules: RulesConfig,
+    pub statistics: StatisticsConfig,
+    pub http: HttpConfig,
+    pub modbus_trace: ModbusTraceConfig,
+    pub snapshot: SnapshotConfig,
+    pub invalid_value: InvalidValueConfig,
+    pub victron: VictronConfig,
+    pub opcua: OpcUaConfig,
+    pub snmp: SnmpConfig,
+    pub grpc: GrpcConfig,
+    pub self_test: SelfTestConfig,
+    pub flags: FlagsConfig,
+    pub scheduler: SchedulerConfig,
+    pub buzzer: BuzzerConfig,
+    pub fault_recovery: FaultRecoveryConfig,
+    pub auto_restart: AutoRestartConfig,
+    pub control_mode: ControlModeConfig,
+    pub relays: Vec<RelayConfig>,
+    pub display: DisplayConfig,
+    pub input: InputConfig,
+    pub runtime: RuntimeConfig,
+    pub command_history: CommandHistoryConfig,
+    pub recorder: RecorderConfig,
+    pub black_box: BlackBoxConfig,
+    pub clock: ClockConfig,
+    pub log: LogConfig,
+    pub storage: StorageConfig,
+    pub persist: PersistConfig,
+    pub flow_state: FlowStateConfig,
+    pub supervisor: SupervisorConfig,
+    pub gpio: GpioConfig,
+    pub remote: RemoteConfig,
+    pub uplink: UplinkConfig,
+    pub api_auth: ApiAuthConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            maintenance: Vec::new(),
+            language: Language::En,
+            can: CanConfig::default(),
+            modbus_servers: vec![
+                ModbusServerConfig::new("172.18.143.93:40502"), // Address for BMS 1 server
+                ModbusServerConfig::new("172.18.143.93:41502"), // Address for BMS 2 server
+            ],
+            site_map: SiteMapConfig::default(),
+            inverters: vec![
+                InverterConfig::new("inverter1", "192.168.2.100:30502"),
+                InverterConfig::new("inverter2", "192.168.2.100:31502"),
+            ],
+            arbiter: ArbiterConfig::default(),
+            rules: RulesConfig::default(),
+            statistics: StatisticsConfig::default(),
+            http: HttpConfig::default(),
+            modbus_trace: ModbusTraceConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            invalid_value: InvalidValueConfig::default(),
+            victron: VictronConfig::default(),
+            opcua: OpcUaConfig::default(),
+            snmp: SnmpConfig::default(),
+            grpc: GrpcConfig::default(),
+            self_test: SelfTestConfig::default(),
+            flags: FlagsConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            buzzer: BuzzerConfig::default(),
+            fault_recovery: FaultRecoveryConfig::default(),
+            auto_restart: AutoRestartConfig::default(),
+            control_mode: ControlModeConfig::default(),
+            relays: Vec::new(),
+            display: DisplayConfig::default(),
+            input: InputConfig::default(),
+            runtime: RuntimeConfig::default(),
+            command_history: CommandHistoryConfig::default(),
+            recorder: RecorderConfig::default(),
+            black_box: BlackBoxConfig::default(),
+            clock: ClockConfig::default(),
+            log: LogConfig::default(),
+            storage: StorageConfig::default(),
+            persist: PersistConfig::default(),
+            flow_state: FlowStateConfig::default(),
+            supervisor: SupervisorConfig::default(),
+            gpio: GpioConfig::default(),
+            remote: RemoteConfig::default(),
+            uplink: UplinkConfig::default(),
+            api_auth: ApiAuthConfig::default(),
+        }
+    }
+}
+
+// --- Overrides ---
+/// Prefix of the environment variables overriding configuration keys
+pub const ENV_PREFIX: &str = "IWENT_";
+
+/// Overrides from the environment: `IWENT_HTTP__ADDR` sets `http.addr`, `__` separates the
+/// levels (and array indices, `IWENT_MODBUS_SERVERS__0__ADDR`). Sorted by key.
+pub fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = std::env::vars()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(ENV_PREFIX)?;
+            Some((key.to_lowercase().replace("__", "."), value))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
+// Value of an override, TOML syntax if it parses (numbers, booleans, arrays, quoted
+// strings), otherwise the raw text as string (addresses, names)
+fn override_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+// Sets the key at `path` below `value`. Levels missing in the file start from their
+// defaults, so e.g. one field of a default Modbus server can be overridden.
+fn set_override(value: &mut toml::Value, default: Option<&toml::Value>, path: &[&str], new: toml::Value) -> Result<(), String> {
+    let Some((key, rest)) = path.split_first() else {
+        *value = new;
+        return Ok(());
+    };
+    let default = default.and_then(|default| match default {
+        toml::Value::Table(table) => table.get(*key),
+        toml::Value::Array(array) => key.parse::<usize>().ok().and_then(|index| array.get(index)),
+        _ => None,
+    });
+    let child = match value {
+        toml::Value::Table(table) => table
+            .entry(key.to_string())
+            .or_insert(default.cloned().unwrap_or_else(|| toml::Value::Table(toml::Table::new()))),
+        toml::Value::Array(array) => {
+            let index: usize = key.parse().map_err(|_| format!("'{}' is not an array index", key))?;
+            let len = array.len();
+            array
+                .get_mut(index)
+                .ok_or_else(|| format!("index {} is out of range ({} entries)", index, len))?
+        }
+        _ => return Err(format!("'{}' is below a value", key)),
+    };
+    set_override(child, default, rest, new)
+}
+
+impl Config {
+    /// Loads the configuration from `path`, falling back to defaults if the file does not exist.
+    /// `overrides` (dotted key, value) are applied over the file in order, so later ones win.
+    /// With `remote.enabled` the file only bootstraps the fetch of the remote configuration.
+    pub fn load(path: &Path, overrides: &[(String, String)]) -> Result<Self, AppError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::warn!("Configuration file {} not found, using defaults.", path.display());
+                String::new()
+            }
+            Err(e) => return Err(AppError::Config(format!("{}: {}", path.display(), e))),
+        };
+        let config = Self::parse(&content, &path.display().to_string(), overrides)?;
+        if !content.is_empty() {
+            log::info!("Configuration loaded from {}", path.display());
+        }
+        #[cfg(feature = "remote-config")]
+        if config.remote.enabled {
+            return crate::remote_config::load(config, overrides);
+        }
+        #[cfg(not(feature = "remote-config"))]
+        if config.remote.enabled {
+            log::error!("Remote configuration is enabled, but the gateway was built without the remote-config feature.");
+        }
+        Ok(config)
+    }
+
+    /// Parses the configuration `content` read from `origin` (a path or URL) and applies
+    /// `overrides` over it.
+    pub fn parse(content: &str, origin: &str, overrides: &[(String, String)]) -> Result<Self, AppError> {
+        let table = content
+            .parse::<toml::Table>()
+            .map_err(|e| AppError::Config(format!("{}: {}", origin, e)))?;
+        let mut table = toml::Value::Table(table);
+        if !overrides.is_empty() {
+            let defaults = toml::Value::try_from(Config::default())
+                .map_err(|e| AppError::Config(format!("Default configuration: {}", e)))?;
+            for (key, raw) in overrides {
+                let path: Vec<&str> = key.split('.').collect();
+                set_override(&mut table, Some(&defaults), &path, override_value(raw))
+                    .map_err(|e| AppError::Config(format!("Override of {}: {}", key, e)))?;
+                log::info!("Configuration override: {} = {}", key, raw);
+            }
+        }
+        let mut config: Config = table
+            .try_into()
+            .map_err(|e| AppError::Config(format!("{}: {}", origin, e)))?;
+        // Everything after loading sees the texts of the selected language only
+        config.flags = config.flags.localized(config.language);
+        Ok(config)
+    }
+
+    /// Checks the settings the tasks would otherwise only fail on after startup: addresses
+    /// that do not parse, listen ports used twice, pins assigned twice, register blocks
+    /// that overlap and zero intervals. Every problem is logged, the error lists all of them.
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut problems = Vec::new();
+
+        // --- Addresses ---
+        let parse = |problems: &mut Vec<String>, what: String, addr: &str| match addr.parse::<SocketAddr>() {
+            Ok(socket_addr) => Some(socket_addr),
+            Err(e) => {
+                problems.push(format!("{}: invalid address '{}': {}", what, addr, e));
+                None
+            }
+        };
+        // Listen addresses of the TCP servers
+        let mut listeners: Vec<(String, SocketAddr)> = Vec::new();
+        if self.modbus_servers.len() < 2 {
+            problems.push(format!(
+                "modbus_servers: 2 servers needed (one per BMS), {} configured",
+                self.modbus_servers.len()
+            ));
+        }
+        for (index, server) in self.modbus_servers.iter().enumerate() {
+            let what = format!("modbus_servers[{}].addr", index);
+            if let Some(socket_addr) = parse(&mut problems, what.clone(), &server.addr) {
+                listeners.push((what, socket_addr));
+            }
+        }
+        for (what, addr, enabled) in [
+            ("http.addr", &self.http.addr, self.http.enabled),
+            ("grpc.addr", &self.grpc.addr, self.grpc.enabled),
+        ] {
+            if let Some(socket_addr) = enabled.then(|| parse(&mut problems, what.to_string(), addr)).flatten() {
+                listeners.push((what.to_string(), socket_addr));
+            }
+        }
+        // The OPC UA host may be a name, only an IP address can be compared
+        if self.opcua.enabled && let Ok(ip) = self.opcua.host.parse::<IpAddr>() {
+            listeners.push(("opcua.port".to_string(), SocketAddr::new(ip, self.opcua.port)));
+        }
+        if self.snmp.enabled {
+            parse(&mut problems, "snmp.addr".to_string(), &self.snmp.addr);
+        }
+        if self.log.output == LogOutput::Syslog {
+            parse(&mut problems, "log.syslog.addr".to_string(), &self.log.syslog.addr);
+        }
+        let mut names = Vec::new();
+        for (index, inverter) in self.inverters.iter().enumerate() {
+            parse(&mut problems, format!("inverters[{}].addr", index), &inverter.addr);
+            if names.contains(&&inverter.name) {
+                problems.push(format!("inverters[{}].name: '{}' is used by another inverter", index, inverter.name));
+            }
+            names.push(&inverter.name);
+        }
+        // A wildcard address listens on every interface, so it collides with any other
+        for (index, (what, addr)) in listeners.iter().enumerate() {
+            let collision = listeners[..index].iter().find(|(_, other)| {
+                other.port() == addr.port()
+                    && (other.ip() == addr.ip() || other.ip().is_unspecified() || addr.ip().is_unspecified())
+            });
+            if let Some((other_what, _)) = collision {
+                problems.push(format!("{}: port {} is already used by {}", what, addr.port(), other_what));
+            }
+        }
+
+        // --- Pins ---
+        let pins = &self.gpio.pins;
+        let mut assigned: Vec<(String, u8)> = [
+            ("gpio.pins.off", pins.off),
+            ("gpio.pins.on", pins.on),
+            ("gpio.pins.quit", pins.quit),
+            ("gpio.pins.red_led", pins.red_led),
+            ("gpio.pins.green_led", pins.green_led),
+        ]
+        .into_iter()
+        .map(|(what, pin)| (what.to_string(), pin))
+        .collect();
+        if self.buzzer.enabled {
+            assigned.push(("buzzer.pin".to_string(), self.buzzer.pin));
+        }
+        for (index, relay) in self.relays.iter().enumerate() {
+            assigned.push((format!("relays[{}].pin", index), relay.pin));
+        }
+        if let Some(encoder) = &self.input.encoder {
+            assigned.push(("input.encoder.pin_a".to_string(), encoder.pin_a));
+            assigned.push(("input.encoder.pin_b".to_string(), encoder.pin_b));
+        }
+        if let Some(trip) = &self.input.trip {
+            assigned.push(("input.trip.pin".to_string(), trip.pin));
+        }
+        for (index, (what, pin)) in assigned.iter().enumerate() {
+            if let Some((other_what, _)) = assigned[..index].iter().find(|(_, other)| other == pin) {
+                problems.push(format!("{}: pin {} is already assigned to {}", what, pin, other_what));
+            }
+        }
+
+        // --- Register Map ---
+        // Blocks served next to the BMS registers, as (name, first, end exclusive)
+        let block = |what: String, start: u16, len: u16| (what, u32::from(start), u32::from(start) + u32::from(len));
+        let mut blocks = vec![block("BMS registers".to_string(), 0, data::REG_LAST + 1)];
+        if self.site_map.enabled {
+            blocks.push(block("site_map".to_string(), self.site_map.start, site::SITE_LEN));
+        }
+        for (index, server) in self.modbus_servers.iter().enumerate() {
+            let mut server_blocks = blocks.clone();
+            if let Some(tunnel) = &server.tunnel {
+                for (what, can_id) in [("request_id", tunnel.request_id), ("response_id", tunnel.response_id)] {
+                    if can_id > 0x7FF {
+                        problems.push(format!("modbus_servers[{}].tunnel.{}: {:#X} is no 11-bit CAN ID", index, what, can_id));
+                    }
+                }
+                server_blocks.push(block(format!("modbus_servers[{}].tunnel", index), tunnel.start, tunnel.count));
+            }
+            for (index_in_server, (what, start, end)) in server_blocks.iter().enumerate() {
+                // The shared blocks are checked once, not again for every server
+                if index > 0 && index_in_server < blocks.len() {
+                    continue;
+                }
+                if *end > 0x1_0000 {
+                    problems.push(format!("{}: registers {}..{} exceed the address space", what, start, end));
+                }
+                let overlap = server_blocks[..index_in_server]
+                    .iter()
+                    .find(|(_, other_start, other_end)| start < other_end && other_start < end);
+                if let Some((other_what, _, _)) = overlap {
+                    problems.push(format!("{}: registers {}..{} overlap the {}", what, start, end, other_what));
+                }
+            }
+        }
+
+        // --- Intervals ---
+        // They feed tokio::time::interval, which panics on a zero period
+        for (what, interval_ms) in [
+            ("snapshot.interval_ms", self.snapshot.interval_ms),
+            ("snmp.poll_interval_ms", self.snmp.poll_interval_ms),
+            ("victron.interval_ms", self.victron.interval_ms),
+            ("recorder.interval_ms", self.recorder.interval_ms),
+            ("flow_state.interval_ms", self.flow_state.interval_ms),
+            ("display.page_interval_ms", self.display.page_interval_ms),
+            ("statistics.sample_interval_ms", self.statistics.sample_interval_ms),
+            ("statistics.persist_interval_ms", self.statistics.persist_interval_ms),
+            ("clock.check_interval_ms", self.clock.check_interval_ms),
+            ("storage.check_interval_ms", self.storage.check_interval_ms),
+            ("grpc.update_interval_ms", self.grpc.update_interval_ms),
+        ] {
+            if interval_ms == 0 {
+                problems.push(format!("{}: has to be greater than 0", what));
+            }
+        }
+
+        // --- API Authentication ---
+        let auth = &self.api_auth;
+        if auth.enabled {
+            if auth.tls_cert.is_some() != auth.tls_key.is_some() {
+                problems.push("api_auth: tls_cert and tls_key have to be set together".to_string());
+            }
+            if auth.client_ca.is_some() && !auth.tls() {
+                problems.push("api_auth: client_ca needs tls_cert and tls_key".to_string());
+            }
+            for (index, client) in auth.clients.iter().enumerate() {
+                if client.certificate_name.is_none() && client.token.is_none() {
+                    problems.push(format!("api_auth.clients[{}] ({}): neither certificate_name nor token", index, client.name));
+                }
+                if client.certificate_name.is_some() && auth.client_ca.is_none() {
+                    problems.push(format!("api_auth.clients[{}] ({}): certificate_name needs client_ca", index, client.name));
+                }
+                let token_taken = client.token.as_ref().is_some_and(|token| {
+                    auth.clients[..index].iter().any(|other| other.token.as_ref() == Some(token))
+                });
+                if token_taken {
+                    problems.push(format!("api_auth.clients[{}] ({}): token used by another client", index, client.name));
+                }
+            }
+        }
+
+        // --- OPC UA Users ---
+        for (index, user) in self.opcua.users.iter().enumerate() {
+            if user.password.is_empty() {
+                problems.push(format!("opcua.users[{}] ({}): empty password", index, user.name));
+            }
+            if self.opcua.users[..index].iter().any(|other| other.name == user.name) {
+                problems.push(format!("opcua.users[{}]: duplicate name {}", index, user.name));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        for problem in &problems {
+            log::error!("Configuration: {}", problem);
+        }
+        Err(AppError::Config(format!("{} problems: {}", problems.len(), problems.join("; "))))
+    }
+}
+
+// --- CAN Configuration ---
+/// Protocol spoken by the BMS on the CAN bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanMode {
+    /// Proprietary 0xB1xx/0xB2xx/0xB3xx frames
+    #[default]
+    Native,
+    /// CANopen node with TPDOs and heartbeat
+    Canopen,
+}
+
+/// CAN interfaces of one BMS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BmsBusConfig {
+    pub bms_id: u8,
+    pub primary: String,
+    /// Redundant bus, received from while the primary delivers no frames (native mode only)
+    pub secondary: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanConfig {
+    /// SocketCAN interface shared by all BMS
+    pub interface: String,
+    pub mode: CanMode,
+    /// Only used in CANopen mode
+    pub canopen: CanopenConfig,
+    /// Per-BMS interfaces, BMS without an entry use `interface`
+    pub buses: Vec<BmsBusConfig>,
+    /// Time without frames after which a bus counts as failed
+    pub failover_timeout_ms: u64,
+    /// Time without native frames of a BMS after which its data is stale and the fault
+    /// signal is raised
+    pub data_timeout_ms: u64,
+    /// Rolling counter and checksum validation of the native frames
+    pub frame_check: FrameCheckConfig,
+    /// CAN IDs of the native frames
+    pub ids: CanIdConfig,
+    /// Acknowledgement of the ON/OFF command frames
+    pub command_ack: CommandAckConfig,
+    /// Detection of the BMS present on the bus at startup
+    pub discovery: DiscoveryConfig,
+    /// Limits of the frames sent by the gateway
+    pub tx: CanTxConfig,
+    /// Verification that the command frames actually went out on the bus
+    pub tx_confirm: TxConfirmConfig,
+    /// Sanity checks of the decoded values
+    pub plausibility: PlausibilityConfig,
+}
+
+impl CanConfig {
+    /// Interfaces the given BMS is received on.
+    pub fn bus(&self, bms_id: u8) -> BmsBusConfig {
+        self.buses
+            .iter()
+            .find(|bus| bus.bms_id == bms_id)
+            .cloned()
+            .unwrap_or_else(|| BmsBusConfig {
+                bms_id,
+                primary: self.interface.clone(),
+                secondary: None,
+            })
+    }
+
+    pub fn failover_timeout(&self) -> Duration {
+        Duration::from_millis(self.failover_timeout_ms)
+    }
+
+    pub fn data_timeout(&self) -> Duration {
+        Duration::from_millis(self.data_timeout_ms)
+    }
+}
+
+impl Default for CanConfig {
+    fn default() -> Self {
+        Self {
+            interface: "can0".to_string(),
+            mode: CanMode::default(),
+            canopen: CanopenConfig::default(),
+            buses: Vec::new(),
+            failover_timeout_ms: 5000,
+            data_timeout_ms: 5000,
+            frame_check: FrameCheckConfig::default(),
+            ids: CanIdConfig::default(),
+            command_ack: CommandAckConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            tx: CanTxConfig::default(),
+            tx_confirm: TxConfirmConfig::default(),
+            plausibility: PlausibilityConfig::default(),
+        }
+    }
+}
+
+/// Transmit limits per CAN interface. Commands are never held back, the other frames
+/// (Victron messages, parameter access) keep the gap and the bus load share.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanTxConfig {
+    /// Bit rate of the buses
+    pub bitrate: u32,
+    /// Share of the bus time the gateway may use, 0 for no limit
+    pub max_bus_load_percent: f64,
+    /// Minimum time between two frames, 0 for none
+    pub min_frame_gap_us: u64,
+    /// Period the bus load is averaged over, bursts up to the budget of one window pass
+    pub window_ms: u64,
+}
+
+impl CanTxConfig {
+    pub fn min_frame_gap(&self) -> Duration {
+        Duration::from_micros(self.min_frame_gap_us)
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+impl Default for CanTxConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: 250_000,
+            max_bus_load_percent: 10.0,
+            min_frame_gap_us: 1000,
+            window_ms: 100,
+        }
+    }
+}
+
+/// Waits for the echo (loopback) of every command frame, which SocketCAN delivers once
+/// the controller has sent the frame and it was acknowledged on the bus. Needs a driver
+/// with TX confirmation (IFF_ECHO), others echo the frame as soon as it is queued.
+/// An error frame or a missing echo fails the command, a failed OFF also raises the fault signal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TxConfirmConfig {
+    pub enabled: bool,
+    /// How long to wait for the echo of one frame
+    pub timeout_ms: u64,
+}
+
+impl TxConfirmConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for TxConfirmConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_ms: 100,
+        }
+    }
+}
+
+/// Sanity checks applied to the BMS data after every frame. Values that cannot be right
+/// (min. cell above max. cell, SOC above 100 %, temperatures out of range) point to a wrong
+/// decoder or frame definition. They are counted and flagged, and withheld from the Modbus
+/// clients with `invalid_value.implausible_is_invalid`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlausibilityConfig {
+    pub enabled: bool,
+    pub max_soc: u8,
+    /// Range of the raw temperatures (before `display.temperature_offset`)
+    pub min_temperature: u8,
+    pub max_temperature: u8,
+}
+
+impl Default for PlausibilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_soc: 100,
+            min_temperature: 0,
+            max_temperature: 120,
+        }
+    }
+}
+
+/// Listens unfiltered on all CAN interfaces at startup and assigns the BMS found there
+/// to the two data slots (and the Modbus servers serving them), lowest ID first.
+/// Slots without a discovered BMS keep the default ID (1 or 2).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    pub listen_ms: u64,
+}
+
+impl DiscoveryConfig {
+    pub fn listen(&self) -> Duration {
+        Duration::from_millis(self.listen_ms)
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_ms: 3000,
+        }
+    }
+}
+
+/// Acknowledgement frame the BMS answers the 0xA300 ON/OFF command frames with.
+/// Without an acknowledgement in time the command frame is re-sent, after the last retry
+/// the command counts as failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandAckConfig {
+    pub enabled: bool,
+    /// CAN ID of the acknowledgement frame
+    pub can_id: u32,
+    /// The first data byte of the acknowledgement has to repeat the first byte of the command
+    pub match_command: bool,
+    /// How long to wait for the acknowledgement of one command frame
+    pub timeout_ms: u64,
+    /// How often the command frame is re-sent when it is not acknowledged
+    pub retries: u32,
+}
+
+impl CommandAckConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for CommandAckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            can_id: 0xA301,
+            match_command: true,
+            timeout_ms: 500,
+            retries: 2,
+        }
+    }
+}
+
+/// The three native BMS messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeMessage {
+    /// Cell voltages, temperatures, info and SOC
+    Cells,
+    /// Current, total voltage, warnings and errors
+    Status,
+    /// 32-bit current and total voltage of high voltage packs
+    Wide,
+}
+
+impl NativeMessage {
+    pub const ALL: [NativeMessage; 3] = [NativeMessage::Cells, NativeMessage::Status, NativeMessage::Wide];
+}
+
+/// CAN ID scheme of the native frames: the ID of a message of BMS n is
+/// `<message>_base + n * pack_offset`. Defaults to the 0xB1xx/0xB2xx/0xB3xx scheme.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanIdConfig {
+    pub cells_base: u32,
+    pub status_base: u32,
+    pub wide_base: u32,
+    pub pack_offset: u32,
+    /// Bits of the ID that have to match, for the socket filters and decoding
+    pub mask: u32,
+}
+
+impl CanIdConfig {
+    pub fn id(&self, message: NativeMessage, bms_id: u8) -> u32 {
+        let base = match message {
+            NativeMessage::Cells => self.cells_base,
+            NativeMessage::Status => self.status_base,
+            NativeMessage::Wide => self.wide_base,
+        };
+        base.wrapping_add(u32::from(bms_id).wrapping_mul(self.pack_offset))
+    }
+
+    /// IDs of all messages of a BMS.
+    pub fn ids(&self, bms_id: u8) -> [u32; 3] {
+        NativeMessage::ALL.map(|message| self.id(message, bms_id))
+    }
+
+    /// Message of a BMS the ID belongs to, None for foreign IDs.
+    pub fn message(&self, bms_id: u8, can_id: u32) -> Option<NativeMessage> {
+        NativeMessage::ALL
+            .into_iter()
+            .find(|&message| (self.id(message, bms_id) ^ can_id) & self.mask == 0)
+    }
+}
+
+impl Default for CanIdConfig {
+    fn default() -> Self {
+        Self {
+            cells_base: 0xB100,
+            status_base: 0xB200,
+            wide_base: 0xB300,
+            pack_offset: 1,
+            mask: 0x1FFF_FFFF,
+        }
+    }
+}
+
+/// Checksum algorithm of the native frames, computed over all other data bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// Sum of the bytes modulo 256
+    Sum8,
+    /// XOR of the bytes
+    Xor8,
+    /// CRC-8 SAE J1850 (polynomial 0x1D, init and final XOR 0xFF)
+    #[default]
+    Crc8,
+}
+
+/// Validation of the rolling counter and checksum the BMS embeds in its frames.
+/// The fields decoded from the configured bytes carry no measurement then.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrameCheckConfig {
+    pub enabled: bool,
+    /// Frames carrying counter and checksum, empty for all native frames
+    pub can_ids: Vec<u32>,
+    /// Data byte holding the rolling counter, None if there is none
+    pub counter_byte: Option<usize>,
+    /// Bits of the counter byte used by the counter
+    pub counter_mask: u8,
+    /// Data byte holding the checksum, None if there is none
+    pub checksum_byte: Option<usize>,
+    pub checksum: ChecksumAlgorithm,
+}
+
+impl FrameCheckConfig {
+    /// Whether frames with the given CAN ID are validated.
+    pub fn applies_to(&self, can_id: u32) -> bool {
+        self.enabled && (self.can_ids.is_empty() || self.can_ids.contains(&can_id))
+    }
+}
+
+impl Default for FrameCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            can_ids: Vec::new(),
+            counter_byte: None,
+            counter_mask: 0x0F,
+            checksum_byte: None,
+            checksum: ChecksumAlgorithm::default(),
+        }
+    }
+}
+
+/// BmsData fields a CANopen TPDO entry can be mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BmsField {
+    MinCellVoltage,
+    MaxCellVoltage,
+    MinTemperature,
+    MaxTemperature,
+    Info,
+    Soc,
+    Current,
+    TotalVoltage,
+    Current32,
+    TotalVoltage32,
+    Warning1,
+    Warning2,
+    Error1,
+    Error2,
+}
+
+/// One little endian value of a TPDO.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PdoMapping {
+    /// TPDO number 1-4 (COB-ID 0x180/0x280/0x380/0x480 + node ID)
+    pub pdo: u8,
+    /// Byte offset within the PDO
+    pub offset: usize,
+    /// Length in bytes (1-4)
+    pub length: usize,
+    pub field: BmsField,
+}
+
+/// Object dictionary entry read via expedited SDO upload at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SdoRead {
+    /// Key under which the value is stored in the pack metadata
+    pub name: String,
+    pub index: u16,
+    #[serde(default)]
+    pub subindex: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanopenConfig {
+    /// Node ID of each BMS, in BMS ID order (first entry is BMS 1)
+    pub node_ids: Vec<u8>,
+    /// Send NMT "start remote node" at startup and whenever the node boots
+    pub start_node: bool,
+    /// Heartbeat consumer time, 0 disables node guarding
+    pub heartbeat_timeout_ms: u64,
+    /// Mapping of the TPDO contents into BmsData
+    pub tpdo_mapping: Vec<PdoMapping>,
+    /// Metadata read once at startup, before the node is started
+    pub sdo_reads: Vec<SdoRead>,
+    pub sdo_timeout_ms: u64,
+}
+
+impl CanopenConfig {
+    /// Node ID of the given BMS, None if none is configured.
+    pub fn node_id(&self, bms_id: u8) -> Option<u8> {
+        self.node_ids.get(usize::from(bms_id).checked_sub(1)?).copied()
+    }
+
+    pub fn heartbeat_timeout(&self) -> Option<Duration> {
+        (self.heartbeat_timeout_ms > 0).then(|| Duration::from_millis(self.heartbeat_timeout_ms))
+    }
+
+    pub fn sdo_timeout(&self) -> Duration {
+        Duration::from_millis(self.sdo_timeout_ms)
+    }
+}
+
+impl Default for CanopenConfig {
+    fn default() -> Self {
+        Self {
+            node_ids: vec![1, 2],
+            start_node: true,
+            heartbeat_timeout_ms: 3000,
+            tpdo_mapping: crate::can::default_tpdo_mapping(),
+            sdo_reads: Vec::new(),
+            sdo_timeout_ms: 500,
+        }
+    }
+}
+
+// --- Victron CAN-BMS Output ---
+/// Emits the data of one BMS as Victron/Pylontech CAN-BMS messages on the CAN interface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VictronConfig {
+    pub enabled: bool,
+    /// BMS whose data is sent
+    pub bms_id: u8,
+    pub interval_ms: u64,
+    /// Charge voltage limit (V)
+    pub charge_voltage_limit: f64,
+    /// Discharge voltage limit (V)
+    pub discharge_voltage_limit: f64,
+    /// Charge current limit (A)
+    pub charge_current_limit: f64,
+    /// Discharge current limit (A)
+    pub discharge_current_limit: f64,
+    /// Factor applied to the current limits while a rule is at derate level
+    pub derate_factor: f64,
+    /// State of health reported in 0x355 (%)
+    pub state_of_health: u16,
+    /// Volts per LSB of the total voltage value
+    pub voltage_scale: f64,
+    /// Amperes per LSB of the (signed) current value
+    pub current_scale: f64,
+    /// Added to the raw temperature to get degrees Celsius
+    pub temperature_offset: f64,
+}
+
+impl VictronConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+impl Default for VictronConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bms_id: 1,
+            interval_ms: 1000,
+            charge_voltage_limit: 53.2,
+            discharge_voltage_limit: 44.0,
+            charge_current_limit: 50.0,
+            discharge_current_limit: 50.0,
+            derate_factor: 0.5,
+            state_of_health: 100,
+            voltage_scale: 0.1,
+            current_scale: 0.1,
+            temperature_offset: 0.0,
+        }
+    }
+}
+
+// --- Modbus Server Configuration ---
+/// Settings for one Modbus TCP server instance (one per BMS).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModbusServerConfig {
+    /// Listen address, e.g. "0.0.0.0:502"
+    pub addr: String,
+    /// Reject all write requests regardless of the client address
+    pub read_only: bool,
+    /// Client IPs allowed to write ON and OFF, as operators. Empty means every client
+    /// may. Ignored for the clients listed in `clients`.
+    pub write_allowlist: Vec<IpAddr>,
+    /// Role per client IP, the only way to make a client a maintainer. If set, unlisted
+    /// clients are viewers.
+    pub clients: Vec<ModbusClientConfig>,
+    /// Maximum number of simultaneous client connections, 0 for unlimited
+    pub max_connections: usize,
+    /// Sustained requests per second allowed per connection, 0 for unlimited.
+    /// Excess requests are answered with ServerDeviceBusy.
+    pub max_requests_per_second: f64,
+    /// Number of requests a connection may send in a burst above the sustained rate
+    pub request_burst: u32,
+    /// Longest time a request may take, it is answered with ServerDeviceBusy after that.
+    /// 0 for unlimited.
+    pub request_timeout_ms: u64,
+    /// Connections without any traffic for this long are closed, so half-open connections
+    /// don't keep their handler alive. 0 for never.
+    pub idle_timeout_ms: u64,
+    /// Order of the two registers of 32-bit values
+    pub word_order: WordOrder,
+    /// An OFF written to REG_ON is only executed once `off_confirm_value` is written
+    /// to REG_OFF_CONFIRM within `off_confirm_timeout_ms`
+    pub off_confirm: bool,
+    pub off_confirm_value: u16,
+    pub off_confirm_timeout_ms: u64,
+    /// Unit conversion of register values for the clients of this server
+    pub scaling: Vec<RegisterScaling>,
+    /// Register window passed through to the BMS parameters over CAN
+    pub tunnel: Option<RegisterTunnelConfig>,
+}
+
+impl ModbusServerConfig {
+    fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn off_confirm_timeout(&self) -> Duration {
+        Duration::from_millis(self.off_confirm_timeout_ms)
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_ms > 0).then(|| Duration::from_millis(self.request_timeout_ms))
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_ms > 0).then(|| Duration::from_millis(self.idle_timeout_ms))
+    }
+
+    /// Role of a client connecting from `ip`. Without `clients` a client allowed to
+    /// write (see `write_allowlist`) is an operator, settings need a maintainer entry.
+    pub fn role(&self, ip: IpAddr) -> Role {
+        // Normalize IPv4-mapped IPv6 addresses so "::ffff:10.0.0.1" matches "10.0.0.1"
+        let ip = ip.to_canonical();
+        if self.read_only {
+            return Role::Viewer;
+        }
+        if let Some(client) = self.clients.iter().find(|client| client.ip == ip) {
+            return client.role;
+        }
+        if self.clients.is_empty() && (self.write_allowlist.is_empty() || self.write_allowlist.contains(&ip)) {
+            Role::Operator
+        } else {
+            Role::Viewer
+        }
+    }
+}
+
+impl Default for ModbusServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:502".to_string(),
+            read_only: false,
+            write_allowlist: Vec::new(),
+            clients: Vec::new(),
+            max_connections: 10,
+            max_requests_per_second: 0.0,
+            request_burst: 10,
+            request_timeout_ms: 10000,
+            idle_timeout_ms: 120000,
+            word_order: WordOrder::default(),
+            off_confirm: true,
+            off_confirm_value: 0xA55A,
+            off_confirm_timeout_ms: 5000,
+            scaling: Vec::new(),
+            tunnel: None,
+        }
+    }
+}
+
+/// Role of the Modbus client connecting from `ip`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusClientConfig {
+    pub ip: IpAddr,
+    pub role: Role,
+}
+
+/// Aggregate register block of all packs (see site.rs), served by every Modbus server
+/// next to the registers of its own BMS, so the EMS can poll the whole site at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteMapConfig {
+    pub enabled: bool,
+    /// First register of the block, it must not overlap the BMS registers or a tunnel window
+    pub start: u16,
+    /// Capacity of each pack in Ah, in BMS order. The site SOC is weighted by them, with
+    /// a capacity missing for any pack it is the lowest SOC of the packs.
+    pub capacities_ah: Vec<f64>,
+}
+
+impl Default for SiteMapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: 100,
+            capacities_ah: Vec::new(),
+        }
+    }
+}
+
+/// Register window of a Modbus server that is passed through to the BMS as SDO-style
+/// CAN requests (expedited upload/download): register `start + n` reads and writes the
+/// 16-bit parameter at index `index + n`, subindex 0. Service tools can so access the
+/// BMS configuration through the gateway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegisterTunnelConfig {
+    /// First register of the window
+    pub start: u16,
+    /// Number of registers in the window
+    pub count: u16,
+    /// Parameter index of the first register
+    pub index: u16,
+    /// 11-bit CAN ID of the requests to the BMS, at most 0x7FF
+    pub request_id: u16,
+    /// 11-bit CAN ID of the BMS responses, at most 0x7FF
+    pub response_id: u16,
+    /// How long to wait for the response to one parameter access
+    pub timeout_ms: u64,
+}
+
+impl RegisterTunnelConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Parameter index of a register, None outside of the window.
+    pub fn parameter(&self, register: u16) -> Option<u16> {
+        let offset = register.checked_sub(self.start)?;
+        (offset < self.count).then(|| self.index.wrapping_add(offset))
+    }
+}
+
+impl Default for RegisterTunnelConfig {
+    fn default() -> Self {
+        Self {
+            start: 5000,
+            count: 256,
+            index: 0x2000,
+            request_id: 0x601,
+            response_id: 0x581,
+            timeout_ms: 500,
+        }
+    }
+}
+
+/// Linear conversion `value * scale + offset` (rounded, saturating) of a register value,
+/// e.g. scale 0.01 turns mV into 0.1 V. For 32-bit values `register` is the first
+/// register of the pair, the value is converted before it is split.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterScaling {
+    pub register: u16,
+    #[serde(default = "RegisterScaling::default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    /// The value is two's complement (e.g. the current)
+    #[serde(default)]
+    pub signed: bool,
+}
+
+impl RegisterScaling {
+    fn default_scale() -> f64 {
+        1.0
+    }
+
+    fn convert(&self, value: f64) -> f64 {
+        (value * self.scale + self.offset).round()
+    }
+
+    /// Converts a 16-bit register value, results outside the range saturate.
+    pub fn apply_16(&self, value: u16) -> u16 {
+        if self.signed {
+            self.convert(f64::from(value as i16)) as i16 as u16
+        } else {
+            self.convert(f64::from(value)) as u16
+        }
+    }
+
+    /// Converts a 32-bit value, results outside the range saturate.
+    pub fn apply_32(&self, value: u32) -> u32 {
+        if self.signed {
+            self.convert(f64::from(value as i32)) as i32 as u32
+        } else {
+            self.convert(f64::from(value)) as u32
+        }
+    }
+}
+
+/// Order of the two 16-bit words of a value spanning a register pair.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WordOrder {
+    /// High word in the lower register address
+    #[default]
+    BigEndian,
+    /// Low word in the lower register address
+    LittleEndian,
+}
+
+/// How a BMS measurement register without a valid value is answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidValuePolicy {
+    /// Answer 0 (original behavior)
+    #[default]
+    Zero,
+    /// Answer 0xFFFF
+    Marker,
+    /// Answer the last known (possibly stale) value, 0xFFFF if there is none
+    LastKnown,
+    /// Answer with the Modbus exception GatewayTargetDevice
+    Exception,
+}
+
+/// Policy override for a single register.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterPolicy {
+    pub register: u16,
+    pub policy: InvalidValuePolicy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvalidValueConfig {
+    /// Policy for all BMS measurement registers without an override
+    pub default: InvalidValuePolicy,
+    /// Treat values restored from a snapshot (not yet confirmed by CAN) as invalid
+    pub stale_is_invalid: bool,
+    /// Treat the values of a BMS whose data failed the plausibility checks as invalid
+    pub implausible_is_invalid: bool,
+    pub registers: Vec<RegisterPolicy>,
+}
+
+impl InvalidValueConfig {
+    pub fn policy_for(&self, register: u16) -> InvalidValuePolicy {
+        self.registers
+            .iter()
+            .find(|r| r.register == register)
+            .map_or(self.default, |r| r.policy)
+    }
+}
+
+/// Wire-level recording of all Modbus server transactions, downloadable via GET /modbus/trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModbusTraceConfig {
+    pub enabled: bool,
+    /// Number of request/response pairs kept in memory
+    pub capacity: usize,
+}
+
+impl Default for ModbusTraceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 1000,
+        }
+    }
+}
+
+/// In-memory history of the BMS datasets, downloadable via GET /recorder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    /// How much history is kept per BMS
+    pub minutes: u64,
+    pub interval_ms: u64,
+}
+
+impl RecorderConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// Number of samples kept per BMS.
+    pub fn capacity(&self) -> usize {
+        (self.minutes * 60_000 / self.interval_ms.max(1)) as usize
+    }
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            minutes: 10,
+            interval_ms: 1000,
+        }
+    }
+}
+
+/// Monitoring of the NTP synchronization of the system clock. The state is reported
+/// by GET /health, a warning is logged while the clock is not synchronized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    pub enabled: bool,
+    pub check_interval_ms: u64,
+}
+
+impl ClockConfig {
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_millis(self.check_interval_ms)
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_ms: 60_000,
+        }
+    }
+}
+
+// --- Log Output ---
+/// Where the log records go. The filter is set by RUST_LOG in every case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogOutput {
+    #[default]
+    Stderr,
+    /// RFC 5424 messages to a remote syslog server, nothing is written locally
+    Syslog,
+    /// Native journal protocol with the module and source location as fields
+    Journald,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogTransport {
+    #[default]
+    Udp,
+    /// Octet-counting framing (RFC 6587), records are dropped while disconnected
+    Tcp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub output: LogOutput,
+    /// APP-NAME of the syslog messages and SYSLOG_IDENTIFIER of the journal entries
+    pub app_name: String,
+    pub syslog: SyslogConfig,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            output: LogOutput::Stderr,
+            app_name: "can_modbus_gateway".to_string(),
+            syslog: SyslogConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogConfig {
+    pub addr: String,
+    pub transport: SyslogTransport,
+    /// Syslog facility code, 16 = local0
+    pub facility: u8,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:514".to_string(),
+            transport: SyslogTransport::Udp,
+            facility: 16,
+        }
+    }
+}
+
+// --- Inverter Configuration ---
+/// A single register write of an inverter command sequence.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterWrite {
+    pub register: u16,
+    pub value: u16,
+    /// Pause after this write before the next step, the inverter's `write_delay_ms` if not set
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+}
+
+impl RegisterWrite {
+    /// Pause after this write, `default` unless the write sets its own.
+    pub fn delay(&self, default: Duration) -> Duration {
+        self.delay_ms.map(Duration::from_millis).unwrap_or(default)
+    }
+}
+
+/// Register checked before the ON sequence; any set bit of `mask` means a fault is latched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultCheckConfig {
+    pub register: u16,
+    #[serde(default = "FaultCheckConfig::default_mask")]
+    pub mask: u16,
+}
+
+impl FaultCheckConfig {
+    fn default_mask() -> u16 {
+        0xFFFF
+    }
+}
+
+/// Register polled after a sequence until `value & mask == expected`, i.e. the inverter
+/// is running after ON or stopped after OFF.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunningCheckConfig {
+    pub register: u16,
+    #[serde(default = "FaultCheckConfig::default_mask")]
+    pub mask: u16,
+    pub expected: u16,
+    /// How long the inverter may take to report the state
+    #[serde(default = "RunningCheckConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "RunningCheckConfig::default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+impl RunningCheckConfig {
+    /// Check for `register == expected` with the default timing.
+    pub fn new(register: u16, expected: u16) -> Self {
+        Self {
+            register,
+            mask: FaultCheckConfig::default_mask(),
+            expected,
+            timeout_ms: Self::default_timeout_ms(),
+            poll_interval_ms: Self::default_poll_interval_ms(),
+        }
+    }
+
+    fn default_timeout_ms() -> u64 {
+        20_000
+    }
+
+    fn default_poll_interval_ms() -> u64 {
+        1000
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+/// Charge/discharge detection from the pack current. The thresholds are in the units of
+/// the current registers (0.1 A for the default packs).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlowStateConfig {
+    pub enabled: bool,
+    /// The BMS reports charging current as positive
+    pub charge_positive: bool,
+    /// Magnitude above which a direction is entered
+    pub enter_threshold: u32,
+    /// Magnitude below which the direction is left again
+    pub exit_threshold: u32,
+    pub interval_ms: u64,
+}
+
+impl FlowStateConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+impl Default for FlowStateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            charge_positive: true,
+            enter_threshold: 20,
+            exit_threshold: 10,
+            interval_ms: 500,
+        }
+    }
+}
+
+/// Built-in register maps of known inverter models (see inverter_profiles.rs).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InverterProfile {
+    /// The original registers: OFF only, no ON, derate or running check
+    #[default]
+    Generic,
+    /// Selected by the SunSpec manufacturer (needs `identify`), generic until then
+    Auto,
+    Sma,
+    Fronius,
+    Victron,
+    Sungrow,
+}
+
+/// Settings for one inverter driven by its own Modbus client task.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InverterConfig {
+    /// Name used in logs and command results
+    pub name: String,
+    /// Modbus TCP address of the inverter, e.g. "192.168.2.100:502"
+    pub addr: String,
+    /// Modbus unit (slave) ID
+    pub unit_id: u8,
+    /// Register map the sequences and checks below default to
+    pub profile: InverterProfile,
+    /// Read the SunSpec common model (manufacturer, model, serial) on every connect
+    pub identify: bool,
+    /// Delay between connection attempts
+    pub reconnect_delay_ms: u64,
+    /// How long the inverter may be unreachable before it counts as down
+    pub down_after_ms: u64,
+    /// Registers written (in order) to switch the inverter off, the profile's if not set
+    pub off_sequence: Option<Vec<RegisterWrite>>,
+    /// Registers written (in order) to restart the inverter, the profile's if not set.
+    /// Empty means ON does nothing.
+    pub on_sequence: Option<Vec<RegisterWrite>>,
+    /// Fault register checked before the ON sequence is executed
+    pub fault_check: Option<FaultCheckConfig>,
+    /// Status register verified after the ON sequence, ON only succeeds once the inverter
+    /// runs. The profile's if not set.
+    pub running_check: Option<RunningCheckConfig>,
+    /// Register read back after the OFF sequence, OFF only succeeds once the inverter
+    /// reports stopped. The profile's if not set.
+    pub off_check: Option<RunningCheckConfig>,
+    /// How often the OFF sequence is repeated when the inverter does not report stopped,
+    /// before the OFF fails and is raised as alarm
+    pub off_retries: u32,
+    /// Registers written when a rule reaches the derate level (e.g. power limit), the
+    /// profile's if not set
+    pub derate_sequence: Option<Vec<RegisterWrite>>,
+    /// Registers written when no rule is at derate level anymore, the profile's if not set
+    pub derate_release_sequence: Option<Vec<RegisterWrite>>,
+    /// Derate sequence while the packs are charging (charge limit registers), the derate
+    /// sequence above is used for both directions if not set
+    pub derate_charge_sequence: Option<Vec<RegisterWrite>>,
+    /// Delays and time limits of the Modbus transactions
+    pub timing: InverterTimingConfig,
+    /// Retry policy for commands that failed to execute
+    pub retry: RetryConfig,
+    /// Periodic read used to detect dead connections
+    pub keep_alive: KeepAliveConfig,
+    /// Log register writes instead of sending them, overrides the global `dry_run`
+    pub dry_run: Option<bool>,
+}
+
+impl InverterConfig {
+    fn new(name: &str, addr: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            addr: addr.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn reconnect_delay(&self) -> Duration {
+        Duration::from_millis(self.reconnect_delay_ms)
+    }
+
+    pub fn down_after(&self) -> Duration {
+        Duration::from_millis(self.down_after_ms)
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
+
+    pub fn off_sequence(&self) -> Vec<RegisterWrite> {
+        self.off_sequence.clone().unwrap_or_else(|| self.profile.off_sequence())
+    }
+
+    pub fn on_sequence(&self) -> Vec<RegisterWrite> {
+        self.on_sequence.clone().unwrap_or_else(|| self.profile.on_sequence())
+    }
+
+    pub fn running_check(&self) -> Option<RunningCheckConfig> {
+        self.running_check.clone().or_else(|| self.profile.running_check())
+    }
+
+    pub fn off_check(&self) -> Option<RunningCheckConfig> {
+        self.off_check.clone().or_else(|| self.profile.off_check())
+    }
+
+    pub fn derate_sequence(&self) -> Vec<RegisterWrite> {
+        self.derate_sequence.clone().unwrap_or_else(|| self.profile.derate_sequence())
+    }
+
+    pub fn derate_release_sequence(&self) -> Vec<RegisterWrite> {
+        self.derate_release_sequence.clone().unwrap_or_else(|| self.profile.derate_release_sequence())
+    }
+
+    /// Derate sequence for the direction of the current.
+    pub fn derate_sequence_for(&self, flow: FlowState) -> Vec<RegisterWrite> {
+        match (&self.derate_charge_sequence, flow) {
+            (Some(sequence), FlowState::Charging) => sequence.clone(),
+            _ => self.derate_sequence(),
+        }
+    }
+}
+
+impl Default for InverterConfig {
+    fn default() -> Self {
+        Self {
+            name: "inverter".to_string(),
+            addr: "0.0.0.0:502".to_string(),
+            unit_id: 1,
+            profile: InverterProfile::Generic,
+            identify: true,
+            reconnect_delay_ms: 5000,
+            down_after_ms: 30_000,
+            off_sequence: None,
+            on_sequence: None,
+            fault_check: None,
+            running_check: None,
+            off_check: None,
+            off_retries: 2,
+            derate_sequence: None,
+            derate_release_sequence: None,
+            derate_charge_sequence: None,
+            timing: InverterTimingConfig::default(),
+            retry: RetryConfig::default(),
+            keep_alive: KeepAliveConfig::default(),
+            dry_run: None,
+        }
+    }
+}
+
+/// Keep-alive read of holding registers while the connection is idle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepAliveConfig {
+    pub enabled: bool,
+    /// First holding register to read
+    pub register: u16,
+    /// Number of registers to read
+    pub count: u16,
+    /// If set, the read values must match exactly, otherwise the connection is re-established
+    pub expected: Option<Vec<u16>>,
+    /// Idle time before a keep-alive read is sent
+    pub interval_ms: u64,
+}
+
+impl KeepAliveConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            register: 40070,
+            count: 1,
+            expected: None,
+            interval_ms: 30_000,
+        }
+    }
+}
+
+/// Exponential backoff used when replaying failed inverter commands after reconnecting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Delay before the first retry, doubled on every further attempt
+    pub initial_backoff_ms: u64,
+    /// Upper bound for the retry delay
+    pub max_backoff_ms: u64,
+    /// Random variation of each delay as a fraction (0.2 = +/-20 %)
+    pub jitter: f64,
+    /// A command is dropped (and reported as failed) once it is older than this
+    pub deadline_ms: u64,
+}
+
+impl RetryConfig {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+
+    pub fn deadline(&self) -> Duration {
+        Duration::from_millis(self.deadline_ms)
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: 0.2,
+            deadline_ms: 120_000,
+        }
+    }
+}
+
+/// Delays and time limits of the Modbus transactions with one inverter. A transaction or
+/// sequence running into its limit counts as connection failure, the connection is
+/// re-established and the command retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InverterTimingConfig {
+    /// Pause after every write of a sequence, unless the write sets its own `delay_ms`
+    pub write_delay_ms: u64,
+    /// Limit of one request and its response
+    pub transaction_timeout_ms: u64,
+    /// Limit of a whole sequence with its delays and read-back checks, 0 for none
+    pub sequence_deadline_ms: u64,
+    /// Limit of establishing the TCP connection
+    pub connect_timeout_ms: u64,
+}
+
+impl InverterTimingConfig {
+    pub fn write_delay(&self) -> Duration {
+        Duration::from_millis(self.write_delay_ms)
+    }
+
+    pub fn transaction_timeout(&self) -> Duration {
+        Duration::from_millis(self.transaction_timeout_ms)
+    }
+
+    pub fn sequence_deadline(&self) -> Option<Duration> {
+        (self.sequence_deadline_ms > 0).then(|| Duration::from_millis(self.sequence_deadline_ms))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+}
+
+impl Default for InverterTimingConfig {
+    fn default() -> Self {
+        Self {
+            write_delay_ms: 50,
+            transaction_timeout_ms: 3000,
+            sequence_deadline_ms: 0,
+            connect_timeout_ms: 5000,
+        }
+    }
+}
+
+// --- BMS Status Flags ---
+/// Labels of the bits of the BMS status bytes, indexed by bit number (0 = LSB).
+/// Bits without a label are named "<byte>.bit<n>".
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlagsConfig {
+    pub info: Vec<String>,
+    pub warning1: Vec<String>,
+    pub warning2: Vec<String>,
+    pub error1: Vec<String>,
+    pub error2: Vec<String>,
+    /// Texts of whole byte values, for firmware reporting error codes instead of bits.
+    /// A value with a text is not split into bits.
+    pub codes: Vec<ErrorCodeConfig>,
+    /// Language packs, e.g. [flags.translations.de]. Labels and codes missing in a pack
+    /// keep the texts above.
+    pub translations: BTreeMap<Language, FlagsTranslation>,
+}
+
+impl FlagsConfig {
+    /// The labels and code texts in `language`, without the language packs.
+    pub fn localized(&self, language: Language) -> FlagsConfig {
+        let Some(pack) = self.translations.get(&language) else {
+            return FlagsConfig { translations: BTreeMap::new(), ..self.clone() };
+        };
+        let labels = |default: &[String], translated: &[String]| -> Vec<String> {
+            (0..default.len().max(translated.len()))
+                .map(|bit| {
+                    translated
+                        .get(bit)
+                        .filter(|label| !label.is_empty())
+                        .or(default.get(bit))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect()
+        };
+        let mut codes = pack.codes.clone();
+        codes.extend(
+            self.codes
+                .iter()
+                .filter(|code| !pack.codes.iter().any(|c| c.byte == code.byte && c.value == code.value))
+                .cloned(),
+        );
+        FlagsConfig {
+            info: labels(&self.info, &pack.info),
+            warning1: labels(&self.warning1, &pack.warning1),
+            warning2: labels(&self.warning2, &pack.warning2),
+            error1: labels(&self.error1, &pack.error1),
+            error2: labels(&self.error2, &pack.error2),
+            codes,
+            translations: BTreeMap::new(),
+        }
+    }
+}
+
+/// Bit labels and code texts of one language.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlagsTranslation {
+    pub info: Vec<String>,
+    pub warning1: Vec<String>,
+    pub warning2: Vec<String>,
+    pub error1: Vec<String>,
+    pub error2: Vec<String>,
+    pub codes: Vec<ErrorCodeConfig>,
+}
+
+/// Language of the operator-facing texts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    #[default]
+    En,
+    De,
+}
+
+/// Fault text of one value of a status byte, e.g. error1 = 0x21 "Contactor welded".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorCodeConfig {
+    pub byte: StatusByte,
+    pub value: u8,
+    pub text: String,
+}
+
+// --- Command Arbiter Configuration ---
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArbiterConfig {
+    /// How long to wait for every output to report the result of a command
+    pub result_timeout_ms: u64,
+    /// How often a command is re-sent to outputs that failed or did not answer
+    pub max_retries: u32,
+    /// Refuse ON while an inverter is down
+    pub block_on_while_down: bool,
+    /// Which source wins when commands conflict within the lockout window, highest first
+    pub source_priority: Vec<CommandSource>,
+    /// Accept OFF from any source even within the lockout window
+    pub off_always_wins: bool,
+    /// Refuse ON while a BMS reports errors or its data is stale (BMS in maintenance excepted)
+    pub inhibit_on_fault: bool,
+    /// Start the outputs one after another on ON instead of all at once
+    pub soft_start: SoftStartConfig,
+    /// Switch all outputs off at once with a deadline when a BMS signals an error
+    pub emergency_off: EmergencyOffConfig,
+    /// Length of the lockout window per command type
+    pub lockout: LockoutConfig,
+}
+
+impl ArbiterConfig {
+    pub fn result_timeout(&self) -> Duration {
+        Duration::from_millis(self.result_timeout_ms)
+    }
+}
+
+impl Default for ArbiterConfig {
+    fn default() -> Self {
+        Self {
+            result_timeout_ms: 5000,
+            max_retries: 2,
+            block_on_while_down: true,
+            source_priority: vec![
+                CommandSource::ExternalTrip,
+                CommandSource::Fault,
+                CommandSource::Gpio,
+                CommandSource::Modbus,
+                CommandSource::OpcUa,
+                CommandSource::Grpc,
+                CommandSource::Scheduler,
+            ],
+            off_always_wins: true,
+            inhibit_on_fault: true,
+            soft_start: SoftStartConfig::default(),
+            emergency_off: EmergencyOffConfig::default(),
+            lockout: LockoutConfig::default(),
+        }
+    }
+}
+
+/// Lockout window after an accepted command, per type of the incoming command. Within
+/// its window a command only gets through if the arbitration policy allows it, outside
+/// it is always accepted. ON stays debounced, OFF and QUIT are safety relevant and pass
+/// right away by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    pub on_ms: u64,
+    pub off_ms: u64,
+    pub quit_ms: u64,
+    /// Keep the most recent command held back by the lockout and dispatch it when its
+    /// window ends, instead of dropping it
+    pub queue: bool,
+}
+
+impl LockoutConfig {
+    pub fn window(&self, command: &SystemCommand) -> Duration {
+        Duration::from_millis(match command {
+            SystemCommand::On => self.on_ms,
+            SystemCommand::Off => self.off_ms,
+            SystemCommand::Quit => self.quit_ms,
+        })
+    }
+
+    /// The longest of the windows, for how long the controls show as frozen
+    pub fn longest(&self) -> Duration {
+        Duration::from_millis(self.on_ms.max(self.off_ms).max(self.quit_ms))
+    }
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            on_ms: 1000,
+            off_ms: 0,
+            quit_ms: 0,
+            queue: true,
+        }
+    }
+}
+
+/// Emergency OFF on BMS error signals: the arbiter sends OFF to all outputs at the same
+/// time and checks that every one confirmed within `deadline_ms`. Outputs that missed the
+/// deadline are retried as usual. Without it, each inverter client runs its own OFF
+/// sequence on the error signal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmergencyOffConfig {
+    pub enabled: bool,
+    pub deadline_ms: u64,
+}
+
+impl EmergencyOffConfig {
+    pub fn deadline(&self) -> Duration {
+        Duration::from_millis(self.deadline_ms)
+    }
+}
+
+impl Default for EmergencyOffConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            deadline_ms: 2000,
+        }
+    }
+}
+
+/// Staggered ON: every output has to confirm ON (see `InverterConfig::running_check`)
+/// before the next one is started, in configuration order, to limit the inrush current.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoftStartConfig {
+    pub enabled: bool,
+    /// Pause after an output confirmed ON before the next one is started
+    pub delay_ms: u64,
+    /// How long to wait for one output to confirm ON, replaces `result_timeout_ms` during soft start
+    pub result_timeout_ms: u64,
+}
+
+impl SoftStartConfig {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+
+    pub fn result_timeout(&self) -> Duration {
+        Duration::from_millis(self.result_timeout_ms)
+    }
+}
+
+impl Default for SoftStartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_ms: 5000,
+            result_timeout_ms: 30_000,
+        }
+    }
+}
+
+// --- GPIO Backend ---
+/// How the GPIO pins are accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioBackend {
+    /// Raspberry Pi GPIO registers (BCM numbers)
+    #[default]
+    Rppal,
+    /// Kernel sysfs interface (/sys/class/gpio), for other boards such as the BeagleBone
+    Sysfs,
+    /// GPIO character device (/dev/gpiochipN), pin numbers are line offsets on `chip`.
+    /// Requires the gateway to be built with the `gpiocdev` feature.
+    Cdev,
+    /// No hardware, outputs are only logged and inputs stay inactive
+    Mock,
+}
+
+/// Pins of the front panel buttons and LEDs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PanelPins {
+    pub off: u8,
+    pub on: u8,
+    pub quit: u8,
+    pub red_led: u8,
+    pub green_led: u8,
+}
+
+impl Default for PanelPins {
+    fn default() -> Self {
+        Self {
+            off: 13,
+            on: 6,
+            quit: 16,
+            red_led: 22,
+            green_led: 23,
+        }
+    }
+}
+
+/// Pin of the configuration that the cdev backend looks up by its line name (the label
+/// given by the device tree), on whichever chip provides it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GpioLineConfig {
+    pub pin: u8,
+    pub name: String,
+}
+
+/// GPIO backend and front panel pins. All pin numbers of the configuration (panel,
+/// buzzer, relays, inputs) are numbers of the selected backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpioConfig {
+    pub backend: GpioBackend,
+    /// Added to the pin numbers to get the sysfs GPIO numbers (base of the GPIO chip)
+    pub sysfs_base: u32,
+    /// GPIO chip of the cdev backend
+    pub chip: String,
+    /// Pins the cdev backend requests by line name instead of by offset on `chip`
+    pub lines: Vec<GpioLineConfig>,
+    pub pins: PanelPins,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        Self {
+            backend: GpioBackend::default(),
+            sysfs_base: 0,
+            chip: "/dev/gpiochip0".to_string(),
+            lines: Vec::new(),
+            pins: PanelPins::default(),
+        }
+    }
+}
+
+// --- Buzzer ---
+/// Piezo sounder driven by the LED output task: continuous on a trip or fault signal,
+/// a chirp on warnings. QUIT silences it until the severity rises again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuzzerConfig {
+    pub enabled: bool,
+    /// Number of the buzzer output (BCM number with the rppal backend)
+    pub pin: u8,
+    /// Time between two chirps while a warning is active
+    pub chirp_interval_ms: u64,
+}
+
+impl Default for BuzzerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pin: 24,
+            chirp_interval_ms: 5000,
+        }
+    }
+}
+
+// --- Fault Recovery ---
+/// Leaving the latched fault state without a restart or QUIT: once the error bytes of all
+/// BMS are zero and no rule is tripped for `clear_after_ms`, the LEDs, buzzer and fault
+/// relay return to the stopped (Ready) state. The inverters stay off until an ON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultRecoveryConfig {
+    pub auto_clear: bool,
+    pub clear_after_ms: u64,
+}
+
+impl FaultRecoveryConfig {
+    pub fn clear_after(&self) -> Duration {
+        Duration::from_millis(self.clear_after_ms)
+    }
+}
+
+impl Default for FaultRecoveryConfig {
+    fn default() -> Self {
+        Self {
+            auto_clear: false,
+            clear_after_ms: 30_000,
+        }
+    }
+}
+
+// --- Automatic Restart ---
+/// Value of an error byte that is known to be transient, e.g. error1 = 0x04.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransientCode {
+    pub byte: StatusByte,
+    pub value: u8,
+}
+
+/// Switching the inverters back on after a fault that only showed whitelisted error codes
+/// while the system was running. ON is sent once the errors are clear for `delay_minutes`,
+/// at most `max_attempts_per_day` times in 24 hours. Every attempt is audit-logged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoRestartConfig {
+    pub enabled: bool,
+    pub codes: Vec<TransientCode>,
+    pub delay_minutes: u64,
+    pub max_attempts_per_day: u32,
+}
+
+impl AutoRestartConfig {
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay_minutes * 60)
+    }
+
+    /// True if the error byte `byte` may show `value` without blocking the restart.
+    pub fn is_transient(&self, byte: StatusByte, value: u8) -> bool {
+        self.codes.iter().any(|code| code.byte == byte && code.value == value)
+    }
+}
+
+impl Default for AutoRestartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            codes: Vec::new(),
+            delay_minutes: 5,
+            max_attempts_per_day: 3,
+        }
+    }
+}
+
+// --- Control Mode ---
+/// Gateway control mode written via REG_CONTROL_MODE (see control_mode.rs). The
+/// maintenance mode, in which fault signals do not switch the inverters off, falls back
+/// to auto after `maintenance_timeout_minutes` so it cannot be forgotten.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlModeConfig {
+    pub maintenance_timeout_minutes: u64,
+}
+
+impl ControlModeConfig {
+    pub fn maintenance_timeout(&self) -> Duration {
+        Duration::from_secs(self.maintenance_timeout_minutes * 60)
+    }
+}
+
+impl Default for ControlModeConfig {
+    fn default() -> Self {
+        Self {
+            maintenance_timeout_minutes: 60,
+        }
+    }
+}
+
+// --- Relay Outputs ---
+/// System state mirrored by a relay output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayFunction {
+    /// Energized after a successful ON until the next OFF
+    SystemRunning,
+    /// Energized while a fault signal or tripped rule is not acknowledged
+    FaultPresent,
+}
+
+/// Relay output for external hardwired interlocks. All relays are de-energized
+/// on gateway shutdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Number of the output (BCM number with the rppal backend)
+    pub pin: u8,
+    pub function: RelayFunction,
+    /// The relay board energizes the relay on a low level
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+// --- GPIO Inputs ---
+/// Quadrature rotary encoder, turning it steps through the display pages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderConfig {
+    /// Numbers of the two encoder outputs (BCM numbers with the rppal backend)
+    pub pin_a: u8,
+    pub pin_b: u8,
+}
+
+/// External trip contact (fire alarm, e-stop). Asserting it forces OFF on all outputs,
+/// bypassing the command lockout, and latches the alarm until QUIT.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TripInputConfig {
+    /// Number of the input (BCM number with the rppal backend)
+    pub pin: u8,
+    /// The trip is asserted by a low level (input with pull-up), otherwise by a high level
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+/// Timings of the GPIO buttons. Holding OFF for `long_press_ms` forces both inverters
+/// off, bypassing the command lockout and source priority.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub debounce_ms: u64,
+    pub long_press_ms: u64,
+    pub encoder: Option<EncoderConfig>,
+    pub trip: Option<TripInputConfig>,
+}
+
+impl InputConfig {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    pub fn long_press(&self) -> Duration {
+        Duration::from_millis(self.long_press_ms)
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 25,
+            long_press_ms: 2000,
+            encoder: None,
+            trip: None,
+        }
+    }
+}
+
+// --- Status Display ---
+/// HD44780 character LCD behind a PCF8574 I2C backpack.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub enabled: bool,
+    pub i2c_bus: u8,
+    /// I2C address of the backpack, usually 0x27 or 0x3F
+    pub address: u16,
+    pub columns: u8,
+    pub rows: u8,
+    /// How long each page is shown
+    pub page_interval_ms: u64,
+    /// Volts/amperes per raw unit of the BMS values
+    pub voltage_scale: f64,
+    pub current_scale: f64,
+}
+
+impl DisplayConfig {
+    pub fn page_interval(&self) -> Duration {
+        Duration::from_millis(self.page_interval_ms)
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            i2c_bus: 1,
+            address: 0x27,
+            columns: 16,
+            rows: 2,
+            page_interval_ms: 3000,
+            voltage_scale: 0.1,
+            current_scale: 0.1,
+        }
+    }
+}
+
+// --- Scheduled Commands ---
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+/// A command issued at a fixed local time, e.g. OFF at "22:00".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    pub command: crate::SystemCommand,
+    /// Local time as "HH:MM"
+    pub at: String,
+    /// Days the entry is active on, empty means every day
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+/// Commands injected through the arbiter at fixed times.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    pub enabled: bool,
+    /// Offset of the local time from UTC, e.g. 60 for CET
+    pub utc_offset_minutes: i32,
+    pub entries: Vec<ScheduleEntry>,
+}
+
+// --- Threshold Rules ---
+/// Thresholds of one rule, in the raw units of the corresponding register.
+/// Unset levels are not evaluated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub warning: Option<u16>,
+    pub derate: Option<u16>,
+    pub trip: Option<u16>,
+}
+
+/// Gateway-side limits evaluated on every received CAN frame, in addition to the BMS error bytes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub enabled: bool,
+    /// Upper limits for the max. cell voltage (mV)
+    pub cell_voltage_high: Limits,
+    /// Lower limits for the min. cell voltage (mV)
+    pub cell_voltage_low: Limits,
+    /// Upper limits for the max. temperature
+    pub temperature_high: Limits,
+    /// Lower limits for the min. temperature
+    pub temperature_low: Limits,
+    /// Lower limits for the SOC (%)
+    pub soc_low: Limits,
+}
+
+// --- Statistics ---
+/// Daily charge/energy counters integrated from the BMS current.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatisticsConfig {
+    /// Longest time between two samples, current and voltage are sampled whenever they change
+    pub sample_interval_ms: u64,
+    /// Amperes per LSB of the (signed) current value, negative to flip the charge direction
+    pub current_scale: f64,
+    /// Volts per LSB of the total voltage value
+    pub voltage_scale: f64,
+    /// File the counters are persisted to
+    pub persist_path: PathBuf,
+    /// How often the counters are written to disk
+    pub persist_interval_ms: u64,
+}
+
+impl StatisticsConfig {
+    pub fn sample_interval(&self) -> Duration {
+        Duration::from_millis(self.sample_interval_ms)
+    }
+
+    pub fn persist_interval(&self) -> Duration {
+        Duration::from_millis(self.persist_interval_ms)
+    }
+}
+
+impl Default for StatisticsConfig {
+    fn default() -> Self {
+        Self {
+            sample_interval_ms: 1000,
+            current_scale: 0.1,
+            voltage_scale: 0.1,
+            persist_path: PathBuf::from("statistics.json"),
+            persist_interval_ms: 60_000,
+        }
+    }
+}
+
+// --- BMS Data Snapshot ---
+/// Periodic persistence of the last known BmsData, restored (as stale) on startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub interval_ms: u64,
+}
+
+impl SnapshotConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("bms_snapshot.json"),
+            interval_ms: 10_000,
+        }
+    }
+}
+
+// --- Black Box ---
+/// Flight recorder: on a fault event the raw CAN frames and decoded data before and after
+/// the event are written to a file in `dir`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlackBoxConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    /// History before the event included in the dump
+    pub pre_trigger_s: u64,
+    /// How long recording continues after the event before the dump is written
+    pub post_trigger_s: u64,
+    /// Upper bound of the buffered frames, protects against bus floods
+    pub max_frames: usize,
+    /// Total size of the dumps in `dir`, the oldest are deleted above it
+    pub quota_bytes: u64,
+}
+
+/// Storage manager: deletes the oldest recorded data so the outputs stay within their
+/// byte quotas and the filesystem keeps `min_free_bytes` free. The black box dumps are
+/// always covered, other outputs are listed in `areas`. Rotated files (all but the newest
+/// of an area) are compressed with zstd at `compression_level` before the quotas are checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub enabled: bool,
+    pub check_interval_ms: u64,
+    pub min_free_bytes: u64,
+    pub compress: bool,
+    pub compression_level: i32,
+    pub areas: Vec<StorageAreaConfig>,
+}
+
+impl StorageConfig {
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_millis(self.check_interval_ms)
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_ms: 60_000,
+            min_free_bytes: 100 * 1024 * 1024,
+            compress: true,
+            compression_level: 9,
+            areas: Vec::new(),
+        }
+    }
+}
+
+// --- Persistence Writer ---
+/// Batching of the files rewritten periodically (see persist.rs), to spare the SD card:
+/// a file is written at most once per flush interval of its class, updates in between
+/// only replace the pending content. 0 writes at once. Everything pending is written
+/// when more than `max_pending_bytes` are queued and on shutdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistConfig {
+    pub batching: bool,
+    /// Statistics counters
+    pub telemetry_flush_ms: u64,
+    /// Command history
+    pub audit_flush_ms: u64,
+    /// BMS data snapshot
+    pub snapshot_flush_ms: u64,
+    pub max_pending_bytes: usize,
+}
+
+impl PersistConfig {
+    pub fn telemetry_flush(&self) -> Duration {
+        Duration::from_millis(self.telemetry_flush_ms)
+    }
+
+    pub fn audit_flush(&self) -> Duration {
+        Duration::from_millis(self.audit_flush_ms)
+    }
+
+    pub fn snapshot_flush(&self) -> Duration {
+        Duration::from_millis(self.snapshot_flush_ms)
+    }
+}
+
+impl Default for PersistConfig {
+    fn default() -> Self {
+        Self {
+            batching: true,
+            telemetry_flush_ms: 300_000,
+            audit_flush_ms: 10_000,
+            snapshot_flush_ms: 60_000,
+            max_pending_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// A directory of recorded data. The file names have to sort oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageAreaConfig {
+    pub dir: PathBuf,
+    /// Only files starting with it are counted and deleted
+    #[serde(default)]
+    pub prefix: String,
+    pub quota_bytes: u64,
+}
+
+impl BlackBoxConfig {
+    pub fn pre_trigger(&self) -> Duration {
+        Duration::from_secs(self.pre_trigger_s)
+    }
+
+    pub fn post_trigger(&self) -> Duration {
+        Duration::from_secs(self.post_trigger_s)
+    }
+}
+
+impl Default for BlackBoxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: PathBuf::from("blackbox"),
+            pre_trigger_s: 60,
+            post_trigger_s: 30,
+            max_frames: 100_000,
+            quota_bytes: 50 * 1024 * 1024,
+        }
+    }
+}
+
+// --- Command History ---
+/// Persistence of the last accepted command, restored on startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandHistoryConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Send OFF again at startup if OFF was the last command before the restart.
+    /// ON is never sent automatically.
+    pub restore_off: bool,
+}
+
+impl Default for CommandHistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from("command_history.json"),
+            restore_off: true,
+        }
+    }
+}
+
+// --- OPC UA Server ---
+/// A user of the OPC UA server, authenticated by name and password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpcUaUserConfig {
+    pub name: String,
+    pub password: String,
+    pub role: Role,
+}
+
+/// OPC UA server exposing the BMS data and command methods. Anonymous clients can only
+/// read, the command methods exist only with `users` and check the role of the caller.
+/// Requires the gateway to be built with the `opcua` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpcUaConfig {
+    pub enabled: bool,
+    /// Host name or IP the endpoint is bound to and advertised with
+    pub host: String,
+    pub port: u16,
+    pub application_name: String,
+    /// Directory holding the server certificate and trusted/rejected client certificates
+    pub pki_dir: PathBuf,
+    /// How often the variable values are refreshed from the BMS data
+    pub update_interval_ms: u64,
+    /// Users allowed to log in, over an encrypted endpoint only
+    pub users: Vec<OpcUaUserConfig>,
+}
+
+impl Default for OpcUaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "0.0.0.0".to_string(),
+            port: 4840,
+            application_name: "CAN Modbus Gateway".to_string(),
+            pki_dir: PathBuf::from("pki"),
+            update_interval_ms: 1000,
+            users: Vec::new(),
+        }
+    }
+}
+
+// --- SNMP Agent ---
+/// Read-only SNMPv1/v2c agent with traps on fault and stale-data events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnmpConfig {
+    pub enabled: bool,
+    /// UDP listen address of the agent
+    pub addr: String,
+    /// Community required for requests
+    pub community: String,
+    /// Root of the gateway's objects, e.g. "1.3.6.1.4.1.<enterprise number>"
+    pub enterprise_oid: String,
+    /// Trap receivers, e.g. "10.0.0.5:162"
+    pub trap_targets: Vec<String>,
+    pub trap_community: String,
+    /// How often the BMS data is checked for trap events
+    pub poll_interval_ms: u64,
+}
+
+impl SnmpConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    /// The enterprise OID as its arcs.
+    pub fn enterprise_arcs(&self) -> Result<Vec<u32>, AppError> {
+        self.enterprise_oid
+            .trim_start_matches('.')
+            .split('.')
+            .map(|arc| arc.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AppError::Config(format!("Invalid SNMP enterprise OID '{}': {}", self.enterprise_oid, e)))
+    }
+}
+
+impl Default for SnmpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "0.0.0.0:161".to_string(),
+            community: "public".to_string(),
+            enterprise_oid: "1.3.6.1.4.1.99999".to_string(),
+            trap_targets: Vec::new(),
+            trap_community: "public".to_string(),
+            poll_interval_ms: 1000,
+        }
+    }
+}
+
+// --- gRPC API ---
+/// gRPC telemetry/command service. Requires the gateway to be built with the `grpc` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub addr: String,
+    /// How often subscriptions check the BMS data for changes
+    pub update_interval_ms: u64,
+}
+
+impl GrpcConfig {
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_millis(self.update_interval_ms)
+    }
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "0.0.0.0:50051".to_string(),
+            update_interval_ms: 1000,
+        }
+    }
+}
+
+// --- Startup Self-Test ---
+/// Checks run once at startup, before the gateway tasks are spawned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelfTestConfig {
+    pub enabled: bool,
+    /// Expected bitrate of every CAN interface, not checked if unset
+    pub can_bitrate: Option<u32>,
+    /// How long to wait for an inverter to accept the TCP connection
+    pub inverter_timeout_ms: u64,
+    /// Round-trip every Modbus register through the data fields, see data::check_register_map
+    pub register_map: bool,
+    /// Exit instead of starting degraded when a check fails
+    pub abort_on_failure: bool,
+}
+
+impl SelfTestConfig {
+    pub fn inverter_timeout(&self) -> Duration {
+        Duration::from_millis(self.inverter_timeout_ms)
+    }
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            can_bitrate: None,
+            inverter_timeout_ms: 2000,
+            register_map: true,
+            abort_on_failure: false,
+        }
+    }
+}
+
+// --- HTTP API ---
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// The HTTP API is opt-in
+    pub enabled: bool,
+    pub addr: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr: "0.0.0.0:8080".to_string(),
+        }
+    }
+}
+
+// --- Runtime ---
+/// Scheduler of the async runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeFlavor {
+    #[default]
+    MultiThread,
+    /// All tasks on the main thread, for single core boards
+    CurrentThread,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// Worker threads of the multi-threaded runtime, None for one per CPU core
+    pub worker_threads: Option<usize>,
+    /// Upper limit for threads of blocking operations, None for the tokio default (512)
+    pub max_blocking_threads: Option<usize>,
+    /// Run the CAN receivers on a dedicated thread with their own runtime
+    pub can_rx_thread: bool,
+    /// SCHED_FIFO priority (1-99) of the CAN RX thread, needs CAP_SYS_NICE
+    pub can_rx_priority: Option<i32>,
+    /// CPU core the CAN RX thread is pinned to
+    pub can_rx_cpu: Option<usize>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            flavor: RuntimeFlavor::default(),
+            worker_threads: None,
+            max_blocking_threads: None,
+            can_rx_thread: false,
+            can_rx_priority: None,
+            can_rx_cpu: None,
+        }
+    }
+}
+
+/// Restarts of tasks that panicked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub restart_on_panic: bool,
+    pub restart_delay_ms: u64,
+    /// Restarts per task before it stays down, 0 for unlimited
+    pub max_restarts: u32,
+}
+
+impl SupervisorConfig {
+    pub fn restart_delay(&self) -> Duration {
+        Duration::from_millis(self.restart_delay_ms)
+    }
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart_on_panic: true,
+            restart_delay_ms: 1000,
+            max_restarts: 5,
+        }
+    }
+}
+
+// --- Remote Configuration ---
+/// Central configuration of a fleet: at startup the configuration is fetched from `url`
+/// (HTTPS) and only applied if the ed25519 signature at `signature_url` verifies with
+/// `public_key`. The verified copy is cached at `cache_path` (signature next to it with
+/// `.sig` appended) and used while the server is unreachable. This section always comes
+/// from the local file, a remote configuration cannot change it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteConfig {
+    pub enabled: bool,
+    pub url: String,
+    /// Detached signature of the configuration (64 bytes, raw or hex), empty for `url` + ".sig"
+    pub signature_url: String,
+    /// Hex-encoded ed25519 public key (32 bytes)
+    pub public_key: String,
+    pub cache_path: PathBuf,
+    pub timeout_ms: u64,
+}
+
+impl RemoteConfig {
+    pub fn signature_url(&self) -> String {
+        if self.signature_url.is_empty() {
+            format!("{}.sig", self.url)
+        } else {
+            self.signature_url.clone()
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            signature_url: String::new(),
+            public_key: String::new(),
+            cache_path: PathBuf::from("remote_config.toml"),
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+// --- Fleet Uplink ---
+/// Heartbeat POSTed to a central server, so the sites that are alive can be seen there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UplinkConfig {
+    pub enabled: bool,
+    pub url: String,
+    /// Name of the site in the heartbeat, empty for the hostname
+    pub site_id: String,
+    pub interval_minutes: u64,
+    /// Delay between delivery attempts while the server is unreachable
+    pub retry_delay_ms: u64,
+    pub timeout_ms: u64,
+    /// Heartbeats kept while offline, the oldest are dropped beyond it
+    pub buffer_len: usize,
+    /// With a value above 0 a heartbeat only carries the fields that changed since the
+    /// previous one (as JSON merge patch), and the full heartbeat is sent this often.
+    /// 0 sends the full heartbeat every time.
+    pub full_snapshot_minutes: u64,
+}
+
+impl UplinkConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_minutes.max(1) * 60)
+    }
+
+    pub fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Interval of the full heartbeats, None if every heartbeat is full.
+    pub fn full_snapshot_interval(&self) -> Option<Duration> {
+        (self.full_snapshot_minutes > 0).then(|| Duration::from_secs(self.full_snapshot_minutes * 60))
+    }
+}
+
+impl Default for UplinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            site_id: String::new(),
+            interval_minutes: 5,
+            retry_delay_ms: 30_000,
+            timeout_ms: 10_000,
+            // A day at the default interval
+            buffer_len: 288,
+            full_snapshot_minutes: 0,
+        }
+    }
+}
+
+// --- Roles ---
+/// What a client of the HTTP, gRPC or Modbus interfaces may do. Each role includes the
+/// rights of the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reading data and status
+    #[serde(alias = "read_only")]
+    Viewer,
+    /// Also ON and OFF
+    #[serde(alias = "command")]
+    Operator,
+    /// Also QUIT, the control mode, the BMS parameters and the runtime settings
+    /// (log filter, maintenance, CAN filters)
+    Maintainer,
+}
+
+impl Role {
+    /// Name of the role in the configuration and the audit log.
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Maintainer => "maintainer",
+        }
+    }
+}
+
+// --- API Authentication ---
+
+/// A client of the HTTP and gRPC APIs, identified by the common name of its TLS client
+/// certificate or by a static token (`Authorization: Bearer <token>`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiClientConfig {
+    pub name: String,
+    /// Common name of the client certificate, None if the client has no certificate
+    #[serde(default)]
+    pub certificate_name: Option<String>,
+    /// Static token, the fallback for clients without certificate
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(alias = "permission")]
+    pub role: Role,
+}
+
+/// Authentication of the HTTP and gRPC APIs. Without it every client may send commands.
+/// TLS (with `tls_cert` and `tls_key`, needs the tls feature) protects the tokens on the
+/// wire; with `client_ca` the servers also ask for client certificates (mutual TLS).
+/// Clients that are not listed in `clients` are refused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiAuthConfig {
+    pub enabled: bool,
+    /// Server certificate chain and private key (PEM)
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// CA certificates the client certificates are verified against (PEM)
+    pub client_ca: Option<PathBuf>,
+    pub clients: Vec<ApiClientConfig>,
+}
+
+impl ApiAuthConfig {
+    /// True if the servers use TLS.
+    pub fn tls(&self) -> bool {
+        self.tls_cert.is_some() || self.tls_key.is_some()
+    }
+}
+
+impl Default for ApiAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tls_cert: None,
+            tls_key: None,
+            client_ca: None,
+            clients: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(config: &Config) -> String {
+        match config.validate() {
+            Ok(()) => String::new(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(problems(&Config::default()), "");
+    }
+
+    #[test]
+    fn zero_intervals_are_rejected() {
+        let mut config = Config::default();
+        config.snapshot.interval_ms = 0;
+        config.grpc.update_interval_ms = 0;
+        let problems = problems(&config);
+        assert!(problems.contains("snapshot.interval_ms"), "{}", problems);
+        assert!(problems.contains("grpc.update_interval_ms"), "{}", problems);
+    }
+
+    #[test]
+    fn listen_port_collisions_are_rejected() {
+        let mut config = Config::default();
+        config.http.enabled = true;
+        config.http.addr = "0.0.0.0:8080".to_string();
+        config.grpc.enabled = true;
+        config.grpc.addr = "127.0.0.1:8080".to_string();
+        let problems = problems(&config);
+        assert!(problems.contains("grpc.addr: port 8080 is already used by http.addr"), "{}", problems);
+    }
+
+    #[test]
+    fn invalid_addresses_and_duplicate_inverters_are_rejected() {
+        let mut config = Config::default();
+        config.inverters[0].addr = "inverter".to_string();
+        config.inverters[1].name = config.inverters[0].name.clone();
+        let problems = problems(&config);
+        assert!(problems.contains("inverters[0].addr: invalid address"), "{}", problems);
+        assert!(problems.contains("inverters[1].name"), "{}", problems);
+    }
+
+    #[test]
+    fn shared_pins_are_rejected() {
+        let mut config = Config::default();
+        config.gpio.pins.quit = config.gpio.pins.off;
+        assert!(problems(&config).contains("gpio.pins.quit: pin 13 is already assigned to gpio.pins.off"));
+    }
+}
@@ -0,0 +1,228 @@
+// src/connections.rs
+use crate::{
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+// --- Connection State ---
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,
+    /// Not connected, but not for longer than the inverter's `down_after_ms`
+    Reconnecting,
+    Down,
+}
+
+/// Device information from the SunSpec common model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InverterIdentity {
+    pub manufacturer: String,
+    pub model: String,
+    pub version: String,
+    pub serial: String,
+}
+
+/// Connection status of one inverter. Timestamps are Unix time in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InverterStatus {
+    pub name: String,
+    pub state: ConnectionState,
+    /// When the current state was entered
+    pub since_ms: u128,
+    pub last_connected_ms: Option<u128>,
+    /// None until the inverter was identified
+    pub identity: Option<InverterIdentity>,
+    /// The inverter did not report stopped after the last OFF, it may still be running
+    #[serde(default)]
+    pub off_unconfirmed: bool,
+}
+
+// CAN data of a pack is missing, maintenance hides it like the faults
+fn is_stale(data: &BmsData) -> bool {
+    data.stale.unwrap_or(true) && !data.in_maintenance()
+}
+
+// --- Connection Registry ---
+/// Connection status of all inverters, written by their Modbus client tasks.
+/// Changes are mirrored into the diagnostics registers of every BMS dataset.
+#[derive(Debug)]
+pub struct ConnectionRegistry {
+    // In configuration order, the index is the bit in the diagnostics registers
+    inverters: RwLock<Vec<InverterStatus>>,
+    bms: Vec<SharedBmsData>,
+    // Signalled on connection and staleness changes, holds at most one pending signal
+    changed_tx: crossbeam_channel::Sender<()>,
+    changed_rx: crossbeam_channel::Receiver<()>,
+}
+
+impl ConnectionRegistry {
+    /// All inverters start as Reconnecting until their first connection attempt.
+    pub fn new(names: &[String], bms: Vec<SharedBmsData>) -> Self {
+        let since_ms = now_ms();
+        let inverters = names
+            .iter()
+            .map(|name| InverterStatus {
+                name: name.clone(),
+                state: ConnectionState::Reconnecting,
+                since_ms,
+                last_connected_ms: None,
+                identity: None,
+                off_unconfirmed: false,
+            })
+            .collect();
+        let (changed_tx, changed_rx) = crossbeam_channel::bounded(1);
+        Self { inverters: RwLock::new(inverters), bms, changed_tx, changed_rx }
+    }
+
+    /// Signalled when an inverter changes its connection state or the CAN data of a BMS
+    /// turns stale or fresh again. Signals are coalesced, so there is one consumer (the
+    /// LED output task) that reads the current state after each of them.
+    pub fn changes(&self) -> crossbeam_channel::Receiver<()> {
+        self.changed_rx.clone()
+    }
+
+    fn notify(&self) {
+        // A full channel already has a signal pending
+        let _ = self.changed_tx.try_send(());
+    }
+
+    /// Records the state of the inverter at `index`, timestamps only change on transitions.
+    pub fn set(&self, index: usize, state: ConnectionState) {
+        let (connected, down) = {
+            let Ok(mut inverters) = self.inverters.write() else {
+                log::error!("Connection registry lock poisoned");
+                return;
+            };
+            let Some(status) = inverters.get_mut(index) else {
+                return;
+            };
+            if status.state == state {
+                return;
+            }
+            log::info!("Inverter {}: {:?} -> {:?}", status.name, status.state, state);
+            status.state = state;
+            status.since_ms = now_ms();
+            if state == ConnectionState::Connected {
+                status.last_connected_ms = Some(status.since_ms);
+            }
+            (Self::mask(&inverters, ConnectionState::Connected), Self::mask(&inverters, ConnectionState::Down))
+        };
+
+        for bms_data in &self.bms {
+            bms_data.modify(|data| {
+                data.inverters_connected = Some(connected);
+                data.inverters_down = Some(down);
+            });
+        }
+        self.notify();
+    }
+
+    /// Records whether the inverter at `index` failed to confirm the last OFF. The flag is
+    /// raised as alarm in the diagnostics registers until an OFF is confirmed.
+    pub fn set_off_unconfirmed(&self, index: usize, unconfirmed: bool) {
+        let mask = {
+            let Ok(mut inverters) = self.inverters.write() else {
+                log::error!("Connection registry lock poisoned");
+                return;
+            };
+            let Some(status) = inverters.get_mut(index) else {
+                return;
+            };
+            if status.off_unconfirmed == unconfirmed {
+                return;
+            }
+            status.off_unconfirmed = unconfirmed;
+            inverters
+                .iter()
+                .take(16)
+                .enumerate()
+                .filter(|(_, status)| status.off_unconfirmed)
+                .fold(0u16, |mask, (bit, _)| mask | 1 << bit)
+        };
+
+        for bms_data in &self.bms {
+            bms_data.modify(|data| data.inverters_off_unconfirmed = Some(mask));
+        }
+    }
+
+    /// Records what the inverter at `index` reported about itself.
+    pub fn set_identity(&self, index: usize, identity: InverterIdentity) {
+        match self.inverters.write() {
+            Ok(mut inverters) => {
+                if let Some(status) = inverters.get_mut(index) {
+                    status.identity = Some(identity);
+                }
+            }
+            Err(_) => log::error!("Connection registry lock poisoned"),
+        }
+    }
+
+    // Bit n is set if the n-th inverter is in `state` (only the first 16 inverters fit)
+    fn mask(inverters: &[InverterStatus], state: ConnectionState) -> u16 {
+        inverters
+            .iter()
+            .take(16)
+            .enumerate()
+            .filter(|(_, status)| status.state == state)
+            .fold(0, |mask, (bit, _)| mask | 1 << bit)
+    }
+
+    pub fn snapshot(&self) -> Vec<InverterStatus> {
+        self.inverters.read().map(|inverters| inverters.clone()).unwrap_or_default()
+    }
+
+    /// True if any BMS outside of maintenance reports errors.
+    pub fn bms_errors(&self) -> bool {
+        self.bms.iter().any(|bms_data| {
+            bms_data.read(|data| {
+                !data.in_maintenance() && (data.error1.unwrap_or(0) != 0 || data.error2.unwrap_or(0) != 0)
+            })
+        })
+    }
+
+    /// True if the CAN data of any BMS is stale.
+    pub fn bms_stale(&self) -> bool {
+        self.bms.iter().any(|bms_data| bms_data.read(is_stale))
+    }
+
+    /// Signals the changes of the staleness of the BMS at `index` (configuration order).
+    pub async fn watch_stale(self: Arc<Self>, index: usize) -> Result<(), AppError> {
+        let Some(bms_data) = self.bms.get(index) else {
+            return Ok(());
+        };
+        let mut rx = bms_data.subscribe();
+        let mut stale = is_stale(&rx.borrow_and_update());
+        self.notify();
+        while rx.changed().await.is_ok() {
+            let now_stale = is_stale(&rx.borrow_and_update());
+            if now_stale != stale {
+                stale = now_stale;
+                log::info!("BMS dataset {}: CAN data {}", index + 1, if stale { "stale" } else { "fresh" });
+                self.notify();
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the inverters that are currently down.
+    pub fn down(&self) -> Vec<String> {
+        self.snapshot()
+            .into_iter()
+            .filter(|status| status.state == ConnectionState::Down)
+            .map(|status| status.name)
+            .collect()
+    }
+}
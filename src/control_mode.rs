@@ -0,0 +1,97 @@
+// src/control_mode.rs
+use crate::{
+    config::ControlModeConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// --- Control Mode ---
+/// Operating mode of the whole gateway, written via REG_CONTROL_MODE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlMode {
+    /// Normal operation
+    #[default]
+    Auto,
+    /// Only operator commands are accepted, the scheduler and the automatic restart are refused
+    Manual,
+    /// Fault signals raise the alarms but do not switch the inverters off, for pack tests.
+    /// Falls back to Auto after `ControlModeConfig::maintenance_timeout_minutes`.
+    Maintenance,
+}
+
+impl ControlMode {
+    /// Value of the mode in REG_CONTROL_MODE.
+    pub fn code(self) -> u16 {
+        match self {
+            ControlMode::Auto => 0,
+            ControlMode::Manual => 1,
+            ControlMode::Maintenance => 2,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(ControlMode::Auto),
+            1 => Some(ControlMode::Manual),
+            2 => Some(ControlMode::Maintenance),
+            _ => None,
+        }
+    }
+}
+
+// --- Control Mode Task ---
+/// Keeps the control mode of all datasets in sync: a mode written via the Modbus server
+/// of one BMS is adopted by the others. Ends the maintenance mode after its timeout.
+pub async fn task(config: ControlModeConfig, bms: Vec<SharedBmsData>) -> Result<(), AppError> {
+    // Auto at startup, a restarted task keeps the mode of the first dataset
+    let mut mode = bms.first().map(|bms_data| bms_data.read(BmsData::control_mode)).unwrap_or_default();
+    let mut since = Instant::now();
+    for bms_data in &bms {
+        bms_data.modify(|data| data.control_mode = Some(mode.code()));
+    }
+
+    loop {
+        sleep(POLL_INTERVAL).await;
+        // A dataset that differs from the current mode was written by a client
+        let requested = bms.iter().map(|bms_data| bms_data.read(BmsData::control_mode)).find(|&m| m != mode);
+        let next = match requested {
+            Some(requested) => {
+                log::warn!(target: "audit", "Control mode {:?} -> {:?} via Modbus.", mode, requested);
+                Some(requested)
+            }
+            None if mode == ControlMode::Maintenance && since.elapsed() >= config.maintenance_timeout() => {
+                log::warn!(
+                    target: "audit",
+                    "Maintenance mode timed out after {:?}, back to {:?}.",
+                    config.maintenance_timeout(),
+                    ControlMode::Auto
+                );
+                Some(ControlMode::Auto)
+            }
+            None => None,
+        };
+        let Some(next) = next else {
+            continue;
+        };
+        if next == ControlMode::Maintenance {
+            log::warn!(
+                target: "audit",
+                "Fault signals do not switch the inverters off for the next {:?}.",
+                config.maintenance_timeout()
+            );
+        }
+        mode = next;
+        since = Instant::now();
+        // The first dataset goes first: the fault consumers check it, and the CAN tasks
+        // raise the still active faults again once their dataset leaves Maintenance
+        for bms_data in &bms {
+            bms_data.modify(|data| data.control_mode = Some(mode.code()));
+        }
+    }
+}
@@ -1,8 +1,18 @@
 // src/data.rs
+use crate::control_mode::ControlMode;
+use crate::config::{
+    BmsField, ChecksumAlgorithm, FrameCheckConfig, InvalidValueConfig, InvalidValuePolicy, NativeMessage,
+    PlausibilityConfig, RegisterScaling, WordOrder,
+};
 use crate::error::AppError;
+use crate::version;
 // Use the trait and the concrete type likely used by socketcan::CANSocket::read_frame
-use socketcan::{CanFrame, Frame as CanFrameTrait, frame::AsPtr}; // Renamed Frame trait to avoid conflict
+use crate::can_sys::{AsPtr, CanFrame, Frame as CanFrameTrait}; // Renamed Frame trait to avoid conflict
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use tokio::sync::watch;
 use tokio_modbus::prelude::ExceptionCode; // For Modbus exceptions
 
 // --- Constants for Modbus Register Mapping ---
@@ -21,11 +31,242 @@ pub const REG_WARNING_2: u16 = 10;
 pub const REG_ERROR_1: u16 = 11;
 pub const REG_ERROR_2: u16 = 12;
 // Writeable registers
+// 1 while the BMS is administratively disabled for servicing (no faults are raised)
+pub const REG_MAINTENANCE: u16 = 20;
 pub const REG_ON: u16 = 21;
 pub const REG_QUIT: u16 = 22;
+// Confirms a pending OFF written to REG_ON (see ModbusServerConfig::off_confirm), reads as 0
+pub const REG_OFF_CONFIRM: u16 = 29;
+// Gateway status registers
+pub const REG_COMMAND_STATUS: u16 = 23;
+pub const REG_RULE_SEVERITY: u16 = 24;
+// 0 = fresh, 1 = stale, 2 = maintenance (see BmsData::in_maintenance)
+pub const REG_DATA_STALE: u16 = 25;
+pub const REG_REDUNDANCY_LOST: u16 = 26;
+// Bit n is set for the n-th configured inverter
+pub const REG_INVERTERS_CONNECTED: u16 = 27;
+pub const REG_INVERTERS_DOWN: u16 = 28;
+// Bit n is set while the n-th inverter did not confirm the last OFF (see InverterConfig::off_check)
+pub const REG_INVERTERS_OFF_UNCONFIRMED: u16 = 48;
+// Daily statistics (0.1 Ah / 0.1 kWh, UTC day)
+pub const REG_CHARGED_AH_TODAY: u16 = 30;
+pub const REG_DISCHARGED_AH_TODAY: u16 = 31;
+pub const REG_CHARGED_KWH_TODAY: u16 = 32;
+pub const REG_DISCHARGED_KWH_TODAY: u16 = 33;
+// Values derived from the BMS measurements (same units as the source registers)
+pub const REG_CELL_VOLTAGE_DELTA: u16 = 34;
+pub const REG_AVG_TEMPERATURE: u16 = 35;
+// Lowest min and highest max cell voltage of the last minute
+pub const REG_MIN_CELL_VOLTAGE_1MIN: u16 = 36;
+pub const REG_MAX_CELL_VOLTAGE_1MIN: u16 = 37;
+// Frame validation diagnostics (wrapping counters, see FrameCheckConfig)
+pub const REG_CORRUPTED_FRAMES: u16 = 38;
+pub const REG_SEQUENCE_GAPS: u16 = 39;
+// Gateway build information (see version.rs), hash and build time span two registers
+pub const REG_VERSION_MAJOR: u16 = 50;
+pub const REG_VERSION_MINOR: u16 = 51;
+pub const REG_VERSION_PATCH: u16 = 52;
+pub const REG_GIT_HASH: u16 = 53;
+pub const REG_GIT_HASH_WORD2: u16 = 54;
+pub const REG_BUILD_TIMESTAMP: u16 = 55;
+pub const REG_BUILD_TIMESTAMP_WORD2: u16 = 56;
+// Last accepted command (0 = none, see arbiter::command_code), its source
+// (see CommandSource::code) and Unix time, restored from the command history
+pub const REG_LAST_COMMAND: u16 = 57;
+pub const REG_LAST_COMMAND_SOURCE: u16 = 58;
+pub const REG_LAST_COMMAND_TIME: u16 = 59;
+pub const REG_LAST_COMMAND_TIME_WORD2: u16 = 60;
+/// Highest register of the BMS map, other register blocks must start above it
+pub const REG_LAST: u16 = REG_LAST_COMMAND_TIME_WORD2;
+// 32-bit values spanning two registers each (same units as the 16-bit registers)
+pub const REG_CURRENT_32: u16 = 40;
+pub const REG_CURRENT_32_WORD2: u16 = 41;
+pub const REG_TOTAL_VOLTAGE_32: u16 = 42;
+pub const REG_TOTAL_VOLTAGE_32_WORD2: u16 = 43;
+// Set while the storage manager has to delete recorded data (see storage.rs)
+pub const REG_STORAGE_PRUNED: u16 = 44;
+// 0 = idle, 1 = charging, 2 = discharging (see flow::FlowState)
+pub const REG_FLOW_STATE: u16 = 45;
+// Frames after which the data failed the plausibility checks, and whether it still does
+pub const REG_DECODER_SUSPICION: u16 = 46;
+pub const REG_DATA_IMPLAUSIBLE: u16 = 47;
+// Control mode of the gateway, 0 = auto, 1 = manual, 2 = maintenance (see ControlMode)
+pub const REG_CONTROL_MODE: u16 = 49;
+
+// Registers holding values measured by the BMS (as opposed to gateway state)
+fn is_bms_measurement(address: u16) -> bool {
+    (REG_MIN_CELL_VOLTAGE..=REG_ERROR_2).contains(&address)
+        || (REG_CELL_VOLTAGE_DELTA..=REG_MAX_CELL_VOLTAGE_1MIN).contains(&address)
+        || (REG_CURRENT_32..=REG_TOTAL_VOLTAGE_32_WORD2).contains(&address)
+}
+
+/// Word of a 32-bit value at `offset` (0 or 1) within its register pair.
+pub fn register_word(value: u32, offset: u16, word_order: WordOrder) -> u16 {
+    let high_word_first = word_order == WordOrder::BigEndian;
+    if (offset == 0) == high_word_first {
+        (value >> 16) as u16
+    } else {
+        value as u16
+    }
+}
+
+// Checksum over the data bytes of a frame
+fn checksum(algorithm: ChecksumAlgorithm, bytes: impl Iterator<Item = u8>) -> u8 {
+    match algorithm {
+        ChecksumAlgorithm::Sum8 => bytes.fold(0u8, |sum, byte| sum.wrapping_add(byte)),
+        ChecksumAlgorithm::Xor8 => bytes.fold(0u8, |sum, byte| sum ^ byte),
+        ChecksumAlgorithm::Crc8 => {
+            let crc = bytes.fold(0xFFu8, |mut crc, byte| {
+                crc ^= byte;
+                for _ in 0..8 {
+                    crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x1D } else { crc << 1 };
+                }
+                crc
+            });
+            crc ^ 0xFF
+        }
+    }
+}
+
+// Marker value for invalid registers
+pub const INVALID_REGISTER_VALUE: u16 = 0xFFFF;
+
+/// Reads a register for a Modbus response, applying the invalid value policy to
+/// BMS measurements that are not populated (or stale, if so configured).
+pub fn read_register(
+    data: Option<&BmsData>,
+    address: u16,
+    word_order: WordOrder,
+    scaling: &[RegisterScaling],
+    config: &InvalidValueConfig,
+) -> Result<u16, ExceptionCode> {
+    let value = data.and_then(|d| d.get_register(address, word_order, scaling));
+    if !is_bms_measurement(address) {
+        return Ok(value.unwrap_or(0));
+    }
+
+    let stale = data.is_none_or(|d| d.stale.unwrap_or(false));
+    let implausible = data.is_some_and(|d| d.implausible.unwrap_or(false));
+    match value {
+        Some(value) if !(stale && config.stale_is_invalid) && !(implausible && config.implausible_is_invalid) => {
+            Ok(value)
+        }
+        _ => match config.policy_for(address) {
+            InvalidValuePolicy::Zero => Ok(0),
+            InvalidValuePolicy::Marker => Ok(INVALID_REGISTER_VALUE),
+            InvalidValuePolicy::LastKnown => Ok(value.unwrap_or(INVALID_REGISTER_VALUE)),
+            InvalidValuePolicy::Exception => {
+                log::debug!("Register {} has no valid value, answering with exception.", address);
+                Err(ExceptionCode::GatewayTargetDevice)
+            }
+        },
+    }
+}
+
+// --- Register Map Self-Test ---
+// The fields behind the registers, written independently of get_register as the
+// reference the register map is checked against
+type FieldSetter = fn(&mut BmsData, u16);
+const FIELD_REGISTERS: &[(u16, &str, FieldSetter)] = &[
+    (REG_MIN_CELL_VOLTAGE, "min_cell_voltage", |d, v| d.min_cell_voltage = Some(v)),
+    (REG_MAX_CELL_VOLTAGE, "max_cell_voltage", |d, v| d.max_cell_voltage = Some(v)),
+    (REG_MIN_TEMPERATURE, "min_temperature", |d, v| d.min_temperature = Some(v as u8)),
+    (REG_MAX_TEMPERATURE, "max_temperature", |d, v| d.max_temperature = Some(v as u8)),
+    (REG_SOC, "soc", |d, v| d.soc = Some(v as u8)),
+    (REG_CURRENT, "current", |d, v| d.current = Some(v)),
+    (REG_TOTAL_VOLTAGE, "total_voltage", |d, v| d.total_voltage = Some(v)),
+    (REG_BMS_INFO, "info", |d, v| d.info = Some(v as u8)),
+    (REG_WARNING_1, "warning1", |d, v| d.warning1 = Some(v as u8)),
+    (REG_WARNING_2, "warning2", |d, v| d.warning2 = Some(v as u8)),
+    (REG_ERROR_1, "error1", |d, v| d.error1 = Some(v as u8)),
+    (REG_ERROR_2, "error2", |d, v| d.error2 = Some(v as u8)),
+    (REG_COMMAND_STATUS, "command_status", |d, v| d.command_status = Some(v)),
+    (REG_RULE_SEVERITY, "rule_severity", |d, v| d.rule_severity = Some(v)),
+    (REG_INVERTERS_CONNECTED, "inverters_connected", |d, v| d.inverters_connected = Some(v)),
+    (REG_INVERTERS_DOWN, "inverters_down", |d, v| d.inverters_down = Some(v)),
+    (REG_INVERTERS_OFF_UNCONFIRMED, "inverters_off_unconfirmed", |d, v| d.inverters_off_unconfirmed = Some(v)),
+    (REG_CHARGED_AH_TODAY, "charged_ah_today", |d, v| d.charged_ah_today = Some(v)),
+    (REG_DISCHARGED_AH_TODAY, "discharged_ah_today", |d, v| d.discharged_ah_today = Some(v)),
+    (REG_CHARGED_KWH_TODAY, "charged_kwh_today", |d, v| d.charged_kwh_today = Some(v)),
+    (REG_DISCHARGED_KWH_TODAY, "discharged_kwh_today", |d, v| d.discharged_kwh_today = Some(v)),
+    (REG_CELL_VOLTAGE_DELTA, "cell_voltage_delta", |d, v| d.cell_voltage_delta = Some(v)),
+    (REG_AVG_TEMPERATURE, "avg_temperature", |d, v| d.avg_temperature = Some(v)),
+    (REG_MIN_CELL_VOLTAGE_1MIN, "min_cell_voltage_1min", |d, v| d.min_cell_voltage_1min = Some(v)),
+    (REG_MAX_CELL_VOLTAGE_1MIN, "max_cell_voltage_1min", |d, v| d.max_cell_voltage_1min = Some(v)),
+    (REG_CORRUPTED_FRAMES, "corrupted_frames", |d, v| d.corrupted_frames = Some(v)),
+    (REG_SEQUENCE_GAPS, "sequence_gaps", |d, v| d.sequence_gaps = Some(v)),
+    (REG_LAST_COMMAND, "last_command", |d, v| d.last_command = Some(v)),
+    (REG_LAST_COMMAND_SOURCE, "last_command_source", |d, v| d.last_command_source = Some(v)),
+    (REG_FLOW_STATE, "flow_state", |d, v| d.flow_state = Some(v)),
+    (REG_DECODER_SUSPICION, "decoder_suspicion", |d, v| d.decoder_suspicion = Some(v)),
+];
+
+type WideFieldSetter = fn(&mut BmsData, u32);
+const WIDE_FIELD_REGISTERS: &[(u16, &str, WideFieldSetter)] = &[
+    (REG_CURRENT_32, "current_32", |d, v| d.current_32 = Some(v)),
+    (REG_TOTAL_VOLTAGE_32, "total_voltage_32", |d, v| d.total_voltage_32 = Some(v)),
+    (REG_LAST_COMMAND_TIME, "last_command_time", |d, v| d.last_command_time = Some(v)),
+];
+
+// Registers written via Modbus, with distinct values they accept and read back
+// (maintenance and the control mode are left out, switching them would be logged as a real request)
+const WRITABLE_REGISTERS: &[(u16, &str, u16)] = &[(REG_ON, "on", 0x21), (REG_QUIT, "quit", 0x22)];
+
+/// Round-trips a distinct value through every mapped register, the fields via
+/// `get_register` and the writable registers via `set_register` and back, so a register
+/// serving the wrong field is noticed at startup. Returns the mismatches as error.
+pub fn check_register_map() -> Result<String, String> {
+    let mut data = BmsData::default();
+    for (index, (_, _, set)) in FIELD_REGISTERS.iter().enumerate() {
+        set(&mut data, 0x10 + index as u16);
+    }
+    let wide_value = |index: usize| ((0x1000 + index as u32) << 16) | (0x2000 + index as u32);
+    for (index, (_, _, set)) in WIDE_FIELD_REGISTERS.iter().enumerate() {
+        set(&mut data, wide_value(index));
+    }
+    let mut mismatches = Vec::new();
+    for &(register, name, value) in WRITABLE_REGISTERS {
+        if let Err(e) = data.set_register(register, value) {
+            mismatches.push(format!("write of {} to register {} ({}) rejected: {:?}", value, register, name, e));
+        }
+    }
+
+    let mut checked = 0;
+    let mut expect = |register: u16, name: &str, word_order: WordOrder, expected: u16| {
+        checked += 1;
+        let actual = data.get_register(register, word_order, &[]);
+        if actual != Some(expected) {
+            mismatches.push(format!("register {} ({}) reads {:?}, expected {}", register, name, actual, expected));
+        }
+    };
+    for (index, &(register, name, _)) in FIELD_REGISTERS.iter().enumerate() {
+        expect(register, name, WordOrder::default(), 0x10 + index as u16);
+    }
+    for (index, &(register, name, _)) in WIDE_FIELD_REGISTERS.iter().enumerate() {
+        let value = wide_value(index);
+        for word_order in [WordOrder::BigEndian, WordOrder::LittleEndian] {
+            let (first, second) = match word_order {
+                WordOrder::BigEndian => ((value >> 16) as u16, value as u16),
+                WordOrder::LittleEndian => (value as u16, (value >> 16) as u16),
+            };
+            expect(register, name, word_order, first);
+            expect(register + 1, name, word_order, second);
+        }
+    }
+    for &(register, name, value) in WRITABLE_REGISTERS {
+        expect(register, name, WordOrder::default(), value);
+    }
+
+    if mismatches.is_empty() {
+        Ok(format!("{} register reads match", checked))
+    } else {
+        Err(mismatches.join("; "))
+    }
+}
 
 // --- BmsData Struct ---
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BmsData {
     // Raw integer values directly from CAN or scaled for Modbus
     pub min_cell_voltage: Option<u16>,
@@ -36,6 +277,9 @@ pub struct BmsData {
     pub soc: Option<u8>,
     pub current: Option<u16>, // Kept as u16 as per user code. Note: May not represent negative current easily.
     pub total_voltage: Option<u16>,
+    // 32-bit current (two's complement) and total voltage, sent by packs above the 16-bit range
+    pub current_32: Option<u32>,
+    pub total_voltage_32: Option<u32>,
     pub warning1: Option<u8>,
     pub warning2: Option<u8>,
     pub error1: Option<u8>,
@@ -45,17 +289,130 @@ pub struct BmsData {
     pub quit: Option<u8>,
     // Control freeze flag
     pub control_frozen: Option<bool>,
+    // Outcome of the last dispatched command (see arbiter::CommandStatus)
+    pub command_status: Option<u16>,
+    // Highest active threshold rule severity (see rules::Severity)
+    pub rule_severity: Option<u16>,
+    // Daily counters maintained by the statistics task
+    pub charged_ah_today: Option<u16>,
+    pub discharged_ah_today: Option<u16>,
+    pub charged_kwh_today: Option<u16>,
+    pub discharged_kwh_today: Option<u16>,
+    // Derived from the measurements on every update (see BmsData::update_derived)
+    pub cell_voltage_delta: Option<u16>,
+    pub avg_temperature: Option<u16>,
+    // Rolling extremes maintained by the statistics task
+    pub min_cell_voltage_1min: Option<u16>,
+    pub max_cell_voltage_1min: Option<u16>,
+    // Last accepted command, maintained by the arbiter
+    pub last_command: Option<u16>,
+    pub last_command_source: Option<u16>,
+    pub last_command_time: Option<u32>,
+    // Frames rejected by the checksum and rolling counter jumps (see FrameCheckConfig)
+    pub corrupted_frames: Option<u16>,
+    pub sequence_gaps: Option<u16>,
+    // Last rolling counter per CAN ID
+    #[serde(skip)]
+    pub last_counters: BTreeMap<u32, u8>,
+    // Set while the values are restored from a snapshot and no CAN frame was received yet
+    pub stale: Option<bool>,
+    // Set while one of the redundant CAN buses delivers no frames
+    pub redundancy_lost: Option<bool>,
+    // Set while the pack is being serviced, faults and stale data are not signalled
+    pub maintenance: Option<bool>,
+    // Control mode of the gateway (see ControlMode::code), kept in sync by the control mode task
+    pub control_mode: Option<u16>,
+    // Inverter connection states (see connections::ConnectionRegistry), one bit per inverter
+    pub inverters_connected: Option<u16>,
+    pub inverters_down: Option<u16>,
+    pub inverters_off_unconfirmed: Option<u16>,
+    // Set while the storage manager has to delete recorded data to stay within the quotas
+    pub storage_pruned: Option<bool>,
+    // Direction of the current with hysteresis (see flow.rs)
+    pub flow_state: Option<u16>,
+    // Frames after which the data failed the plausibility checks (see PlausibilityConfig)
+    pub decoder_suspicion: Option<u16>,
+    // Set while the data fails the plausibility checks
+    pub implausible: Option<bool>,
+    // Pack metadata read from the CANopen object dictionary at startup, by configured name
+    pub pack_metadata: BTreeMap<String, u32>,
 }
 
 impl BmsData {
-    // Function to update data from a CAN frame
+    /// True while the BMS is administratively disabled for servicing.
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.unwrap_or(false)
+    }
+
+    /// Control mode of the gateway, Auto until the control mode task set it.
+    pub fn control_mode(&self) -> ControlMode {
+        self.control_mode.and_then(ControlMode::from_code).unwrap_or_default()
+    }
+
+    /// Pack current in the raw unit of the BMS, positive while charging. The 32-bit value
+    /// takes precedence, the 16-bit one overflows on high voltage packs.
+    pub fn pack_current(&self) -> Option<i32> {
+        self.current_32
+            .map(|current| current as i32)
+            .or(self.current.map(|current| i32::from(current as i16)))
+    }
+
+    /// Total voltage of the pack in the raw unit of the BMS, the 32-bit value first.
+    pub fn pack_voltage(&self) -> Option<u32> {
+        self.total_voltage_32.or(self.total_voltage.map(u32::from))
+    }
+
+    // Validates checksum and rolling counter of a frame. Corrupted frames are counted and
+    // rejected, counter jumps (lost frames) are only counted.
+    fn check_frame(&mut self, can_id: u32, data: &[u8], check: &FrameCheckConfig) -> Result<(), AppError> {
+        if !check.applies_to(can_id) {
+            return Ok(());
+        }
+        if let Some(index) = check.checksum_byte {
+            let Some(&received) = data.get(index) else {
+                return Err(AppError::InvalidCanDataLength { can_id, expected: index + 1, actual: data.len() });
+            };
+            let bytes = data.iter().enumerate().filter(|(i, _)| *i != index).map(|(_, byte)| *byte);
+            let expected = checksum(check.checksum, bytes);
+            if received != expected {
+                self.corrupted_frames = Some(self.corrupted_frames.unwrap_or(0).wrapping_add(1));
+                return Err(AppError::CorruptedFrame {
+                    can_id,
+                    reason: format!("checksum {:#04X}, expected {:#04X}", received, expected),
+                });
+            }
+        }
+        if let Some(&byte) = check.counter_byte.and_then(|index| data.get(index)) {
+            let shift = check.counter_mask.trailing_zeros();
+            let counter = (byte & check.counter_mask) >> shift;
+            if let Some(last) = self.last_counters.insert(can_id, counter) {
+                let expected = last.wrapping_add(1) & (check.counter_mask >> shift);
+                if counter != expected {
+                    self.sequence_gaps = Some(self.sequence_gaps.unwrap_or(0).wrapping_add(1));
+                    log::warn!(
+                        "Sequence gap on CAN ID {:#X}: counter {}, expected {}",
+                        can_id, counter, expected
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Function to update data from a CAN frame, `message` is decided by the CAN ID scheme
     // Changed signature back to CANFrame for consistency with previous example and socketcan="2.0" CANSocket
-    pub fn update_from_frame(&mut self, frame: &CanFrame) -> Result<(), AppError> {
+    pub fn update_from_frame(
+        &mut self,
+        frame: &CanFrame,
+        message: NativeMessage,
+        check: &FrameCheckConfig,
+    ) -> Result<(), AppError> {
         let can_id = frame.raw_id(); // Use id() method
         let data = frame.as_bytes(); // Use data() method
+        self.check_frame(can_id, data, check)?;
 
-        match can_id {
-            0xB101 | 0xB102 => {
+        match message {
+            NativeMessage::Cells => {
                 // Message 1 processing
                 if data.len() != 8 {
                     return Err(AppError::InvalidCanDataLength {
@@ -78,7 +435,7 @@ impl BmsData {
                 self.soc = Some(data[7]);
                 log::debug!("Processed CAN ID {:#X} (Type 1)", can_id);
             }
-            0xB201 | 0xB202 => {
+            NativeMessage::Status => {
                 // Message 2 processing
                 if data.len() != 8 {
                     return Err(AppError::InvalidCanDataLength {
@@ -101,16 +458,124 @@ impl BmsData {
                 self.error2 = Some(data[7]);
                 log::debug!("Processed CAN ID {:#X} (Type 2)", can_id);
             }
-            _ => {
-                // This shouldn't happen if filters are set correctly, but good practice
-                return Err(AppError::UnsupportedCanId(can_id));
+            NativeMessage::Wide => {
+                // Message 3 processing (32-bit values of high voltage packs)
+                if data.len() != 8 {
+                    return Err(AppError::InvalidCanDataLength {
+                        can_id,
+                        expected: 8,
+                        actual: data.len(),
+                    });
+                }
+                // Current (data0..data3) - Little Endian, two's complement
+                self.current_32 = Some(u32::from_le_bytes(data[0..4].try_into().unwrap()));
+                // Total voltage (data4..data7) - Little Endian
+                self.total_voltage_32 = Some(u32::from_le_bytes(data[4..8].try_into().unwrap()));
+                log::debug!("Processed CAN ID {:#X} (Type 3)", can_id);
             }
         }
+        self.update_derived();
+        self.stale = Some(false);
         Ok(())
     }
 
+    /// Reasons why the decoded values cannot be right, empty if they are plausible.
+    pub fn implausibilities(&self, config: &PlausibilityConfig) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let (Some(min), Some(max)) = (self.min_cell_voltage, self.max_cell_voltage)
+            && min > max
+        {
+            reasons.push(format!("min. cell voltage {} above max. {}", min, max));
+        }
+        if let (Some(min), Some(max)) = (self.min_temperature, self.max_temperature)
+            && min > max
+        {
+            reasons.push(format!("min. temperature {} above max. {}", min, max));
+        }
+        if let Some(soc) = self.soc
+            && soc > config.max_soc
+        {
+            reasons.push(format!("SOC {} above {}", soc, config.max_soc));
+        }
+        let range = config.min_temperature..=config.max_temperature;
+        for (name, value) in [("min.", self.min_temperature), ("max.", self.max_temperature)] {
+            if let Some(value) = value
+                && !range.contains(&value)
+            {
+                reasons.push(format!("{} temperature {} outside {:?}", name, value, range));
+            }
+        }
+        reasons
+    }
+
+    // Recomputes the values derived from the cell voltages and temperatures
+    pub fn update_derived(&mut self) {
+        self.cell_voltage_delta = self
+            .max_cell_voltage
+            .zip(self.min_cell_voltage)
+            .map(|(max, min)| max.saturating_sub(min));
+        self.avg_temperature = self
+            .min_temperature
+            .zip(self.max_temperature)
+            .map(|(min, max)| (u16::from(min) + u16::from(max)).div_ceil(2));
+    }
+
+    // Stores a value decoded from a CANopen TPDO, truncated to the width of the field
+    pub fn set_field(&mut self, field: BmsField, value: u32) {
+        match field {
+            BmsField::MinCellVoltage => self.min_cell_voltage = Some(value as u16),
+            BmsField::MaxCellVoltage => self.max_cell_voltage = Some(value as u16),
+            BmsField::MinTemperature => self.min_temperature = Some(value as u8),
+            BmsField::MaxTemperature => self.max_temperature = Some(value as u8),
+            BmsField::Info => self.info = Some(value as u8),
+            BmsField::Soc => self.soc = Some(value as u8),
+            BmsField::Current => self.current = Some(value as u16),
+            BmsField::TotalVoltage => self.total_voltage = Some(value as u16),
+            BmsField::Current32 => self.current_32 = Some(value),
+            BmsField::TotalVoltage32 => self.total_voltage_32 = Some(value),
+            BmsField::Warning1 => self.warning1 = Some(value as u8),
+            BmsField::Warning2 => self.warning2 = Some(value as u8),
+            BmsField::Error1 => self.error1 = Some(value as u8),
+            BmsField::Error2 => self.error2 = Some(value as u8),
+        }
+        self.update_derived();
+    }
+
     // Function to get data for a specific Modbus register (READ)
-    pub fn get_register(&self, address: u16) -> Option<u16> {
+    // `word_order` selects which half of a 32-bit value each register of a pair holds,
+    // `scaling` converts the values to the units expected by the client
+    pub fn get_register(&self, address: u16, word_order: WordOrder, scaling: &[RegisterScaling]) -> Option<u16> {
+        let scaling_of = |register: u16| scaling.iter().find(|s| s.register == register);
+        // 32-bit values are converted as a whole before they are split into two registers
+        if let Some((first, value)) = self.wide_value(address) {
+            let value = value?;
+            let value = scaling_of(first).map_or(value, |s| s.apply_32(value));
+            return Some(register_word(value, address - first, word_order));
+        }
+        let value = self.register_value(address)?;
+        Some(scaling_of(address).map_or(value, |s| s.apply_16(value)))
+    }
+
+    // 32-bit value a register belongs to, with the address of the first register of the pair
+    fn wide_value(&self, address: u16) -> Option<(u16, Option<u32>)> {
+        match address {
+            REG_LAST_COMMAND_TIME | REG_LAST_COMMAND_TIME_WORD2 => {
+                Some((REG_LAST_COMMAND_TIME, self.last_command_time))
+            }
+            REG_CURRENT_32 | REG_CURRENT_32_WORD2 => Some((REG_CURRENT_32, self.current_32)),
+            REG_TOTAL_VOLTAGE_32 | REG_TOTAL_VOLTAGE_32_WORD2 => {
+                Some((REG_TOTAL_VOLTAGE_32, self.total_voltage_32))
+            }
+            REG_GIT_HASH | REG_GIT_HASH_WORD2 => Some((REG_GIT_HASH, Some(version::git_hash_value()))),
+            REG_BUILD_TIMESTAMP | REG_BUILD_TIMESTAMP_WORD2 => {
+                Some((REG_BUILD_TIMESTAMP, Some(version::build_info().build_timestamp as u32)))
+            }
+            _ => None,
+        }
+    }
+
+    // Value of a single 16-bit register, unconverted
+    fn register_value(&self, address: u16) -> Option<u16> {
         match address {
             REG_MIN_CELL_VOLTAGE => self.min_cell_voltage,
             REG_MAX_CELL_VOLTAGE => self.max_cell_voltage,
@@ -122,11 +587,40 @@ impl BmsData {
             REG_TOTAL_VOLTAGE => self.total_voltage,
             REG_WARNING_1 => self.warning1.map(u16::from),
             REG_WARNING_2 => self.warning2.map(u16::from),
-            REG_ERROR_1 => Some(self.info.map(u16::from).unwrap_or(0xFF)),
-            REG_ERROR_2 => Some(self.info.map(u16::from).unwrap_or(0xFF)),
+            REG_ERROR_1 => self.error1.map(u16::from),
+            REG_ERROR_2 => self.error2.map(u16::from),
             // Read back the values written via Modbus
             REG_ON => self.on.map(u16::from),
             REG_QUIT => self.quit.map(u16::from),
+            REG_COMMAND_STATUS => self.command_status,
+            REG_RULE_SEVERITY => self.rule_severity,
+            REG_DATA_STALE if self.in_maintenance() => Some(2),
+            REG_DATA_STALE => Some(u16::from(self.stale.unwrap_or(true))),
+            REG_MAINTENANCE => Some(u16::from(self.in_maintenance())),
+            REG_CONTROL_MODE => Some(self.control_mode().code()),
+            REG_REDUNDANCY_LOST => Some(u16::from(self.redundancy_lost.unwrap_or(false))),
+            REG_INVERTERS_CONNECTED => self.inverters_connected,
+            REG_INVERTERS_DOWN => self.inverters_down,
+            REG_INVERTERS_OFF_UNCONFIRMED => Some(self.inverters_off_unconfirmed.unwrap_or(0)),
+            REG_STORAGE_PRUNED => Some(u16::from(self.storage_pruned.unwrap_or(false))),
+            REG_FLOW_STATE => Some(self.flow_state.unwrap_or(0)),
+            REG_DECODER_SUSPICION => Some(self.decoder_suspicion.unwrap_or(0)),
+            REG_DATA_IMPLAUSIBLE => Some(u16::from(self.implausible.unwrap_or(false))),
+            REG_CHARGED_AH_TODAY => self.charged_ah_today,
+            REG_DISCHARGED_AH_TODAY => self.discharged_ah_today,
+            REG_CHARGED_KWH_TODAY => self.charged_kwh_today,
+            REG_DISCHARGED_KWH_TODAY => self.discharged_kwh_today,
+            REG_CELL_VOLTAGE_DELTA => self.cell_voltage_delta,
+            REG_AVG_TEMPERATURE => self.avg_temperature,
+            REG_MIN_CELL_VOLTAGE_1MIN => self.min_cell_voltage_1min,
+            REG_MAX_CELL_VOLTAGE_1MIN => self.max_cell_voltage_1min,
+            REG_LAST_COMMAND => self.last_command,
+            REG_LAST_COMMAND_SOURCE => self.last_command_source,
+            REG_CORRUPTED_FRAMES => Some(self.corrupted_frames.unwrap_or(0)),
+            REG_SEQUENCE_GAPS => Some(self.sequence_gaps.unwrap_or(0)),
+            REG_VERSION_MAJOR..=REG_VERSION_PATCH => {
+                Some(version::semver()[usize::from(address - REG_VERSION_MAJOR)])
+            }
             _ => None, // Address out of defined range or not readable
         }
     }
@@ -138,7 +632,8 @@ impl BmsData {
                 // Validate if the u16 value fits into u8
                 match u8::try_from(value) {
                     Ok(val_u8) => {
-                        if !self.control_frozen.unwrap_or(false) {
+                        // OFF is never held back, only ON is debounced
+                        if val_u8 == 0 || !self.control_frozen.unwrap_or(false) {
                             log::info!("Set REG_ON (addr {}) to {}", address, val_u8);
                             self.on = Some(val_u8);
                         } else {
@@ -161,13 +656,9 @@ impl BmsData {
                 // Validate if the u16 value fits into u8
                 match u8::try_from(value) {
                     Ok(val_u8) => {
-                        if !self.control_frozen.unwrap_or(false) {
-                            log::info!("Set REG_QUIT (addr {}) to {}", address, val_u8);
-                            self.on = Some(val_u8);
-                        } else {
-                            log::warn!("Attempt to set frozen REG_QUIT (addr {}) rejected", address);
-                        }
-
+                        // QUIT is safety relevant and not subject to the lockout
+                        log::info!("Set REG_QUIT (addr {}) to {}", address, val_u8);
+                        self.quit = Some(val_u8);
                         Ok(())
                     }
                     Err(_) => {
@@ -180,12 +671,57 @@ impl BmsData {
                     }
                 }
             }
+            REG_MAINTENANCE => match value {
+                0 | 1 => {
+                    log::warn!(
+                        "Maintenance mode {} via Modbus (addr {})",
+                        if value == 1 { "enabled" } else { "disabled" },
+                        address
+                    );
+                    self.maintenance = Some(value == 1);
+                    Ok(())
+                }
+                _ => {
+                    log::warn!(
+                        "Modbus write to REG_MAINTENANCE (addr {}): Value {} is neither 0 nor 1.",
+                        address,
+                        value
+                    );
+                    Err(ExceptionCode::IllegalDataValue)
+                }
+            },
+            REG_CONTROL_MODE => match ControlMode::from_code(value) {
+                Some(mode) => {
+                    log::warn!("Control mode {:?} requested via Modbus (addr {})", mode, address);
+                    self.control_mode = Some(value);
+                    Ok(())
+                }
+                None => {
+                    log::warn!(
+                        "Modbus write to REG_CONTROL_MODE (addr {}): Value {} is not a control mode.",
+                        address,
+                        value
+                    );
+                    Err(ExceptionCode::IllegalDataValue)
+                }
+            },
             // Add other writable registers here if needed in the future
 
             // If the address is known but not writable
             REG_MIN_CELL_VOLTAGE | REG_MAX_CELL_VOLTAGE | REG_MIN_TEMPERATURE
             | REG_MAX_TEMPERATURE | REG_BMS_INFO | REG_SOC | REG_CURRENT | REG_TOTAL_VOLTAGE
-            | REG_WARNING_1 | REG_WARNING_2 | REG_ERROR_1 | REG_ERROR_2 => {
+            | REG_WARNING_1 | REG_WARNING_2 | REG_ERROR_1 | REG_ERROR_2 | REG_COMMAND_STATUS
+            | REG_RULE_SEVERITY | REG_CHARGED_AH_TODAY | REG_DISCHARGED_AH_TODAY
+            | REG_CHARGED_KWH_TODAY | REG_DISCHARGED_KWH_TODAY | REG_CELL_VOLTAGE_DELTA
+            | REG_AVG_TEMPERATURE | REG_MIN_CELL_VOLTAGE_1MIN | REG_MAX_CELL_VOLTAGE_1MIN
+            | REG_CORRUPTED_FRAMES | REG_SEQUENCE_GAPS | REG_VERSION_MAJOR | REG_VERSION_MINOR
+            | REG_VERSION_PATCH | REG_GIT_HASH | REG_GIT_HASH_WORD2 | REG_BUILD_TIMESTAMP
+            | REG_BUILD_TIMESTAMP_WORD2 | REG_LAST_COMMAND | REG_LAST_COMMAND_SOURCE | REG_LAST_COMMAND_TIME
+            | REG_LAST_COMMAND_TIME_WORD2
+            | REG_DATA_STALE | REG_REDUNDANCY_LOST | REG_STORAGE_PRUNED | REG_FLOW_STATE
+            | REG_DECODER_SUSPICION | REG_DATA_IMPLAUSIBLE
+            | REG_INVERTERS_CONNECTED | REG_INVERTERS_DOWN | REG_INVERTERS_OFF_UNCONFIRMED | REG_CURRENT_32 | REG_CURRENT_32_WORD2 | REG_TOTAL_VOLTAGE_32
+            | REG_TOTAL_VOLTAGE_32_WORD2 => {
                 log::warn!("Attempted write to read-only register address {}", address);
                 Err(ExceptionCode::IllegalFunction) // Or IllegalDataAddress
             }
@@ -198,3 +734,119 @@ impl BmsData {
         }
     }
 }
+
+// --- Shared State ---
+/// The dataset of one BMS, shared by all tasks. Writers publish their changes atomically,
+/// readers see a consistent version without holding off the writers for longer than the
+/// read itself, and consumers can await changes via `subscribe` or `changes`.
+#[derive(Debug, Clone)]
+pub struct SharedBmsData {
+    tx: Arc<watch::Sender<BmsData>>,
+}
+
+impl SharedBmsData {
+    pub fn new(data: BmsData) -> Self {
+        Self { tx: Arc::new(watch::Sender::new(data)) }
+    }
+
+    /// Copy of the current dataset.
+    pub fn get(&self) -> BmsData {
+        self.tx.borrow().clone()
+    }
+
+    /// Evaluates `f` on the current dataset. Keep `f` short, writers wait for it.
+    pub fn read<R>(&self, f: impl FnOnce(&BmsData) -> R) -> R {
+        f(&self.tx.borrow())
+    }
+
+    /// Changes the dataset and notifies the subscribers.
+    pub fn modify<R>(&self, f: impl FnOnce(&mut BmsData) -> R) -> R {
+        let mut result = None;
+        self.tx.send_modify(|data| result = Some(f(data)));
+        result.expect("send_modify runs the closure exactly once")
+    }
+
+    /// Receiver that is notified on every change.
+    pub fn subscribe(&self) -> watch::Receiver<BmsData> {
+        self.tx.subscribe()
+    }
+
+    /// Receiver of the changes of the fields picked by `select` (e.g. a tuple of
+    /// fields), starting from the current dataset.
+    pub fn changes<T, F>(&self, select: F) -> BmsChanges<T, F>
+    where
+        T: PartialEq + Clone,
+        F: Fn(&BmsData) -> T,
+    {
+        let rx = self.tx.subscribe();
+        let last = select(&rx.borrow());
+        BmsChanges { rx, select, last }
+    }
+}
+
+// --- Change Notifications ---
+/// The fields a consumer selected, before and after a change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange<T> {
+    pub old: T,
+    pub new: T,
+}
+
+/// Yields the changes of the fields a consumer uses, so it reacts to them instead of
+/// polling the whole dataset. The fields are compared as they are, nothing is serialized.
+pub struct BmsChanges<T, F> {
+    rx: watch::Receiver<BmsData>,
+    select: F,
+    // Selected fields as of the last call of `next`
+    last: T,
+}
+
+impl<T, F> BmsChanges<T, F>
+where
+    T: PartialEq + Clone,
+    F: Fn(&BmsData) -> T,
+{
+    /// Waits until the selected fields differ from the last call and returns them.
+    /// Several changes in between are merged, `old` is the value at the last call. None
+    /// once the dataset is gone. Cancel safe, so it can be used with a timeout.
+    pub async fn next(&mut self) -> Option<FieldChange<T>> {
+        loop {
+            self.rx.changed().await.ok()?;
+            let current = (self.select)(&self.rx.borrow_and_update());
+            // A modify does not have to change the selected fields
+            if current != self.last {
+                let old = std::mem::replace(&mut self.last, current.clone());
+                return Some(FieldChange { old, new: current });
+            }
+        }
+    }
+}
+
+impl Default for SharedBmsData {
+    fn default() -> Self {
+        Self::new(BmsData::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_map_round_trips() {
+        if let Err(e) = check_register_map() {
+            panic!("register map: {}", e);
+        }
+    }
+
+    #[test]
+    fn pack_values_prefer_the_32_bit_fields() {
+        let mut data = BmsData { current: Some(0xFFFF), total_voltage: Some(500), ..BmsData::default() };
+        assert_eq!(data.pack_current(), Some(-1));
+        assert_eq!(data.pack_voltage(), Some(500));
+        data.current_32 = Some(-70_000i32 as u32);
+        data.total_voltage_32 = Some(80_000);
+        assert_eq!(data.pack_current(), Some(-70_000));
+        assert_eq!(data.pack_voltage(), Some(80_000));
+    }
+}
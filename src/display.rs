@@ -0,0 +1,221 @@
+// src/display.rs
+use crate::{
+    config::{DisplayConfig, Language},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    i18n::{self, Text},
+    rules::Severity,
+};
+#[cfg(feature = "rppal")]
+use rppal::i2c::I2c;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Stand-in of builds without rppal, the display cannot be opened
+#[cfg(not(feature = "rppal"))]
+struct I2c;
+
+#[cfg(not(feature = "rppal"))]
+impl I2c {
+    fn with_bus(_bus: u8) -> Result<Self, AppError> {
+        Err(AppError::I2cUnavailable)
+    }
+
+    fn set_slave_address(&mut self, _address: u16) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    fn write(&mut self, _buffer: &[u8]) -> Result<usize, AppError> {
+        Ok(0)
+    }
+}
+
+// --- HD44780 over a PCF8574 I2C backpack ---
+// Expander bits: P0 = RS, P1 = RW, P2 = E, P3 = backlight, P4-P7 = D4-D7
+const LCD_RS: u8 = 0x01;
+const LCD_ENABLE: u8 = 0x04;
+const LCD_BACKLIGHT: u8 = 0x08;
+
+const CMD_CLEAR: u8 = 0x01;
+const CMD_ENTRY_MODE_INCREMENT: u8 = 0x06;
+const CMD_DISPLAY_ON: u8 = 0x0C;
+const CMD_FUNCTION_4BIT_2LINE: u8 = 0x28;
+const CMD_SET_DDRAM: u8 = 0x80;
+// DDRAM address of the first character of each line (20x4 layout, 16x2 uses the first two)
+const LINE_OFFSETS: [u8; 4] = [0x00, 0x40, 0x14, 0x54];
+
+// Execution time of the clear command
+const CLEAR_DELAY: Duration = Duration::from_millis(2);
+
+struct Lcd {
+    i2c: I2c,
+    columns: usize,
+    rows: usize,
+}
+
+impl Lcd {
+    fn open(config: &DisplayConfig) -> Result<Self, AppError> {
+        let mut i2c = I2c::with_bus(config.i2c_bus)?;
+        i2c.set_slave_address(config.address)?;
+        let mut lcd = Self {
+            i2c,
+            columns: usize::from(config.columns),
+            rows: usize::from(config.rows).min(LINE_OFFSETS.len()),
+        };
+        // Reset into 4-bit mode regardless of the state the controller is in
+        for nibble in [0x03, 0x03, 0x03, 0x02] {
+            lcd.write_nibble(nibble, 0)?;
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        for command in [CMD_FUNCTION_4BIT_2LINE, CMD_DISPLAY_ON, CMD_ENTRY_MODE_INCREMENT] {
+            lcd.command(command)?;
+        }
+        lcd.command(CMD_CLEAR)?;
+        std::thread::sleep(CLEAR_DELAY);
+        Ok(lcd)
+    }
+
+    // Latches the nibble with a falling edge of E
+    fn write_nibble(&mut self, nibble: u8, mode: u8) -> Result<(), AppError> {
+        let data = (nibble << 4) | mode | LCD_BACKLIGHT;
+        self.i2c.write(&[data | LCD_ENABLE, data])?;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8, mode: u8) -> Result<(), AppError> {
+        self.write_nibble(byte >> 4, mode)?;
+        self.write_nibble(byte & 0x0F, mode)
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), AppError> {
+        self.write_byte(command, 0)
+    }
+
+    // Writes all lines, padded so no characters of the previous page remain
+    fn show(&mut self, lines: &[String]) -> Result<(), AppError> {
+        for (row, offset) in LINE_OFFSETS.iter().enumerate().take(self.rows) {
+            self.command(CMD_SET_DDRAM | offset)?;
+            let line = lines.get(row).map(String::as_str).unwrap_or("");
+            // The character ROM only has ASCII in common between the variants
+            let text: Vec<u8> = line
+                .chars()
+                .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+                .chain(std::iter::repeat(b' '))
+                .take(self.columns)
+                .collect();
+            for byte in text {
+                self.write_byte(byte, LCD_RS)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// --- Pages ---
+/// Name of a rule severity as shown to operators.
+pub fn severity_name(value: Option<u16>, language: Language) -> &'static str {
+    let text = match value {
+        Some(v) if v == Severity::Trip as u16 => Text::Trip,
+        Some(v) if v == Severity::Derate as u16 => Text::Derate,
+        Some(v) if v == Severity::Warning as u16 => Text::Warning,
+        _ => Text::Ok,
+    };
+    i18n::text(language, text)
+}
+
+/// Pack voltage in V (see `BmsData::pack_voltage`).
+pub fn pack_voltage(data: &BmsData, config: &DisplayConfig) -> Option<f64> {
+    data.pack_voltage().map(|voltage| f64::from(voltage) * config.voltage_scale)
+}
+
+/// Pack current in A (see `BmsData::pack_current`).
+pub fn pack_current(data: &BmsData, config: &DisplayConfig) -> Option<f64> {
+    data.pack_current().map(|current| f64::from(current) * config.current_scale)
+}
+
+// Lines of the measurement page and the state page of one BMS
+fn pages(bms_id: u8, data: &BmsData, config: &DisplayConfig, language: Language) -> [Vec<String>; 2] {
+    let format_value = |value: Option<f64>, unit: &str| match value {
+        Some(value) => format!("{:.1}{}", value, unit),
+        None => format!("--{}", unit),
+    };
+    let voltage = pack_voltage(data, config);
+    let current = pack_current(data, config);
+    let soc = data.soc.map(|soc| format!("{}%", soc)).unwrap_or_else(|| "--%".to_string());
+    let stale = if data.in_maintenance() {
+        format!(" {}", i18n::text(language, Text::MaintenanceShort))
+    } else if data.stale.unwrap_or(true) {
+        format!(" {}", i18n::text(language, Text::Stale))
+    } else {
+        String::new()
+    };
+
+    let hex = |byte: Option<u8>| byte.map(|b| format!("{:02X}", b)).unwrap_or_else(|| "--".to_string());
+    [
+        vec![
+            format!("BMS{} SOC {}{}", bms_id, soc, stale),
+            format!("{} {}", format_value(voltage, "V"), format_value(current, "A")),
+        ],
+        vec![
+            format!("BMS{} {}", bms_id, severity_name(data.rule_severity, language)),
+            format!(
+                "E{}{} W{}{}",
+                hex(data.error1),
+                hex(data.error2),
+                hex(data.warning1),
+                hex(data.warning2)
+            ),
+        ],
+    ]
+}
+
+// --- Display Task ---
+/// Cycles through the measurement and state pages of every BMS on a character LCD.
+/// Displays with four rows show both pages of a BMS at once. Steps received on
+/// `page_rx` (rotary encoder) move to another page right away.
+pub async fn task(
+    config: DisplayConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    mut page_rx: tokio::sync::mpsc::UnboundedReceiver<i8>,
+    language: Language,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting display on I2C bus {} address {:#04X} ({}x{})",
+        config.i2c_bus,
+        config.address,
+        config.columns,
+        config.rows
+    );
+    let mut lcd = Lcd::open(&config)?;
+    let mut ticker = interval(config.page_interval());
+    // The first tick moves to page 0
+    let mut page = -1isize;
+
+    loop {
+        let step = tokio::select! {
+            _ = ticker.tick() => 1,
+            Some(step) = page_rx.recv() => {
+                // Show the selected page for a full interval
+                ticker.reset();
+                isize::from(step)
+            }
+        };
+        let mut screens = Vec::new();
+        for (bms_id, bms_data) in &bms {
+            let [measurements, state] = bms_data.read(|data| pages(*bms_id, data, &config, language));
+            if lcd.rows >= 4 {
+                screens.push([measurements, state].concat());
+            } else {
+                screens.push(measurements);
+                screens.push(state);
+            }
+        }
+        if screens.is_empty() {
+            continue;
+        }
+        page = (page + step).rem_euclid(screens.len() as isize);
+        if let Err(e) = lcd.show(&screens[page as usize]) {
+            log::error!("Display: Failed to write page {}: {}", page, e);
+        }
+    }
+}
@@ -1,13 +1,20 @@
 // src/error.rs
 use thiserror::Error;
-use std::io;
+use std::{io, net::SocketAddr};
 use tokio::sync::{broadcast, mpsc}; // For channel send errors
 
 #[derive(Error, Debug)]
+#[allow(dead_code)] // Some variants are reserved for future use
 pub enum AppError {
     #[error("CAN socket error: {0}")]
     CanSocket(#[from] io::Error), // Covers socketcan I/O errors
 
+    #[error("Cannot open CAN interface {interface}: {source}")]
+    CanInit { interface: String, source: io::Error },
+
+    #[error("Cannot bind {addr}: {source}")]
+    Bind { addr: SocketAddr, source: io::Error },
+
     #[error("Modbus IO error: {0}")]
     ModbusIo(io::Error), // Separate Modbus IO errors if needed
 
@@ -21,19 +28,36 @@ pub enum AppError {
     #[error("Unsupported CAN ID: {0:#X}")]
     UnsupportedCanId(u32),
 
+    #[error("Corrupted CAN frame {can_id:#X}: {reason}")]
+    CorruptedFrame { can_id: u32, reason: String },
+
     #[error("Lock is poisoned")]
     LockPoisoned,
 
+    #[error("Runtime error: {0}")]
+    Runtime(io::Error),
+
     #[error("Task join error: {0}")]
     JoinError(#[from] tokio::task::JoinError),
 
     // --- New Errors ---
+    #[cfg(feature = "rppal")]
     #[error("GPIO error: {0}")]
     Gpio(#[from] rppal::gpio::Error),
 
+    #[cfg(feature = "rppal")]
+    #[error("I2C error: {0}")]
+    I2c(#[from] rppal::i2c::Error),
+
+    #[error("GPIO {pin}: {source}")]
+    GpioPin { pin: u32, source: io::Error },
+
     #[error("GPIO unavailable on this platform")]
     GpioUnavailable, // For non-Pi builds
 
+    #[error("I2C unavailable on this platform")]
+    I2cUnavailable,
+
     #[error("Broadcast channel send error: {0}")]
     BroadcastSendError(#[from] broadcast::error::SendError<crate::SystemCommand>), // Use specific command type
 
@@ -52,11 +76,50 @@ pub enum AppError {
     #[error("Channel receive error: {0}")]
     ReceiveError(String),
 
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Persistence error: {0}")]
+    Persist(String),
+
+    #[error("gRPC server error: {0}")]
+    Grpc(String),
+
+    #[error("Startup self-test failed: {0}")]
+    SelfTest(String),
+
     // Add other specific error types as needed
     #[error("Unknown error")]
     _Unknown,
 }
 
+// --- Exit Codes ---
+// Exit status of the process by termination reason, so systemd and the deployment scripts
+// can react to it (e.g. RestartPreventExitStatus=10 11 to stay down after QUIT and on
+// configuration errors)
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_QUIT: u8 = 10;
+pub const EXIT_CONFIG: u8 = 11;
+pub const EXIT_CAN_INIT: u8 = 12;
+pub const EXIT_BIND: u8 = 13;
+
+impl AppError {
+    /// Exit status of the process when it terminates with this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Config(_) => EXIT_CONFIG,
+            AppError::CanInit { .. } => EXIT_CAN_INIT,
+            AppError::Bind { .. } => EXIT_BIND,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// Errors of a task that a restart cannot fix, they stop the gateway.
+    pub fn is_fatal(&self) -> bool {
+        self.exit_code() != EXIT_FAILURE
+    }
+}
+
 // Implement conversion from PoisonError if needed, simplified here
 impl<T> From<std::sync::PoisonError<T>> for AppError {
     fn from(_: std::sync::PoisonError<T>) -> Self {
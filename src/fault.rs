@@ -0,0 +1,221 @@
+// src/fault.rs
+use crate::{control_mode::ControlMode, rules::Rule};
+use std::time::Duration;
+
+// Edges are rare, a full queue means a consumer is stuck
+const FAULT_QUEUE_LEN: usize = 64;
+// Longest wait for room in a full queue before a raised fault is dropped
+const RAISED_SEND_TIMEOUT: Duration = Duration::from_millis(200);
+
+// --- Fault Events ---
+/// Condition that raises the fault signal, with the BMS it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultCondition {
+    /// Error bytes of the BMS are not zero
+    ErrorBits(u8),
+    /// A threshold rule of the BMS tripped
+    RuleTrip(u8, Rule),
+    /// The CANopen heartbeat of the BMS is missing
+    HeartbeatLost(u8),
+    /// The BMS sent no native frames within the data timeout
+    DataTimeout(u8),
+    /// An OFF frame did not go out on the bus
+    OffNotSent,
+}
+
+/// Edge of a fault condition. Only `Raised` switches the outputs off, `Cleared` is
+/// informational: the alarm stays latched until QUIT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultEvent {
+    Raised(FaultCondition),
+    Cleared(FaultCondition),
+}
+
+/// Sending side of the fault events. Every consumer has its own bounded queue and gets
+/// every event: an edge is sent only once, a shared queue would hand it to one of them.
+#[derive(Debug, Clone)]
+pub struct FaultSender {
+    txs: Vec<crossbeam_channel::Sender<FaultEvent>>,
+}
+
+impl FaultSender {
+    /// Sends `event` to every consumer. A raised fault switches the outputs off, for it
+    /// the sender (a CAN RX task) waits up to `RAISED_SEND_TIMEOUT` for room in a full
+    /// queue. A cleared fault is only informational, a full queue drops it right away.
+    pub fn send(&self, event: FaultEvent) {
+        for tx in &self.txs {
+            let wait = match event {
+                FaultEvent::Raised(_) => RAISED_SEND_TIMEOUT,
+                FaultEvent::Cleared(_) => Duration::ZERO,
+            };
+            match tx.send_timeout(event, wait) {
+                Ok(()) => {}
+                Err(crossbeam_channel::SendTimeoutError::Timeout(event)) => {
+                    log::error!("Fault channel full, dropping {:?}.", event);
+                }
+                // A consumer that stopped does not keep the others from getting the event
+                Err(crossbeam_channel::SendTimeoutError::Disconnected(event)) => {
+                    log::debug!("Fault channel closed, cannot send {:?}.", event);
+                }
+            }
+        }
+    }
+}
+
+/// Fault channel with one receiver per consumer, each receives every event.
+pub fn channel(consumers: usize) -> (FaultSender, Vec<crossbeam_channel::Receiver<FaultEvent>>) {
+    let (txs, rxs) = (0..consumers).map(|_| crossbeam_channel::bounded(FAULT_QUEUE_LEN)).unzip();
+    (FaultSender { txs }, rxs)
+}
+
+// --- Fault Signal ---
+/// Turns the level of the fault conditions of a task into edges: a condition that stays
+/// active (error bits repeated in every status frame) is sent once, when it becomes
+/// active, and once more when it clears. The active conditions are raised again when
+/// the maintenance mode ends, while it lasted they did not switch off.
+#[derive(Debug)]
+pub struct FaultSignal {
+    tx: FaultSender,
+    active: Vec<FaultCondition>,
+    mode: ControlMode,
+}
+
+impl FaultSignal {
+    pub fn new(tx: FaultSender) -> Self {
+        Self { tx, active: Vec::new(), mode: ControlMode::default() }
+    }
+
+    /// Follows the control mode, call it regularly with the mode of the dataset.
+    pub fn follow_control_mode(&mut self, mode: ControlMode) {
+        let leaving_maintenance = self.mode == ControlMode::Maintenance && mode != ControlMode::Maintenance;
+        self.mode = mode;
+        if !leaving_maintenance {
+            return;
+        }
+        for condition in &self.active {
+            log::warn!(target: "audit", "Fault {:?} still active after the maintenance mode, raising it again.", condition);
+            self.tx.send(FaultEvent::Raised(*condition));
+        }
+    }
+
+    /// Records the current level of `condition`, sends an event if it changed.
+    pub fn set(&mut self, condition: FaultCondition, active: bool) {
+        let position = self.active.iter().position(|&known| known == condition);
+        match (active, position) {
+            (true, None) => {
+                self.active.push(condition);
+                self.tx.send(FaultEvent::Raised(condition));
+            }
+            (false, Some(index)) => {
+                self.active.remove(index);
+                log::info!("Fault condition {:?} cleared.", condition);
+                self.tx.send(FaultEvent::Cleared(condition));
+            }
+            _ => {}
+        }
+    }
+
+    /// Clears all conditions of `bms_id`, e.g. while it is in maintenance.
+    pub fn clear_bms(&mut self, bms_id: u8) {
+        let conditions: Vec<FaultCondition> = self
+            .active
+            .iter()
+            .copied()
+            .filter(|condition| condition.bms_id() == Some(bms_id))
+            .collect();
+        for condition in conditions {
+            self.set(condition, false);
+        }
+    }
+}
+
+impl FaultCondition {
+    /// The BMS of the condition, None for conditions of the gateway itself.
+    pub fn bms_id(&self) -> Option<u8> {
+        match self {
+            FaultCondition::ErrorBits(bms_id)
+            | FaultCondition::RuleTrip(bms_id, _)
+            | FaultCondition::HeartbeatLost(bms_id)
+            | FaultCondition::DataTimeout(bms_id) => Some(*bms_id),
+            FaultCondition::OffNotSent => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(rx: &crossbeam_channel::Receiver<FaultEvent>) -> Vec<FaultEvent> {
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn a_lasting_condition_is_sent_once() {
+        let (tx, rxs) = channel(1);
+        let mut signal = FaultSignal::new(tx);
+        let condition = FaultCondition::ErrorBits(1);
+        signal.set(condition, true);
+        signal.set(condition, true);
+        signal.set(condition, false);
+        signal.set(condition, false);
+        assert_eq!(events(&rxs[0]), vec![FaultEvent::Raised(condition), FaultEvent::Cleared(condition)]);
+    }
+
+    #[test]
+    fn every_consumer_gets_every_event() {
+        let (tx, rxs) = channel(2);
+        let mut signal = FaultSignal::new(tx);
+        signal.set(FaultCondition::OffNotSent, true);
+        for rx in &rxs {
+            assert_eq!(events(rx), vec![FaultEvent::Raised(FaultCondition::OffNotSent)]);
+        }
+    }
+
+    #[test]
+    fn a_raised_fault_waits_for_room_in_a_full_queue() {
+        let (tx, rxs) = channel(1);
+        let cleared = FaultEvent::Cleared(FaultCondition::OffNotSent);
+        for _ in 0..FAULT_QUEUE_LEN {
+            tx.send(cleared);
+        }
+        // Dropped, the queue is full
+        tx.send(cleared);
+        assert_eq!(rxs[0].len(), FAULT_QUEUE_LEN);
+
+        let rx = rxs[0].clone();
+        let consumer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            rx.recv().expect("queue is open")
+        });
+        tx.send(FaultEvent::Raised(FaultCondition::OffNotSent));
+        assert_eq!(consumer.join().expect("consumer runs"), cleared);
+        assert_eq!(events(&rxs[0]).last(), Some(&FaultEvent::Raised(FaultCondition::OffNotSent)));
+    }
+
+    #[test]
+    fn clear_bms_clears_only_its_conditions() {
+        let (tx, rxs) = channel(1);
+        let mut signal = FaultSignal::new(tx);
+        signal.set(FaultCondition::ErrorBits(1), true);
+        signal.set(FaultCondition::HeartbeatLost(2), true);
+        signal.set(FaultCondition::OffNotSent, true);
+        events(&rxs[0]);
+        signal.clear_bms(1);
+        assert_eq!(events(&rxs[0]), vec![FaultEvent::Cleared(FaultCondition::ErrorBits(1))]);
+    }
+
+    #[test]
+    fn active_conditions_are_raised_again_after_maintenance() {
+        let (tx, rxs) = channel(1);
+        let mut signal = FaultSignal::new(tx);
+        signal.follow_control_mode(ControlMode::Maintenance);
+        signal.set(FaultCondition::HeartbeatLost(1), true);
+        signal.follow_control_mode(ControlMode::Maintenance);
+        assert_eq!(events(&rxs[0]), vec![FaultEvent::Raised(FaultCondition::HeartbeatLost(1))]);
+        signal.follow_control_mode(ControlMode::Auto);
+        assert_eq!(events(&rxs[0]), vec![FaultEvent::Raised(FaultCondition::HeartbeatLost(1))]);
+        signal.follow_control_mode(ControlMode::Manual);
+        assert!(events(&rxs[0]).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+// src/flags.rs
+use crate::{config::FlagsConfig, data::BmsData};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// --- Status Bytes ---
+/// The BMS bytes that are bitfields. Their bits are discrete inputs
+/// `8 * position + bit`, in the order of `StatusByte::ALL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusByte {
+    Info,
+    Warning1,
+    Warning2,
+    Error1,
+    Error2,
+}
+
+impl StatusByte {
+    pub const ALL: [StatusByte; 5] = [
+        StatusByte::Info,
+        StatusByte::Warning1,
+        StatusByte::Warning2,
+        StatusByte::Error1,
+        StatusByte::Error2,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatusByte::Info => "info",
+            StatusByte::Warning1 => "warning1",
+            StatusByte::Warning2 => "warning2",
+            StatusByte::Error1 => "error1",
+            StatusByte::Error2 => "error2",
+        }
+    }
+
+    pub fn value(self, data: &BmsData) -> Option<u8> {
+        match self {
+            StatusByte::Info => data.info,
+            StatusByte::Warning1 => data.warning1,
+            StatusByte::Warning2 => data.warning2,
+            StatusByte::Error1 => data.error1,
+            StatusByte::Error2 => data.error2,
+        }
+    }
+
+    fn labels(self, config: &FlagsConfig) -> &[String] {
+        match self {
+            StatusByte::Info => &config.info,
+            StatusByte::Warning1 => &config.warning1,
+            StatusByte::Warning2 => &config.warning2,
+            StatusByte::Error1 => &config.error1,
+            StatusByte::Error2 => &config.error2,
+        }
+    }
+
+    /// Configured label of a bit, or "<byte>.bit<n>".
+    pub fn label(self, bit: u8, config: &FlagsConfig) -> String {
+        self.labels(config)
+            .get(usize::from(bit))
+            .filter(|label| !label.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("{}.bit{}", self.name(), bit))
+    }
+
+    /// Labels of the set bits of `value`.
+    pub fn active(self, value: u8, config: &FlagsConfig) -> Vec<String> {
+        (0..8)
+            .filter(|bit| value & (1 << bit) != 0)
+            .map(|bit| self.label(bit, config))
+            .collect()
+    }
+
+    /// Configured text of the whole value (error code dictionary).
+    pub fn code_text(self, value: u8, config: &FlagsConfig) -> Option<&str> {
+        config
+            .codes
+            .iter()
+            .find(|code| code.byte == self && code.value == value)
+            .map(|code| code.text.as_str())
+    }
+
+    /// Readable texts of `value`: the text of the code if there is one, otherwise the
+    /// labels of the set bits. Empty for 0.
+    pub fn texts(self, value: u8, config: &FlagsConfig) -> Vec<String> {
+        match self.code_text(value, config) {
+            Some(text) if value != 0 => vec![text.to_string()],
+            _ => self.active(value, config),
+        }
+    }
+}
+
+// --- Decoded Flags ---
+/// One bit of a status byte, as served by the JSON API.
+#[derive(Debug, Clone, Serialize)]
+pub struct Flag {
+    pub byte: StatusByte,
+    pub bit: u8,
+    pub label: String,
+    /// None while the byte was not received yet
+    pub active: Option<bool>,
+    /// Discrete input address of the flag
+    pub discrete_input: u16,
+}
+
+/// All flags of all status bytes.
+pub fn decode(data: &BmsData, config: &FlagsConfig) -> Vec<Flag> {
+    StatusByte::ALL
+        .iter()
+        .enumerate()
+        .flat_map(|(position, &byte)| {
+            let value = byte.value(data);
+            (0..8).map(move |bit| Flag {
+                byte,
+                bit,
+                label: byte.label(bit, config),
+                active: value.map(|value| value & (1 << bit) != 0),
+                discrete_input: position as u16 * 8 + u16::from(bit),
+            })
+        })
+        .collect()
+}
+
+/// Texts of the active warnings and errors per status byte name, bytes without any are left out.
+pub fn fault_texts(data: &BmsData, config: &FlagsConfig) -> BTreeMap<&'static str, Vec<String>> {
+    [StatusByte::Warning1, StatusByte::Warning2, StatusByte::Error1, StatusByte::Error2]
+        .into_iter()
+        .filter_map(|byte| {
+            let texts = byte.texts(byte.value(data)?, config);
+            (!texts.is_empty()).then_some((byte.name(), texts))
+        })
+        .collect()
+}
+
+/// Texts of the active errors, for log messages and reasons.
+pub fn error_summary(data: &BmsData, config: &FlagsConfig) -> String {
+    [StatusByte::Error1, StatusByte::Error2]
+        .into_iter()
+        .flat_map(|byte| byte.texts(byte.value(data).unwrap_or(0), config))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reads a discrete input, None if the address is not mapped. Bytes that were not
+/// received yet read as 0.
+pub fn discrete_input(data: &BmsData, address: u16) -> Option<bool> {
+    let byte = StatusByte::ALL.get(usize::from(address / 8))?;
+    Some(byte.value(data).unwrap_or(0) & (1 << (address % 8)) != 0)
+}
+
+/// Logs the warning and error flags that changed between `before` and `after`.
+pub fn log_changes(bms_id: u8, before: &BmsData, after: &BmsData, config: &FlagsConfig) {
+    for byte in [StatusByte::Warning1, StatusByte::Warning2, StatusByte::Error1, StatusByte::Error2] {
+        let old = byte.value(before).unwrap_or(0);
+        let new = byte.value(after).unwrap_or(0);
+        if old == new {
+            continue;
+        }
+        // Codes change as a whole, bits one by one
+        let (raised, cleared) = if byte.code_text(new, config).is_some() || byte.code_text(old, config).is_some() {
+            (byte.texts(new, config), byte.texts(old, config))
+        } else {
+            (byte.active(new & !old, config), byte.active(old & !new, config))
+        };
+        if !raised.is_empty() {
+            if matches!(byte, StatusByte::Error1 | StatusByte::Error2) {
+                log::error!("BMS {}: Fault raised: {} ({} = {:#04X})", bms_id, raised.join(", "), byte.name(), new);
+            } else {
+                log::warn!("BMS {}: Warning raised: {} ({} = {:#04X})", bms_id, raised.join(", "), byte.name(), new);
+            }
+        }
+        if !cleared.is_empty() {
+            log::info!("BMS {}: Cleared: {} ({} = {:#04X})", bms_id, cleared.join(", "), byte.name(), new);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// src/flow.rs
+use crate::{
+    config::FlowStateConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use tokio::{sync::watch, time::interval};
+
+// --- Flow State ---
+/// Direction of the pack current, exposed via REG_FLOW_STATE.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlowState {
+    #[default]
+    Idle = 0,
+    Charging = 1,
+    Discharging = 2,
+}
+
+impl FlowState {
+    fn from_register(value: Option<u16>) -> Self {
+        match value {
+            Some(1) => FlowState::Charging,
+            Some(2) => FlowState::Discharging,
+            _ => FlowState::Idle,
+        }
+    }
+}
+
+// Signed pack current in register units, positive while charging
+fn charge_current(data: &BmsData, config: &FlowStateConfig) -> Option<i64> {
+    let current = i64::from(data.pack_current()?);
+    Some(if config.charge_positive { current } else { -current })
+}
+
+/// Next state for `current` (positive = charging): a direction is entered above
+/// `enter_threshold` and left below `exit_threshold`, so noise around zero does not toggle it.
+pub fn next_state(state: FlowState, current: i64, config: &FlowStateConfig) -> FlowState {
+    let enter = i64::from(config.enter_threshold);
+    let exit = i64::from(config.exit_threshold);
+    match state {
+        FlowState::Charging if current >= exit => FlowState::Charging,
+        FlowState::Discharging if current <= -exit => FlowState::Discharging,
+        _ if current >= enter => FlowState::Charging,
+        _ if current <= -enter => FlowState::Discharging,
+        _ => FlowState::Idle,
+    }
+}
+
+// Direction of the whole system, discharging wins as it is the one limited on faults
+fn system_state(states: &[FlowState]) -> FlowState {
+    if states.contains(&FlowState::Discharging) {
+        FlowState::Discharging
+    } else if states.contains(&FlowState::Charging) {
+        FlowState::Charging
+    } else {
+        FlowState::Idle
+    }
+}
+
+// --- Flow State Task ---
+/// Derives the charge/discharge state of every BMS from its current and publishes the
+/// state of the whole system to the inverter clients, which pick their limit registers by it.
+pub async fn task(
+    config: FlowStateConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    flow_tx: watch::Sender<FlowState>,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting flow state detection (enter at {}, leave at {}, every {:?})",
+        config.enter_threshold,
+        config.exit_threshold,
+        config.interval()
+    );
+    let mut ticker = interval(config.interval());
+    loop {
+        ticker.tick().await;
+        let mut states = Vec::with_capacity(bms.len());
+        for (bms_id, bms_data) in &bms {
+            let (previous, state) = bms_data.read(|data| {
+                let previous = FlowState::from_register(data.flow_state);
+                // Stale data keeps the last state, it says nothing about the current now
+                let state = match charge_current(data, &config) {
+                    Some(current) if !data.stale.unwrap_or(true) => next_state(previous, current, &config),
+                    _ => previous,
+                };
+                (previous, state)
+            });
+            if state != previous {
+                log::info!("BMS {}: {:?} -> {:?}", bms_id, previous, state);
+                bms_data.modify(|data| data.flow_state = Some(state as u16));
+            }
+            states.push(state);
+        }
+        flow_tx.send_if_modified(|current| {
+            let state = system_state(&states);
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+}
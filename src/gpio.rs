@@ -1,94 +1,343 @@
 // src/gpio.rs
 
 use crate::SystemCommand; // Import the command enum from main or a shared module
+use crate::arbiter::{CommandReport, CommandSource, CommandStatus, SourcedCommand};
+use crate::config::{BuzzerConfig, FaultRecoveryConfig, InputConfig, PanelPins, RelayConfig, RelayFunction};
+use crate::connections::ConnectionRegistry;
+use crate::fault::FaultEvent;
+use crate::gpio_backend::{GpioProvider, OutputPin, Pull};
+use crate::rules::{Severity, SeverityMap};
 use crate::error::AppError;
-use std::time::Duration;
-use rppal::gpio::Gpio;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::time::sleep;
 
-// --- GPIO Pin Definitions ---
-const PIN_OFF: u8 = 13;
-const PIN_ON: u8 = 6;
-const PIN_QUIT: u8 = 16;
-const PIN_RED_LED: u8 = 22;
-const PIN_GREEN_LED: u8 = 23;
-
-// Debounce time for inputs
-const DEBOUNCE_DURATION: Duration = Duration::from_millis(25);
 // Poll interval to check button state - adjust as needed
 const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Blink interval of the red LED when a command failed on some outputs
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+// Blink intervals between two short flashes of the red LED while CAN data is stale
+const STALE_FLASH_TICKS: u32 = 4;
+
+// On and off time of a single blink of a self-test LED code
+const CODE_BLINK_DURATION: Duration = Duration::from_millis(300);
+// Pause between two LED codes
+const CODE_PAUSE: Duration = Duration::from_millis(1500);
+
+// --- Buzzer ---
+// Decides the buzzer level once per blink interval
+struct BuzzerState {
+    // Blink intervals between two chirps
+    chirp_ticks: u32,
+    tick: u32,
+    // Set by the error signal until acknowledged
+    fault_signal: bool,
+    // Severity silenced by the last QUIT
+    acknowledged: Severity,
+}
+
+impl BuzzerState {
+    fn new(config: &BuzzerConfig) -> Self {
+        let chirp_ticks = (config.chirp_interval_ms / BLINK_INTERVAL.as_millis() as u64).max(1);
+        Self {
+            chirp_ticks: u32::try_from(chirp_ticks).unwrap_or(u32::MAX),
+            tick: 0,
+            fault_signal: false,
+            acknowledged: Severity::Normal,
+        }
+    }
+
+    fn severity(&self, severities: &SeverityMap) -> Severity {
+        let highest = severities.values().max().copied().unwrap_or_default();
+        if self.fault_signal { Severity::Trip } else { highest }
+    }
+
+    fn acknowledge(&mut self, severities: &SeverityMap) {
+        self.fault_signal = false;
+        self.acknowledged = self.severity(severities);
+        log::info!("Buzzer acknowledged ({:?}).", self.acknowledged);
+    }
+
+    fn level(&mut self, severities: &SeverityMap) -> bool {
+        self.tick = self.tick.wrapping_add(1);
+        let severity = self.severity(severities);
+        // A lower severity re-arms the buzzer for the levels above it
+        self.acknowledged = self.acknowledged.min(severity);
+        if severity <= self.acknowledged {
+            return false;
+        }
+        match severity {
+            Severity::Trip => true,
+            Severity::Warning | Severity::Derate => self.tick % self.chirp_ticks == 0,
+            Severity::Normal => false,
+        }
+    }
+}
+
+// --- Relay Outputs ---
+struct Relay {
+    config: RelayConfig,
+    pin: Box<dyn OutputPin>,
+}
+
+impl Relay {
+    fn set(&mut self, energized: bool) {
+        if energized != self.config.active_low {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+}
+
+// Relays and whether they were released at shutdown
+#[derive(Default)]
+struct RelayState {
+    relays: Vec<Relay>,
+    released: bool,
+}
+
+/// Relay outputs mirroring the system state for external interlocks. Shared with
+/// main, which de-energizes them on shutdown.
+#[derive(Default)]
+pub struct RelayOutputs {
+    state: Mutex<RelayState>,
+}
+
+impl RelayOutputs {
+    /// Acquires the configured pins, all relays start de-energized.
+    pub fn open(gpio: &dyn GpioProvider, configs: &[RelayConfig]) -> Result<Self, AppError> {
+        if configs.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut relays = Vec::with_capacity(configs.len());
+        for config in configs {
+            let pin = gpio.output(config.pin, config.active_low)?;
+            log::info!("Relay output {:?} on pin {}.", config.function, config.pin);
+            relays.push(Relay { config: config.clone(), pin });
+        }
+        Ok(Self { state: Mutex::new(RelayState { relays, released: false }) })
+    }
+
+    /// Energizes or de-energizes every relay with `function`. Ignored after `release`.
+    pub fn set(&self, function: RelayFunction, energized: bool) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.released {
+            return;
+        }
+        for relay in state.relays.iter_mut().filter(|relay| relay.config.function == function) {
+            relay.set(energized);
+        }
+    }
+
+    /// Fail-safe state on shutdown: de-energizes all relays and keeps them so.
+    pub fn release(&self) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.released = true;
+        for relay in &mut state.relays {
+            relay.set(false);
+        }
+        if !state.relays.is_empty() {
+            log::info!("All relay outputs de-energized.");
+        }
+    }
+}
+
+// --- Self-Test ---
+/// Checks that the GPIO chip and every pin used by the gateway can be acquired.
+/// The pins are released again when this returns.
+pub fn check_pins(gpio: &dyn GpioProvider, pins: &PanelPins) -> Result<String, AppError> {
+    for pin in [pins.off, pins.on, pins.quit] {
+        gpio.input(pin, Pull::Down)?;
+    }
+    for pin in [pins.red_led, pins.green_led] {
+        gpio.output(pin, false)?;
+    }
+    Ok("all pins available".to_string())
+}
+
+/// Shows the self-test result before the output task takes over the LEDs: the green LED
+/// blinks twice if all checks passed, otherwise the red LED blinks each failed code once.
+pub async fn show_self_test_result(
+    gpio: &dyn GpioProvider,
+    pins: &PanelPins,
+    failed_codes: &[u8],
+) -> Result<(), AppError> {
+    let mut red_led = gpio.output(pins.red_led, false)?;
+    let mut green_led = gpio.output(pins.green_led, false)?;
+
+    let (led, codes) = if failed_codes.is_empty() {
+        (&mut green_led, &[2u8][..])
+    } else {
+        (&mut red_led, failed_codes)
+    };
+    for &count in codes {
+        for _ in 0..count {
+            led.set_high();
+            sleep(CODE_BLINK_DURATION).await;
+            led.set_low();
+            sleep(CODE_BLINK_DURATION).await;
+        }
+        sleep(CODE_PAUSE).await;
+    }
+    Ok(())
+}
 
-// --- GPIO Input Task (unverändert) ---
+/// Switches both LEDs off when the gateway stops after QUIT. The pins keep the level
+/// after the process has exited, so the dark panel shows that the gateway is down.
+pub fn show_shutdown(gpio: &dyn GpioProvider, pins: &PanelPins) -> Result<(), AppError> {
+    for pin in [pins.red_led, pins.green_led] {
+        gpio.output(pin, false)?.keep_level();
+    }
+    Ok(())
+}
+
+// Debounce of the rotary encoder edges
+const ENCODER_DEBOUNCE: Duration = Duration::from_millis(2);
+
+// --- GPIO Input Task ---
 /// Monitors GPIO input pins for On, Off, and Quit signals and sends corresponding SystemCommands.
-pub async fn input_task(input_tx: std::sync::mpsc::Sender<SystemCommand>) -> Result<(), AppError> {
+/// A short press of OFF is arbitrated normally, a long press forces OFF. Encoder steps
+/// (+1/-1) are sent to `page_tx`. Asserting the external trip input forces OFF and
+/// latches the alarm via `trip_tx`.
+pub async fn input_task(
+    config: InputConfig,
+    gpio: Arc<dyn GpioProvider>,
+    pins: PanelPins,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    page_tx: tokio::sync::mpsc::UnboundedSender<i8>,
+    trip_tx: crossbeam_channel::Sender<()>,
+) -> Result<(), AppError> {
     {
-        log::info!("Initializing GPIO input task for Raspberry Pi...");
-        // Initialize GPIO
-        let gpio = Gpio::new().map_err(AppError::Gpio)?;
+        log::info!("Initializing GPIO input task...");
+
+        // The direction follows from the level of B on a falling edge of A.
+        // The watch must stay alive for the edges to keep being reported.
+        let _encoder_watch = match &config.encoder {
+            Some(encoder) => {
+                let pin_b = gpio.input(encoder.pin_b, Pull::Up)?;
+                let watch = gpio.on_falling_edge(
+                    encoder.pin_a,
+                    Pull::Up,
+                    ENCODER_DEBOUNCE,
+                    Box::new(move || {
+                        let step = if pin_b.is_high() { 1 } else { -1 };
+                        // Nobody listens if the display is disabled
+                        let _ = page_tx.send(step);
+                    }),
+                )?;
+                log::info!("Rotary encoder initialized (A: {}, B: {}).", encoder.pin_a, encoder.pin_b);
+                Some(watch)
+            }
+            None => None,
+        };
 
         // Configure input pins with pull-down resistors
-        // Rppal doesn't have built-in debounce config, so we handle it manually after reading.
-        let pin_off = gpio.get(PIN_OFF)
-            .map_err( AppError::Gpio)?
-            .into_input_pulldown();
-        let pin_on = gpio.get(PIN_ON)
-            .map_err( AppError::Gpio)?
-            .into_input_pulldown();
-        let pin_quit = gpio.get(PIN_QUIT)
-            .map_err( AppError::Gpio)?
-            .into_input_pulldown();
-
-        log::info!("GPIO inputs initialized (Off: {}, On: {}, Quit: {}). Starting poll loop.", PIN_OFF, PIN_ON, PIN_QUIT);
+        // The backends have no debounce config, so we handle it manually after reading.
+        let pin_off = gpio.input(pins.off, Pull::Down)?;
+        let pin_on = gpio.input(pins.on, Pull::Down)?;
+        let pin_quit = gpio.input(pins.quit, Pull::Down)?;
+
+        // The input idles at the inactive level if the contact is disconnected
+        let trip_pin = match &config.trip {
+            Some(trip) => {
+                let pin = gpio.input(trip.pin, if trip.active_low { Pull::Up } else { Pull::Down })?;
+                log::info!("External trip input initialized (Pin {}, active {}).", trip.pin, if trip.active_low { "low" } else { "high" });
+                Some((pin, trip))
+            }
+            None => None,
+        };
+
+        log::info!("GPIO inputs initialized (Off: {}, On: {}, Quit: {}). Starting poll loop.", pins.off, pins.on, pins.quit);
 
         // State tracking to detect changes
         let mut last_off_state = false;
         let mut last_on_state = false;
         let mut last_quit_state = false;
+        let mut last_trip_state = false;
+        // Set while OFF is held and neither a short nor a long press was sent yet
+        let mut off_pressed_at: Option<Instant> = None;
 
         loop {
             let current_off_state = pin_off.is_high();
             let current_on_state = pin_on.is_high();
             let current_quit_state = pin_quit.is_high();
 
+            // --- External Trip Logic ---
+            // Checked first, it does not wait for the buttons
+            if let Some((pin, trip)) = &trip_pin {
+                let asserted = pin.is_high() != trip.active_low;
+                if asserted && !last_trip_state {
+                    sleep(config.debounce()).await;
+                    if pin.is_high() != trip.active_low {
+                        log::error!(target: "audit", "External trip asserted (Pin {}), forcing OFF.", trip.pin);
+                        // The alarm stays latched until QUIT, even if the contact is released
+                        let _ = trip_tx.send(());
+                        input_tx.send(SourcedCommand::forced(CommandSource::ExternalTrip, SystemCommand::Off)).map_err(|e| AppError::SendError(format!("Failed to send Off command: {}", e)))?;
+                        last_trip_state = true;
+                    }
+                } else if !asserted && last_trip_state {
+                    log::warn!(target: "audit", "External trip released (Pin {}).", trip.pin);
+                    last_trip_state = false;
+                }
+            }
+
             // --- Off Button Logic ---
             if current_off_state && !last_off_state {
                 // Rising edge detected
-                sleep(DEBOUNCE_DURATION).await; // Wait for debounce
+                sleep(config.debounce()).await; // Wait for debounce
                 if pin_off.is_high() { // Re-check state after debounce
-                    log::debug!("Off button pressed (Pin {})", PIN_OFF);
-                    // Send command only once per press
-                    input_tx.send(SystemCommand::Off).map_err(|e| AppError::SendError(format!("Failed to send Off command: {}", e)))?;
+                    log::debug!("Off button pressed (Pin {})", pins.off);
+                    off_pressed_at = Some(Instant::now());
                     last_off_state = true; // Mark as pressed
                 }
+            } else if current_off_state && last_off_state {
+                // Held long enough: force OFF right away instead of waiting for the release
+                if off_pressed_at.is_some_and(|at| at.elapsed() >= config.long_press()) {
+                    log::warn!("Off button long press (Pin {}), forcing OFF.", pins.off);
+                    input_tx.send(SourcedCommand::forced(CommandSource::Gpio, SystemCommand::Off)).map_err(|e| AppError::SendError(format!("Failed to send Off command: {}", e)))?;
+                    off_pressed_at = None;
+                }
             } else if !current_off_state && last_off_state {
                 // Falling edge detected (button released)
-                 log::debug!("Off button released (Pin {})", PIN_OFF);
+                 log::debug!("Off button released (Pin {})", pins.off);
+                // Released before the long press time: normal OFF
+                if off_pressed_at.take().is_some() {
+                    input_tx.send(SourcedCommand::new(CommandSource::Gpio, SystemCommand::Off)).map_err(|e| AppError::SendError(format!("Failed to send Off command: {}", e)))?;
+                }
                 last_off_state = false; // Mark as released
             }
 
             // --- On Button Logic ---
             if current_on_state && !last_on_state {
-                sleep(DEBOUNCE_DURATION).await;
+                sleep(config.debounce()).await;
                 if pin_on.is_high() {
-                    log::debug!("On button pressed (Pin {})", PIN_ON);
-                    input_tx.send(SystemCommand::On).map_err(|e| AppError::SendError(format!("Failed to send On command: {}", e)))?;
+                    log::debug!("On button pressed (Pin {})", pins.on);
+                    input_tx.send(SourcedCommand::new(CommandSource::Gpio, SystemCommand::On)).map_err(|e| AppError::SendError(format!("Failed to send On command: {}", e)))?;
                     last_on_state = true;
                 }
             } else if !current_on_state && last_on_state {
-                 log::debug!("On button released (Pin {})", PIN_ON);
+                 log::debug!("On button released (Pin {})", pins.on);
                 last_on_state = false;
             }
 
             // --- Quit Button Logic ---
             if current_quit_state && !last_quit_state {
-                sleep(DEBOUNCE_DURATION).await;
+                sleep(config.debounce()).await;
                 if pin_quit.is_high() {
-                    log::debug!("Quit button pressed (Pin {})", PIN_QUIT);
-                    input_tx.send(SystemCommand::Quit).map_err(|e| AppError::SendError(format!("Failed to send Quit command: {}", e)))?;
+                    log::debug!("Quit button pressed (Pin {})", pins.quit);
+                    input_tx.send(SourcedCommand::new(CommandSource::Gpio, SystemCommand::Quit)).map_err(|e| AppError::SendError(format!("Failed to send Quit command: {}", e)))?;
                     last_quit_state = true;
                 }
             } else if !current_quit_state && last_quit_state {
-                 log::debug!("Quit button released (Pin {})", PIN_QUIT);
+                 log::debug!("Quit button released (Pin {})", pins.quit);
                 last_quit_state = false;
             }
 
@@ -102,33 +351,80 @@ pub async fn input_task(input_tx: std::sync::mpsc::Sender<SystemCommand>) -> Res
 }
 
 // --- GPIO Output Task ---
-/// Controls LEDs based on commands received from `output_rx` and error signals from `error_rx`.
+/// Controls LEDs based on command reports received from `output_rx` and fault events from `error_rx`.
+/// If a command failed on one or more outputs, the red LED blinks until the next command or error.
+/// The green LED blinks while an inverter is down, the red LED flashes shortly every two seconds
+/// while the CAN data of a BMS is stale. Both follow the connection registry without an operator
+/// action. The optional buzzer follows the rule severity.
+/// The relay outputs mirror whether the system is running and whether a fault is present.
+/// An external trip from `trip_rx` is latched like an error signal. With `recovery.auto_clear`
+/// a latched fault (not a trip) returns to the stopped state once the BMS errors stay clear.
 pub async fn output_task(
-    error_rx: crossbeam_channel::Receiver<()>, // Original crossbeam receiver
-    output_rx: crossbeam_channel::Receiver<SystemCommand>, // Original crossbeam receiver
+    error_rx: crossbeam_channel::Receiver<FaultEvent>, // Original crossbeam receiver
+    trip_rx: crossbeam_channel::Receiver<()>,
+    output_rx: crossbeam_channel::Receiver<CommandReport>, // Command reports from the arbiter
+    connections: Arc<ConnectionRegistry>,
+    buzzer: BuzzerConfig,
+    recovery: FaultRecoveryConfig,
+    severity_rx: tokio::sync::watch::Receiver<SeverityMap>,
+    relays: Arc<RelayOutputs>,
+    gpio: Arc<dyn GpioProvider>,
+    pins: PanelPins,
 ) -> Result<(), AppError> {
 
     // --- Main Logic (using the bridge receivers) ---
     {
-        log::info!("Initializing GPIO output task for Raspberry Pi...");
-        let gpio = Gpio::new().map_err(AppError::Gpio)?;
+        log::info!("Initializing GPIO output task...");
 
         // Configure output pins, initial level low (off)
-        let mut red_led = gpio.get(PIN_RED_LED)
-            .map_err(AppError::Gpio)?
-            .into_output_low(); // Initializes low
-        let mut green_led = gpio.get(PIN_GREEN_LED)
-            .map_err(AppError::Gpio)?
-            .into_output_low(); // Initializes low
+        let mut red_led = gpio.output(pins.red_led, false)?;
+        let mut green_led = gpio.output(pins.green_led, false)?;
+
+        let mut buzzer_pin = if buzzer.enabled {
+            log::info!("Buzzer enabled on pin {}.", buzzer.pin);
+            Some(gpio.output(buzzer.pin, false)?)
+        } else {
+            None
+        };
+        let mut buzzer_state = BuzzerState::new(&buzzer);
 
-        log::info!("GPIO outputs initialized (Red: {}, Green: {}). Starting event loop.", PIN_RED_LED, PIN_GREEN_LED);
+        log::info!("GPIO outputs initialized (Red: {}, Green: {}). Starting event loop.", pins.red_led, pins.green_led);
+
+        // Set while the last command was not confirmed by every output
+        let mut blink_red = false;
+        // Level of the red LED while no CAN data is stale
+        let mut red_on = false;
+        // Level of the green LED while no inverter is down
+        let mut green_on = false;
+        // Set by a confirmed ON until the next OFF
+        let mut running = false;
+        // Set by a raised fault until QUIT or the automatic recovery, by a trip until QUIT
+        let mut fault_latched = false;
+        let mut tripped = false;
+        // Since when the BMS errors are clear while a fault is latched
+        let mut clear_since: Option<Instant> = None;
+        // Status from the connection registry, refreshed on each of its signals
+        let changes_rx = connections.changes();
+        let mut inverter_down = !connections.down().is_empty();
+        let mut bms_stale = connections.bms_stale();
+        let mut tick: u32 = 0;
 
         loop {
             crossbeam_channel::select! {
                 recv(error_rx) -> err_msg => {
                     match err_msg {
-                        Ok(_) => {
-                            log::error!("Error signal received. Setting LEDs ON.");
+                        // The alarm stays latched until QUIT or the automatic recovery
+                        Ok(FaultEvent::Cleared(condition)) => {
+                            log::info!("Fault {:?} cleared, the alarm stays latched.", condition);
+                        },
+                        Ok(FaultEvent::Raised(condition)) => {
+                            log::error!("Fault {:?} raised. Setting LEDs ON.", condition);
+                            fault_latched = true;
+                            clear_since = None;
+                            blink_red = false;
+                            red_on = true;
+                            green_on = true;
+                            buzzer_state.fault_signal = true;
                             red_led.set_high();
                             green_led.set_high();
                         },
@@ -138,30 +434,129 @@ pub async fn output_task(
                         }
                     }
                 },
-                recv(output_rx) -> cmd_msg => {
-                    match cmd_msg {
-                        Ok(command) => {
-                            log::debug!("Received command: {:?}", command);
+                recv(trip_rx) -> trip_msg => {
+                    if trip_msg.is_ok() {
+                        log::error!("External trip received. Setting LEDs ON.");
+                        tripped = true;
+                        blink_red = false;
+                        red_on = true;
+                        green_on = true;
+                        running = false;
+                        buzzer_state.fault_signal = true;
+                        red_led.set_high();
+                        green_led.set_high();
+                    }
+                },
+                recv(output_rx) -> report_msg => {
+                    match report_msg {
+                        Ok(CommandReport { command, status }) => {
+                            log::debug!("Received command: {:?} ({:?})", command, status);
                             match command {
                                 SystemCommand::On => {
+                                    running = status != CommandStatus::Failed;
                                     log::info!("Setting Green LED ON, Red LED OFF.");
+                                    red_on = false;
                                     red_led.set_low();
+                                    green_on = true;
                                     green_led.set_high();
                                 },
                                 SystemCommand::Off => {
+                                    running = false;
                                     log::info!("Setting Red LED ON, Green LED OFF.");
+                                    red_on = true;
                                     red_led.set_high();
+                                    green_on = false;
                                     green_led.set_low();
                                 }
-                                _ => {}
+                                SystemCommand::Quit => {
+                                    buzzer_state.acknowledge(&severity_rx.borrow());
+                                    fault_latched = false;
+                                    tripped = false;
+                                }
+                            }
+                            blink_red = matches!(status, CommandStatus::PartialFailure | CommandStatus::Failed);
+                            if blink_red {
+                                log::warn!("{:?} not confirmed by all outputs. Blinking Red LED.", command);
                             }
                         },
                         Err(_) => {
                             log::error!("Output channel closed. Exiting loop.");
                         }
                     }
+                },
+                recv(changes_rx) -> _ => {
+                    // Show a new state right away, not only after the next blink interval
+                    let down = !connections.down().is_empty();
+                    let stale = connections.bms_stale();
+                    if down && !inverter_down {
+                        log::warn!("Inverter unreachable. Blinking Green LED.");
+                    }
+                    if stale && !bms_stale {
+                        log::warn!("CAN data stale. Flashing Red LED.");
+                    }
+                    (inverter_down, bms_stale) = (down, stale);
+                    tick = 0;
+                    if !down {
+                        if green_on { green_led.set_high() } else { green_led.set_low() }
+                    }
+                    if !stale && !blink_red {
+                        if red_on { red_led.set_high() } else { red_led.set_low() }
+                    }
+                },
+                default(BLINK_INTERVAL) => {
+                    tick = tick.wrapping_add(1);
+                    // --- Automatic fault recovery ---
+                    if recovery.auto_clear && fault_latched && !tripped {
+                        let rule_tripped = severity_rx.borrow().values().any(|severity| *severity == Severity::Trip);
+                        if connections.bms_errors() || rule_tripped {
+                            clear_since = None;
+                        } else if clear_since.get_or_insert_with(Instant::now).elapsed() >= recovery.clear_after() {
+                            log::warn!(
+                                target: "audit",
+                                "BMS errors clear for {:?}, leaving the fault state. Inverters stay off until ON.",
+                                recovery.clear_after()
+                            );
+                            fault_latched = false;
+                            clear_since = None;
+                            buzzer_state.fault_signal = false;
+                            // The stopped state, as after an OFF
+                            red_on = true;
+                            green_on = false;
+                            if !blink_red {
+                                red_led.set_high();
+                            }
+                        }
+                    }
+                    if blink_red {
+                        red_led.toggle();
+                    } else if bms_stale {
+                        // Short flash against the steady level, so it shows with the LED on or off
+                        if (tick % STALE_FLASH_TICKS == 0) != red_on {
+                            red_led.set_high();
+                        } else {
+                            red_led.set_low();
+                        }
+                    }
+                    if inverter_down {
+                        green_led.toggle();
+                    } else if green_on {
+                        green_led.set_high();
+                    } else {
+                        green_led.set_low();
+                    }
+                    if let Some(pin) = &mut buzzer_pin {
+                        if buzzer_state.level(&severity_rx.borrow()) {
+                            pin.set_high();
+                        } else {
+                            pin.set_low();
+                        }
+                    }
                 }
             }
+
+            relays.set(RelayFunction::SystemRunning, running);
+            let fault_present = buzzer_state.severity(&severity_rx.borrow()) == Severity::Trip;
+            relays.set(RelayFunction::FaultPresent, fault_present);
         }
 
         // If the loop breaks (e.g., by uncommenting 'break' under Quit command)
@@ -0,0 +1,481 @@
+// src/gpio_backend.rs
+#[cfg(feature = "gpiocdev")]
+use crate::config::GpioLineConfig;
+use crate::{
+    config::{GpioBackend, GpioConfig},
+    error::AppError,
+};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// Poll interval of the edge detection on backends without interrupts
+const EDGE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// --- Pins ---
+/// Pull resistor of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    Up,
+    Down,
+}
+
+/// Input pin acquired from an `InputProvider`, released when dropped.
+pub trait InputPin: Send + Sync {
+    fn is_high(&self) -> bool;
+}
+
+/// Output pin acquired from an `OutputProvider`. It is reset when dropped, unless
+/// `keep_level` was called.
+pub trait OutputPin: Send {
+    fn set_level(&mut self, high: bool);
+    fn is_set_high(&self) -> bool;
+    /// Keeps the current level after the pin was dropped, even after the process exited.
+    fn keep_level(&mut self);
+
+    fn set_high(&mut self) {
+        self.set_level(true);
+    }
+
+    fn set_low(&mut self) {
+        self.set_level(false);
+    }
+
+    fn toggle(&mut self) {
+        let high = self.is_set_high();
+        self.set_level(!high);
+    }
+}
+
+/// Keeps an edge callback registered, it is removed when dropped.
+pub struct EdgeWatch {
+    _guard: Box<dyn Any + Send>,
+}
+
+// Stops the polling thread of an edge watch
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// --- Providers ---
+pub trait InputProvider: Send + Sync {
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn InputPin>, AppError>;
+
+    /// Calls `callback` on every falling edge of `pin`, at most once per `debounce`.
+    /// Backends without interrupts poll the pin on a thread of its own.
+    fn on_falling_edge(
+        &self,
+        pin: u8,
+        pull: Pull,
+        debounce: Duration,
+        mut callback: Box<dyn FnMut() + Send>,
+    ) -> Result<EdgeWatch, AppError> {
+        let input = self.input(pin, pull)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        std::thread::Builder::new()
+            .name(format!("gpio-edge-{}", pin))
+            .spawn(move || {
+                let mut was_high = input.is_high();
+                let mut last_edge: Option<Instant> = None;
+                while !stopped.load(Ordering::Relaxed) {
+                    let high = input.is_high();
+                    if was_high && !high && last_edge.is_none_or(|at| at.elapsed() >= debounce) {
+                        last_edge = Some(Instant::now());
+                        callback();
+                    }
+                    was_high = high;
+                    std::thread::sleep(EDGE_POLL_INTERVAL);
+                }
+            })
+            .map_err(AppError::Runtime)?;
+        Ok(EdgeWatch { _guard: Box::new(StopOnDrop(stop)) })
+    }
+}
+
+pub trait OutputProvider: Send + Sync {
+    fn output(&self, pin: u8, initial_high: bool) -> Result<Box<dyn OutputPin>, AppError>;
+}
+
+/// Access to the inputs and outputs of one GPIO backend.
+pub trait GpioProvider: InputProvider + OutputProvider {}
+
+impl<T: InputProvider + OutputProvider> GpioProvider for T {}
+
+/// The backend selected by `config`. The hardware is only accessed when pins are
+/// acquired, so a missing GPIO chip fails the tasks using it and not the startup.
+pub fn provider(config: &GpioConfig) -> Arc<dyn GpioProvider> {
+    match config.backend {
+        #[cfg(feature = "rppal")]
+        GpioBackend::Rppal => Arc::new(RppalGpio),
+        #[cfg(not(feature = "rppal"))]
+        GpioBackend::Rppal => {
+            log::error!("The rppal GPIO backend is configured, but the gateway was built without the rppal feature.");
+            Arc::new(UnavailableGpio)
+        }
+        GpioBackend::Sysfs => Arc::new(SysfsGpio { base: config.sysfs_base }),
+        #[cfg(feature = "gpiocdev")]
+        GpioBackend::Cdev => Arc::new(CdevGpio { chip: config.chip.clone(), lines: config.lines.clone() }),
+        #[cfg(not(feature = "gpiocdev"))]
+        GpioBackend::Cdev => {
+            log::error!("The cdev GPIO backend is configured, but the gateway was built without the gpiocdev feature.");
+            Arc::new(UnavailableGpio)
+        }
+        GpioBackend::Mock => Arc::new(MockGpio::default()),
+    }
+}
+
+// Backend of a build without support for the configured one, every pin fails
+#[cfg(any(not(feature = "rppal"), not(feature = "gpiocdev")))]
+struct UnavailableGpio;
+
+#[cfg(any(not(feature = "rppal"), not(feature = "gpiocdev")))]
+impl InputProvider for UnavailableGpio {
+    fn input(&self, _pin: u8, _pull: Pull) -> Result<Box<dyn InputPin>, AppError> {
+        Err(AppError::GpioUnavailable)
+    }
+}
+
+#[cfg(any(not(feature = "rppal"), not(feature = "gpiocdev")))]
+impl OutputProvider for UnavailableGpio {
+    fn output(&self, _pin: u8, _initial_high: bool) -> Result<Box<dyn OutputPin>, AppError> {
+        Err(AppError::GpioUnavailable)
+    }
+}
+
+// --- Raspberry Pi (rppal) ---
+#[cfg(feature = "rppal")]
+struct RppalGpio;
+
+#[cfg(feature = "rppal")]
+impl RppalGpio {
+    fn pin(pin: u8) -> Result<rppal::gpio::Pin, AppError> {
+        rppal::gpio::Gpio::new()?.get(pin).map_err(AppError::Gpio)
+    }
+
+    fn input_pin(pin: u8, pull: Pull) -> Result<rppal::gpio::InputPin, AppError> {
+        let pin = Self::pin(pin)?;
+        Ok(match pull {
+            Pull::Up => pin.into_input_pullup(),
+            Pull::Down => pin.into_input_pulldown(),
+        })
+    }
+}
+
+#[cfg(feature = "rppal")]
+impl InputPin for rppal::gpio::InputPin {
+    fn is_high(&self) -> bool {
+        rppal::gpio::InputPin::is_high(self)
+    }
+}
+
+#[cfg(feature = "rppal")]
+impl OutputPin for rppal::gpio::OutputPin {
+    fn set_level(&mut self, high: bool) {
+        if high {
+            rppal::gpio::OutputPin::set_high(self);
+        } else {
+            rppal::gpio::OutputPin::set_low(self);
+        }
+    }
+
+    fn is_set_high(&self) -> bool {
+        rppal::gpio::OutputPin::is_set_high(self)
+    }
+
+    fn keep_level(&mut self) {
+        self.set_reset_on_drop(false);
+    }
+}
+
+#[cfg(feature = "rppal")]
+impl InputProvider for RppalGpio {
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn InputPin>, AppError> {
+        Ok(Box::new(Self::input_pin(pin, pull)?))
+    }
+
+    fn on_falling_edge(
+        &self,
+        pin: u8,
+        pull: Pull,
+        debounce: Duration,
+        mut callback: Box<dyn FnMut() + Send>,
+    ) -> Result<EdgeWatch, AppError> {
+        let mut input = Self::input_pin(pin, pull)?;
+        input
+            .set_async_interrupt(rppal::gpio::Trigger::FallingEdge, Some(debounce), move |_| callback())
+            .map_err(AppError::Gpio)?;
+        // The interrupt runs as long as the pin is alive
+        Ok(EdgeWatch { _guard: Box::new(input) })
+    }
+}
+
+#[cfg(feature = "rppal")]
+impl OutputProvider for RppalGpio {
+    fn output(&self, pin: u8, initial_high: bool) -> Result<Box<dyn OutputPin>, AppError> {
+        let pin = Self::pin(pin)?;
+        Ok(Box::new(if initial_high { pin.into_output_high() } else { pin.into_output_low() }))
+    }
+}
+
+// --- sysfs ---
+// The sysfs interface has no pull resistors, they are set by the device tree of the board
+struct SysfsGpio {
+    base: u32,
+}
+
+struct SysfsPin {
+    number: u32,
+    dir: PathBuf,
+    // Last level written to an output
+    level: bool,
+    keep: bool,
+}
+
+impl SysfsPin {
+    // Exports the GPIO if needed and sets its direction ("in", "low" or "high")
+    fn open(number: u32, direction: &str) -> Result<Self, AppError> {
+        let io_error = |source| AppError::GpioPin { pin: number, source };
+        let dir = PathBuf::from(format!("/sys/class/gpio/gpio{}", number));
+        if !dir.exists() {
+            fs::write("/sys/class/gpio/export", number.to_string()).map_err(io_error)?;
+        }
+        // udev needs a moment to hand a freshly exported GPIO to the gpio group
+        let mut attempts = 0;
+        loop {
+            match fs::write(dir.join("direction"), direction) {
+                Ok(()) => break,
+                Err(e) if attempts < 10 && e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    attempts += 1;
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(Self { number, dir, level: direction == "high", keep: false })
+    }
+}
+
+impl InputPin for SysfsPin {
+    // A failed read counts as low, the inactive level of the buttons
+    fn is_high(&self) -> bool {
+        fs::read(self.dir.join("value")).is_ok_and(|value| value.first() == Some(&b'1'))
+    }
+}
+
+impl OutputPin for SysfsPin {
+    fn set_level(&mut self, high: bool) {
+        self.level = high;
+        if let Err(e) = fs::write(self.dir.join("value"), if high { "1" } else { "0" }) {
+            log::warn!("GPIO {}: Cannot set the level: {}", self.number, e);
+        }
+    }
+
+    fn is_set_high(&self) -> bool {
+        self.level
+    }
+
+    fn keep_level(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for SysfsPin {
+    // Like rppal, a released pin returns to an input
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        let _ = fs::write(self.dir.join("direction"), "in");
+        let _ = fs::write("/sys/class/gpio/unexport", self.number.to_string());
+    }
+}
+
+impl InputProvider for SysfsGpio {
+    fn input(&self, pin: u8, _pull: Pull) -> Result<Box<dyn InputPin>, AppError> {
+        Ok(Box::new(SysfsPin::open(self.base + u32::from(pin), "in")?))
+    }
+}
+
+impl OutputProvider for SysfsGpio {
+    fn output(&self, pin: u8, initial_high: bool) -> Result<Box<dyn OutputPin>, AppError> {
+        let direction = if initial_high { "high" } else { "low" };
+        Ok(Box::new(SysfsPin::open(self.base + u32::from(pin), direction)?))
+    }
+}
+
+// --- GPIO Character Device (gpiocdev) ---
+// Consumer shown for the requested lines, e.g. by gpioinfo
+#[cfg(feature = "gpiocdev")]
+const CDEV_CONSUMER: &str = "can_modbus_gateway";
+
+#[cfg(feature = "gpiocdev")]
+struct CdevGpio {
+    chip: String,
+    lines: Vec<GpioLineConfig>,
+}
+
+#[cfg(feature = "gpiocdev")]
+impl CdevGpio {
+    // Chip and offset of a pin, by line name if one is configured for it
+    fn line(&self, pin: u8) -> Result<(PathBuf, u32), AppError> {
+        let Some(line) = self.lines.iter().find(|line| line.pin == pin) else {
+            return Ok((PathBuf::from(&self.chip), u32::from(pin)));
+        };
+        let found = gpiocdev::find_named_line(&line.name)
+            .ok_or_else(|| AppError::Config(format!("GPIO line '{}' (pin {}) not found", line.name, pin)))?;
+        log::debug!("GPIO pin {} is line '{}' ({} offset {}).", pin, line.name, found.chip.display(), found.info.offset);
+        Ok((found.chip, found.info.offset))
+    }
+
+    fn request(&self, pin: u8, configure: impl FnOnce(&mut gpiocdev::request::Builder)) -> Result<CdevPin, AppError> {
+        let (chip, offset) = self.line(pin)?;
+        let mut builder = gpiocdev::Request::builder();
+        builder.on_chip(&chip).with_consumer(CDEV_CONSUMER).with_line(offset);
+        configure(&mut builder);
+        let request = builder.request().map_err(|e| AppError::GpioPin {
+            pin: u32::from(pin),
+            source: std::io::Error::other(e),
+        })?;
+        Ok(CdevPin { pin, offset, request: Some(request), level: false })
+    }
+}
+
+#[cfg(feature = "gpiocdev")]
+struct CdevPin {
+    pin: u8,
+    offset: u32,
+    // Taken by keep_level, the line then stays requested until the process exits
+    request: Option<gpiocdev::Request>,
+    level: bool,
+}
+
+#[cfg(feature = "gpiocdev")]
+impl InputPin for CdevPin {
+    // A failed read counts as low, the inactive level of the buttons
+    fn is_high(&self) -> bool {
+        self.request
+            .as_ref()
+            .and_then(|request| request.value(self.offset).ok())
+            .is_some_and(|value| value == gpiocdev::line::Value::Active)
+    }
+}
+
+#[cfg(feature = "gpiocdev")]
+impl OutputPin for CdevPin {
+    fn set_level(&mut self, high: bool) {
+        self.level = high;
+        let value = if high { gpiocdev::line::Value::Active } else { gpiocdev::line::Value::Inactive };
+        if let Some(request) = &self.request
+            && let Err(e) = request.set_value(self.offset, value)
+        {
+            log::warn!("GPIO {}: Cannot set the level: {}", self.pin, e);
+        }
+    }
+
+    fn is_set_high(&self) -> bool {
+        self.level
+    }
+
+    // A released line may fall back to its default, so it is never released
+    fn keep_level(&mut self) {
+        std::mem::forget(self.request.take());
+    }
+}
+
+#[cfg(feature = "gpiocdev")]
+impl InputProvider for CdevGpio {
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn InputPin>, AppError> {
+        let bias = match pull {
+            Pull::Up => gpiocdev::line::Bias::PullUp,
+            Pull::Down => gpiocdev::line::Bias::PullDown,
+        };
+        Ok(Box::new(self.request(pin, |builder| {
+            builder.as_input().with_bias(bias);
+        })?))
+    }
+}
+
+#[cfg(feature = "gpiocdev")]
+impl OutputProvider for CdevGpio {
+    fn output(&self, pin: u8, initial_high: bool) -> Result<Box<dyn OutputPin>, AppError> {
+        let value = if initial_high { gpiocdev::line::Value::Active } else { gpiocdev::line::Value::Inactive };
+        let mut output = self.request(pin, |builder| {
+            builder.as_output(value);
+        })?;
+        output.level = initial_high;
+        Ok(Box::new(output))
+    }
+}
+
+// --- Mock ---
+// Levels of the mock pins, shared by all pins acquired from the provider
+type MockLevels = Arc<Mutex<BTreeMap<u8, bool>>>;
+
+#[derive(Default)]
+struct MockGpio {
+    levels: MockLevels,
+}
+
+struct MockPin {
+    pin: u8,
+    levels: MockLevels,
+}
+
+impl MockPin {
+    fn level(&self) -> bool {
+        self.levels.lock().map(|levels| levels.get(&self.pin).copied().unwrap_or(false)).unwrap_or(false)
+    }
+}
+
+impl InputPin for MockPin {
+    fn is_high(&self) -> bool {
+        self.level()
+    }
+}
+
+impl OutputPin for MockPin {
+    fn set_level(&mut self, high: bool) {
+        if let Ok(mut levels) = self.levels.lock()
+            && levels.insert(self.pin, high) != Some(high)
+        {
+            log::debug!("Mock GPIO {}: {}", self.pin, if high { "high" } else { "low" });
+        }
+    }
+
+    fn is_set_high(&self) -> bool {
+        self.level()
+    }
+
+    fn keep_level(&mut self) {}
+}
+
+impl InputProvider for MockGpio {
+    // Inputs idle at the level of their pull resistor, so no button is ever pressed
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn InputPin>, AppError> {
+        if let Ok(mut levels) = self.levels.lock() {
+            levels.entry(pin).or_insert(pull == Pull::Up);
+        }
+        Ok(Box::new(MockPin { pin, levels: Arc::clone(&self.levels) }))
+    }
+}
+
+impl OutputProvider for MockGpio {
+    fn output(&self, pin: u8, initial_high: bool) -> Result<Box<dyn OutputPin>, AppError> {
+        let mut output = MockPin { pin, levels: Arc::clone(&self.levels) };
+        output.set_level(initial_high);
+        Ok(Box::new(output))
+    }
+}
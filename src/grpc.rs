@@ -0,0 +1,213 @@
+// src/grpc.rs
+use crate::{
+    SystemCommand,
+    arbiter::{CommandSource, SourcedCommand},
+    auth::{self, AuthError, Authorizer, Caller},
+    config::{ApiAuthConfig, GrpcConfig, Role},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, time::interval};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("gateway");
+}
+
+use proto::{
+    BmsUpdate, Command, CommandRequest, CommandResponse, SubscribeRequest,
+    gateway_server::{Gateway, GatewayServer},
+};
+
+// Updates buffered per subscriber before the stream applies backpressure
+const SUBSCRIBER_BUFFER: usize = 16;
+
+fn to_update(bms_id: u8, data: &BmsData) -> BmsUpdate {
+    BmsUpdate {
+        bms_id: u32::from(bms_id),
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        min_cell_voltage: data.min_cell_voltage.map(u32::from),
+        max_cell_voltage: data.max_cell_voltage.map(u32::from),
+        min_temperature: data.min_temperature.map(u32::from),
+        max_temperature: data.max_temperature.map(u32::from),
+        info: data.info.map(u32::from),
+        soc: data.soc.map(u32::from),
+        current: data.pack_current(),
+        total_voltage: data.pack_voltage(),
+        warning1: data.warning1.map(u32::from),
+        warning2: data.warning2.map(u32::from),
+        error1: data.error1.map(u32::from),
+        error2: data.error2.map(u32::from),
+        command_status: data.command_status.map(u32::from),
+        rule_severity: data.rule_severity.map(u32::from),
+        charged_ah_today: data.charged_ah_today.map(u32::from),
+        discharged_ah_today: data.discharged_ah_today.map(u32::from),
+        charged_kwh_today: data.charged_kwh_today.map(u32::from),
+        discharged_kwh_today: data.discharged_kwh_today.map(u32::from),
+        stale: data.stale.unwrap_or(true),
+    }
+}
+
+// --- Gateway Service ---
+struct GatewayService {
+    bms: Vec<(u8, SharedBmsData)>,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    update_interval: Duration,
+    auth: Arc<Authorizer>,
+}
+
+impl GatewayService {
+    // The client if its role includes `needed`: certificate of the TLS connection
+    // first, then the bearer token in the `authorization` metadata
+    fn authorize<T>(&self, request: &Request<T>, needed: Role) -> Result<Caller, Status> {
+        #[cfg(feature = "tls")]
+        let certificate_name = request
+            .peer_certs()
+            .and_then(|certs| certs.first().and_then(|cert| auth::certificate_name(cert.as_ref())));
+        #[cfg(not(feature = "tls"))]
+        let certificate_name: Option<String> = None;
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(auth::bearer_token);
+        self.auth.check(certificate_name.as_deref(), token, needed).map_err(|e| match e {
+            AuthError::Unauthenticated => Status::unauthenticated(e.to_string()),
+            AuthError::Forbidden { .. } => Status::permission_denied(e.to_string()),
+        })
+    }
+}
+
+// Sends the data of every BMS to the subscriber whenever it changed, until the subscriber disconnects
+async fn stream_updates(
+    bms: Vec<(u8, SharedBmsData)>,
+    update_interval: Duration,
+    tx: mpsc::Sender<Result<BmsUpdate, Status>>,
+) {
+    let mut last_sent: BTreeMap<u8, BmsData> = BTreeMap::new();
+    let mut ticker = interval(update_interval);
+
+    loop {
+        ticker.tick().await;
+        for (bms_id, bms_data) in &bms {
+            let data = bms_data.get();
+            if last_sent.get(bms_id) == Some(&data) {
+                continue;
+            }
+
+            let update = to_update(*bms_id, &data);
+            last_sent.insert(*bms_id, data);
+            if tx.send(Ok(update)).await.is_err() {
+                log::debug!("gRPC: Subscriber disconnected.");
+                return;
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Gateway for GatewayService {
+    type SubscribeStream = ReceiverStream<Result<BmsUpdate, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        self.authorize(&request, Role::Viewer)?;
+        let bms_ids = request.into_inner().bms_ids;
+        let bms: Vec<_> = self
+            .bms
+            .iter()
+            .filter(|(bms_id, _)| bms_ids.is_empty() || bms_ids.contains(&u32::from(*bms_id)))
+            .cloned()
+            .collect();
+        if bms.is_empty() {
+            return Err(Status::not_found(format!("Unknown BMS IDs {:?}", bms_ids)));
+        }
+        log::info!("gRPC: New subscription for BMS {:?}", bms.iter().map(|(id, _)| id).collect::<Vec<_>>());
+
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+        tokio::spawn(stream_updates(bms, self.update_interval, tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn send_command(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandResponse>, Status> {
+        let command = match Command::try_from(request.get_ref().command) {
+            Ok(Command::Off) => SystemCommand::Off,
+            Ok(Command::On) => SystemCommand::On,
+            Ok(Command::Quit) => SystemCommand::Quit,
+            Ok(Command::Unspecified) | Err(_) => {
+                return Err(Status::invalid_argument("Unknown command"));
+            }
+        };
+        let client = self.authorize(&request, auth::command_role(&command))?;
+        log::info!(target: "audit", "gRPC: {:?} command received from {} ({})", command, client.name, client.role.name());
+
+        // Forwarded like a write of the command registers
+        let request = SourcedCommand::new(CommandSource::Grpc, command.clone()).with_role(client.role);
+        self.input_tx.send(request).map_err(|e| {
+            log::error!("Error when sending {:#?}: {:?}", command, e);
+            Status::unavailable("Command arbiter is not running")
+        })?;
+        Ok(Response::new(CommandResponse { accepted: true }))
+    }
+}
+
+// --- gRPC Server Task ---
+/// Serves the Gateway gRPC service (see proto/gateway.proto).
+pub async fn task(
+    config: GrpcConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    auth_config: ApiAuthConfig,
+) -> Result<(), AppError> {
+    let socket_addr: SocketAddr = config
+        .addr
+        .parse()
+        .map_err(|e| AppError::Config(format!("Invalid gRPC address '{}': {}", config.addr, e)))?;
+    log::info!("gRPC API listening on {}", socket_addr);
+
+    let server = tonic::transport::Server::builder();
+    #[cfg(feature = "tls")]
+    let server = if auth_config.enabled && auth_config.tls() {
+        use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+        let (cert, key, client_ca) = auth::tls::pem_files(&auth_config)?;
+        let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        // Clients without certificate can still authenticate with a token
+        if let Some(client_ca) = client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(client_ca)).client_auth_optional(true);
+        }
+        server.tls_config(tls).map_err(|e| AppError::Grpc(e.to_string()))?
+    } else {
+        server
+    };
+    #[cfg(not(feature = "tls"))]
+    if auth_config.enabled && auth_config.tls() {
+        return Err(AppError::Config("api_auth: TLS needs a build with the tls feature".to_string()));
+    }
+
+    let service = GatewayService {
+        bms,
+        input_tx,
+        update_interval: config.update_interval(),
+        auth: Arc::new(Authorizer::new(auth_config)),
+    };
+    server
+        .add_service(GatewayServer::new(service))
+        .serve(socket_addr)
+        .await
+        .map_err(|e| AppError::Grpc(e.to_string()))
+}
@@ -0,0 +1,55 @@
+// src/history.rs
+use crate::{
+    SystemCommand,
+    arbiter::CommandSource,
+    config::CommandHistoryConfig,
+    error::AppError,
+    persist::{self, WriteClass},
+};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// --- Command Record ---
+/// The last command accepted by the arbiter, persisted across restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub command: SystemCommand,
+    pub source: CommandSource,
+    /// Unix time in seconds
+    pub timestamp: u64,
+}
+
+impl CommandRecord {
+    pub fn now(command: SystemCommand, source: CommandSource) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self { command, source, timestamp }
+    }
+}
+
+/// Loads the last command, None if there is none or the file is unreadable.
+pub fn load(config: &CommandHistoryConfig) -> Option<CommandRecord> {
+    let content = match std::fs::read(&config.path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::error!("Failed to read command history {}: {}", config.path.display(), e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            log::error!("Failed to parse command history {}: {}", config.path.display(), e);
+            None
+        }
+    }
+}
+
+/// Replaces the persisted command (batched, see PersistConfig::audit_flush_ms).
+pub fn save(config: &CommandHistoryConfig, record: &CommandRecord) -> Result<(), AppError> {
+    let content = serde_json::to_vec_pretty(record).map_err(|e| AppError::Persist(e.to_string()))?;
+    persist::queue(WriteClass::Audit, &config.path, content)
+}
@@ -0,0 +1,454 @@
+// src/http.rs
+use crate::{
+    archive,
+    auth::{Acceptor, AuthError, Authorizer, Connection},
+    can::CanFilters,
+    can_stats::CanRxStats,
+    clock,
+    config::{ApiAuthConfig, FlagsConfig, HttpConfig, Role, StorageAreaConfig},
+    connections::ConnectionRegistry,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    flags, logging,
+    modbus_client::InverterLink,
+    modbus_stats::ModbusCounters,
+    recorder::{self, DataRecorder},
+    selftest::SelfTestReport,
+    statistics::SharedStatistics,
+    supervisor::Supervisor,
+    trace::ProtocolTrace,
+    version,
+};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+// Inverter reads wait at most this long, also while the inverter is disconnected
+const INVERTER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+// Register limit of a Modbus read request
+const INVERTER_READ_MAX_COUNT: u16 = 125;
+
+// --- Shared State ---
+/// Everything the HTTP API can report on.
+#[derive(Clone)]
+pub struct HttpState {
+    pub statistics: SharedStatistics,
+    pub modbus_trace: Option<Arc<ProtocolTrace>>,
+    pub modbus_counters: Arc<ModbusCounters>,
+    pub self_test: Arc<SelfTestReport>,
+    pub connections: Arc<ConnectionRegistry>,
+    /// Read access to the inverters, in configuration order
+    pub inverter_links: Vec<InverterLink>,
+    pub bms: Vec<(u8, SharedBmsData)>,
+    pub flag_labels: FlagsConfig,
+    /// Empty in CANopen mode
+    pub can_filters: Vec<(u8, CanFilters)>,
+    /// None if the data recorder is disabled
+    pub recorder: Option<Arc<DataRecorder>>,
+    pub supervisor: Arc<Supervisor>,
+    pub can_rx_stats: Arc<CanRxStats>,
+    /// Data directories whose files can be listed and fetched (see archive.rs)
+    pub archive_areas: Vec<StorageAreaConfig>,
+}
+
+// --- Response ---
+struct HttpResponse {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn json(value: &impl serde::Serialize) -> Self {
+        match serde_json::to_string_pretty(value) {
+            Ok(body) => Self { status: "200 OK", content_type: "application/json", body: body.into_bytes() },
+            Err(e) => Self::error("500 Internal Server Error", &e.to_string()),
+        }
+    }
+
+    fn csv(body: String) -> Self {
+        Self { status: "200 OK", content_type: "text/csv", body: body.into_bytes() }
+    }
+
+    fn binary(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self { status: "200 OK", content_type, body }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": message }).to_string().into_bytes(),
+        }
+    }
+}
+
+// Current dataset per BMS ID
+fn bms_response(state: &HttpState) -> HttpResponse {
+    let data: BTreeMap<u8, BmsData> = state.bms.iter().map(|(bms_id, bms_data)| (*bms_id, bms_data.get())).collect();
+    HttpResponse::json(&data)
+}
+
+// Texts of the active warnings and errors per BMS ID
+fn faults_response(state: &HttpState) -> HttpResponse {
+    let mut texts = BTreeMap::new();
+    for (bms_id, bms_data) in &state.bms {
+        texts.insert(*bms_id, bms_data.read(|data| flags::fault_texts(data, &state.flag_labels)));
+    }
+    HttpResponse::json(&texts)
+}
+
+// Decoded status flags per BMS ID
+fn flags_response(state: &HttpState) -> HttpResponse {
+    let mut decoded = BTreeMap::new();
+    for (bms_id, bms_data) in &state.bms {
+        decoded.insert(*bms_id, bms_data.read(|data| flags::decode(data, &state.flag_labels)));
+    }
+    HttpResponse::json(&decoded)
+}
+
+// --- Routing ---
+// Decodes %XX escapes of a query value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Decoded value of a query parameter
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+        .map(percent_decode)
+}
+
+fn log_filter_response() -> HttpResponse {
+    match logging::filter() {
+        Some(filter) => HttpResponse::json(&serde_json::json!({ "filter": filter.to_string() })),
+        None => HttpResponse::error("500 Internal Server Error", "logger not initialized"),
+    }
+}
+
+// POST /log?filter=info,modbus_server=trace replaces the log filter until the next restart
+fn set_log_filter(query: &str) -> HttpResponse {
+    let spec = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("filter="))
+        .map(percent_decode);
+    let Some(spec) = spec else {
+        return HttpResponse::error("400 Bad Request", "missing filter parameter");
+    };
+    match logging::set_filter(&spec) {
+        Ok(_) => log_filter_response(),
+        Err(e) => HttpResponse::error("400 Bad Request", &e.to_string()),
+    }
+}
+
+// Maintenance mode per BMS ID
+fn maintenance_response(state: &HttpState) -> HttpResponse {
+    let mut maintenance = BTreeMap::new();
+    for (bms_id, bms_data) in &state.bms {
+        maintenance.insert(*bms_id, bms_data.read(BmsData::in_maintenance));
+    }
+    HttpResponse::json(&maintenance)
+}
+
+// POST /maintenance?bms=1&enabled=1 puts a BMS into maintenance mode (enabled=0 ends it)
+fn set_maintenance(state: &HttpState, query: &str) -> HttpResponse {
+    let Some(bms_id) = query_param(query, "bms").and_then(|id| id.parse::<u8>().ok()) else {
+        return HttpResponse::error("400 Bad Request", "missing or invalid bms parameter");
+    };
+    let enabled = match query_param(query, "enabled").as_deref() {
+        Some("1" | "true") => true,
+        Some("0" | "false") => false,
+        _ => return HttpResponse::error("400 Bad Request", "enabled must be 0 or 1"),
+    };
+    let Some((_, bms_data)) = state.bms.iter().find(|(id, _)| *id == bms_id) else {
+        return HttpResponse::error("404 Not Found", "unknown BMS ID");
+    };
+    bms_data.modify(|data| data.maintenance = Some(enabled));
+    log::warn!(
+        "BMS {}: Maintenance mode {} via HTTP API.",
+        bms_id,
+        if enabled { "enabled" } else { "disabled" }
+    );
+    maintenance_response(state)
+}
+
+// GET /recorder?bms=1&format=csv downloads the recorded history of a BMS (JSON by default)
+fn recorder_response(state: &HttpState, query: &str) -> HttpResponse {
+    let Some(recorder) = &state.recorder else {
+        return HttpResponse::error("404 Not Found", "data recorder is disabled");
+    };
+    let Some(bms_id) = query_param(query, "bms").and_then(|id| id.parse::<u8>().ok()) else {
+        return HttpResponse::error("400 Bad Request", "missing or invalid bms parameter");
+    };
+    if !state.bms.iter().any(|(id, _)| *id == bms_id) {
+        return HttpResponse::error("404 Not Found", "unknown BMS ID");
+    }
+    let samples = recorder.snapshot(bms_id);
+    match query_param(query, "format").as_deref() {
+        None | Some("json") => HttpResponse::json(&samples),
+        Some("csv") => HttpResponse::csv(recorder::to_csv(&samples)),
+        Some(_) => HttpResponse::error("400 Bad Request", "format must be json or csv"),
+    }
+}
+
+// GET /archives/file?name=blackbox_1700000000_bms1.json.zst fetches a file of a data directory
+fn archive_file_response(state: &HttpState, query: &str) -> HttpResponse {
+    let Some(name) = query_param(query, "name") else {
+        return HttpResponse::error("400 Bad Request", "missing name parameter");
+    };
+    match archive::read(&state.archive_areas, &name) {
+        Ok(Some(content)) if name.ends_with(archive::EXTENSION) => HttpResponse::binary("application/zstd", content),
+        Ok(Some(content)) => HttpResponse::binary("application/octet-stream", content),
+        Ok(None) => HttpResponse::error("404 Not Found", "unknown file"),
+        Err(e) => HttpResponse::error("500 Internal Server Error", &e.to_string()),
+    }
+}
+
+// Received CAN IDs per BMS ID (native mode only)
+fn can_filters_response(state: &HttpState) -> HttpResponse {
+    let filters: BTreeMap<u8, Vec<String>> = state
+        .can_filters
+        .iter()
+        .map(|(bms_id, filters)| (*bms_id, filters.ids().iter().map(|id| format!("{:#X}", id)).collect()))
+        .collect();
+    HttpResponse::json(&filters)
+}
+
+// POST /can/filters?bms=1&add=0xB401 (or remove=...) changes the received CAN IDs of a BMS
+fn update_can_filters(state: &HttpState, query: &str) -> HttpResponse {
+    let Some(bms_id) = query_param(query, "bms").and_then(|id| id.parse::<u8>().ok()) else {
+        return HttpResponse::error("400 Bad Request", "missing or invalid bms parameter");
+    };
+    let Some((_, filters)) = state.can_filters.iter().find(|(id, _)| *id == bms_id) else {
+        return HttpResponse::error("404 Not Found", "no runtime CAN filters for this BMS ID");
+    };
+    let parse_id = |value: String| match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+    let (action, can_id) = match (query_param(query, "add"), query_param(query, "remove")) {
+        (Some(id), None) => ("Added", parse_id(id)),
+        (None, Some(id)) => ("Removed", parse_id(id)),
+        _ => return HttpResponse::error("400 Bad Request", "expected either an add or a remove parameter"),
+    };
+    let Some(can_id) = can_id.filter(|id| *id <= 0x1FFF_FFFF) else {
+        return HttpResponse::error("400 Bad Request", "invalid CAN ID");
+    };
+    let changed = if action == "Added" { filters.add(can_id) } else { filters.remove(can_id) };
+    if changed {
+        log::info!("BMS {}: {} CAN filter {:#X} via HTTP API.", bms_id, action, can_id);
+    }
+    can_filters_response(state)
+}
+
+// Holding registers of an inverter, read through its client task:
+// /inverters/registers?name=<inverter>&register=<address>&count=<n>
+async fn inverter_registers_response(state: &HttpState, query: &str) -> HttpResponse {
+    let Some(link) = query_param(query, "name").and_then(|name| state.inverter_links.iter().find(|link| link.name == name))
+    else {
+        return HttpResponse::error("404 Not Found", "unknown inverter, expected ?name=<inverter>");
+    };
+    let Some(register) = query_param(query, "register").and_then(|value| value.parse::<u16>().ok()) else {
+        return HttpResponse::error("400 Bad Request", "expected &register=<address>");
+    };
+    let count = match query_param(query, "count").map(|value| value.parse::<u16>()) {
+        None => 1,
+        Some(Ok(count)) if (1..=INVERTER_READ_MAX_COUNT).contains(&count) => count,
+        Some(_) => return HttpResponse::error("400 Bad Request", "count has to be 1-125"),
+    };
+    match link.read_holding_registers(register, count, INVERTER_READ_TIMEOUT).await {
+        Ok(values) => HttpResponse::json(&serde_json::json!({
+            "inverter": link.name,
+            "register": register,
+            "values": values,
+        })),
+        Err(e) => HttpResponse::error("504 Gateway Timeout", &e),
+    }
+}
+
+fn route(state: &HttpState, method: &str, path: &str, query: &str) -> HttpResponse {
+    match (method, path) {
+        ("GET", "/log") => return log_filter_response(),
+        ("POST", "/log") => return set_log_filter(query),
+        ("POST", "/maintenance") => return set_maintenance(state, query),
+        ("POST", "/can/filters") => return update_can_filters(state, query),
+        ("GET", _) => {}
+        _ => {
+            return HttpResponse::error(
+                "405 Method Not Allowed",
+                "only GET and POST /log, /maintenance or /can/filters are supported",
+            );
+        }
+    }
+    match path {
+        "/statistics" => match state.statistics.read() {
+            Ok(stats) => HttpResponse::json(&*stats),
+            Err(_) => HttpResponse::error("500 Internal Server Error", "statistics lock poisoned"),
+        },
+        "/health" => {
+            let mut response = HttpResponse::json(&serde_json::json!({
+                "state": "running",
+                "self_test": &*state.self_test,
+                "clock": clock::latest(),
+            }));
+            // Load balancers and monitoring only look at the status code
+            if !state.self_test.passed {
+                response.status = "503 Service Unavailable";
+            }
+            response
+        }
+        "/version" => HttpResponse::json(&version::build_info()),
+        "/bms" => bms_response(state),
+        "/events" => HttpResponse::json(&logging::recent_events()),
+        "/flags" => flags_response(state),
+        "/faults" => faults_response(state),
+        "/inverters" => HttpResponse::json(&state.connections.snapshot()),
+        "/tasks" => HttpResponse::json(&state.supervisor.snapshot()),
+        "/maintenance" => maintenance_response(state),
+        "/can/filters" => can_filters_response(state),
+        "/can/stats" => HttpResponse::json(&state.can_rx_stats.snapshot()),
+        "/recorder" => recorder_response(state, query),
+        "/archives" => match archive::list(&state.archive_areas) {
+            Ok(entries) => HttpResponse::json(&entries),
+            Err(e) => HttpResponse::error("500 Internal Server Error", &e.to_string()),
+        },
+        "/archives/file" => archive_file_response(state, query),
+        "/modbus/counters" => HttpResponse::json(&state.modbus_counters.snapshot()),
+        "/modbus/trace" => match &state.modbus_trace {
+            Some(trace) => HttpResponse::json(&trace.snapshot()),
+            None => HttpResponse::error("404 Not Found", "Modbus protocol trace is disabled"),
+        },
+        _ => HttpResponse::error("404 Not Found", "unknown endpoint"),
+    }
+}
+
+// Reads one request, answers it and closes the connection
+// Reading needs a viewer, every POST changes a runtime setting and needs a maintainer
+fn authorize(
+    auth: &Authorizer,
+    method: &str,
+    target: &str,
+    certificate_name: Option<&str>,
+    token: Option<&str>,
+) -> Result<(), HttpResponse> {
+    let needed = if method == "GET" { Role::Viewer } else { Role::Maintainer };
+    match auth.check(certificate_name, token, needed) {
+        Ok(client) => {
+            if needed > Role::Viewer {
+                log::info!(target: "audit", "HTTP {} {} by client {} ({})", method, target, client.name, client.role.name());
+            }
+            Ok(())
+        }
+        Err(e @ AuthError::Unauthenticated) => Err(HttpResponse::error("401 Unauthorized", &e.to_string())),
+        Err(e @ AuthError::Forbidden { .. }) => Err(HttpResponse::error("403 Forbidden", &e.to_string())),
+    }
+}
+
+async fn handle_connection(
+    state: HttpState,
+    auth: Arc<Authorizer>,
+    stream: Box<dyn Connection>,
+    certificate_name: Option<String>,
+    peer_addr: SocketAddr,
+) -> Result<(), std::io::Error> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // Only the Authorization header is used, the others are skipped
+    let mut token = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("authorization")
+        {
+            token = crate::auth::bearer_token(value).map(str::to_string);
+        }
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    log::debug!("HTTP {} {} from {}", method, target, peer_addr);
+
+    let response = match authorize(&auth, method, target, certificate_name.as_deref(), token.as_deref()) {
+        Err(refused) => {
+            log::warn!("HTTP {} {} from {} refused: {}", method, target, peer_addr, refused.status);
+            refused
+        }
+        // The only endpoint waiting for a device, everything else answers from memory
+        Ok(()) if (method, path) == ("GET", "/inverters/registers") => inverter_registers_response(&state, query).await,
+        Ok(()) => route(&state, method, path, query),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.shutdown().await
+}
+
+// --- HTTP Server Task ---
+/// Minimal HTTP/1.1 server for the JSON API (one request per connection), over TLS and
+/// with client authentication if configured in `auth_config`.
+pub async fn task(config: HttpConfig, state: HttpState, auth_config: ApiAuthConfig) -> Result<(), AppError> {
+    let socket_addr: SocketAddr = config
+        .addr
+        .parse()
+        .map_err(|e| AppError::Config(format!("Invalid HTTP address '{}': {}", config.addr, e)))?;
+    let listener = TcpListener::bind(socket_addr)
+        .await
+        .map_err(|source| AppError::Bind { addr: socket_addr, source })?;
+    let acceptor = Acceptor::new(&auth_config)?;
+    let auth = Arc::new(Authorizer::new(auth_config));
+    log::info!("HTTP API listening on {}", socket_addr);
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        let (state, auth, acceptor) = (state.clone(), Arc::clone(&auth), acceptor.clone());
+        tokio::spawn(async move {
+            // The TLS handshake runs here, so a slow client does not hold up the others
+            let result = match acceptor.accept(stream).await {
+                Ok((stream, certificate_name)) => handle_connection(state, auth, stream, certificate_name, peer_addr).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("HTTP connection from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
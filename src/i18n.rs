@@ -0,0 +1,40 @@
+// src/i18n.rs
+use crate::config::Language;
+
+// --- Operator Texts ---
+/// Built-in texts shown to operators. The display character ROM only has ASCII in common
+/// between its variants, so the translations avoid umlauts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    Ok,
+    Warning,
+    Derate,
+    Trip,
+    Error,
+    Stale,
+    Maintenance,
+    /// Short form for the display
+    MaintenanceShort,
+}
+
+/// Text in the given language.
+pub fn text(language: Language, text: Text) -> &'static str {
+    match (language, text) {
+        (Language::En, Text::Ok) => "OK",
+        (Language::En, Text::Warning) => "WARNING",
+        (Language::En, Text::Derate) => "DERATE",
+        (Language::En, Text::Trip) => "TRIP",
+        (Language::En, Text::Error) => "ERROR",
+        (Language::En, Text::Stale) => "STALE",
+        (Language::En, Text::Maintenance) => "MAINTENANCE",
+        (Language::En, Text::MaintenanceShort) => "MAINT",
+        (Language::De, Text::Ok) => "OK",
+        (Language::De, Text::Warning) => "WARNUNG",
+        (Language::De, Text::Derate) => "REDUZIERT",
+        (Language::De, Text::Trip) => "ABSCHALTUNG",
+        (Language::De, Text::Error) => "FEHLER",
+        (Language::De, Text::Stale) => "VERALTET",
+        (Language::De, Text::Maintenance) => "WARTUNG",
+        (Language::De, Text::MaintenanceShort) => "WART",
+    }
+}
@@ -0,0 +1,147 @@
+// src/inverter_profiles.rs
+use crate::config::{InverterProfile, RegisterWrite, RunningCheckConfig};
+
+// --- Generic (the original hard-coded registers) ---
+const GENERIC_REG_MODE: u16 = 40231;
+const GENERIC_REG_UNKNOWN1: u16 = 40191;
+const GENERIC_REG_UNKNOWN2: u16 = 40187;
+
+const GENERIC_OFF_MODE_VALUE: u16 = 3;
+const GENERIC_OFF_UNKNOWN1_VALUE: u16 = 0;
+const GENERIC_OFF_UNKNOWN2_VALUE: u16 = 0;
+
+// --- SMA (SMA Modbus, U32 values high word first) ---
+// Fast shut-down, 381 = stop, 1467 = start
+const SMA_REG_FAST_STOP: u16 = 40018;
+const SMA_STOP: u16 = 381;
+const SMA_START: u16 = 1467;
+// Active power limitation in % of the nominal power
+const SMA_REG_POWER_LIMIT_PCT: u16 = 40016;
+
+// --- Fronius (SunSpec int+SF map, model 123 immediate controls) ---
+// Connection control, 0 = disconnected, 1 = connected
+const FRONIUS_REG_CONN: u16 = 40231;
+// Power limit in % of WMax (scale factor -2) and its enable flag
+const FRONIUS_REG_WMAXLIMPCT: u16 = 40233;
+const FRONIUS_REG_WMAXLIM_ENA: u16 = 40237;
+
+// --- Victron (GX Modbus-TCP, VE.Bus unit) ---
+// Switch position, 3 = on, 4 = off
+const VICTRON_REG_MODE: u16 = 33;
+const VICTRON_MODE_ON: u16 = 3;
+const VICTRON_MODE_OFF: u16 = 4;
+// VE.Bus state, 9 = inverting
+const VICTRON_REG_STATE: u16 = 31;
+const VICTRON_STATE_INVERTING: u16 = 9;
+
+// --- Sungrow (string inverters, protocol addresses = documented number - 1) ---
+// Start/stop, 0xCF = start, 0xCE = stop
+const SUNGROW_REG_START_STOP: u16 = 5005;
+const SUNGROW_START: u16 = 0xCF;
+const SUNGROW_STOP: u16 = 0xCE;
+// Power limitation switch (0xAA = enabled, 0x55 = disabled) and setting in 0.1 %
+const SUNGROW_REG_LIMIT_SWITCH: u16 = 5006;
+const SUNGROW_REG_LIMIT: u16 = 5007;
+const SUNGROW_LIMIT_ON: u16 = 0xAA;
+const SUNGROW_LIMIT_OFF: u16 = 0x55;
+
+// Power limit of the derate sequences
+const DERATE_PERCENT: u16 = 50;
+// The mode registers read back the written value right away, unlike the state after ON
+const OFF_CHECK_TIMEOUT_MS: u64 = 5000;
+
+fn writes(steps: &[(u16, u16)]) -> Vec<RegisterWrite> {
+    steps
+        .iter()
+        .map(|&(register, value)| RegisterWrite { register, value, delay_ms: None })
+        .collect()
+}
+
+// --- Profiles ---
+// The register maps follow the vendors' Modbus documentation, check them against the
+// firmware of the site. Everything a profile provides can be overridden per inverter.
+impl InverterProfile {
+    /// Registers written to switch the inverter off.
+    pub fn off_sequence(self) -> Vec<RegisterWrite> {
+        match self {
+            InverterProfile::Generic | InverterProfile::Auto => writes(&[
+                (GENERIC_REG_MODE, GENERIC_OFF_MODE_VALUE),
+                (GENERIC_REG_UNKNOWN1, GENERIC_OFF_UNKNOWN1_VALUE),
+                (GENERIC_REG_UNKNOWN2, GENERIC_OFF_UNKNOWN2_VALUE),
+            ]),
+            InverterProfile::Sma => writes(&[(SMA_REG_FAST_STOP, 0), (SMA_REG_FAST_STOP + 1, SMA_STOP)]),
+            InverterProfile::Fronius => writes(&[(FRONIUS_REG_CONN, 0)]),
+            InverterProfile::Victron => writes(&[(VICTRON_REG_MODE, VICTRON_MODE_OFF)]),
+            InverterProfile::Sungrow => writes(&[(SUNGROW_REG_START_STOP, SUNGROW_STOP)]),
+        }
+    }
+
+    /// Registers written to restart the inverter, empty if ON does nothing.
+    pub fn on_sequence(self) -> Vec<RegisterWrite> {
+        match self {
+            InverterProfile::Generic | InverterProfile::Auto => Vec::new(),
+            InverterProfile::Sma => writes(&[(SMA_REG_FAST_STOP, 0), (SMA_REG_FAST_STOP + 1, SMA_START)]),
+            InverterProfile::Fronius => writes(&[(FRONIUS_REG_CONN, 1)]),
+            InverterProfile::Victron => writes(&[(VICTRON_REG_MODE, VICTRON_MODE_ON)]),
+            InverterProfile::Sungrow => writes(&[(SUNGROW_REG_START_STOP, SUNGROW_START)]),
+        }
+    }
+
+    /// Status register showing that the inverter runs after ON.
+    pub fn running_check(self) -> Option<RunningCheckConfig> {
+        match self {
+            InverterProfile::Victron => Some(RunningCheckConfig::new(VICTRON_REG_STATE, VICTRON_STATE_INVERTING)),
+            _ => None,
+        }
+    }
+
+    /// Register read back after OFF, it has to report the value the OFF sequence wrote.
+    pub fn off_check(self) -> Option<RunningCheckConfig> {
+        let (register, expected) = match self {
+            InverterProfile::Generic | InverterProfile::Auto => (GENERIC_REG_MODE, GENERIC_OFF_MODE_VALUE),
+            InverterProfile::Sma => (SMA_REG_FAST_STOP + 1, SMA_STOP),
+            InverterProfile::Fronius => (FRONIUS_REG_CONN, 0),
+            InverterProfile::Victron => (VICTRON_REG_MODE, VICTRON_MODE_OFF),
+            InverterProfile::Sungrow => (SUNGROW_REG_START_STOP, SUNGROW_STOP),
+        };
+        Some(RunningCheckConfig { timeout_ms: OFF_CHECK_TIMEOUT_MS, ..RunningCheckConfig::new(register, expected) })
+    }
+
+    /// Registers written when a rule reaches the derate level.
+    pub fn derate_sequence(self) -> Vec<RegisterWrite> {
+        match self {
+            InverterProfile::Generic | InverterProfile::Auto | InverterProfile::Victron => Vec::new(),
+            InverterProfile::Sma => writes(&[(SMA_REG_POWER_LIMIT_PCT, 0), (SMA_REG_POWER_LIMIT_PCT + 1, DERATE_PERCENT)]),
+            InverterProfile::Fronius => {
+                writes(&[(FRONIUS_REG_WMAXLIMPCT, DERATE_PERCENT * 100), (FRONIUS_REG_WMAXLIM_ENA, 1)])
+            }
+            InverterProfile::Sungrow => {
+                writes(&[(SUNGROW_REG_LIMIT, DERATE_PERCENT * 10), (SUNGROW_REG_LIMIT_SWITCH, SUNGROW_LIMIT_ON)])
+            }
+        }
+    }
+
+    /// Registers written when no rule is at derate level anymore.
+    pub fn derate_release_sequence(self) -> Vec<RegisterWrite> {
+        match self {
+            InverterProfile::Generic | InverterProfile::Auto | InverterProfile::Victron => Vec::new(),
+            InverterProfile::Sma => writes(&[(SMA_REG_POWER_LIMIT_PCT, 0), (SMA_REG_POWER_LIMIT_PCT + 1, 100)]),
+            InverterProfile::Fronius => writes(&[(FRONIUS_REG_WMAXLIM_ENA, 0)]),
+            InverterProfile::Sungrow => writes(&[(SUNGROW_REG_LIMIT_SWITCH, SUNGROW_LIMIT_OFF)]),
+        }
+    }
+
+    /// Profile matching the manufacturer name of the SunSpec common model.
+    pub fn from_manufacturer(manufacturer: &str) -> Option<InverterProfile> {
+        let manufacturer = manufacturer.to_ascii_lowercase();
+        [
+            ("sma", InverterProfile::Sma),
+            ("fronius", InverterProfile::Fronius),
+            ("victron", InverterProfile::Victron),
+            ("sungrow", InverterProfile::Sungrow),
+        ]
+        .into_iter()
+        .find(|(name, _)| manufacturer.starts_with(name))
+        .map(|(_, profile)| profile)
+    }
+}
@@ -0,0 +1,245 @@
+// src/log_output.rs
+use crate::{
+    clock,
+    config::{LogConfig, LogOutput, SyslogConfig, SyslogTransport},
+    error::AppError,
+};
+use log::{Level, Record};
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    time::{Duration, Instant},
+};
+
+// Socket of the native journal protocol
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// Blocking limit for the TCP connection, the logger runs on the caller's thread
+const TCP_TIMEOUT: Duration = Duration::from_millis(500);
+// Records are dropped for this long after the syslog server could not be reached
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Length limit of the MSGID field (RFC 5424 section 6)
+const MSGID_MAX_LEN: usize = 32;
+
+// Syslog severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// Module path without the crate name
+fn short_target<'a>(record: &'a Record) -> &'a str {
+    record.target().strip_prefix("can_modbus_gateway::").unwrap_or(record.target())
+}
+
+/// Name of this host, "-" if it is unknown.
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// RFC 3339 UTC timestamp with milliseconds, days to civil date after H. Hinnant
+fn rfc3339(unix_ms: u128) -> String {
+    let secs = (unix_ms / 1000) as i64;
+    let (days, time) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        unix_ms % 1000
+    )
+}
+
+// --- Syslog (RFC 5424) ---
+enum SyslogSocket {
+    Udp(UdpSocket),
+    // Reconnected on the next record after a failure, but not before `retry_at`
+    Tcp { stream: Option<TcpStream>, retry_at: Option<Instant> },
+}
+
+struct Syslog {
+    addr: SocketAddr,
+    socket: SyslogSocket,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+impl Syslog {
+    fn open(config: &SyslogConfig, app_name: &str) -> Result<Self, AppError> {
+        let addr: SocketAddr = config
+            .addr
+            .parse()
+            .map_err(|e| AppError::Config(format!("Invalid syslog address '{}': {}", config.addr, e)))?;
+        if config.facility > 23 {
+            return Err(AppError::Config(format!("Invalid syslog facility {}, expected 0-23", config.facility)));
+        }
+        let socket = match config.transport {
+            SyslogTransport::Udp => {
+                let bind_addr: SocketAddr = if addr.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+                SyslogSocket::Udp(UdpSocket::bind(bind_addr)?)
+            }
+            SyslogTransport::Tcp => SyslogSocket::Tcp { stream: None, retry_at: None },
+        };
+        Ok(Self {
+            addr,
+            socket,
+            facility: config.facility,
+            hostname: hostname(),
+            app_name: app_name.to_string(),
+        })
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let msgid: String = short_target(record)
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .take(MSGID_MAX_LEN)
+            .collect();
+        format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility * 8 + severity(record.level()),
+            rfc3339(clock::unix_millis()),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            if msgid.is_empty() { "-" } else { &msgid },
+            record.args()
+        )
+    }
+
+    fn send(&mut self, record: &Record) -> io::Result<()> {
+        let message = self.format(record);
+        match &mut self.socket {
+            SyslogSocket::Udp(socket) => socket.send_to(message.as_bytes(), self.addr).map(|_| ()),
+            SyslogSocket::Tcp { stream, retry_at } => {
+                if retry_at.is_some_and(|at| Instant::now() < at) {
+                    return Ok(());
+                }
+                if stream.is_none() {
+                    let connected = TcpStream::connect_timeout(&self.addr, TCP_TIMEOUT).and_then(|s| {
+                        s.set_write_timeout(Some(TCP_TIMEOUT))?;
+                        Ok(s)
+                    });
+                    match connected {
+                        Ok(s) => *stream = Some(s),
+                        Err(e) => {
+                            *retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                            return Err(e);
+                        }
+                    }
+                }
+                let Some(s) = stream else {
+                    return Ok(());
+                };
+                // Octet-counting framing (RFC 6587)
+                let result = write!(s, "{} {}", message.len(), message);
+                if result.is_err() {
+                    *stream = None;
+                    *retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                }
+                result
+            }
+        }
+    }
+}
+
+// --- Journald ---
+// Appends a field of the native journal protocol, multi-line values in the binary form
+#[cfg(unix)]
+fn journal_field(buffer: &mut Vec<u8>, name: &str, value: &str) {
+    buffer.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buffer.push(b'\n');
+        buffer.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buffer.push(b'=');
+    }
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(b'\n');
+}
+
+#[cfg(unix)]
+struct Journald {
+    socket: std::os::unix::net::UnixDatagram,
+    identifier: String,
+}
+
+#[cfg(unix)]
+impl Journald {
+    fn open(identifier: &str) -> Result<Self, AppError> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(Self { socket, identifier: identifier.to_string() })
+    }
+
+    fn send(&mut self, record: &Record) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        journal_field(&mut buffer, "MESSAGE", &record.args().to_string());
+        journal_field(&mut buffer, "PRIORITY", &severity(record.level()).to_string());
+        journal_field(&mut buffer, "SYSLOG_IDENTIFIER", &self.identifier);
+        journal_field(&mut buffer, "TARGET", short_target(record));
+        if let Some(file) = record.file() {
+            journal_field(&mut buffer, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            journal_field(&mut buffer, "CODE_LINE", &line.to_string());
+        }
+        self.socket.send(&buffer).map(|_| ())
+    }
+}
+
+// --- Output ---
+enum Sink {
+    Syslog(Syslog),
+    #[cfg(unix)]
+    Journald(Journald),
+}
+
+/// Destination of the log records other than stderr.
+pub struct Output(Sink);
+
+impl Output {
+    /// Opens the configured output, None for stderr.
+    pub fn open(config: &LogConfig) -> Result<Option<Self>, AppError> {
+        let sink = match config.output {
+            LogOutput::Stderr => return Ok(None),
+            LogOutput::Syslog => Sink::Syslog(Syslog::open(&config.syslog, &config.app_name)?),
+            #[cfg(unix)]
+            LogOutput::Journald => Sink::Journald(Journald::open(&config.app_name)?),
+            #[cfg(not(unix))]
+            LogOutput::Journald => {
+                return Err(AppError::Config("The journald log output is only available on Linux".to_string()));
+            }
+        };
+        Ok(Some(Self(sink)))
+    }
+
+    pub fn send(&mut self, record: &Record) -> io::Result<()> {
+        match &mut self.0 {
+            Sink::Syslog(syslog) => syslog.send(record),
+            #[cfg(unix)]
+            Sink::Journald(journald) => journald.send(record),
+        }
+    }
+}
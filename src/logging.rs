@@ -0,0 +1,195 @@
+// src/logging.rs
+use crate::error::AppError;
+use crate::{clock, config::LogConfig, log_output::Output};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+};
+
+// Module paths of the gateway start with the crate name, filters may omit it
+const CRATE_PREFIX: &str = "can_modbus_gateway::";
+// Number of recent events kept for the HTTP API
+const EVENT_CAPACITY: usize = 100;
+
+// --- Log Filter ---
+/// Parsed filter in RUST_LOG syntax, e.g. "info,modbus_server=trace".
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    pub default: LevelFilter,
+    /// (module, level), the longest matching module wins
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogFilter {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let parse_level = |level: &str| {
+            level
+                .parse::<LevelFilter>()
+                .map_err(|_| AppError::Config(format!("Invalid log level '{}'", level)))
+        };
+        let mut filter = LogFilter { default: LevelFilter::Error, modules: Vec::new() };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim().strip_prefix(CRATE_PREFIX).unwrap_or(module.trim());
+                    filter.modules.push((module.to_string(), parse_level(level.trim())?));
+                }
+                // A bare level sets the default, a bare module enables everything of it
+                None => match parse_level(directive) {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.modules.push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        filter.modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+impl LogFilter {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    // Info at least, the recent events are collected even if they are not logged
+    fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default.max(LevelFilter::Info), LevelFilter::max)
+    }
+}
+
+// --- Recent Events ---
+/// A warning, error or audit record, kept regardless of the log filter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEvent {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+static EVENTS: Mutex<VecDeque<LogEvent>> = Mutex::new(VecDeque::new());
+
+fn record_event(record: &Record) {
+    if record.level() > Level::Warn && record.target() != "audit" {
+        return;
+    }
+    let event = LogEvent {
+        timestamp_ms: clock::unix_millis(),
+        level: record.level().to_string(),
+        target: record.target().strip_prefix(CRATE_PREFIX).unwrap_or(record.target()).to_string(),
+        message: record.args().to_string(),
+    };
+    if let Ok(mut events) = EVENTS.lock() {
+        if events.len() >= EVENT_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+}
+
+/// The most recent warnings, errors and audit records, oldest first.
+pub fn recent_events() -> Vec<LogEvent> {
+    EVENTS.lock().map(|events| events.iter().cloned().collect()).unwrap_or_default()
+}
+
+// --- Logger ---
+// env_logger does the formatting, the filtering is done here so it can change at runtime
+struct Logger {
+    inner: env_logger::Logger,
+}
+
+static FILTER: RwLock<Option<LogFilter>> = RwLock::new(None);
+// None until the configuration is loaded and for stderr
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER
+            .read()
+            .ok()
+            .and_then(|filter| filter.as_ref().map(|filter| metadata.level() <= filter.level_for(metadata.target())))
+            .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        record_event(record);
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match OUTPUT.lock().as_deref_mut() {
+            Ok(Some(output)) => {
+                // Not logged, that would end up here again
+                if let Err(e) = output.send(record) {
+                    eprintln!("Log output failed: {}", e);
+                }
+            }
+            _ => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger with the filter from RUST_LOG, only errors are logged without it.
+pub fn init() {
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| "error".to_string());
+    let filter = spec.parse().unwrap_or_else(|e| {
+        eprintln!("Ignoring invalid RUST_LOG '{}': {}", spec, e);
+        LogFilter { default: LevelFilter::Error, modules: Vec::new() }
+    });
+    let inner = env_logger::Builder::new().filter_level(LevelFilter::Trace).build();
+    log::set_max_level(filter.max_level());
+    if let Ok(mut current) = FILTER.write() {
+        *current = Some(filter);
+    }
+    if log::set_boxed_logger(Box::new(Logger { inner })).is_err() {
+        eprintln!("A logger is already installed.");
+    }
+}
+
+/// Switches to the configured output, records go to stderr until then.
+pub fn set_output(config: &LogConfig) -> Result<(), AppError> {
+    let output = Output::open(config)?;
+    *OUTPUT.lock().map_err(|_| AppError::LockPoisoned)? = output;
+    Ok(())
+}
+
+/// The active log filter.
+pub fn filter() -> Option<LogFilter> {
+    FILTER.read().ok().and_then(|filter| filter.clone())
+}
+
+/// Replaces the log filter, e.g. with "info,modbus_server=trace".
+pub fn set_filter(spec: &str) -> Result<LogFilter, AppError> {
+    let filter: LogFilter = spec.parse()?;
+    log::set_max_level(filter.max_level());
+    *FILTER.write().map_err(|_| AppError::LockPoisoned)? = Some(filter.clone());
+    log::warn!("Log filter changed to '{}'", spec);
+    Ok(filter)
+}
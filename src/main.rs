@@ -1,275 +1,877 @@
 // src/main.rs
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::BTreeSet,
+    process::ExitCode,
+    sync::{Arc, RwLock},
+};
 use tokio::signal; // For graceful shutdown on Ctrl+C
 
+mod arbiter;
+mod archive;
+mod auth;
+mod auto_restart;
+mod blackbox;
 mod can;
+mod can_stats;
+mod can_sys;
+mod can_tx;
+mod clock;
+mod config;
+mod connections;
+mod control_mode;
 mod data;
+mod display;
 mod error;
+mod fault;
+mod flags;
+mod flow;
 mod modbus_server;
+mod modbus_stats;
 mod gpio;
+mod gpio_backend;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod history;
+mod http;
+mod i18n;
+mod inverter_profiles;
+mod log_output;
+mod logging;
 mod modbus_client;
-
-use data::BmsData;
+#[cfg(feature = "opcua")]
+mod opcua_server;
+mod persist;
+mod recorder;
+#[cfg(feature = "remote-config")]
+mod remote_config;
+mod rules;
+mod runtime;
+mod scheduler;
+mod selftest;
+mod site;
+mod snapshot;
+mod snmp;
+mod statistics;
+mod storage;
+mod supervisor;
+mod trace;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "uplink")]
+mod uplink;
+mod version;
+mod victron;
+
+use arbiter::{ArbitrationPolicy, CommandOutputs, CommandReport, CommandResult, OutputTarget, SoftStart, SourcedCommand};
+use config::{CanMode, Config};
+use control_mode::ControlMode;
+use data::{BmsData, SharedBmsData};
 use error::AppError; // Import the AppError type
 
 // --- Define Command Enum for Broadcast Channel ---
-#[derive(Debug, Clone, PartialEq, Eq)] // Ensure it can be cloned and compared
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)] // Ensure it can be cloned and compared
+#[serde(rename_all = "snake_case")] // Used by scheduled commands in the configuration and the command history
 pub enum SystemCommand {
     Off,
     On,
     Quit
 }
 
-fn reset_control_frozen(
-    bms_data1: Arc<RwLock<Option<BmsData>>>,
-    bms_data2: Arc<RwLock<Option<BmsData>>>,
-) -> Result<(), AppError> {
-    std::thread::sleep(std::time::Duration::from_secs(1));
 
-    {
-        let mut data_guard1 = bms_data1.write().map_err(|_| AppError::LockPoisoned)?;
-        let data = data_guard1.get_or_insert_default();
-        data.control_frozen = Some(false);
+// Values served before the first CAN frame arrives (and no snapshot exists)
+fn initial_bms_data() -> BmsData {
+    BmsData {
+        min_cell_voltage: Some(0),
+        max_cell_voltage: Some(0),
+        min_temperature: Some(0),
+        max_temperature: Some(0),
+        info: Some(0xFF),
+        soc: Some(0),
+        current: Some(0),
+        total_voltage: Some(0),
+        // Only reported by packs sending the 32-bit message
+        current_32: None,
+        total_voltage_32: None,
+        warning1: Some(0),
+        warning2: Some(0),
+        error1: Some(0xFF),
+        error2: Some(0xFF),
+        on: Some(0),
+        quit: Some(0),
+        control_frozen: Some(false),
+        command_status: Some(0),
+        rule_severity: Some(0),
+        charged_ah_today: Some(0),
+        discharged_ah_today: Some(0),
+        charged_kwh_today: Some(0),
+        discharged_kwh_today: Some(0),
+        cell_voltage_delta: Some(0),
+        avg_temperature: Some(0),
+        min_cell_voltage_1min: Some(0),
+        max_cell_voltage_1min: Some(0),
+        last_command: Some(0),
+        last_command_source: Some(0),
+        last_command_time: Some(0),
+        corrupted_frames: Some(0),
+        sequence_gaps: Some(0),
+        last_counters: Default::default(),
+        stale: Some(true),
+        redundancy_lost: Some(false),
+        maintenance: Some(false),
+        control_mode: Some(0),
+        inverters_connected: Some(0),
+        inverters_down: Some(0),
+        inverters_off_unconfirmed: Some(0),
+        storage_pruned: Some(false),
+        flow_state: Some(0),
+        decoder_suspicion: Some(0),
+        implausible: Some(false),
+        pack_metadata: Default::default(),
+    }
+}
+
+// Assigns the discovered BMS to the two data slots, lowest ID first. Slots left over
+// take the lowest IDs not on the bus.
+fn assign_slots(discovered: &BTreeSet<u8>) -> [u8; 2] {
+    if discovered.len() > 2 {
+        log::warn!("Discovery: Only two BMS are supported, ignoring {:?}.", discovered.iter().skip(2).collect::<Vec<_>>());
     }
+    let mut ids = discovered
+        .iter()
+        .copied()
+        .chain((1..=u8::MAX).filter(|id| !discovered.contains(id)));
+    let slots = [ids.next().unwrap_or(1), ids.next().unwrap_or(2)];
+    log::info!("Data slot 1 serves BMS {}, data slot 2 serves BMS {}.", slots[0], slots[1]);
+    slots
+}
 
+// Dashboard of the gateway running on this host (`tui` subcommand)
+fn dashboard(config_path: &str, overrides: &[(String, String)]) -> Result<(), AppError> {
+    #[cfg(feature = "tui")]
+    return tui::run(&Config::load(std::path::Path::new(config_path), overrides)?);
+    #[cfg(not(feature = "tui"))]
     {
-        let mut data_guard2 = bms_data2.write().map_err(|_| AppError::LockPoisoned)?;
-        let data = data_guard2.get_or_insert_default();
-        data.control_frozen = Some(false);
+        let _ = overrides;
+        Err(AppError::Config(format!("The dashboard needs a build with the tui feature (config {})", config_path)))
     }
+}
 
-    log::debug!("Control frozen reset after 1 second.");
+// Loads and validates the configuration and prints it with all defaults filled in (TOML,
+// or JSON with `json`), without starting the gateway (`--check-config` option)
+fn check_config(config_path: &str, overrides: &[(String, String)], json: bool) -> Result<(), AppError> {
+    let config = Config::load(std::path::Path::new(config_path), overrides)?;
+    config.validate()?;
+    data::check_register_map().map_err(|e| AppError::Config(format!("Register map: {}", e)))?;
+    let effective = if json {
+        serde_json::to_string_pretty(&config).map_err(|e| AppError::Config(e.to_string()))?
+    } else {
+        toml::to_string_pretty(&config).map_err(|e| AppError::Config(e.to_string()))?
+    };
+    println!("{}", effective);
+    eprintln!("Configuration {} is valid.", config_path);
     Ok(())
 }
 
-async fn input_flag_manager_task(
-    bms_data1: Arc<RwLock<Option<BmsData>>>,
-    bms_data2: Arc<RwLock<Option<BmsData>>>,
-    input_rx: std::sync::mpsc::Receiver<SystemCommand>,
-    output_tx: crossbeam_channel::Sender<SystemCommand>
-)  -> Result<(), AppError> {
-
-    for msg in input_rx.iter() {
-        let control_frozen1;
-        {
-            let data_guard1 = bms_data1.read().map_err(|_| {
-                log::error!("Failed to acquire read lock 1 (poisoned)");
-                AppError::LockPoisoned
-            })?;
-    
-            let maybe_data1 = &*data_guard1;
-
-            match maybe_data1 {
-                Some(data) => {
-                    control_frozen1 = data.control_frozen.unwrap();
-                }
-                None => {
-                    control_frozen1 = false;
-                    log::warn!("No BmsData1 object available yet.");
-                }
-            }
-        }
+fn start(config_path: &str, overrides: &[(String, String)]) -> Result<ExitCode, AppError> {
+    let config = Config::load(std::path::Path::new(config_path), overrides)?;
+    // All problems are reported at once, before any task is spawned
+    config.validate()?;
+    if let Err(e) = logging::set_output(&config.log) {
+        log::error!("Cannot open the {:?} log output, logging to stderr: {}", config.log.output, e);
+    }
+    persist::init(&config.persist);
 
-        let control_frozen2;
-        {
-            let data_guard2 = bms_data2.read().map_err(|_| {
-                log::error!("Failed to acquire read lock 2 (poisoned)");
-                AppError::LockPoisoned
-            })?;
-    
-            let maybe_data2 = &*data_guard2;
-
-            match maybe_data2 {
-                Some(data) => {
-                    control_frozen2 = data.control_frozen.unwrap();
-                }
-                None => {
-                    control_frozen2 = false;
-                    log::warn!("No BmsData2 object available yet.");
-                }
+    // The runtime is configurable, so it is built once the configuration is loaded
+    runtime::build(&config.runtime)?.block_on(run(config))
+}
+
+// The exit status tells the reason of the termination, see the EXIT_* codes in error.rs
+fn main() -> ExitCode {
+    // `tui [config]` shows the dashboard of the gateway running on this host instead
+    let mut args = std::env::args().skip(1).peekable();
+    let show_dashboard = args.next_if(|arg| arg == "tui").is_some();
+    // `--check-config [--json] [config]` only verifies the configuration, for deployments
+    let check_only = args.next_if(|arg| arg == "--check-config").is_some();
+    let json = check_only && args.next_if(|arg| arg == "--json").is_some();
+    // Configuration path, and `--set key=value` options overriding single keys. They are
+    // applied after the IWENT_* environment variables, so the command line wins.
+    let mut overrides = config::env_overrides();
+    let mut config_path = None;
+    while let Some(arg) = args.next() {
+        if arg != "--set" {
+            config_path = config_path.or(Some(arg));
+            continue;
+        }
+        match args.next().as_deref().and_then(|set| set.split_once('=')) {
+            Some((key, value)) => overrides.push((key.to_string(), value.to_string())),
+            None => {
+                eprintln!("Error: --set expects key=value, e.g. --set http.addr=0.0.0.0:8080");
+                return ExitCode::from(error::EXIT_CONFIG);
             }
         }
-
-        let control_frozen = control_frozen1 || control_frozen2;
-        if !control_frozen {
-            {
-                let mut data_guard1 = bms_data1.write().map_err(|_| AppError::LockPoisoned)?;
-                let data_ref1 = data_guard1.get_or_insert_default();
-                data_ref1.control_frozen = Some(true);
-                log::debug!("Control for BMS 1 frozen.");
+    }
+    let config_path = config_path.unwrap_or_else(|| config::DEFAULT_CONFIG_PATH.to_string());
+
+    if check_only {
+        // stdout carries the configuration only, problems go to stderr
+        return match check_config(&config_path, &overrides, json) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::from(e.exit_code())
             }
+        };
+    }
 
-            {
-                let mut data_guard2 = bms_data2.write().map_err(|_| AppError::LockPoisoned)?;
-                let data_ref2 = data_guard2.get_or_insert_default();
-                data_ref2.control_frozen = Some(true);
-                log::debug!("Control for BMS 2 frozen.");
+    if show_dashboard {
+        // No logger, its output would garble the dashboard
+        return match dashboard(&config_path, &overrides) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::from(e.exit_code())
             }
+        };
+    }
 
-            let bms_data1_clone = Arc::clone(&bms_data1);
-            let bms_data2_clone = Arc::clone(&bms_data2);
-            std::thread::spawn(move || reset_control_frozen(bms_data1_clone, bms_data2_clone));
-            if let Err(e) = output_tx.send(msg.clone()) {
-                log::error!(
-                    "Error when sending {:#?}: {:?}",
-                    msg,
-                    e
-                );
-            } else {
-                log::debug!("{:#?} sent.", msg);
-            }
+    logging::init();
+    supervisor::install_panic_hook();
+
+    log::info!("Application starting, gateway {}", version::describe());
+
+    match start(&config_path, &overrides) {
+        Ok(code) => code,
+        Err(e) => {
+            log::error!("Gateway terminated: {} (exit code {})", e, e.exit_code());
+            ExitCode::from(e.exit_code())
         }
     }
-
-    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), AppError> {
-    env_logger::init();
-
-    log::info!("Application starting...");
+async fn run(config: Config) -> Result<ExitCode, AppError> {
+    // Backend of the buttons, LEDs, buzzer and relays
+    let gpio_provider = gpio_backend::provider(&config.gpio);
+    let panel_pins = config.gpio.pins.clone();
+
+    // Startup self-test, the result is shown by the LEDs and served as /health
+    let self_test = if config.self_test.enabled {
+        let report = selftest::run(&config, gpio_provider.as_ref()).await;
+        let failed_codes = report.failed_codes();
+        if let Err(e) = gpio::show_self_test_result(gpio_provider.as_ref(), &panel_pins, &failed_codes).await {
+            log::warn!("Cannot show the self-test result on the LEDs: {}", e);
+        }
+        if !report.passed && config.self_test.abort_on_failure {
+            return Err(AppError::SelfTest(format!("LED codes {:?}", report.failed_codes())));
+        }
+        report
+    } else {
+        selftest::SelfTestReport { passed: true, checks: Vec::new() }
+    };
+    let self_test = Arc::new(self_test);
+
+    // Runs the tasks below, reports their panics and restarts them
+    let (supervisor, mut fatal_rx) = supervisor::Supervisor::new(config.supervisor.clone());
+
+    // BMS served by the two data slots, taken from the bus if discovery is enabled
+    let [bms_id1, bms_id2] = if config.can.discovery.enabled {
+        let can_config = config.can.clone();
+        match tokio::task::spawn_blocking(move || can::discover(&can_config)).await? {
+            Ok(discovered) => assign_slots(&discovered.into_keys().collect()),
+            Err(e) => {
+                log::error!("Discovery failed, serving BMS 1 and 2: {}", e);
+                [1, 2]
+            }
+        }
+    } else {
+        [1, 2]
+    };
 
     // Create shared data structures with thread-safe access
-    let bms_data1: Arc<RwLock<Option<BmsData>>> = Arc::new(RwLock::new(Some(BmsData {
-        min_cell_voltage: Some(0),
-        max_cell_voltage: Some(0),
-        min_temperature: Some(0),
-        max_temperature: Some(0),
-        info: Some(0xFF),
-        soc: Some(0),
-        current: Some(0),
-        total_voltage: Some(0),
-        warning1: Some(0),
-        warning2: Some(0),
-        error1: Some(0xFF),
-        error2: Some(0xFF),
-        on: Some(0),
-        quit: Some(0),
-        control_frozen: Some(false),
-    })));
-
-    let bms_data2: Arc<RwLock<Option<BmsData>>> = Arc::new(RwLock::new(Some(BmsData {
-        min_cell_voltage: Some(0),
-        max_cell_voltage: Some(0),
-        min_temperature: Some(0),
-        max_temperature: Some(0),
-        info: Some(0xFF),
-        soc: Some(0),
-        current: Some(0),
-        total_voltage: Some(0),
-        warning1: Some(0),
-        warning2: Some(0),
-        error1: Some(0xFF),
-        error2: Some(0xFF),
-        on: Some(0),
-        quit: Some(0),
-        control_frozen: Some(false),
-    })));
+    // Restore the last known data (flagged stale) so SCADA doesn't see zeros after a reboot
+    let mut snapshots = if config.snapshot.enabled {
+        snapshot::load(&config.snapshot)
+    } else {
+        Default::default()
+    };
+    let bms_data1 = SharedBmsData::new(snapshots.remove(&bms_id1).unwrap_or_else(initial_bms_data));
+    let bms_data2 = SharedBmsData::new(snapshots.remove(&bms_id2).unwrap_or_else(initial_bms_data));
+    // The configuration decides about maintenance at startup, not the restored snapshot,
+    // and the gateway always starts in auto mode
+    for (bms_id, bms_data) in [(bms_id1, &bms_data1), (bms_id2, &bms_data2)] {
+        let maintenance = config.maintenance.contains(&bms_id);
+        if maintenance {
+            log::warn!("BMS {}: Starting in maintenance mode.", bms_id);
+        }
+        bms_data.modify(|data| {
+            data.maintenance = Some(maintenance);
+            data.control_mode = Some(ControlMode::Auto.code());
+        });
+    }
 
     // --- Create Communication Channels ---
 
     // 1. Channel for system commands from input
-    let (input_tx1, input_rx) = std::sync::mpsc::channel::<SystemCommand>();
+    let (input_tx1, input_rx) = std::sync::mpsc::channel::<SourcedCommand>();
     let input_tx2 = input_tx1.clone();
     let input_tx3 = input_tx2.clone();
+    let input_tx_scheduler = input_tx1.clone();
+    let input_tx_restart = input_tx1.clone();
+    #[cfg(feature = "opcua")]
+    let input_tx_opcua = input_tx1.clone();
+    #[cfg(feature = "grpc")]
+    let input_tx_grpc = input_tx1.clone();
 
     // 1. Channel for errors from CAN
-    let (error_tx1, error_rx1) = crossbeam_channel::unbounded::<()>();
+    // Edges of the fault conditions, each consumer gets every edge in its own queue.
+    // With the emergency OFF the coordinator is the only consumer of the error signals,
+    // the inverter clients then get no signals of their own.
+    let fault_consumers = if config.arbiter.emergency_off.enabled { 1 } else { config.inverters.len() + 1 };
+    let (error_tx1, mut error_rxs) = fault::channel(fault_consumers);
     let error_tx2 = error_tx1.clone();
-    let error_rx2 = error_rx1.clone();
-    let error_rx3 = error_rx2.clone();
-
-    // 2. Broadcast Channel for system commands to output
-    let (output_tx, output_rx1) = crossbeam_channel::unbounded::<SystemCommand>();
-    let output_rx2 = output_rx1.clone();
-    let output_rx3 = output_rx2.clone();
-    let output_rx4 = output_rx3.clone();
+    let error_tx_can_tx = error_tx1.clone();
+    let (client_error_rxs, led_error_rx) = if config.arbiter.emergency_off.enabled {
+        let error_rx = error_rxs.pop().unwrap_or_else(crossbeam_channel::never);
+        let (led_error_tx, mut led_error_rxs) = fault::channel(1);
+        let input_tx = input_tx1.clone();
+        let hold_off = config.arbiter.emergency_off.deadline();
+        let control = bms_data1.clone();
+        std::thread::spawn(move || arbiter::emergency_off_coordinator(error_rx, led_error_tx, input_tx, hold_off, control));
+        (Vec::new(), led_error_rxs.pop().unwrap_or_else(crossbeam_channel::never))
+    } else {
+        let led_error_rx = error_rxs.pop().unwrap_or_else(crossbeam_channel::never);
+        (error_rxs, led_error_rx)
+    };
+
+    // 2. One command channel per output (fan-out is done by the arbiter)
+    let (can_out_tx, can_out_rx) = crossbeam_channel::unbounded::<SystemCommand>();
+    let (led_out_tx, led_out_rx) = crossbeam_channel::unbounded::<CommandReport>();
+
+    // 3. Channel for per-output command results back to the arbiter
+    let (result_tx, result_rx) = crossbeam_channel::unbounded::<CommandResult>();
+
+    // 4. Threshold rule severity per BMS, used by the inverter clients for derating
+    let (severity_tx, severity_rx) = tokio::sync::watch::channel(rules::SeverityMap::new());
+
+    // 5. Charge/discharge state, used by the inverter clients to pick their limit registers.
+    //    The sender lives until shutdown so the clients don't see a closed channel when
+    //    detection is disabled.
+    let (flow_tx, flow_rx) = tokio::sync::watch::channel(flow::FlowState::Idle);
+
+    // 6. Shutdown request of the arbiter after QUIT
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<arbiter::CommandSource>();
 
     // --- Spawn asynchronous tasks ---
     log::info!("Spawning input tasks...");
 
     // CAN Receiver tasks
-    let can_rx1_handle = tokio::spawn(can::rx_task(
-        "can0",
-        1, 
-        Arc::clone(&bms_data1),
-        error_tx1,
-    ));
-    let can_rx2_handle = tokio::spawn(can::rx_task(
-        "can0",
-        2, 
-        Arc::clone(&bms_data2),
-        error_tx2,
-    ));
+    // Received native frame IDs per BMS, changeable at runtime via the HTTP API
+    let can_filters: Vec<(u8, can::CanFilters)> = match config.can.mode {
+        CanMode::Native => [bms_id1, bms_id2]
+            .into_iter()
+            .map(|bms_id| (bms_id, can::CanFilters::new(config.can.ids.ids(bms_id))))
+            .collect(),
+        CanMode::Canopen => Vec::new(),
+    };
+    // Raw frames for the black box, fed by the RX tasks
+    let black_box = config
+        .black_box
+        .enabled
+        .then(|| Arc::new(blackbox::BlackBox::new(&config.black_box)));
+    // Gap and bus load limits of everything the gateway sends, per interface
+    let tx_scheduler = can_tx::TxScheduler::new(config.can.tx.clone());
+    // Buffer and drop counters of the RX sockets
+    let can_rx_stats = Arc::new(can_stats::CanRxStats::default());
+    // Optionally on a dedicated thread, so a busy main runtime doesn't delay frame reception
+    let can_rx_runtime = runtime::can_rx_handle(&config.runtime)?;
+    let spawn_can_rx = |bms_id: u8, bms_data: &SharedBmsData, error_tx: fault::FaultSender, severity_tx: tokio::sync::watch::Sender<rules::SeverityMap>| {
+        let bus = config.can.bus(bms_id);
+        let bms_data = bms_data.clone();
+        let rules = config.rules.clone();
+        let flag_labels = config.flags.clone();
+        let black_box = black_box.clone();
+        let rx_stats = Arc::clone(&can_rx_stats);
+        let name = format!("can_rx_{}", bms_id);
+        match config.can.mode {
+            CanMode::Native => {
+                let can = config.can.clone();
+                let filters = can_filters
+                    .iter()
+                    .find(|(id, _)| *id == bms_id)
+                    .map(|(_, filters)| filters.clone())
+                    .unwrap_or_else(|| can::CanFilters::new(config.can.ids.ids(bms_id)));
+                supervisor.spawn_on(&can_rx_runtime, &name, move || {
+                    can::rx_task(
+                        can.clone(),
+                        bms_id,
+                        bms_data.clone(),
+                        error_tx.clone(),
+                        rules.clone(),
+                        flag_labels.clone(),
+                        filters.clone(),
+                        severity_tx.clone(),
+                        black_box.clone(),
+                        Arc::clone(&rx_stats),
+                    )
+                })
+            }
+            CanMode::Canopen => {
+                if let Some(secondary) = &bus.secondary {
+                    log::warn!("BMS {}: CAN failover is not supported in CANopen mode, ignoring {}.", bms_id, secondary);
+                }
+                let canopen = config.can.canopen.clone();
+                let plausibility = config.can.plausibility.clone();
+                let tx = tx_scheduler.lane(&bus.primary);
+                supervisor.spawn_on(&can_rx_runtime, &name, move || {
+                    let primary = bus.primary.clone();
+                    let tx = Arc::clone(&tx);
+                    let canopen = canopen.clone();
+                    let plausibility = plausibility.clone();
+                    let bms_data = bms_data.clone();
+                    let error_tx = error_tx.clone();
+                    let rules = rules.clone();
+                    let flag_labels = flag_labels.clone();
+                    let severity_tx = severity_tx.clone();
+                    let black_box = black_box.clone();
+                    let rx_stats = Arc::clone(&rx_stats);
+                    async move {
+                        can::canopen_rx_task(
+                            &primary,
+                            bms_id,
+                            canopen,
+                            plausibility,
+                            bms_data,
+                            error_tx,
+                            rules,
+                            flag_labels,
+                            severity_tx,
+                            black_box,
+                            tx,
+                            rx_stats,
+                        )
+                        .await
+                    }
+                })
+            }
+        }
+    };
+    let can_rx1_handle = spawn_can_rx(bms_id1, &bms_data1, error_tx1, severity_tx.clone());
+    let can_rx2_handle = spawn_can_rx(bms_id2, &bms_data2, error_tx2, severity_tx);
 
     // GPIO Input Task
-    let gp_in_handle = tokio::spawn(gpio::input_task(
-        input_tx1
-    ));
+    // Rotary encoder steps for the display
+    let (page_tx, page_rx) = tokio::sync::mpsc::unbounded_channel::<i8>();
+    // External trip signal, latched by the LED output task
+    let (trip_tx, trip_rx) = crossbeam_channel::unbounded::<()>();
+    let input_config = config.input.clone();
+    let (input_gpio, input_pins) = (Arc::clone(&gpio_provider), panel_pins.clone());
+    let gp_in_handle = supervisor.spawn("gpio_input", move || {
+        gpio::input_task(
+            input_config.clone(),
+            Arc::clone(&input_gpio),
+            input_pins.clone(),
+            input_tx1.clone(),
+            page_tx.clone(),
+            trip_tx.clone(),
+        )
+    });
 
     // Modbus Server tasks
-    let modbus_server1_handle = tokio::spawn(modbus_server::task(
-        "172.18.143.93:40502", // Address for BMS 1 server
-        Arc::clone(&bms_data1),
-        input_tx2
-    ));
-    let modbus_server2_handle = tokio::spawn(modbus_server::task(
-        "172.18.143.93:41502", // Address for BMS 2 server
-        Arc::clone(&bms_data2),
-        input_tx3
-    ));
+    let modbus_trace = config
+        .modbus_trace
+        .enabled
+        .then(|| Arc::new(trace::ProtocolTrace::new(config.modbus_trace.capacity)));
+    let modbus_counters = Arc::new(modbus_stats::ModbusCounters::default());
+    let invalid_value = Arc::new(config.invalid_value.clone());
+    let mut server_configs = config.modbus_servers.iter().cloned();
+    // Register windows passed through to the BMS parameters over CAN
+    let open_tunnel = |server: Option<&config::ModbusServerConfig>| {
+        server
+            .and_then(|server| server.tunnel.clone())
+            .map(|tunnel| {
+                can::RegisterTunnel::open(&config.can.interface, tunnel, tx_scheduler.lane(&config.can.interface)).map(Arc::new)
+            })
+            .transpose()
+    };
+    let tunnel1 = open_tunnel(config.modbus_servers.first())?;
+    let tunnel2 = open_tunnel(config.modbus_servers.get(1))?;
+    // Combined register block of both packs, served by every Modbus server
+    let site_map = config
+        .site_map
+        .enabled
+        .then(|| Arc::new(site::SiteMap::new(&config.site_map, vec![bms_data1.clone(), bms_data2.clone()])));
+    let spawn_modbus_server = |name: &str, server_config: config::ModbusServerConfig, bms_data: &SharedBmsData, input_tx: std::sync::mpsc::Sender<SourcedCommand>, tunnel: Option<Arc<can::RegisterTunnel>>| {
+        let bms_data = bms_data.clone();
+        let modbus_trace = modbus_trace.clone();
+        let modbus_counters = Arc::clone(&modbus_counters);
+        let invalid_value = Arc::clone(&invalid_value);
+        let site_map = site_map.clone();
+        supervisor.spawn(name, move || {
+            modbus_server::task(
+                server_config.clone(),
+                bms_data.clone(),
+                input_tx.clone(),
+                modbus_trace.clone(),
+                Arc::clone(&modbus_counters),
+                Arc::clone(&invalid_value),
+                tunnel.clone(),
+                site_map.clone(),
+            )
+        })
+    };
+    let modbus_server1_handle = spawn_modbus_server(
+        "modbus_server1",
+        server_configs.next().ok_or_else(|| AppError::Config("Missing Modbus server config for BMS 1".into()))?,
+        &bms_data1,
+        input_tx2,
+        tunnel1,
+    );
+    let modbus_server2_handle = spawn_modbus_server(
+        "modbus_server2",
+        server_configs.next().ok_or_else(|| AppError::Config("Missing Modbus server config for BMS 2".into()))?,
+        &bms_data2,
+        input_tx3,
+        tunnel2,
+    );
 
     log::info!("Spawning output tasks...");
 
-    // Modbus Client Tasks (each subscribes to broadcast channel)
-    let modbus_client1_handle = tokio::spawn(modbus_client::task(
-        "192.168.2.100:30502", // Inverter 1 Address
-        error_rx1,
-        output_rx1
-    ));
-    let modbus_client2_handle = tokio::spawn(modbus_client::task(
-        "192.168.2.100:31502", // Inverter 2 Address
-        error_rx2,
-        output_rx2
+    // Modbus Client Tasks (one per configured inverter, each with its own command channel)
+    let mut output_targets = Vec::with_capacity(config.inverters.len() + 1);
+    let mut modbus_client_handles = Vec::with_capacity(config.inverters.len());
+    let mut inverter_links = Vec::with_capacity(config.inverters.len());
+    let inverter_names: Vec<String> = config.inverters.iter().map(|inverter| inverter.name.clone()).collect();
+    let connections = Arc::new(connections::ConnectionRegistry::new(
+        &inverter_names,
+        vec![bms_data1.clone(), bms_data2.clone()],
     ));
+    for (index, inverter) in config.inverters.iter().enumerate() {
+        let (inverter_tx, inverter_rx) = crossbeam_channel::unbounded::<SystemCommand>();
+        output_targets.push(OutputTarget { name: inverter.name.clone(), tx: inverter_tx });
+        let (link, read_rx) = modbus_client::InverterLink::new(&inverter.name);
+        inverter_links.push(link);
+        let client_error_rx = client_error_rxs.get(index).cloned().unwrap_or_else(crossbeam_channel::never);
+        let channels = modbus_client::ClientChannels::new(&inverter.name, inverter_rx, client_error_rx, read_rx)?;
+        // The per-inverter dry-run flag overrides the global one
+        let mut inverter = inverter.clone();
+        inverter.dry_run = Some(inverter.dry_run.unwrap_or(config.dry_run));
+        let connections = Arc::clone(&connections);
+        let control = bms_data1.clone();
+        let result_tx = result_tx.clone();
+        let severity_rx = severity_rx.clone();
+        let flow_rx = flow_rx.clone();
+        modbus_client_handles.push(supervisor.spawn(&format!("modbus_client_{}", inverter.name), move || {
+            modbus_client::task(
+                inverter.clone(),
+                index,
+                Arc::clone(&connections),
+                control.clone(),
+                Arc::clone(&channels),
+                result_tx.clone(),
+                severity_rx.clone(),
+                flow_rx.clone(),
+            )
+        }));
+    }
 
     // CAN Transmitter task
-    let can_tx_handle = tokio::spawn(can::tx_task(
-        "can0",
-        output_rx3
-    ));
+    output_targets.push(OutputTarget { name: can::TX_OUTPUT_NAME.to_string(), tx: can_out_tx });
+    let can_interface = config.can.interface.clone();
+    let command_ack = config.can.command_ack.clone();
+    let tx_confirm = config.can.tx_confirm.clone();
+    let command_tx = tx_scheduler.lane(&can_interface);
+    let can_tx_handle = supervisor.spawn("can_tx", move || {
+        let can_interface = can_interface.clone();
+        let command_ack = command_ack.clone();
+        let tx_confirm = tx_confirm.clone();
+        let can_out_rx = can_out_rx.clone();
+        let result_tx = result_tx.clone();
+        let error_tx = error_tx_can_tx.clone();
+        let command_tx = Arc::clone(&command_tx);
+        async move {
+            can::tx_task(&can_interface, command_ack, tx_confirm, can_out_rx, result_tx, error_tx, command_tx).await
+        }
+    });
+
+    // Victron CAN-BMS output (battery data for Victron GX devices)
+    let victron_handle = if config.victron.enabled {
+        let bms_data = match config.victron.bms_id {
+            id if id == bms_id1 => bms_data1.clone(),
+            id if id == bms_id2 => bms_data2.clone(),
+            id => return Err(AppError::Config(format!("Unknown BMS ID {} for the Victron output", id))),
+        };
+        let can_interface = config.can.interface.clone();
+        let victron_config = config.victron.clone();
+        let rules = config.rules.clone();
+        let tx = tx_scheduler.lane(&can_interface);
+        Some(supervisor.spawn("victron", move || {
+            let can_interface = can_interface.clone();
+            let victron_config = victron_config.clone();
+            let rules = rules.clone();
+            let bms_data = bms_data.clone();
+            let tx = Arc::clone(&tx);
+            async move { victron::task(&can_interface, victron_config, rules, bms_data, tx).await }
+        }))
+    } else {
+        None
+    };
+
+    // GPIO Output Task
+    // A relay that cannot be driven must not stop the gateway, it stays de-energized
+    let relays = Arc::new(gpio::RelayOutputs::open(gpio_provider.as_ref(), &config.relays).unwrap_or_else(|e| {
+        log::error!("Failed to initialize the relay outputs: {}", e);
+        gpio::RelayOutputs::default()
+    }));
+    // Staleness of the CAN data, shown by the LED output task
+    for index in 0..2 {
+        let connections = Arc::clone(&connections);
+        supervisor.spawn(&format!("stale_watch_{}", index + 1), move || Arc::clone(&connections).watch_stale(index));
+    }
+    let gp_out_handle = {
+        let connections = Arc::clone(&connections);
+        let buzzer = config.buzzer.clone();
+        let recovery = config.fault_recovery.clone();
+        let severity_rx = severity_rx.clone();
+        let relays = Arc::clone(&relays);
+        let (output_gpio, output_pins) = (Arc::clone(&gpio_provider), panel_pins.clone());
+        supervisor.spawn("gpio_output", move || {
+            gpio::output_task(
+                led_error_rx.clone(),
+                trip_rx.clone(),
+                led_out_rx.clone(),
+                Arc::clone(&connections),
+                buzzer.clone(),
+                recovery.clone(),
+                severity_rx.clone(),
+                Arc::clone(&relays),
+                Arc::clone(&output_gpio),
+                output_pins.clone(),
+            )
+        })
+    };
+
+    log::info!("Spawning statistics and API tasks...");
+
+    let statistics: statistics::SharedStatistics =
+        Arc::new(RwLock::new(statistics::load(&config.statistics)));
+    // Both datasets, for the tasks serving all BMS
+    let bms = vec![(bms_id1, bms_data1.clone()), (bms_id2, bms_data2.clone())];
+    let statistics_handle = {
+        let (statistics_config, bms, statistics) = (config.statistics.clone(), bms.clone(), Arc::clone(&statistics));
+        supervisor.spawn("statistics", move || {
+            statistics::task(statistics_config.clone(), bms.clone(), Arc::clone(&statistics))
+        })
+    };
+
+    // Wall-clock timestamps of the recorded data are only trustworthy while NTP is synchronized
+    let clock_handle = config.clock.enabled.then(|| {
+        let clock_config = config.clock.clone();
+        supervisor.spawn("clock", move || clock::task(clock_config.clone()))
+    });
+
+    let recorder = config
+        .recorder
+        .enabled
+        .then(|| Arc::new(recorder::DataRecorder::new(config.recorder.capacity())));
+    let recorder_handle = recorder.as_ref().map(|recorder| {
+        let (recorder_config, bms, recorder) = (config.recorder.clone(), bms.clone(), Arc::clone(recorder));
+        supervisor.spawn("recorder", move || {
+            recorder::task(recorder_config.clone(), bms.clone(), Arc::clone(&recorder))
+        })
+    });
+
+    let black_box_handle = black_box.as_ref().map(|black_box| {
+        let (black_box_config, bms, black_box) = (config.black_box.clone(), bms.clone(), Arc::clone(black_box));
+        let flag_labels = config.flags.clone();
+        supervisor.spawn("black_box", move || {
+            blackbox::task(black_box_config.clone(), bms.clone(), Arc::clone(&black_box), flag_labels.clone())
+        })
+    });
+
+    // The black box dumps are managed along with the configured data directories
+    let mut storage_areas = config.storage.areas.clone();
+    if config.black_box.enabled {
+        storage_areas.push(config::StorageAreaConfig {
+            dir: config.black_box.dir.clone(),
+            prefix: blackbox::FILE_PREFIX.to_string(),
+            quota_bytes: config.black_box.quota_bytes,
+        });
+    }
+    let storage_handle = config.storage.enabled.then(|| {
+        let areas = storage_areas.clone();
+        let storage_config = config.storage.clone();
+        let bms_data = vec![bms_data1.clone(), bms_data2.clone()];
+        supervisor.spawn("storage", move || storage::task(storage_config.clone(), areas.clone(), bms_data.clone()))
+    });
+
+    let flow_state_handle = config.flow_state.enabled.then(|| {
+        let (flow_config, bms, flow_tx) = (config.flow_state.clone(), bms.clone(), flow_tx.clone());
+        supervisor.spawn("flow_state", move || flow::task(flow_config.clone(), bms.clone(), flow_tx.clone()))
+    });
+
+    let snapshot_handle = config.snapshot.enabled.then(|| {
+        let (snapshot_config, bms) = (config.snapshot.clone(), bms.clone());
+        supervisor.spawn("snapshot", move || snapshot::task(snapshot_config.clone(), bms.clone()))
+    });
+
+    let persist_handle = config.persist.batching.then(|| supervisor.spawn("persist", persist::task));
+
+    let snmp_handle = config.snmp.enabled.then(|| {
+        let (snmp_config, bms) = (config.snmp.clone(), bms.clone());
+        supervisor.spawn("snmp", move || snmp::task(snmp_config.clone(), bms.clone()))
+    });
+
+    let http_handle = config.http.enabled.then(|| {
+        let (http_config, auth_config) = (config.http.clone(), config.api_auth.clone());
+        let state = http::HttpState {
+            statistics: Arc::clone(&statistics),
+            modbus_trace: modbus_trace.clone(),
+            modbus_counters: Arc::clone(&modbus_counters),
+            self_test: Arc::clone(&self_test),
+            connections: Arc::clone(&connections),
+            inverter_links: inverter_links.clone(),
+            bms: bms.clone(),
+            flag_labels: config.flags.clone(),
+            can_filters: can_filters.clone(),
+            recorder: recorder.clone(),
+            supervisor: Arc::clone(&supervisor),
+            can_rx_stats: Arc::clone(&can_rx_stats),
+            archive_areas: storage_areas.clone(),
+        };
+        supervisor.spawn("http", move || http::task(http_config.clone(), state.clone(), auth_config.clone()))
+    });
+
+    #[cfg(feature = "opcua")]
+    let opcua_handle = config.opcua.enabled.then(|| {
+        let (opcua_config, bms) = (config.opcua.clone(), bms.clone());
+        supervisor.spawn("opcua", move || opcua_server::task(opcua_config.clone(), bms.clone(), input_tx_opcua.clone()))
+    });
+    #[cfg(not(feature = "opcua"))]
+    if config.opcua.enabled {
+        log::error!("OPC UA server is enabled in the configuration, but the gateway was built without the opcua feature.");
+    }
 
-    // GPIO Output Task (subscribes to broadcast channel)
-    let gp_out_handle = tokio::spawn(gpio::output_task(
-        error_rx3,
-        output_rx4
-    ));
+    #[cfg(feature = "grpc")]
+    let grpc_handle = config.grpc.enabled.then(|| {
+        let (grpc_config, bms, auth_config) = (config.grpc.clone(), bms.clone(), config.api_auth.clone());
+        supervisor.spawn("grpc", move || {
+            grpc::task(grpc_config.clone(), bms.clone(), input_tx_grpc.clone(), auth_config.clone())
+        })
+    });
+    #[cfg(not(feature = "grpc"))]
+    if config.grpc.enabled {
+        log::error!("gRPC API is enabled in the configuration, but the gateway was built without the grpc feature.");
+    }
 
-    log::info!("Spawning input flag manager task...");
+    #[cfg(feature = "uplink")]
+    let uplink_handle = config.uplink.enabled.then(|| {
+        let (uplink_config, bms) = (config.uplink.clone(), bms.clone());
+        let (connections, uplink_supervisor) = (Arc::clone(&connections), Arc::clone(&supervisor));
+        supervisor.spawn("uplink", move || {
+            uplink::task(uplink_config.clone(), bms.clone(), Arc::clone(&connections), Arc::clone(&uplink_supervisor))
+        })
+    });
+    #[cfg(not(feature = "uplink"))]
+    if config.uplink.enabled {
+        log::error!("Fleet uplink is enabled in the configuration, but the gateway was built without the uplink feature.");
+    }
 
-    let input_flag_manager_handle = tokio::spawn(input_flag_manager_task(
-        Arc::clone(&bms_data1),
-        Arc::clone(&bms_data2),
-        input_rx,
-        output_tx
-    ));
+    // The display owns the encoder receiver, so it cannot be restarted
+    let display_handle = config.display.enabled.then(|| {
+        supervisor.spawn_once("display", display::task(config.display.clone(), bms.clone(), page_rx, config.language))
+    });
+
+    let scheduler_handle = config.scheduler.enabled.then(|| {
+        let scheduler_config = config.scheduler.clone();
+        supervisor.spawn("scheduler", move || scheduler::task(scheduler_config.clone(), input_tx_scheduler.clone()))
+    });
+
+    let control_mode_config = config.control_mode.clone();
+    let control_mode_bms = vec![bms_data1.clone(), bms_data2.clone()];
+    let control_mode_handle = supervisor.spawn("control_mode", move || {
+        control_mode::task(control_mode_config.clone(), control_mode_bms.clone())
+    });
+
+    let auto_restart_handle = config.auto_restart.enabled.then(|| {
+        let restart_config = config.auto_restart.clone();
+        let restart_bms = vec![bms_data1.clone(), bms_data2.clone()];
+        supervisor.spawn("auto_restart", move || {
+            auto_restart::task(restart_config.clone(), restart_bms.clone(), input_tx_restart.clone())
+        })
+    });
+
+    log::info!("Spawning input flag manager task...");
 
-    log::info!("All tasks spawned.");
+    let outputs = CommandOutputs {
+        targets: output_targets,
+        led_tx: led_out_tx,
+        result_rx,
+        result_timeout: config.arbiter.result_timeout(),
+        max_retries: config.arbiter.max_retries,
+        connections,
+        block_on_while_down: config.arbiter.block_on_while_down,
+        soft_start: config.arbiter.soft_start.enabled.then(|| SoftStart {
+            delay: config.arbiter.soft_start.delay(),
+            result_timeout: config.arbiter.soft_start.result_timeout(),
+        }),
+        emergency_deadline: config.arbiter.emergency_off.enabled.then(|| config.arbiter.emergency_off.deadline()),
+    };
+    // The arbiter owns the command receiver, so it cannot be restarted. It blocks, on a
+    // thread of its own it cannot stall the runtime (e.g. the current_thread flavor).
+    let (arbiter_exit_tx, arbiter_exit_rx) = tokio::sync::oneshot::channel();
+    let policy = ArbitrationPolicy {
+        priority: config.arbiter.source_priority.clone(),
+        off_always_wins: config.arbiter.off_always_wins,
+        inhibit_on_fault: config.arbiter.inhibit_on_fault,
+        lockout: config.arbiter.lockout.clone(),
+        flag_labels: config.flags.clone(),
+    };
+    let arbiter_bms = vec![(bms_id1, bms_data1.clone()), (bms_id2, bms_data2.clone())];
+    let history_config = config.command_history.clone();
+    std::thread::Builder::new()
+        .name("arbiter".to_string())
+        .spawn(move || {
+            let result = arbiter::input_flag_manager_task(
+                arbiter_bms,
+                input_rx,
+                outputs,
+                policy,
+                history_config,
+                shutdown_tx,
+            );
+            let _ = arbiter_exit_tx.send(result);
+        })
+        .map_err(AppError::Runtime)?;
+    let input_flag_manager_handle = supervisor.spawn_once("arbiter", async move {
+        arbiter_exit_rx
+            .await
+            .unwrap_or_else(|_| Err(AppError::ReceiveError("arbiter thread panicked".to_string())))
+    });
+
+    log::info!("All tasks spawned, entering running state.");
 
     // --- Main Control Loop ---
     // This loop waits for state changes from the GPIO input task
     // and broadcasts commands accordingly.
-    loop {
-        tokio::select! {
-              // Handle Ctrl+C signal for graceful shutdown
-              _ = signal::ctrl_c() => {
-                log::info!("Main: Ctrl+C received. Shutting down.");
-                break; // Exit the main loop
-              }
-              // Add other conditions or timeouts if needed
-        }
-    }
+    // Ok(true) after QUIT, Err with the error of a task that cannot run
+    let stop: Result<bool, AppError> = tokio::select! {
+          // Handle Ctrl+C signal for graceful shutdown
+          _ = signal::ctrl_c() => {
+            log::info!("Main: Ctrl+C received. Shutting down.");
+            Ok(false)
+          }
+          // QUIT accepted by the arbiter, its frame has been sent and no commands are taken anymore.
+          // A closed channel (arbiter gone without QUIT) disables this branch.
+          Ok(source) = shutdown_rx => {
+            log::warn!("Main: QUIT from {:?} received. Shutting down.", source);
+            Ok(true)
+          }
+          // A configuration, CAN or bind error that a restart would not fix
+          Some((task, error)) = fatal_rx.recv() => {
+            log::error!("Main: Task '{}' cannot run. Shutting down.", task);
+            Err(error)
+          }
+    };
+    let quit = matches!(stop, Ok(true));
 
     // --- Graceful Shutdown ---
     log::info!("Main: Aborting all tasks...");
@@ -279,12 +881,78 @@ async fn main() -> Result<(), AppError> {
     gp_in_handle.abort();
     modbus_server1_handle.abort();
     modbus_server2_handle.abort();
-    modbus_client1_handle.abort();
-    modbus_client2_handle.abort();
+    for handle in &modbus_client_handles {
+        handle.abort();
+    }
     can_tx_handle.abort();
     gp_out_handle.abort();
     input_flag_manager_handle.abort();
+    statistics_handle.abort();
+    if let Some(handle) = &clock_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &recorder_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &black_box_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &storage_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &flow_state_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &http_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &snapshot_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &snmp_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &victron_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &scheduler_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &auto_restart_handle {
+        handle.abort();
+    }
+    control_mode_handle.abort();
+    if let Some(handle) = &display_handle {
+        handle.abort();
+    }
+    #[cfg(feature = "opcua")]
+    if let Some(handle) = &opcua_handle {
+        handle.abort();
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(handle) = &grpc_handle {
+        handle.abort();
+    }
+    #[cfg(feature = "uplink")]
+    if let Some(handle) = &uplink_handle {
+        handle.abort();
+    }
+
+    // Fail-safe state for the external interlocks
+    relays.release();
+    if quit && let Err(e) = gpio::show_shutdown(gpio_provider.as_ref(), &panel_pins) {
+        log::warn!("Cannot switch the LEDs off: {}", e);
+    }
+
+    if let Err(e) = statistics::save(&config.statistics, &statistics) {
+        log::error!("Failed to persist statistics on shutdown: {}", e);
+    }
+    // Batched files still pending, nothing is queued after this
+    if let Some(handle) = &persist_handle {
+        handle.abort();
+    }
+    log::info!("Wrote {} pending files.", persist::flush());
 
     log::info!("Application finished.");
-    Ok(())
+    stop.map(|quit| if quit { ExitCode::from(error::EXIT_QUIT) } else { ExitCode::SUCCESS })
 }
@@ -1,95 +1,694 @@
 // src/modbus_client.rs
+use crate::arbiter::CommandResult;
+use crate::connections::{ConnectionRegistry, ConnectionState, InverterIdentity};
+use crate::config::{FaultCheckConfig, InverterConfig, InverterProfile, RegisterWrite, RetryConfig, RunningCheckConfig};
+use crate::control_mode::ControlMode;
+use crate::data::{BmsData, SharedBmsData};
 use crate::error::AppError;
+use crate::fault::FaultEvent;
+use crate::flow::FlowState;
+use crate::rules::{Severity, SeverityMap};
 use crate::SystemCommand;
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    hash::{BuildHasher, RandomState},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::net::TcpStream;
-use tokio::time::sleep;
+use tokio::sync::{Mutex, mpsc::UnboundedReceiver, oneshot};
+use tokio::time::{sleep, timeout};
 use tokio_modbus::{
     client::*,
-    prelude::{Client, Slave},
+    prelude::{Client, ExceptionCode, Slave},
 };
 
-// --- Modbus Register Definitions (unverändert) ---
-const INVERTER_REG_MODE: u16 = 40231;
-const INVERTER_REG_UNKNOWN1: u16 = 40191;
-const INVERTER_REG_UNKNOWN2: u16 = 40187;
+// --- Pending Commands ---
+/// A command that could not be executed and is retried after reconnecting.
+#[derive(Debug)]
+struct PendingCommand {
+    command: SystemCommand,
+    queued_at: Instant,
+    // Whether the arbiter expects a result for this command
+    report: bool,
+}
+
+/// Reduces the queued On/Off commands to the one that still matters and moves it to
+/// the front: the latest of them, except that a queued Off always wins over On.
+/// Other commands keep their order. Returns the superseded entries.
+fn coalesce(pending: &mut VecDeque<PendingCommand>) -> Vec<PendingCommand> {
+    let is_switch = |p: &PendingCommand| matches!(p.command, SystemCommand::On | SystemCommand::Off);
+    let keep = pending
+        .iter()
+        .position(|p| p.command == SystemCommand::Off)
+        .or_else(|| pending.iter().rposition(is_switch));
+    let Some(keep) = keep else {
+        return Vec::new();
+    };
+    let Some(kept) = pending.remove(keep) else {
+        return Vec::new();
+    };
+    let (superseded, mut remaining): (Vec<_>, VecDeque<_>) = pending.drain(..).partition(is_switch);
+    remaining.push_front(kept);
+    *pending = remaining;
+    superseded
+}
+
+// --- Read Queue ---
+// Reads waiting for the connection of one inverter, further requests are refused
+const READ_QUEUE_LEN: usize = 16;
+
+/// Read of holding registers queued on the connection of an inverter.
+#[derive(Debug)]
+pub struct ReadRequest {
+    register: u16,
+    count: u16,
+    reply: oneshot::Sender<Result<Vec<u16>, String>>,
+}
+
+/// Handle for reads (telemetry polls, diagnostics) on the connection of one inverter.
+/// The client task serves them only while no command, error signal or derating is
+/// pending, so commands preempt them and sequences are never interleaved with them.
+#[derive(Debug, Clone)]
+pub struct InverterLink {
+    pub name: String,
+    tx: crossbeam_channel::Sender<ReadRequest>,
+}
+
+impl InverterLink {
+    /// The receiver is passed to the client task of the inverter.
+    pub fn new(name: &str) -> (Self, crossbeam_channel::Receiver<ReadRequest>) {
+        let (tx, rx) = crossbeam_channel::bounded(READ_QUEUE_LEN);
+        (Self { name: name.to_string(), tx }, rx)
+    }
+
+    /// Reads `count` holding registers from `register`. Fails if the queue is full, the
+    /// inverter does not answer or nothing was read within `limit` (e.g. while disconnected).
+    pub async fn read_holding_registers(&self, register: u16, count: u16, limit: Duration) -> Result<Vec<u16>, String> {
+        let (reply, response) = oneshot::channel();
+        self.tx.try_send(ReadRequest { register, count, reply }).map_err(|e| match e {
+            crossbeam_channel::TrySendError::Full(_) => "read queue full".to_string(),
+            crossbeam_channel::TrySendError::Disconnected(_) => "client task stopped".to_string(),
+        })?;
+        match timeout(limit, response).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("request dropped by the client task".to_string()),
+            Err(_) => Err(format!("no response within {:?}", limit)),
+        }
+    }
+}
+
+// --- Channel Bridge ---
+// Forwards a crossbeam channel into a tokio channel from a single thread, so the
+// receiver can be awaited in select! without losing messages to a losing branch.
+// The tokio receiver reports the channel as closed once the sender side is gone.
+fn bridge<T: Send + 'static>(
+    name: String,
+    rx: crossbeam_channel::Receiver<T>,
+) -> Result<Mutex<UnboundedReceiver<T>>, AppError> {
+    let (tx, bridged_rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            for message in rx.iter() {
+                if tx.send(message).is_err() {
+                    break; // Channels dropped at shutdown
+                }
+            }
+        })
+        .map_err(AppError::Runtime)?;
+    Ok(Mutex::new(bridged_rx))
+}
 
-const INVERTER_OFF_MODE_VALUE: u16 = 3;
-const INVERTER_OFF_UNKNOWN1_VALUE: u16 = 0;
-const INVERTER_OFF_UNKNOWN2_VALUE: u16 = 0;
+/// The input channels of a client task, bridged once and shared by all runs of the task.
+/// A bridge per run would outlive a panicked run and take messages meant for the next one.
+pub struct ClientChannels {
+    commands: Mutex<UnboundedReceiver<SystemCommand>>,
+    errors: Mutex<UnboundedReceiver<FaultEvent>>,
+    reads: Mutex<UnboundedReceiver<ReadRequest>>,
+}
 
-const SLAVE_ID: Slave = Slave(1);
+impl ClientChannels {
+    pub fn new(
+        name: &str,
+        output_rx: crossbeam_channel::Receiver<SystemCommand>,
+        error_rx: crossbeam_channel::Receiver<FaultEvent>,
+        read_rx: crossbeam_channel::Receiver<ReadRequest>,
+    ) -> Result<Arc<Self>, AppError> {
+        Ok(Arc::new(Self {
+            commands: bridge(format!("{}-commands", name), output_rx)?,
+            errors: bridge(format!("{}-errors", name), error_rx)?,
+            reads: bridge(format!("{}-reads", name), read_rx)?,
+        }))
+    }
+}
+
+// --- Exponential Backoff ---
+/// Delay generator for command retries: doubles every attempt up to a cap, with random jitter.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    attempt: u32,
+    random: RandomState,
+}
+
+impl Backoff {
+    fn new(config: &RetryConfig) -> Self {
+        Self {
+            initial: config.initial_backoff(),
+            max: config.max_backoff(),
+            jitter: config.jitter.clamp(0.0, 1.0),
+            attempt: 0,
+            random: RandomState::new(),
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let base = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        // Spread retries of several inverters by +/- jitter of the base delay
+        let random = self.random.hash_one(self.attempt) as f64 / u64::MAX as f64;
+        base.mul_f64(1.0 + self.jitter * (2.0 * random - 1.0))
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+// --- Sequence Errors ---
+/// Why an inverter command could not be executed.
+#[derive(Debug, thiserror::Error)]
+enum SequenceError {
+    // Transport failure, the connection has to be re-established
+    #[error("{0}")]
+    Transport(#[from] tokio_modbus::Error),
+    // The inverter answered with a Modbus exception
+    #[error("register {register} answered with exception {code:?}")]
+    Exception { register: u16, code: ExceptionCode },
+    // The fault check register reports a latched fault, ON is refused
+    #[error("fault latched (register {register} = {value:#06X})")]
+    FaultLatched { register: u16, value: u16 },
+    // The inverter did not report running within the timeout after ON
+    #[error("not running after ON (register {register} = {value:#06X})")]
+    NotRunning { register: u16, value: u16 },
+    // The inverter did not report stopped after OFF, not even after repeating it
+    #[error("not stopped after {attempts} OFF attempt(s) (register {register} = {value:#06X})")]
+    NotStopped { register: u16, value: u16, attempts: u32 },
+}
+
+// --- Transactions ---
+// A request running into a time limit leaves the connection out of step with the
+// inverter, so it is handled like a transport failure (reconnect and retry)
+fn timed_out(message: String) -> SequenceError {
+    SequenceError::Transport(tokio_modbus::Error::Transport(io::Error::new(io::ErrorKind::TimedOut, message)))
+}
+
+// One request/response on `register`, limited to `limit`
+async fn transaction<T>(
+    limit: Duration,
+    register: u16,
+    request: impl Future<Output = tokio_modbus::Result<T>>,
+) -> Result<T, SequenceError> {
+    match timeout(limit, request).await {
+        Ok(response) => response?.map_err(|code| SequenceError::Exception { register, code }),
+        Err(_) => Err(timed_out(format!("register {} not answered within {:?}", register, limit))),
+    }
+}
+
+// Runs a sequence (with its read-back checks) within the sequence deadline of `config`
+async fn within_deadline<T>(
+    config: &InverterConfig,
+    name: &str,
+    sequence: impl Future<Output = Result<T, SequenceError>>,
+) -> Result<T, SequenceError> {
+    let Some(deadline) = config.timing.sequence_deadline() else {
+        return sequence.await;
+    };
+    timeout(deadline, sequence)
+        .await
+        .unwrap_or_else(|_| Err(timed_out(format!("{} sequence not completed within {:?}", name, deadline))))
+}
 
-// --- Helper Function for Inverter OFF Sequence (unverändert) ---
-async fn execute_inverter_off_sequence<C>(
+// --- Helper Function for Inverter Register Sequences ---
+// In dry-run mode the writes are only logged, the delays are still observed
+async fn execute_sequence<C>(
     ctx: &mut C,
     socket_addr: &SocketAddr,
-) -> Result<(), tokio_modbus::Error>
+    name: &str,
+    sequence: &[RegisterWrite],
+    config: &InverterConfig,
+) -> Result<(), SequenceError>
 where
     C: Client + Unpin + tokio_modbus::prelude::Writer,
 {
-    log::info!("Modbus Client ({}): Executing OFF sequence...", socket_addr);
-    let writes = [
-        (INVERTER_REG_MODE, INVERTER_OFF_MODE_VALUE),
-        (INVERTER_REG_UNKNOWN1, INVERTER_OFF_UNKNOWN1_VALUE),
-        (INVERTER_REG_UNKNOWN2, INVERTER_OFF_UNKNOWN2_VALUE),
-    ];
-
-    for (reg, val) in writes.iter() {
+    log::info!("Modbus Client ({}): Executing {} sequence...", socket_addr, name);
+
+    for write in sequence {
+        let delay = write.delay(config.timing.write_delay());
+        if config.dry_run() {
+            log::info!(
+                "Modbus Client ({}): DRY RUN: Would write {} ({:#06X}) to register {}, then wait {:?}",
+                socket_addr,
+                write.value,
+                write.value,
+                write.register,
+                delay
+            );
+            sleep(delay).await;
+            continue;
+        }
         log::debug!(
             "Modbus Client ({}): Writing {} to register {}",
             socket_addr,
-            val,
-            reg
+            write.value,
+            write.register
         );
-        let _ = ctx.write_single_register(*reg, *val).await?;
-        sleep(Duration::from_millis(50)).await;
+        transaction(
+            config.timing.transaction_timeout(),
+            write.register,
+            ctx.write_single_register(write.register, write.value),
+        )
+        .await?;
+        sleep(delay).await;
     }
 
     log::info!(
-        "Modbus Client ({}): OFF sequence completed successfully.",
-        socket_addr
+        "Modbus Client ({}): {} sequence completed successfully.",
+        socket_addr,
+        name
     );
     Ok(())
 }
 
+// Reads the fault register and fails if any of the configured fault bits is set
+async fn check_no_fault_latched<C>(
+    ctx: &mut C,
+    socket_addr: &SocketAddr,
+    check: &FaultCheckConfig,
+    limit: Duration,
+) -> Result<(), SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader,
+{
+    let values = transaction(limit, check.register, ctx.read_holding_registers(check.register, 1)).await?;
+    let value = values.first().copied().unwrap_or(0);
+    log::debug!("Modbus Client ({}): Fault register {} = {:#06X}", socket_addr, check.register, value);
+    if value & check.mask != 0 {
+        return Err(SequenceError::FaultLatched { register: check.register, value });
+    }
+    Ok(())
+}
+
+// Polls the status register until the inverter reports the state of `check` (named
+// `state` in the logs). Returns the last value read if it did not within the timeout.
+async fn wait_for_state<C>(
+    ctx: &mut C,
+    socket_addr: &SocketAddr,
+    check: &RunningCheckConfig,
+    state: &str,
+    limit: Duration,
+) -> Result<Option<u16>, SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader,
+{
+    let deadline = Instant::now() + check.timeout();
+    loop {
+        let values = transaction(limit, check.register, ctx.read_holding_registers(check.register, 1)).await?;
+        let value = values.first().copied().unwrap_or(0);
+        if value & check.mask == check.expected {
+            log::info!("Modbus Client ({}): Inverter {} (register {} = {:#06X}).", socket_addr, state, check.register, value);
+            return Ok(None);
+        }
+        if Instant::now() >= deadline {
+            return Ok(Some(value));
+        }
+        log::debug!("Modbus Client ({}): Waiting for inverter to be {} (register {} = {:#06X})", socket_addr, state, check.register, value);
+        sleep(check.poll_interval()).await;
+    }
+}
+
+// Polls the status register after ON until the inverter reports running
+async fn wait_until_running<C>(
+    ctx: &mut C,
+    socket_addr: &SocketAddr,
+    check: &RunningCheckConfig,
+    limit: Duration,
+) -> Result<(), SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader,
+{
+    match wait_for_state(ctx, socket_addr, check, "running", limit).await? {
+        None => Ok(()),
+        Some(value) => Err(SequenceError::NotRunning { register: check.register, value }),
+    }
+}
+
+// Executes the OFF sequence and reads back that the inverter stopped. The writes are
+// repeated up to `off_retries` times if it does not, a lost or ignored write would
+// otherwise leave the inverter running unnoticed.
+async fn switch_off<C>(ctx: &mut C, socket_addr: &SocketAddr, config: &InverterConfig) -> Result<(), SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader + tokio_modbus::prelude::Writer,
+{
+    let off_sequence = config.off_sequence();
+    // Nothing is written in dry-run mode, so there is nothing to read back
+    let Some(check) = config.off_check().filter(|_| !config.dry_run()) else {
+        return execute_sequence(ctx, socket_addr, "OFF", &off_sequence, config).await;
+    };
+    let attempts = config.off_retries + 1;
+    let mut value = 0;
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            log::warn!(
+                "Modbus Client ({}): OFF not confirmed (register {} = {:#06X}), repeating the OFF sequence (attempt {} of {}).",
+                socket_addr,
+                check.register,
+                value,
+                attempt,
+                attempts
+            );
+        }
+        execute_sequence(ctx, socket_addr, "OFF", &off_sequence, config).await?;
+        match wait_for_state(ctx, socket_addr, &check, "stopped", config.timing.transaction_timeout()).await? {
+            None => return Ok(()),
+            Some(last) => value = last,
+        }
+    }
+    Err(SequenceError::NotStopped { register: check.register, value, attempts })
+}
+
+// --- SunSpec Identification ---
+// Base addresses where SunSpec devices start their model chain with "SunS"
+const SUNSPEC_BASES: [u16; 3] = [40000, 0, 50000];
+const SUNSPEC_MARKER: [u16; 2] = [0x5375, 0x6E53];
+const SUNSPEC_COMMON_MODEL_ID: u16 = 1;
+// Mn, Md, Opt, Vr and SN of the common model (32 + 32 + 16 + 16 + 32 characters)
+const SUNSPEC_COMMON_LEN: u16 = 64;
+
+// SunSpec string: two characters per register, padded with NUL or spaces
+fn sunspec_string(registers: &[u16]) -> String {
+    let bytes: Vec<u8> = registers.iter().flat_map(|register| register.to_be_bytes()).collect();
+    String::from_utf8_lossy(&bytes).trim_end_matches(['\0', ' ']).to_string()
+}
+
+// Reads the common model, None if the device does not speak SunSpec
+async fn read_sunspec_identity<C>(ctx: &mut C, limit: Duration) -> Result<Option<InverterIdentity>, SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader,
+{
+    for base in SUNSPEC_BASES {
+        // Devices answer unmapped addresses with an exception, the next base is tried then
+        let marker = match transaction(limit, base, ctx.read_holding_registers(base, 4)).await {
+            Ok(marker) => marker,
+            Err(SequenceError::Exception { .. }) => continue,
+            Err(e) => return Err(e),
+        };
+        if marker.len() < 4 || marker[..2] != SUNSPEC_MARKER || marker[2] != SUNSPEC_COMMON_MODEL_ID {
+            continue;
+        }
+        let fields = transaction(limit, base + 4, ctx.read_holding_registers(base + 4, SUNSPEC_COMMON_LEN)).await?;
+        if fields.len() < usize::from(SUNSPEC_COMMON_LEN) {
+            return Ok(None);
+        }
+        return Ok(Some(InverterIdentity {
+            manufacturer: sunspec_string(&fields[0..16]),
+            model: sunspec_string(&fields[16..32]),
+            version: sunspec_string(&fields[40..48]),
+            serial: sunspec_string(&fields[48..64]),
+        }));
+    }
+    Ok(None)
+}
+
+// Checks for latched faults, executes the ON sequence and waits until the inverter runs
+async fn switch_on<C>(ctx: &mut C, socket_addr: &SocketAddr, config: &InverterConfig) -> Result<(), SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader + tokio_modbus::prelude::Writer,
+{
+    let on_sequence = config.on_sequence();
+    if on_sequence.is_empty() {
+        log::info!("Modbus Client ({}): No ON sequence configured (no action needed).", socket_addr);
+        return Ok(());
+    }
+    let limit = config.timing.transaction_timeout();
+    if let Some(check) = &config.fault_check {
+        check_no_fault_latched(ctx, socket_addr, check, limit).await?;
+    }
+    execute_sequence(ctx, socket_addr, "ON", &on_sequence, config).await?;
+    match &config.running_check() {
+        Some(check) if !config.dry_run() => wait_until_running(ctx, socket_addr, check, limit).await,
+        _ => Ok(()),
+    }
+}
+
+// Executes the register sequence belonging to a system command
+async fn execute_command<C>(
+    ctx: &mut C,
+    socket_addr: &SocketAddr,
+    config: &InverterConfig,
+    command: &SystemCommand,
+) -> Result<(), SequenceError>
+where
+    C: Client + Unpin + tokio_modbus::prelude::Reader + tokio_modbus::prelude::Writer,
+{
+    match command {
+        SystemCommand::Off => within_deadline(config, "OFF", switch_off(ctx, socket_addr, config)).await,
+        SystemCommand::On => within_deadline(config, "ON", switch_on(ctx, socket_addr, config)).await,
+        SystemCommand::Quit => {
+            log::info!("Modbus Client ({}): Received QUIT command (no action needed).", socket_addr);
+            Ok(())
+        }
+    }
+}
 
 // --- Modbus Client Task ---
+/// Drives one inverter. `index` is its position in the configuration and in `connections`.
 pub async fn task(
-    addr_str: &str,
-    error_rx: crossbeam_channel::Receiver<()>,
-    output_rx: crossbeam_channel::Receiver<SystemCommand>,
+    mut config: InverterConfig,
+    index: usize,
+    connections: Arc<ConnectionRegistry>,
+    control: SharedBmsData,
+    channels: Arc<ClientChannels>,
+    result_tx: crossbeam_channel::Sender<CommandResult>,
+    mut severity_rx: tokio::sync::watch::Receiver<SeverityMap>,
+    mut flow_rx: tokio::sync::watch::Receiver<FlowState>,
 ) -> Result<(), AppError> {
-    let socket_addr: SocketAddr = addr_str.parse().map_err(|e| {
-        log::error!("Invalid socket address format '{}': {}", addr_str, e);
-    }).unwrap();
+    let socket_addr: SocketAddr = config.addr.parse().map_err(|e| {
+        log::error!("Invalid socket address format '{}': {}", config.addr, e);
+        AppError::Config(format!("Invalid address for {}: '{}'", config.name, config.addr))
+    })?;
+    let slave = Slave(config.unit_id);
+
+    log::info!(
+        "Starting Modbus TCP client task for {} ({}, unit {})",
+        config.name,
+        socket_addr,
+        config.unit_id
+    );
+
+    if config.dry_run() {
+        log::warn!(
+            "Modbus Client ({}): DRY RUN enabled for {}, register writes are logged but not sent.",
+            socket_addr,
+            config.name
+        );
+    }
 
-    log::info!("Starting Modbus TCP client task for {}", socket_addr);
+    // The profile may change once the inverter is identified, the closures only keep what they need
+    let name = config.name.clone();
+    let down_after = config.down_after();
+    // Reports the outcome of an arbiter command; a closed channel only means nobody is waiting
+    let send_result = |command: SystemCommand, success: bool| {
+        let result = CommandResult { output: name.clone(), command, success };
+        if let Err(e) = result_tx.send(result) {
+            log::warn!("Modbus Client ({}): Failed to send command result: {}", socket_addr, e);
+        }
+    };
 
+    // Commands that failed and are retried after reconnecting, until the deadline expires
+    let mut pending: VecDeque<PendingCommand> = VecDeque::new();
+    let queue_command = |pending: &mut VecDeque<PendingCommand>, command: SystemCommand, report: bool| {
+        if let Some(queued) = pending.iter_mut().find(|p| p.command == command) {
+            log::debug!("Modbus Client ({}): {:?} already queued for retry.", socket_addr, command);
+            queued.report |= report;
+        } else {
+            log::warn!("Modbus Client ({}): Queueing {:?} for retry after reconnect.", socket_addr, command);
+            pending.push_back(PendingCommand { command, queued_at: Instant::now(), report });
+        }
+    };
+    let mut backoff = Backoff::new(&config.retry);
+
+    // Free again once a previous run ended, a restarted run continues with its messages
+    let mut output_rx = channels.commands.lock().await;
+    let mut error_rx = channels.errors.lock().await;
+    let mut read_rx = channels.reads.lock().await;
     // Flag, um zu verfolgen, ob der error_rx-Kanal geschlossen ist
     let mut error_rx_closed = false;
+    // Derating state applied to the inverter, None if unknown (e.g. after reconnect)
+    let mut derated: Option<bool>;
+    let mut severity_rx_closed = false;
+    let mut flow_rx_closed = false;
+    let mut read_rx_closed = false;
+    // Start of the current outage, decides between Reconnecting and Down
+    let mut disconnected_since = Instant::now();
+    let set_disconnected = |disconnected_since: Instant| {
+        let state = if disconnected_since.elapsed() >= down_after {
+            ConnectionState::Down
+        } else {
+            ConnectionState::Reconnecting
+        };
+        connections.set(index, state);
+    };
+    // An OFF the inverter did not confirm stays raised as alarm until an OFF is confirmed
+    let track_off = |command: &SystemCommand, result: &Result<(), SequenceError>| {
+        if *command != SystemCommand::Off {
+            return;
+        }
+        match result {
+            Ok(()) => connections.set_off_unconfirmed(index, false),
+            Err(e @ SequenceError::NotStopped { .. }) => {
+                log::error!(target: "audit", "Inverter {} may still be running, OFF failed: {}", name, e);
+                connections.set_off_unconfirmed(index, true);
+            }
+            Err(_) => {}
+        }
+    };
 
     loop {
         // --- Connection Loop (unverändert) ---
         log::info!("Modbus Client ({}): Attempting to connect...", socket_addr);
-        let stream = match TcpStream::connect(socket_addr).await {
+        let connect_timeout = config.timing.connect_timeout();
+        let connected = timeout(connect_timeout, TcpStream::connect(socket_addr)).await.unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::TimedOut, format!("no connection within {:?}", connect_timeout)))
+        });
+        let stream = match connected {
             Ok(s) => {
                 log::info!("Modbus Client ({}): Connection established.", socket_addr);
                 s
             }
             Err(e) => {
                 log::error!(
-                    "Modbus Client ({}): Connection failed: {}. Retrying in 5s.",
+                    "Modbus Client ({}): Connection failed: {}. Retrying in {:?}.",
                     socket_addr,
-                    e
+                    e,
+                    config.reconnect_delay()
                 );
-                sleep(Duration::from_secs(5)).await;
+                set_disconnected(disconnected_since);
+                sleep(config.reconnect_delay()).await;
                 continue; // Retry connection
             }
         };
 
         // Create Modbus context (unverändert)
-        let mut ctx = tcp::attach_slave(stream, SLAVE_ID);
+        let mut ctx = tcp::attach_slave(stream, slave);
+
+        // --- Identification ---
+        if config.identify {
+            match read_sunspec_identity(&mut ctx, config.timing.transaction_timeout()).await {
+                Ok(Some(identity)) => {
+                    log::info!(
+                        "Modbus Client ({}): {} {} (serial {}, firmware {})",
+                        socket_addr, identity.manufacturer, identity.model, identity.serial, identity.version
+                    );
+                    if config.profile == InverterProfile::Auto {
+                        match InverterProfile::from_manufacturer(&identity.manufacturer) {
+                            Some(profile) => {
+                                log::info!("Modbus Client ({}): Using the {:?} profile.", socket_addr, profile);
+                                config.profile = profile;
+                            }
+                            None => log::warn!(
+                                "Modbus Client ({}): No profile for manufacturer '{}', using the generic registers.",
+                                socket_addr, identity.manufacturer
+                            ),
+                        }
+                    }
+                    connections.set_identity(index, identity);
+                }
+                Ok(None) => log::info!("Modbus Client ({}): No SunSpec common model found.", socket_addr),
+                Err(e) => log::warn!("Modbus Client ({}): Identification failed: {}", socket_addr, e),
+            }
+        }
+
+        // --- Replay commands that failed on a previous connection ---
+        // Commands received while disconnected are coalesced with them instead of replayed in order
+        while let Ok(command) = output_rx.try_recv() {
+            queue_command(&mut pending, command, true);
+        }
+        for entry in coalesce(&mut pending) {
+            log::info!(
+                "Modbus Client ({}): Dropping queued {:?}, superseded by {:?}.",
+                socket_addr,
+                entry.command,
+                pending.front().map(|p| &p.command)
+            );
+            if entry.report {
+                send_result(entry.command, false);
+            }
+        }
+        let mut replay_failed = false;
+        while let Some(entry) = pending.front() {
+            if entry.queued_at.elapsed() > config.retry.deadline() {
+                log::error!(
+                    "Modbus Client ({}): Giving up on {:?}, retry deadline of {:?} expired.",
+                    socket_addr,
+                    entry.command,
+                    config.retry.deadline()
+                );
+                if entry.report {
+                    send_result(entry.command.clone(), false);
+                }
+                pending.pop_front();
+                continue;
+            }
+            log::info!("Modbus Client ({}): Retrying queued {:?}...", socket_addr, entry.command);
+            let result = execute_command(&mut ctx, &socket_addr, &config, &entry.command).await;
+            track_off(&entry.command, &result);
+            match result {
+                Ok(()) => {
+                    if entry.report {
+                        send_result(entry.command.clone(), true);
+                    }
+                    pending.pop_front();
+                }
+                Err(e @ SequenceError::Transport(_)) => {
+                    log::error!("Modbus Client ({}): Retry of {:?} failed: {}", socket_addr, entry.command, e);
+                    replay_failed = true;
+                    break;
+                }
+                Err(e) => {
+                    // Rejected by the inverter itself, retrying will not help
+                    log::error!("Modbus Client ({}): {:?} rejected by inverter: {}", socket_addr, entry.command, e);
+                    if entry.report {
+                        send_result(entry.command.clone(), false);
+                    }
+                    pending.pop_front();
+                }
+            }
+        }
+        if replay_failed {
+            set_disconnected(disconnected_since);
+            let delay = backoff.next_delay();
+            log::warn!("Modbus Client ({}): Next retry in {:?}.", socket_addr, delay);
+            sleep(delay).await;
+            continue;
+        }
+        backoff.reset();
+        connections.set(index, ConnectionState::Connected);
+
+        // The inverter may have lost its limits, re-apply the current derating state
+        derated = None;
+        severity_rx.mark_changed();
+
+        // An exception answer to the keep-alive is reported once per connection
+        let mut keep_alive_exception_logged = false;
 
         // --- Command Processing Loop (while connected) ---
         'inner: loop {
@@ -97,75 +696,183 @@ pub async fn task(
                 biased; // Prioritize receiving commands/errors over keep-alive
 
                 // --- output_rx branch ---
-                // Klonen und in spawn_blocking verschieben
-                result = { let rx = output_rx.clone(); tokio::task::spawn_blocking(move || rx.recv()) } => {
-                    match result {
-                        Ok(Ok(command)) => {
+                command = output_rx.recv() => {
+                    match command {
+                        Some(command) => {
                             log::debug!("Modbus Client ({}): Received command: {:?}", socket_addr, command);
-                            match command {
-                                SystemCommand::Off => {
-                                    match execute_inverter_off_sequence(&mut ctx, &socket_addr).await {
-                                        Ok(_) => { /* Success logged */ }
-                                        Err(e) => {
-                                            log::error!("Modbus Client ({}): OFF sequence failed during command execution: {}", socket_addr, e);
-                                            break 'inner; // Reconnect on failure
-                                        }
-                                    }
+                            let result = execute_command(&mut ctx, &socket_addr, &config, &command).await;
+                            track_off(&command, &result);
+                            match result {
+                                Ok(()) => send_result(command, true),
+                                Err(e @ SequenceError::Transport(_)) => {
+                                    log::error!("Modbus Client ({}): {:?} failed during command execution: {}", socket_addr, command, e);
+                                    queue_command(&mut pending, command, true);
+                                    break 'inner; // Reconnect on failure
                                 }
-                                SystemCommand::On => {
-                                    log::info!("Modbus Client ({}): Received ON command (no action needed).", socket_addr);
-                                }
-                                SystemCommand::Quit => {
-                                    log::info!("Modbus Client ({}): Received QUIT command (no action needed).", socket_addr);
+                                Err(e) => {
+                                    log::error!("Modbus Client ({}): {:?} rejected by inverter: {}", socket_addr, command, e);
+                                    send_result(command, false);
                                 }
                             }
                         }
-                        Ok(Err(e)) => {
+                        None => {
                             // Wenn der *Befehlskanal* schließt, wollen wir wahrscheinlich beenden.
-                            log::warn!("Modbus Client ({}): Command channel (output_rx) closed or disconnected: {}. Exiting task.", socket_addr, e);
+                            log::warn!("Modbus Client ({}): Command channel (output_rx) closed. Exiting task.", socket_addr);
                             return Ok(()); // Task beenden, da keine Befehle mehr kommen können
                         }
-                        Err(e) => {
-                            log::error!("Modbus Client ({}): Blocking task for output_rx failed: {}. Breaking connection.", socket_addr, e);
-                            break 'inner; // Kritischer Fehler -> Reconnect
-                        }
                     }
                 }
 
                 // --- error_rx branch (nur pollen, wenn nicht geschlossen) ---
                 // Syntax: future = ..., if condition
-                result = { let rx = error_rx.clone(); tokio::task::spawn_blocking(move || rx.recv()) }, if !error_rx_closed => {
-                     match result {
-                        Ok(Ok(())) => { // Signal empfangen
-                            log::warn!("Modbus Client ({}): Received error signal. Executing OFF sequence...", socket_addr);
-                             match execute_inverter_off_sequence(&mut ctx, &socket_addr).await {
+                signal = error_rx.recv(), if !error_rx_closed => {
+                    match signal {
+                        // A cleared fault does not switch the inverter back on, that takes an ON
+                        Some(FaultEvent::Cleared(condition)) => {
+                            log::info!("Modbus Client ({}): Fault {:?} cleared.", socket_addr, condition);
+                        }
+                        Some(FaultEvent::Raised(condition)) if control.read(BmsData::control_mode) == ControlMode::Maintenance => {
+                            log::warn!("Modbus Client ({}): Fault {:?} raised in maintenance mode, not switching off.", socket_addr, condition);
+                        }
+                        Some(FaultEvent::Raised(condition)) => { // Signal empfangen
+                            log::warn!("Modbus Client ({}): Fault {:?} raised. Executing OFF sequence...", socket_addr, condition);
+                            let result = execute_command(&mut ctx, &socket_addr, &config, &SystemCommand::Off).await;
+                            track_off(&SystemCommand::Off, &result);
+                            match result {
                                 Ok(_) => { /* Success logged */ }
-                                Err(e) => {
+                                Err(e @ SequenceError::Transport(_)) => {
                                     log::error!("Modbus Client ({}): OFF sequence failed after error signal: {}", socket_addr, e);
+                                    queue_command(&mut pending, SystemCommand::Off, false);
                                     // Brechen Sie die Verbindung ab, wenn die *Ausführung* der OFF-Sequenz fehlschlägt
                                     break 'inner;
                                 }
+                                Err(e) => {
+                                    log::error!("Modbus Client ({}): OFF sequence rejected by inverter after error signal: {}", socket_addr, e);
+                                }
                             }
                         }
-                        Ok(Err(e)) => { // Kanal wurde geschlossen (recv-Fehler)
-                             log::warn!("Modbus Client ({}): Error channel (error_rx) closed or disconnected: {}. Will stop listening on this channel.", socket_addr, e);
-                             // Setze Flag, damit dieser Zweig nicht mehr abgefragt wird
-                             error_rx_closed = true;
-                             // NICHT `return` oder `break`! Einfach weiterlaufen lassen.
+                        None => { // Kanal wurde geschlossen
+                            log::warn!("Modbus Client ({}): Error channel (error_rx) closed. Will stop listening on this channel.", socket_addr);
+                            // Setze Flag, damit dieser Zweig nicht mehr abgefragt wird
+                            error_rx_closed = true;
+                            // NICHT `return` oder `break`! Einfach weiterlaufen lassen.
+                        }
+                    }
+                }
+
+                // --- Derating branch (threshold rule severity changed) ---
+                changed = severity_rx.changed(), if !severity_rx_closed => {
+                    if changed.is_err() {
+                        log::warn!("Modbus Client ({}): Severity channel closed. Derating disabled.", socket_addr);
+                        severity_rx_closed = true;
+                        continue;
+                    }
+                    let highest = severity_rx.borrow_and_update().values().max().copied().unwrap_or_default();
+                    let derate = highest >= Severity::Derate;
+                    if derated == Some(derate) {
+                        continue;
+                    }
+                    let (name, sequence) = if derate {
+                        ("DERATE", config.derate_sequence_for(*flow_rx.borrow()))
+                    } else {
+                        ("DERATE RELEASE", config.derate_release_sequence())
+                    };
+                    if sequence.is_empty() {
+                        derated = Some(derate);
+                        continue;
+                    }
+                    let derate_sequence = execute_sequence(&mut ctx, &socket_addr, name, &sequence, &config);
+                    match within_deadline(&config, name, derate_sequence).await {
+                        Ok(()) => derated = Some(derate),
+                        Err(e @ SequenceError::Transport(_)) => {
+                            log::error!("Modbus Client ({}): {} sequence failed: {}", socket_addr, name, e);
+                            break 'inner;
+                        }
+                        Err(e) => {
+                            log::error!("Modbus Client ({}): {} sequence rejected by inverter: {}", socket_addr, name, e);
                         }
-                        Err(e) => { // spawn_blocking-Task selbst fehlgeschlagen
-                             log::error!("Modbus Client ({}): Blocking task for error_rx failed: {}. Assuming channel unusable.", socket_addr, e);
-                             // Setze Flag, damit dieser Zweig nicht mehr abgefragt wird
-                             error_rx_closed = true;
-                             // NICHT `return` oder `break`! Einfach weiterlaufen lassen.
+                    }
+                }
+
+                // --- Flow state branch (switch the limit registers with the current direction) ---
+                changed = flow_rx.changed(), if !flow_rx_closed => {
+                    if changed.is_err() {
+                        log::warn!("Modbus Client ({}): Flow state channel closed. Using the derate sequence for both directions.", socket_addr);
+                        flow_rx_closed = true;
+                        continue;
+                    }
+                    let flow = *flow_rx.borrow_and_update();
+                    // Only a charge specific sequence changes anything while derated
+                    if derated != Some(true) || config.derate_charge_sequence.is_none() {
+                        continue;
+                    }
+                    let sequence = config.derate_sequence_for(flow);
+                    log::info!("Modbus Client ({}): Current direction is now {:?}, re-applying the derate limits.", socket_addr, flow);
+                    let derate_sequence = execute_sequence(&mut ctx, &socket_addr, "DERATE", &sequence, &config);
+                    match within_deadline(&config, "DERATE", derate_sequence).await {
+                        Ok(()) => {}
+                        Err(e @ SequenceError::Transport(_)) => {
+                            log::error!("Modbus Client ({}): DERATE sequence failed: {}", socket_addr, e);
+                            derated = None;
+                            break 'inner;
                         }
+                        Err(e) => {
+                            log::error!("Modbus Client ({}): DERATE sequence rejected by inverter: {}", socket_addr, e);
+                        }
+                    }
+                }
+
+                // --- Queued reads, below everything that changes the inverter ---
+                request = read_rx.recv(), if !read_rx_closed => {
+                    let Some(request) = request else {
+                        read_rx_closed = true;
+                        continue;
+                    };
+                    // The caller gave up (e.g. it was queued while disconnected)
+                    if request.reply.is_closed() {
+                        continue;
+                    }
+                    let read = ctx.read_holding_registers(request.register, request.count);
+                    let (result, transport_failed) = match transaction(config.timing.transaction_timeout(), request.register, read).await {
+                        Ok(values) => (Ok(values), false),
+                        Err(e @ SequenceError::Transport(_)) => (Err(e.to_string()), true),
+                        Err(e) => (Err(e.to_string()), false),
+                    };
+                    // Nobody waits anymore if the caller timed out meanwhile
+                    let _ = request.reply.send(result);
+                    if transport_failed {
+                        log::error!("Modbus Client ({}): Queued read of register {} failed. Reconnecting.", socket_addr, request.register);
+                        break 'inner;
                     }
                 }
 
-                // --- Keep-alive branch (unverändert) ---
-                _ = sleep(Duration::from_secs(30)) => {
-                     match ctx.read_holding_registers(40070, 1).await { // Example register
-                        Ok(_) => { /* Connection seems okay */ }
+                // --- Keep-alive branch ---
+                _ = sleep(config.keep_alive.interval()), if config.keep_alive.enabled => {
+                    let keep_alive = &config.keep_alive;
+                    let read = ctx.read_holding_registers(keep_alive.register, keep_alive.count);
+                    match transaction(config.timing.transaction_timeout(), keep_alive.register, read).await {
+                        Ok(values) => {
+                            if let Some(expected) = &keep_alive.expected
+                                && values != *expected
+                            {
+                                log::error!(
+                                    "Modbus Client ({}): Keep-alive register {} returned {:?}, expected {:?}. Reconnecting.",
+                                    socket_addr, keep_alive.register, values, expected
+                                );
+                                break 'inner;
+                            }
+                            log::trace!("Modbus Client ({}): Keep-alive OK: {:?}", socket_addr, values);
+                        }
+                        // The inverter answered, so the link is alive even without the register
+                        Err(SequenceError::Exception { code, .. }) => {
+                            if !keep_alive_exception_logged {
+                                keep_alive_exception_logged = true;
+                                log::warn!(
+                                    "Modbus Client ({}): Keep-alive read of register {} returned exception {:?}. Check the keep_alive configuration.",
+                                    socket_addr, keep_alive.register, code
+                                );
+                            }
+                        }
                         Err(e) => {
                             log::error!("Modbus Client ({}): Keep-alive read failed: {}. Assuming disconnection.", socket_addr, e);
                             break 'inner; // Break inner loop to reconnect
@@ -175,12 +882,18 @@ pub async fn task(
             } // end tokio::select!
         } // end inner loop (while connected)
 
-        // Reconnect logic (unverändert)
+        // Reconnect logic
         log::warn!(
             "Modbus Client ({}): Connection lost or error occurred. Reconnecting...",
             socket_addr
         );
-        // Ggf. kurze Pause vor dem Neuverbindungsversuch einfügen
-        // sleep(Duration::from_secs(1)).await;
+        disconnected_since = Instant::now();
+        set_disconnected(disconnected_since);
+        // Back off before replaying failed commands on the new connection
+        if !pending.is_empty() {
+            let delay = backoff.next_delay();
+            log::warn!("Modbus Client ({}): {} command(s) pending, retrying in {:?}.", socket_addr, pending.len(), delay);
+            sleep(delay).await;
+        }
     } // end outer loop (reconnection)
 }
\ No newline at end of file
@@ -1,27 +1,403 @@
 // src/modbus_server.rs
 use crate::{
     SystemCommand,
-    data::BmsData, // Import specific register constants
+    arbiter::{CommandSource, SourcedCommand},
+    can::{RegisterTunnel, TunnelError},
+    config::{InvalidValueConfig, ModbusServerConfig, RegisterScaling, Role, WordOrder},
+    data::{BmsData, REG_OFF_CONFIRM, REG_ON, REG_QUIT, SharedBmsData, read_register}, // Import specific register constants
     error::AppError,
+    flags,
+    modbus_stats::ModbusCounters,
+    site::SiteMap,
+    trace::ProtocolTrace,
 };
 use std::{
     future::Future,
+    io,
     net::SocketAddr,
     pin::Pin,
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener, // Use tokio::net::TcpListener
+    time::{Sleep, sleep, timeout},
 };
-use tokio::net::TcpListener; // Use tokio::net::TcpListener
 use tokio_modbus::{
     prelude::*, // Includes ExceptionCode, Request, Response etc.
     server::tcp::{Server, accept_tcp_connection},
 };
 
+// --- Connection Tracking ---
+// Counts a connection as active while alive, decrements the counter when the connection closes
+#[derive(Debug)]
+struct ConnectionGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// --- Idle Timeout ---
+// Client stream that fails with TimedOut once neither a read nor a write made progress for
+// `timeout`. The server then drops the connection, including half-open ones whose client
+// vanished without a FIN.
+#[derive(Debug)]
+struct IdleTimeoutStream<T> {
+    inner: T,
+    peer_addr: SocketAddr,
+    // Timeout and its running deadline, None if connections may idle forever
+    timeout: Option<(Duration, Pin<Box<Sleep>>)>,
+}
+
+impl<T> IdleTimeoutStream<T> {
+    fn new(inner: T, peer_addr: SocketAddr, timeout: Option<Duration>) -> Self {
+        let timeout = timeout.map(|timeout| (timeout, Box::pin(sleep(timeout))));
+        Self { inner, peer_addr, timeout }
+    }
+
+    // Restarts the deadline on progress, fails a pending operation once it has passed
+    fn check<R>(&mut self, cx: &mut Context<'_>, poll: Poll<io::Result<R>>) -> Poll<io::Result<R>> {
+        let Some((timeout, deadline)) = &mut self.timeout else {
+            return poll;
+        };
+        match poll {
+            Poll::Ready(result) => {
+                deadline.as_mut().reset(tokio::time::Instant::now() + *timeout);
+                Poll::Ready(result)
+            }
+            Poll::Pending => match deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Modbus client {} idle for {:?}, closing the connection", self.peer_addr, timeout),
+                ))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleTimeoutStream<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.check(cx, poll)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleTimeoutStream<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.check(cx, poll)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.check(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// --- Request Rate Limiting ---
+// Token bucket per connection: refills `rate` tokens per second up to `burst`
+#[derive(Debug)]
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    // Requests rejected since the limit was last hit, logged once the client slows down
+    rejected: u64,
+}
+
+impl RateLimiter {
+    fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self { rate, burst, tokens: burst, last_refill: Instant::now(), rejected: 0 }
+    }
+
+    fn try_acquire(&mut self, peer_addr: &SocketAddr) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            if self.rejected > 0 {
+                log::warn!("Modbus client {} rate limited: {} requests rejected.", peer_addr, self.rejected);
+                self.rejected = 0;
+            }
+            true
+        } else {
+            if self.rejected == 0 {
+                log::warn!(
+                    "Modbus client {} exceeds {} requests/s, answering with ServerDeviceBusy.",
+                    peer_addr,
+                    self.rate
+                );
+            }
+            self.rejected += 1;
+            false
+        }
+    }
+}
+
+// --- OFF Confirmation Handshake ---
+// OFF written to REG_ON, waiting for the confirm register. Shared by all connections of a server.
+#[derive(Debug)]
+struct OffHandshake {
+    confirm_value: u16,
+    timeout: Duration,
+    armed_at: Mutex<Option<Instant>>,
+}
+
+impl OffHandshake {
+    fn arm(&self) {
+        if let Ok(mut armed_at) = self.armed_at.lock() {
+            *armed_at = Some(Instant::now());
+        }
+        log::warn!(
+            "Modbus OFF requested, waiting for {:#06X} in register {} within {:?}.",
+            self.confirm_value,
+            REG_OFF_CONFIRM,
+            self.timeout
+        );
+    }
+
+    // Consumes the pending OFF if `value` confirms it in time
+    fn confirm(&self, value: u16) -> Result<(), ExceptionCode> {
+        let mut armed_at = self.armed_at.lock().map_err(|_| ExceptionCode::ServerDeviceFailure)?;
+        if value != self.confirm_value {
+            log::warn!("Modbus OFF confirmation rejected: wrong value {:#06X}.", value);
+            *armed_at = None;
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        match armed_at.take() {
+            Some(at) if at.elapsed() <= self.timeout => Ok(()),
+            Some(_) => {
+                log::warn!("Modbus OFF confirmation rejected: more than {:?} after the request.", self.timeout);
+                Err(ExceptionCode::IllegalDataValue)
+            }
+            None => {
+                log::warn!("Modbus OFF confirmation rejected: no OFF pending.");
+                Err(ExceptionCode::IllegalDataValue)
+            }
+        }
+    }
+}
+
 // --- Custom Modbus Service ---
 // Service struct remains the same
 #[derive(Debug, Clone)] // Added Clone trait, needed for the service factory pattern
 struct BmsModbusService {
-    bms_data: Arc<RwLock<Option<BmsData>>>,
-    input_tx: std::sync::mpsc::Sender<SystemCommand>,
+    bms_data: SharedBmsData,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    // Address of the connected client, used for access control logging
+    peer_addr: SocketAddr,
+    // What this client may write (read-only mode / allowlist / client roles)
+    role: Role,
+    // Request rate limit of this connection, None if unlimited
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    // Keeps the connection counted as active as long as the service lives
+    _connection: Arc<ConnectionGuard>,
+    // Listen address of this server, recorded in the protocol trace
+    server_addr: SocketAddr,
+    // Protocol trace shared by all servers, None if tracing is disabled
+    trace: Option<Arc<ProtocolTrace>>,
+    // Request and exception counters shared by all servers
+    counters: Arc<ModbusCounters>,
+    // How unpopulated registers are answered
+    invalid_value: Arc<InvalidValueConfig>,
+    // Register order of 32-bit values on this server
+    word_order: WordOrder,
+    // Unit conversion of the register values on this server
+    scaling: Arc<[RegisterScaling]>,
+    // Two-register handshake for OFF, None if OFF is executed right away
+    off_handshake: Option<Arc<OffHandshake>>,
+    // Register window passed through to the BMS parameters, None if not configured
+    tunnel: Option<Arc<RegisterTunnel>>,
+    // Aggregate register block of all packs, None if not enabled
+    site: Option<Arc<SiteMap>>,
+    // Longest processing time of a request, None if unlimited
+    request_timeout: Option<Duration>,
+}
+
+// --- Register Tunnel ---
+// Parameters behind the registers `addr..addr + cnt`, None if the range is outside of the
+// tunnel window. Ranges only partly inside the window are rejected.
+fn tunnel_parameters(tunnel: &RegisterTunnel, addr: u16, cnt: u16) -> Result<Option<Vec<u16>>, ExceptionCode> {
+    let parameters: Vec<Option<u16>> =
+        (addr..=block_end(addr, cnt)?).map(|register| tunnel.parameter(register)).collect();
+    if parameters.iter().all(Option::is_none) {
+        Ok(None)
+    } else {
+        parameters
+            .into_iter()
+            .collect::<Option<Vec<u16>>>()
+            .map(Some)
+            .ok_or(ExceptionCode::IllegalDataAddress)
+    }
+}
+
+fn tunnel_exception(e: TunnelError) -> ExceptionCode {
+    log::warn!("Register tunnel: {}", e);
+    match e {
+        TunnelError::Timeout => ExceptionCode::GatewayTargetDevice,
+        TunnelError::Aborted(_) => ExceptionCode::IllegalDataValue,
+        TunnelError::UnexpectedResponse(_) | TunnelError::Can(_) => ExceptionCode::ServerDeviceFailure,
+    }
+}
+
+// The CAN transfers block, they run on the blocking pool
+async fn tunnel_read(tunnel: Arc<RegisterTunnel>, parameters: Vec<u16>) -> Result<Vec<u16>, ExceptionCode> {
+    tokio::task::spawn_blocking(move || parameters.iter().map(|&parameter| tunnel.read(parameter)).collect::<Result<Vec<u16>, TunnelError>>())
+        .await
+        .map_err(|_| ExceptionCode::ServerDeviceFailure)?
+        .map_err(tunnel_exception)
+}
+
+// Only maintainers may change the BMS parameters
+fn tunnel_role(peer_addr: SocketAddr, role: Role) -> Result<(), ExceptionCode> {
+    if role < Role::Maintainer {
+        log::warn!(target: "audit", "Modbus: BMS parameter write by {} ({}) refused, needs the maintainer role.", peer_addr, role.name());
+        return Err(ExceptionCode::IllegalFunction);
+    }
+    Ok(())
+}
+
+async fn tunnel_write(tunnel: Arc<RegisterTunnel>, writes: Vec<(u16, u16)>) -> Result<(), ExceptionCode> {
+    tokio::task::spawn_blocking(move || {
+        writes
+            .iter()
+            .try_for_each(|&(parameter, value)| tunnel.write(parameter, value))
+    })
+    .await
+    .map_err(|_| ExceptionCode::ServerDeviceFailure)?
+    .map_err(tunnel_exception)
+}
+
+// Status flag bits `addr..addr + cnt`, served as discrete inputs and coils
+fn status_bits(data: &BmsData, addr: u16, cnt: u16) -> Result<Vec<bool>, ExceptionCode> {
+    (addr..=block_end(addr, cnt)?)
+        .map(|input| flags::discrete_input(data, input).ok_or(ExceptionCode::IllegalDataAddress))
+        .collect()
+}
+
+// Last address of `cnt` registers (or bits) starting at `addr`. The block must not be
+// empty and must not run past the end of the address space.
+fn block_end(addr: u16, cnt: u16) -> Result<u16, ExceptionCode> {
+    let offset = cnt.checked_sub(1).ok_or(ExceptionCode::IllegalDataValue)?;
+    addr.checked_add(offset).ok_or(ExceptionCode::IllegalDataAddress)
+}
+
+// Role needed to write a register: ON and OFF for operators, everything else changes
+// a setting or shuts the gateway down
+fn register_role(addr: u16) -> Role {
+    match addr {
+        REG_ON | REG_OFF_CONFIRM => Role::Operator,
+        _ => Role::Maintainer,
+    }
+}
+
+// Validates and stores a register write, returns the command it requests. The command is
+// sent by the caller once all registers of the request are written (see send_commands).
+// Shared by all write function codes so they behave identically.
+fn write_register(
+    data_ref: &mut BmsData,
+    off_handshake: Option<&OffHandshake>,
+    peer_addr: SocketAddr,
+    role: Role,
+    addr: u16,
+    value: u16,
+) -> Result<Option<SystemCommand>, ExceptionCode> {
+    let needed = register_role(addr);
+    if role < needed {
+        log::warn!(
+            target: "audit",
+            "Modbus: Write of register {} by {} ({}) refused, needs the {} role.",
+            addr,
+            peer_addr,
+            role.name(),
+            needed.name()
+        );
+        return Err(ExceptionCode::IllegalFunction);
+    }
+    let command = match (addr, value, off_handshake) {
+        // The confirm register holds no value
+        (REG_OFF_CONFIRM, value, Some(handshake)) => {
+            handshake.confirm(value)?;
+            return Ok(Some(SystemCommand::Off));
+        }
+        (REG_OFF_CONFIRM, ..) => return Err(ExceptionCode::IllegalDataAddress),
+        // REG_ON keeps its value until the OFF is confirmed
+        (REG_ON, 0, Some(handshake)) => {
+            handshake.arm();
+            return Ok(None);
+        }
+        (REG_ON, 0, None) => Some(SystemCommand::Off),
+        (REG_ON, ..) => Some(SystemCommand::On),
+        (REG_QUIT, value, _) if value != 0 => Some(SystemCommand::Quit),
+        _ => None,
+    };
+    // Use the set_register method which handles validation and updates
+    data_ref.set_register(addr, value)?;
+    Ok(command)
+}
+
+// Writes `values` from `addr` on. Nothing is stored unless every register is accepted.
+fn write_registers(
+    data_ref: &mut BmsData,
+    off_handshake: Option<&OffHandshake>,
+    peer_addr: SocketAddr,
+    role: Role,
+    addr: u16,
+    values: &[u16],
+) -> Result<Vec<SystemCommand>, ExceptionCode> {
+    let last = block_end(addr, u16::try_from(values.len()).map_err(|_| ExceptionCode::IllegalDataValue)?)?;
+    let mut staged = data_ref.clone();
+    let mut commands = Vec::new();
+    for (register, value) in (addr..=last).zip(values) {
+        match write_register(&mut staged, off_handshake, peer_addr, role, register, *value) {
+            Ok(command) => commands.extend(command),
+            Err(e) => {
+                log::error!("Error writing register {} of the registers {}..{}: {:?}", register, addr, last, e);
+                return Err(e);
+            }
+        }
+    }
+    *data_ref = staged;
+    Ok(commands)
+}
+
+// Forwards the commands of a successful write to the arbiter
+fn send_commands(
+    input_tx: &std::sync::mpsc::Sender<SourcedCommand>,
+    peer_addr: SocketAddr,
+    role: Role,
+    commands: impl IntoIterator<Item = SystemCommand>,
+) {
+    for command in commands {
+        log::info!(target: "audit", "Modbus: {:?} written by {} ({})", command, peer_addr, role.name());
+        if let Err(e) = input_tx.send(SourcedCommand::new(CommandSource::Modbus, command.clone()).with_role(role)) {
+            log::error!("Error when sending {:#?}: {:?}", command, e);
+        } else {
+            log::debug!("{:#?} sent.", command);
+        }
+    }
 }
 
 // Implement Service trait
@@ -33,184 +409,231 @@ impl tokio_modbus::server::Service for BmsModbusService {
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Exception>> + Send>>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        // Clone Arc for use in the async block
-        let data_lock = Arc::clone(&self.bms_data);
+        // Clone the handle for use in the async block
+        let bms_data = self.bms_data.clone();
         let input_tx = self.input_tx.clone();
+        let peer_addr = self.peer_addr;
+        let role = self.role;
 
-        Box::pin(async move {
+        // Reject requests above the configured rate before touching the shared data
+        if let Some(limiter) = &self.rate_limiter {
+            let allowed = limiter
+                .lock()
+                .map(|mut limiter| limiter.try_acquire(&peer_addr))
+                .unwrap_or(true);
+            if !allowed {
+                let result = Err(ExceptionCode::ServerDeviceBusy);
+                self.counters.record(self.server_addr, req.function_code().value(), &result);
+                return Box::pin(async { result });
+            }
+        }
+
+        // Clone the request only if it has to be recorded in the protocol trace
+        let trace = self.trace.clone();
+        let server_addr = self.server_addr;
+        let invalid_value = Arc::clone(&self.invalid_value);
+        let word_order = self.word_order;
+        let scaling = Arc::clone(&self.scaling);
+        let off_handshake = self.off_handshake.clone();
+        let tunnel = self.tunnel.clone();
+        let site = self.site.clone();
+        let traced_req = trace.as_ref().map(|_| req.clone());
+        let counters = Arc::clone(&self.counters);
+        let function = req.function_code().value();
+        let request_timeout = self.request_timeout;
+
+        let handler = async move {
             log::debug!("Received Modbus request: {:?}", req);
 
-            match req {
-                // --- Handle Read Holding Registers (0x03) ---
-                Request::ReadHoldingRegisters(addr, cnt) => {
-                    // Acquire read lock (no changes needed here for reads)
-                    let data_guard = data_lock.read().map_err(|_| {
-                        log::error!("ReadHoldingRegisters: Failed to acquire read lock (poisoned)");
-                        ExceptionCode::ServerDeviceFailure
-                    })?;
+            // --- Access control for write requests ---
+            if role == Role::Viewer
+                && matches!(
+                    req,
+                    Request::WriteSingleRegister(..)
+                        | Request::WriteMultipleRegisters(..)
+                        | Request::MaskWriteRegister(..)
+                        | Request::ReadWriteMultipleRegisters(..)
+                )
+            {
+                log::warn!(
+                    "Rejected write request from unauthorized client {}: {:?}",
+                    peer_addr,
+                    req
+                );
+                return Err(ExceptionCode::IllegalFunction);
+            }
+
+            // --- Aggregate site block, read-only ---
+            if let Some(site) = &site {
+                match &req {
+                    Request::ReadHoldingRegisters(addr, cnt) if site.contains(*addr, *cnt)? => {
+                        return site.read(*addr, *cnt, word_order, &invalid_value).map(Response::ReadHoldingRegisters);
+                    }
+                    Request::ReadInputRegisters(addr, cnt) if site.contains(*addr, *cnt)? => {
+                        return site.read(*addr, *cnt, word_order, &invalid_value).map(Response::ReadInputRegisters);
+                    }
+                    Request::WriteSingleRegister(addr, _) if site.contains(*addr, 1)? => {
+                        return Err(ExceptionCode::IllegalDataAddress);
+                    }
+                    Request::WriteMultipleRegisters(addr, values) if site.contains(*addr, values.len() as u16)? => {
+                        return Err(ExceptionCode::IllegalDataAddress);
+                    }
+                    _ => {}
+                }
+            }
 
-                    let maybe_data = &*data_guard;
-                    match maybe_data {
-                        Some(data) => {
-                            let mut registers = Vec::with_capacity(cnt as usize);
-                            for i in 0..cnt {
-                                let current_addr = addr + i;
-                                // Use get_register, defaulting to 0 if None (or address invalid)
-                                // get_register now handles the 0xFF default for REG_BMS_INFO internally
-                                let value = data.get_register(current_addr).unwrap_or(0);
-                                registers.push(value);
-                            }
-                            log::trace!(
-                                "Responding to ReadHoldingRegisters({}..{}) with: {:?}",
-                                addr,
-                                addr + cnt - 1,
-                                registers
-                            );
-                            Ok(Response::ReadHoldingRegisters(registers))
+            // --- Register tunnel to the BMS parameters ---
+            if let Some(tunnel) = tunnel {
+                match &req {
+                    Request::ReadHoldingRegisters(addr, cnt) => {
+                        if let Some(parameters) = tunnel_parameters(&tunnel, *addr, *cnt)? {
+                            return tunnel_read(tunnel, parameters).await.map(Response::ReadHoldingRegisters);
                         }
-                        None => {
-                            // No BMS data object available yet (task hasn't run or failed)
-                            // Decide how to respond: Zeros or specific error
-                            log::warn!(
-                                "ReadHoldingRegisters: No BmsData object available yet. Returning zeros."
-                            );
-                            let registers = vec![0; cnt as usize];
-                            Ok(Response::ReadHoldingRegisters(registers))
-                            // Or: Err(ExceptionCode::ServerDeviceFailure) ?
+                    }
+                    Request::WriteSingleRegister(addr, value) => {
+                        if let Some(parameters) = tunnel_parameters(&tunnel, *addr, 1)? {
+                            tunnel_role(peer_addr, role)?;
+                            tunnel_write(tunnel, parameters.into_iter().zip([*value]).collect()).await?;
+                            return Ok(Response::WriteSingleRegister(*addr, *value));
+                        }
+                    }
+                    Request::WriteMultipleRegisters(addr, values) => {
+                        if let Some(parameters) = tunnel_parameters(&tunnel, *addr, values.len() as u16)? {
+                            tunnel_role(peer_addr, role)?;
+                            tunnel_write(tunnel, parameters.into_iter().zip(values.iter().copied()).collect()).await?;
+                            return Ok(Response::WriteMultipleRegisters(*addr, values.len() as u16));
                         }
                     }
+                    _ => {}
+                }
+            }
+
+            match req {
+                // --- Handle Read Holding Registers (0x03) ---
+                Request::ReadHoldingRegisters(addr, cnt) => {
+                    // Work on a copy, so assembling a long register read does not hold off the CAN receiver
+                    let last = block_end(addr, cnt)?;
+                    let data = bms_data.get();
+                    // Unpopulated registers are answered according to the invalid value policy
+                    // get_register now handles the 0xFF default for REG_BMS_INFO internally
+                    let registers = (addr..=last)
+                        .map(|register| read_register(Some(&data), register, word_order, &scaling, &invalid_value))
+                        .collect::<Result<Vec<u16>, ExceptionCode>>()?;
+                    log::trace!(
+                        "Responding to ReadHoldingRegisters({}..{}) with: {:?}",
+                        addr,
+                        last,
+                        registers
+                    );
+                    Ok(Response::ReadHoldingRegisters(registers))
                 }
 
                 // --- Handle Read Input Registers (0x04) ---
                 Request::ReadInputRegisters(addr, cnt) => {
                     // Logic is identical to ReadHoldingRegisters in this example
-                    let data_guard = data_lock.read().map_err(|_| {
-                        log::error!("ReadInputRegisters: Failed to acquire read lock (poisoned)");
-                        ExceptionCode::ServerDeviceFailure
-                    })?;
+                    let last = block_end(addr, cnt)?;
+                    let data = bms_data.get();
+                    let registers = (addr..=last)
+                        .map(|register| read_register(Some(&data), register, word_order, &scaling, &invalid_value))
+                        .collect::<Result<Vec<u16>, ExceptionCode>>()?;
+                    log::trace!(
+                        "Responding to ReadInputRegisters({}..{}) with: {:?}",
+                        addr,
+                        last,
+                        registers
+                    );
+                    Ok(Response::ReadInputRegisters(registers))
+                }
 
-                    let maybe_data = &*data_guard;
-                    match maybe_data {
-                        Some(data) => {
-                            let mut registers = Vec::with_capacity(cnt as usize);
-                            for i in 0..cnt {
-                                let current_addr = addr + i;
-                                let value = data.get_register(current_addr).unwrap_or(0);
-                                registers.push(value);
-                            }
-                            log::trace!(
-                                "Responding to ReadInputRegisters({}..{}) with: {:?}",
-                                addr,
-                                addr + cnt - 1,
-                                registers
-                            );
-                            Ok(Response::ReadInputRegisters(registers))
-                        }
-                        None => {
-                            log::warn!(
-                                "ReadInputRegisters: No BmsData object available yet. Returning zeros."
-                            );
-                            let registers = vec![0; cnt as usize];
-                            Ok(Response::ReadInputRegisters(registers))
-                        }
-                    }
+                // --- Handle Read Discrete Inputs (0x02) ---
+                // One input per bit of the info, warning and error bytes (see flags::StatusByte)
+                Request::ReadDiscreteInputs(addr, cnt) => {
+                    let inputs = bms_data.read(|data| status_bits(data, addr, cnt))?;
+                    log::trace!(
+                        "Responding to ReadDiscreteInputs({}..{}) with: {:?}",
+                        addr,
+                        block_end(addr, cnt)?,
+                        inputs
+                    );
+                    Ok(Response::ReadDiscreteInputs(inputs))
+                }
+
+                // --- Handle Read Coils (0x01) ---
+                // Same bits as the discrete inputs, for panels that can only map coils.
+                // The coils are read-only, writing them is not supported.
+                Request::ReadCoils(addr, cnt) => {
+                    let coils = bms_data.read(|data| status_bits(data, addr, cnt))?;
+                    log::trace!(
+                        "Responding to ReadCoils({}..{}) with: {:?}",
+                        addr,
+                        block_end(addr, cnt)?,
+                        coils
+                    );
+                    Ok(Response::ReadCoils(coils))
                 }
 
                 // --- Handle Write Single Register (0x06) ---
                 Request::WriteSingleRegister(addr, value) => {
-                    // Acquire write lock - needed to potentially modify data
-                    let mut data_guard = data_lock.write().map_err(|_| {
-                        log::error!("WriteSingleRegister: Failed to acquire write lock (poisoned)");
-                        ExceptionCode::ServerDeviceFailure
+                    let command = bms_data.modify(|data_ref| {
+                        write_register(data_ref, off_handshake.as_deref(), peer_addr, role, addr, value)
                     })?;
-
-                    // Get mutable reference, initializing if None
-                    let data_ref = data_guard.get_or_insert_with(BmsData::default);
-
-                    if addr == 21 {
-                        if value == 0 {
-                            if let Err(e) = input_tx.send(SystemCommand::Off) {
-                                log::error!(
-                                    "Error when sending {:#?}: {:?}",
-                                    SystemCommand::Off,
-                                    e
-                                );
-                            } else {
-                                log::debug!("{:#?} sent.", SystemCommand::Off);
-                            }
-                        } else if let Err(e) = input_tx.send(SystemCommand::On) {
-                            log::error!("Error when sending {:#?}: {:?}", SystemCommand::On, e);
-                        } else {
-                            log::debug!("{:#?} sent.", SystemCommand::On);
-                        }
-                    } else if addr == 22 && value != 0 {
-                        if let Err(e) = input_tx.send(SystemCommand::Quit) {
-                            log::error!("Error when sending {:#?}: {:?}", SystemCommand::Quit, e);
-                        } else {
-                            log::debug!("{:#?} sent.", SystemCommand::Quit);
-                        }
-                    }
-
-                    // Use the new set_register method which handles validation and updates
-                    match data_ref.set_register(addr, value) {
-                        Ok(()) => {
-                            // Echo the request back on success, as per Modbus standard
-                            Ok(Response::WriteSingleRegister(addr, value))
-                        }
-                        Err(exception_code) => {
-                            // set_register returned an error (e.g., IllegalDataValue, IllegalFunction)
-                            Err(exception_code)
-                        }
-                    }
+                    send_commands(&input_tx, peer_addr, role, command);
+                    // Echo the request back on success, as per Modbus standard
+                    Ok(Response::WriteSingleRegister(addr, value))
                 }
 
                 // --- Handle Write Multiple Registers (0x10) ---
-                // Still deny for now, but could be implemented similarly to WriteSingleRegister
+                // All or nothing: the commands are sent once every register is accepted
                 Request::WriteMultipleRegisters(addr, ref values) => {
-                    let mut data_guard = data_lock
-                        .write()
-                        .map_err(|_| ExceptionCode::ServerDeviceFailure)?;
-                    let data_ref = data_guard.get_or_insert_with(BmsData::default);
-                    for (i, value) in values.iter().enumerate() {
-                        let current_addr = addr + i as u16;
-
-                        if current_addr == 21 {
-                            if *value == 0 {
-                                if let Err(e) = input_tx.send(SystemCommand::Off) {
-                                    log::error!(
-                                        "Error when sending {:#?}: {:?}",
-                                        SystemCommand::Off,
-                                        e
-                                    );
-                                } else {
-                                    log::debug!("{:#?} sent.", SystemCommand::Off);
-                                }
-                            } else if let Err(e) = input_tx.send(SystemCommand::On) {
-                                log::error!("Error when sending {:#?}: {:?}", SystemCommand::On, e);
-                            } else {
-                                log::debug!("{:#?} sent.", SystemCommand::On);
-                            }
-                        } else if current_addr == 22 && *value != 0 {
-                            if let Err(e) = input_tx.send(SystemCommand::Quit) {
-                                log::error!("Error when sending {:#?}: {:?}", SystemCommand::Quit, e);
-                            } else {
-                                log::debug!("{:#?} sent.", SystemCommand::Quit);
-                            }
-                        }
-
-                        if let Err(e) = data_ref.set_register(current_addr, *value) {
-                            // Decide on error handling: stop immediately or continue?
-                            // Modbus standard often expects an error on the first failure.
-                            log::error!(
-                                "Error writing multiple registers at offset {}: {:?}",
-                                i,
-                                e
-                            );
-                            return Err(e); // Return the specific error
-                        }
-                    }
+                    let commands = bms_data.modify(|data_ref| {
+                        write_registers(data_ref, off_handshake.as_deref(), peer_addr, role, addr, values)
+                    })?;
+                    send_commands(&input_tx, peer_addr, role, commands);
                     Ok(Response::WriteMultipleRegisters(addr, values.len() as u16))
-                    // Err(ExceptionCode::IllegalFunction)
+                }
+
+                // --- Handle Mask Write Register (0x16) ---
+                // New value = (current AND and_mask) OR (or_mask AND NOT and_mask)
+                Request::MaskWriteRegister(addr, and_mask, or_mask) => {
+                    let command = bms_data.modify(|data_ref| {
+                        // The masks apply to the unconverted value that is written back
+                        let current = data_ref.get_register(addr, word_order, &[]).ok_or_else(|| {
+                            log::warn!("MaskWriteRegister: Register {} has no value to mask", addr);
+                            ExceptionCode::IllegalDataAddress
+                        })?;
+                        let value = (current & and_mask) | (or_mask & !and_mask);
+                        log::debug!(
+                            "MaskWriteRegister({}): {:#06X} -> {:#06X} (and {:#06X}, or {:#06X})",
+                            addr, current, value, and_mask, or_mask
+                        );
+                        write_register(data_ref, off_handshake.as_deref(), peer_addr, role, addr, value)
+                    })?;
+                    send_commands(&input_tx, peer_addr, role, command);
+                    Ok(Response::MaskWriteRegister(addr, and_mask, or_mask))
+                }
+
+                // --- Handle Read/Write Multiple Registers (0x17) ---
+                // The write is performed before the read, as required by the Modbus specification
+                Request::ReadWriteMultipleRegisters(read_addr, read_cnt, write_addr, ref values) => {
+                    let read_last = block_end(read_addr, read_cnt)?;
+                    let (commands, registers) = bms_data.modify(|data_ref| {
+                        let commands =
+                            write_registers(data_ref, off_handshake.as_deref(), peer_addr, role, write_addr, values)?;
+                        let registers = (read_addr..=read_last)
+                            .map(|register| read_register(Some(data_ref), register, word_order, &scaling, &invalid_value))
+                            .collect::<Result<Vec<u16>, ExceptionCode>>();
+                        // A failed read undoes nothing, the written registers stay like with 0x10
+                        Ok::<_, ExceptionCode>((commands, registers))
+                    })?;
+                    send_commands(&input_tx, peer_addr, role, commands);
+                    let registers = registers?;
+                    log::trace!(
+                        "Responding to ReadWriteMultipleRegisters({}..{}) with: {:?}",
+                        read_addr,
+                        read_last,
+                        registers
+                    );
+                    Ok(Response::ReadWriteMultipleRegisters(registers))
                 }
 
                 // Default handler for unsupported function codes
@@ -219,6 +642,22 @@ impl tokio_modbus::server::Service for BmsModbusService {
                     Err(ExceptionCode::IllegalFunction)
                 }
             }
+        };
+
+        Box::pin(async move {
+            // A request that hangs (e.g. on a tunnel transfer) must not block its connection forever
+            let result = match request_timeout {
+                Some(limit) => timeout(limit, handler).await.unwrap_or_else(|_| {
+                    log::warn!("Modbus request from {} took longer than {:?}, answering with ServerDeviceBusy.", peer_addr, limit);
+                    Err(ExceptionCode::ServerDeviceBusy)
+                }),
+                None => handler.await,
+            };
+            counters.record(server_addr, function, &result);
+            if let (Some(trace), Some(req)) = (trace, traced_req) {
+                trace.record(server_addr, peer_addr, &req, &result);
+            }
+            result
         })
     }
 }
@@ -226,30 +665,101 @@ impl tokio_modbus::server::Service for BmsModbusService {
 // --- Modbus Server Task ---
 // Using the server setup structure provided in the user's code snippet
 pub async fn task(
-    addr_str: &str,
-    bms_data: Arc<RwLock<Option<BmsData>>>,
-    input_tx: std::sync::mpsc::Sender<SystemCommand>,
+    config: ModbusServerConfig,
+    bms_data: SharedBmsData,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    trace: Option<Arc<ProtocolTrace>>,
+    counters: Arc<ModbusCounters>,
+    invalid_value: Arc<InvalidValueConfig>,
+    tunnel: Option<Arc<RegisterTunnel>>,
+    site: Option<Arc<SiteMap>>,
 ) -> Result<(), AppError> {
-    let socket_addr: SocketAddr = addr_str.parse().unwrap();
+    let socket_addr: SocketAddr = config
+        .addr
+        .parse()
+        .map_err(|e| AppError::Config(format!("Invalid Modbus server address '{}': {}", config.addr, e)))?;
     log::info!("Starting Modbus TCP server on {}", socket_addr);
-    let listener = TcpListener::bind(socket_addr).await?; // Use await and ?
+    if config.read_only {
+        log::info!("Modbus server on {} is read-only, all writes will be rejected.", socket_addr);
+    } else if !config.clients.is_empty() {
+        log::info!(
+            "Modbus server on {} has roles for {} clients, all others are read-only.",
+            socket_addr,
+            config.clients.len()
+        );
+    } else if !config.write_allowlist.is_empty() {
+        log::info!(
+            "Modbus server on {} accepts writes only from {:?}",
+            socket_addr,
+            config.write_allowlist
+        );
+    }
+    let listener = TcpListener::bind(socket_addr)
+        .await
+        .map_err(|source| AppError::Bind { addr: socket_addr, source })?;
     let server = Server::new(listener);
 
     // Factory closure to create a new service instance for each connection.
-    // Clones the Arc<RwLock<...>> so each service instance shares the same data.
-    let new_service = move |_socket_addr: SocketAddr| {
+    // Clones the SharedBmsData handle so each service instance shares the same data.
+    let active_connections = Arc::new(AtomicUsize::new(0));
+    let off_handshake = config.off_confirm.then(|| {
+        Arc::new(OffHandshake {
+            confirm_value: config.off_confirm_value,
+            timeout: config.off_confirm_timeout(),
+            armed_at: Mutex::new(None),
+        })
+    });
+    let scaling: Arc<[RegisterScaling]> = config.scaling.clone().into();
+    let new_service = move |peer_addr: SocketAddr| {
         // This closure is called by accept_tcp_connection for each new client.
         // It needs to return a Result<Option<Service>, io::Error>
         // The Option is Some if the connection is accepted, None otherwise.
+        let active = active_connections.fetch_add(1, Ordering::SeqCst);
+        let connection = ConnectionGuard { active: Arc::clone(&active_connections) };
+        if config.max_connections > 0 && active >= config.max_connections {
+            log::warn!(
+                "Rejecting Modbus client {}: connection limit of {} reached.",
+                peer_addr,
+                config.max_connections
+            );
+            return Ok(None); // Dropping the guard releases the slot again
+        }
+
+        let role = config.role(peer_addr.ip());
+        if role == Role::Viewer {
+            log::info!("Modbus client {} connected with read-only access.", peer_addr);
+        } else {
+            log::info!("Modbus client {} connected as {}.", peer_addr, role.name());
+        }
         Ok(Some(BmsModbusService {
-            // Clone the Arc here, so the new service instance gets a pointer to the shared data
-            bms_data: Arc::clone(&bms_data),
+            // Clone the handle here, so the new service instance shares the data
+            bms_data: bms_data.clone(),
             input_tx: input_tx.clone(),
+            peer_addr,
+            role,
+            rate_limiter: (config.max_requests_per_second > 0.0).then(|| {
+                Arc::new(Mutex::new(RateLimiter::new(
+                    config.max_requests_per_second,
+                    config.request_burst,
+                )))
+            }),
+            _connection: Arc::new(connection),
+            server_addr: socket_addr,
+            trace: trace.clone(),
+            counters: Arc::clone(&counters),
+            invalid_value: Arc::clone(&invalid_value),
+            word_order: config.word_order,
+            scaling: Arc::clone(&scaling),
+            tunnel: tunnel.clone(),
+            site: site.clone(),
+            off_handshake: off_handshake.clone(),
+            request_timeout: config.request_timeout(),
         }))
     };
 
     // Wrap the factory closure in Arc for the on_connected handler
     let new_service_arc = Arc::new(new_service);
+    let idle_timeout = config.idle_timeout();
 
     // Handler for new connections
     let on_connected = {
@@ -260,7 +770,10 @@ pub async fn task(
                 log::info!("New Modbus client connected: {}", socket_addr);
                 // Pass the stream, address, and the factory closure to accept_tcp_connection
                 // The factory closure (*service_factory) will be called to create the service instance.
-                accept_tcp_connection(stream, socket_addr, move |addr| (*service_factory)(addr))
+                let accepted = accept_tcp_connection(stream, socket_addr, move |addr| (*service_factory)(addr))?;
+                Ok::<_, io::Error>(
+                    accepted.map(|(service, stream)| (service, IdleTimeoutStream::new(stream, socket_addr, idle_timeout))),
+                )
             }
         }
     };
@@ -281,3 +794,66 @@ pub async fn task(
     log::warn!("Modbus TCP server on {} has stopped.", socket_addr);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::REG_MAINTENANCE;
+
+    fn peer() -> SocketAddr {
+        SocketAddr::from(([192, 168, 1, 10], 40000))
+    }
+
+    #[test]
+    fn rejected_write_stores_and_dispatches_nothing() {
+        let mut data = BmsData::default();
+        // ON is valid, the QUIT value does not fit its register
+        let result = write_registers(&mut data, None, peer(), Role::Maintainer, REG_ON, &[1, 300]);
+        assert_eq!(result, Err(ExceptionCode::IllegalDataValue));
+        assert_eq!(data, BmsData::default());
+
+        assert_eq!(
+            write_register(&mut data, None, peer(), Role::Maintainer, REG_ON, 300),
+            Err(ExceptionCode::IllegalDataValue)
+        );
+        assert_eq!(data.on, None);
+    }
+
+    #[test]
+    fn armed_off_keeps_the_on_register() {
+        let handshake = OffHandshake {
+            confirm_value: 0xA5A5,
+            timeout: Duration::from_secs(10),
+            armed_at: Mutex::new(None),
+        };
+        let mut data = BmsData { on: Some(1), ..BmsData::default() };
+        assert_eq!(write_register(&mut data, Some(&handshake), peer(), Role::Operator, REG_ON, 0), Ok(None));
+        assert_eq!(data.on, Some(1));
+        assert_eq!(
+            write_register(&mut data, Some(&handshake), peer(), Role::Operator, REG_OFF_CONFIRM, 0xA5A5),
+            Ok(Some(SystemCommand::Off))
+        );
+    }
+
+    #[test]
+    fn unlisted_client_cannot_write_settings() {
+        let config = ModbusServerConfig::default();
+        let role = config.role(peer().ip());
+        assert_eq!(role, Role::Operator);
+
+        let mut data = BmsData::default();
+        assert_eq!(
+            write_register(&mut data, None, peer(), role, REG_MAINTENANCE, 1),
+            Err(ExceptionCode::IllegalFunction)
+        );
+        assert_eq!(data.maintenance, None);
+        assert_eq!(write_register(&mut data, None, peer(), role, REG_ON, 1), Ok(Some(SystemCommand::On)));
+    }
+
+    #[test]
+    fn blocks_past_the_address_space_are_rejected() {
+        assert_eq!(block_end(0xFFFF, 1), Ok(0xFFFF));
+        assert_eq!(block_end(0xFFFF, 2), Err(ExceptionCode::IllegalDataAddress));
+        assert_eq!(block_end(0xFFF0, 0), Err(ExceptionCode::IllegalDataValue));
+    }
+}
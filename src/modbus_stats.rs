@@ -0,0 +1,48 @@
+// src/modbus_stats.rs
+use serde::Serialize;
+use std::{collections::BTreeMap, net::SocketAddr, sync::Mutex};
+use tokio_modbus::prelude::{ExceptionCode, Response};
+
+// --- Counters ---
+/// Requests and exceptions of one Modbus server since startup.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServerCounters {
+    /// Requests per function code, including rejected ones
+    pub requests: BTreeMap<u8, u64>,
+    /// Exceptions returned, by exception name
+    pub exceptions: BTreeMap<String, u64>,
+}
+
+/// Request and exception counters of all Modbus servers, keyed by listen address.
+#[derive(Debug, Default)]
+pub struct ModbusCounters {
+    servers: Mutex<BTreeMap<SocketAddr, ServerCounters>>,
+}
+
+impl ModbusCounters {
+    /// Counts a served request and the exception it was answered with, if any.
+    pub fn record(&self, server: SocketAddr, function: u8, result: &Result<Response, ExceptionCode>) {
+        let Ok(mut servers) = self.servers.lock() else {
+            log::error!("Modbus counters lock poisoned, dropping request.");
+            return;
+        };
+        let counters = servers.entry(server).or_default();
+        *counters.requests.entry(function).or_default() += 1;
+        if let Err(exception) = result {
+            *counters.exceptions.entry(format!("{:?}", exception)).or_default() += 1;
+        }
+    }
+
+    /// Copy of the counters, keyed by the listen address as string.
+    pub fn snapshot(&self) -> BTreeMap<String, ServerCounters> {
+        self.servers
+            .lock()
+            .map(|servers| {
+                servers
+                    .iter()
+                    .map(|(server, counters)| (server.to_string(), counters.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,207 @@
+// src/opcua_server.rs
+use crate::{
+    SystemCommand,
+    arbiter::{CommandSource, SourcedCommand},
+    auth,
+    config::{OpcUaConfig, OpcUaUserConfig, Role},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use opcua::server::{callbacks, identity_token::IdentityToken, prelude::*};
+use opcua::sync::RwLock as OpcRwLock;
+use std::sync::Arc;
+
+const NAMESPACE_URI: &str = "urn:can_modbus_gateway";
+
+// --- Variables ---
+// Value of every variable of a BMS folder, Empty while the BMS has not reported it yet
+fn variables(data: &BmsData) -> Vec<(&'static str, DataTypeId, Variant)> {
+    fn unsigned(value: Option<impl Into<u32>>) -> Variant {
+        value.map(|v| Variant::from(v.into())).unwrap_or(Variant::Empty)
+    }
+    vec![
+        ("MinCellVoltage", DataTypeId::UInt32, unsigned(data.min_cell_voltage)),
+        ("MaxCellVoltage", DataTypeId::UInt32, unsigned(data.max_cell_voltage)),
+        ("MinTemperature", DataTypeId::UInt32, unsigned(data.min_temperature)),
+        ("MaxTemperature", DataTypeId::UInt32, unsigned(data.max_temperature)),
+        ("Info", DataTypeId::UInt32, unsigned(data.info)),
+        ("Soc", DataTypeId::UInt32, unsigned(data.soc)),
+        ("Current", DataTypeId::Int32, data.pack_current().map(Variant::from).unwrap_or(Variant::Empty)),
+        ("TotalVoltage", DataTypeId::UInt32, unsigned(data.pack_voltage())),
+        ("Warning1", DataTypeId::UInt32, unsigned(data.warning1)),
+        ("Warning2", DataTypeId::UInt32, unsigned(data.warning2)),
+        ("Error1", DataTypeId::UInt32, unsigned(data.error1)),
+        ("Error2", DataTypeId::UInt32, unsigned(data.error2)),
+        ("CommandStatus", DataTypeId::UInt32, unsigned(data.command_status)),
+        ("RuleSeverity", DataTypeId::UInt32, unsigned(data.rule_severity)),
+        ("ChargedAhToday", DataTypeId::UInt32, unsigned(data.charged_ah_today)),
+        ("DischargedAhToday", DataTypeId::UInt32, unsigned(data.discharged_ah_today)),
+        ("ChargedKwhToday", DataTypeId::UInt32, unsigned(data.charged_kwh_today)),
+        ("DischargedKwhToday", DataTypeId::UInt32, unsigned(data.discharged_kwh_today)),
+        ("Stale", DataTypeId::Boolean, Variant::from(data.stale.unwrap_or(true))),
+    ]
+}
+
+fn variable_id(ns: u16, bms_id: u8, name: &str) -> NodeId {
+    NodeId::new(ns, format!("BMS{}.{}", bms_id, name))
+}
+
+// --- Command Methods ---
+// Forwards a method call to the command arbiter, like a write of the command registers
+struct CommandMethod {
+    command: SystemCommand,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+    users: Arc<Vec<OpcUaUserConfig>>,
+}
+
+impl CommandMethod {
+    // The user logged in to the session, None for anonymous sessions
+    fn user(&self, session_id: &NodeId, session_manager: &OpcRwLock<SessionManager>) -> Option<&OpcUaUserConfig> {
+        let session = session_manager.read().find_session_by_id(session_id)?;
+        let session = session.read();
+        let IdentityToken::UserName(token) = session.user_identity() else {
+            return None;
+        };
+        self.users.iter().find(|user| user.name == token.user_name.as_ref())
+    }
+}
+
+impl callbacks::Method for CommandMethod {
+    fn call(
+        &mut self,
+        session_id: &NodeId,
+        session_manager: Arc<OpcRwLock<SessionManager>>,
+        _request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        let needed = auth::command_role(&self.command);
+        let (name, role) = match self.user(session_id, &session_manager) {
+            Some(user) => (user.name.clone(), user.role),
+            None => ("anonymous".to_string(), Role::Viewer),
+        };
+        if role < needed {
+            log::warn!(
+                target: "audit",
+                "OPC UA: {:?} by {} ({}) refused, needs the {} role.",
+                self.command,
+                name,
+                role.name(),
+                needed.name()
+            );
+            return Err(StatusCode::BadUserAccessDenied);
+        }
+        log::info!(target: "audit", "OPC UA: {:?} method called by {} ({})", self.command, name, role.name());
+        let request = SourcedCommand::new(CommandSource::OpcUa, self.command.clone()).with_role(role);
+        self.input_tx.send(request).map_err(|e| {
+            log::error!("Error when sending {:#?}: {:?}", self.command, e);
+            StatusCode::BadInternalError
+        })?;
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: None,
+        })
+    }
+}
+
+// Creates the Gateway folder with one subfolder per BMS and, if there are users who
+// could call them, the command methods
+fn build_address_space(
+    address_space: &mut AddressSpace,
+    bms_ids: &[u8],
+    input_tx: &std::sync::mpsc::Sender<SourcedCommand>,
+    users: &[OpcUaUserConfig],
+) -> Result<u16, AppError> {
+    let ns = address_space
+        .register_namespace(NAMESPACE_URI)
+        .map_err(|_| AppError::Config("Failed to register the OPC UA namespace".into()))?;
+    let gateway = address_space
+        .add_folder("Gateway", "Gateway", &NodeId::objects_folder_id())
+        .map_err(|_| AppError::Config("Failed to create the OPC UA Gateway folder".into()))?;
+
+    for bms_id in bms_ids {
+        let name = format!("BMS{}", bms_id);
+        let folder = address_space
+            .add_folder(name.as_str(), name.as_str(), &gateway)
+            .map_err(|_| AppError::Config(format!("Failed to create the OPC UA folder {}", name)))?;
+        for (variable, data_type, value) in variables(&BmsData::default()) {
+            VariableBuilder::new(&variable_id(ns, *bms_id, variable), variable, variable)
+                .data_type(data_type)
+                .value(value)
+                .organized_by(&folder)
+                .insert(address_space);
+        }
+    }
+
+    if users.is_empty() {
+        log::warn!("OPC UA: No users configured, the command methods are left out.");
+        return Ok(ns);
+    }
+    let users = Arc::new(users.to_vec());
+    for (name, command) in [
+        ("Off", SystemCommand::Off),
+        ("On", SystemCommand::On),
+        ("Quit", SystemCommand::Quit),
+    ] {
+        let method = CommandMethod { command, input_tx: input_tx.clone(), users: Arc::clone(&users) };
+        MethodBuilder::new(&NodeId::new(ns, format!("Gateway.{}", name)), name, name)
+            .component_of(gateway.clone())
+            .callback(Box::new(method))
+            .insert(address_space);
+    }
+    Ok(ns)
+}
+
+// --- OPC UA Server Task ---
+/// Serves the data of all BMS as OPC UA variables below Objects/Gateway/BMS<id>
+/// and the system commands as methods of Objects/Gateway. Anonymous clients connect
+/// without security, the users over an encrypted endpoint.
+pub async fn task(
+    config: OpcUaConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+) -> Result<(), AppError> {
+    log::info!("Starting OPC UA server on {}:{}", config.host, config.port);
+    let mut builder = ServerBuilder::new()
+        .application_name(&config.application_name)
+        .application_uri(NAMESPACE_URI)
+        .host_and_port(&config.host, config.port)
+        .discovery_urls(vec![format!("opc.tcp://{}:{}/", config.host, config.port)])
+        .pki_dir(&config.pki_dir)
+        .create_sample_keypair(true)
+        .endpoint("none", ServerEndpoint::new_none("/", &[ANONYMOUS_USER_TOKEN_ID.to_string()]));
+    if !config.users.is_empty() {
+        // Passwords are never sent over an unencrypted endpoint
+        let user_ids: Vec<String> = config.users.iter().map(|user| user.name.clone()).collect();
+        for user in &config.users {
+            builder = builder.user_token(&user.name, ServerUserToken::user_pass(&user.name, &user.password));
+        }
+        builder = builder.endpoint(
+            "basic256sha256_sign_encrypt",
+            ServerEndpoint::new_basic256sha256_sign_encrypt("/", &user_ids),
+        );
+    }
+    let mut server = builder
+        .server()
+        .ok_or_else(|| AppError::Config("Invalid OPC UA server configuration".into()))?;
+
+    let address_space = server.address_space();
+    let bms_ids: Vec<u8> = bms.iter().map(|(bms_id, _)| *bms_id).collect();
+    let ns = build_address_space(&mut address_space.write(), &bms_ids, &input_tx, &config.users)?;
+
+    // Copy the BMS data into the variables periodically
+    let update_interval_ms = config.update_interval_ms.max(100);
+    server.add_polling_action(update_interval_ms, move || {
+        let now = DateTime::now();
+        let mut address_space = address_space.write();
+        for (bms_id, bms_data) in &bms {
+            for (variable, _, value) in bms_data.read(variables) {
+                address_space.set_variable_value(variable_id(ns, *bms_id, variable), value, &now, &now);
+            }
+        }
+    });
+
+    Server::new_server_task(Arc::new(OpcRwLock::new(server))).await;
+    log::warn!("OPC UA server on {}:{} has stopped.", config.host, config.port);
+    Ok(())
+}
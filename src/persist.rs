@@ -0,0 +1,182 @@
+// src/persist.rs
+use crate::{config::PersistConfig, error::AppError};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+
+// How often the writer task looks for due files
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes `content` to `path` atomically: the data goes to a temporary file
+/// next to the target which is then renamed over it, so a power loss never
+/// leaves a half-written file behind.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        io::Write::write_all(&mut file, content)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+// --- Write Classes ---
+/// Kind of a persisted file, each class has its own flush interval (see PersistConfig).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteClass {
+    Telemetry,
+    Audit,
+    Snapshot,
+}
+
+impl WriteClass {
+    fn flush_interval(self, config: &PersistConfig) -> Duration {
+        match self {
+            WriteClass::Telemetry => config.telemetry_flush(),
+            WriteClass::Audit => config.audit_flush(),
+            WriteClass::Snapshot => config.snapshot_flush(),
+        }
+    }
+}
+
+// --- Batching Writer ---
+// Latest content of a file, with the time its oldest unwritten update was queued
+struct Pending {
+    class: WriteClass,
+    content: Vec<u8>,
+    queued_at: Instant,
+    // Updates replaced before they were written
+    coalesced: u64,
+}
+
+struct Writer {
+    config: PersistConfig,
+    pending: BTreeMap<PathBuf, Pending>,
+}
+
+impl Writer {
+    fn pending_bytes(&self) -> usize {
+        self.pending.values().map(|file| file.content.len()).sum()
+    }
+
+    // Takes the files to write now: all of a class once one of them is due, so the
+    // class goes out in one batch
+    fn take_due(&mut self, all: bool) -> Vec<(PathBuf, Pending)> {
+        let due: Vec<WriteClass> = self
+            .pending
+            .values()
+            .filter(|file| all || file.queued_at.elapsed() >= file.class.flush_interval(&self.config))
+            .map(|file| file.class)
+            .collect();
+        let paths: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, file)| due.contains(&file.class))
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths
+            .into_iter()
+            .filter_map(|path| self.pending.remove(&path).map(|file| (path, file)))
+            .collect()
+    }
+}
+
+// None until `init`, files are written at once until then
+static WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+
+/// Enables the batching writer, a no-op if batching is disabled.
+pub fn init(config: &PersistConfig) {
+    if !config.batching {
+        return;
+    }
+    if let Ok(mut writer) = WRITER.lock() {
+        *writer = Some(Writer { config: config.clone(), pending: BTreeMap::new() });
+    }
+}
+
+/// Queues `content` to replace the file at `path`, content queued for it before is
+/// dropped. Written at once if the writer is not enabled or the class is not batched.
+pub fn queue(class: WriteClass, path: &Path, content: Vec<u8>) -> Result<(), AppError> {
+    let overflow = {
+        let mut guard = WRITER.lock().map_err(|_| AppError::LockPoisoned)?;
+        let Some(writer) = guard.as_mut().filter(|writer| !class.flush_interval(&writer.config).is_zero()) else {
+            drop(guard);
+            return write_atomic(path, &content).map_err(|e| AppError::Persist(e.to_string()));
+        };
+        match writer.pending.get_mut(path) {
+            Some(file) => {
+                file.content = content;
+                file.coalesced += 1;
+            }
+            None => {
+                let queued_at = Instant::now();
+                writer.pending.insert(path.to_path_buf(), Pending { class, content, queued_at, coalesced: 0 });
+            }
+        }
+        writer.pending_bytes() > writer.config.max_pending_bytes
+    };
+    if overflow {
+        log::warn!("Persist: Pending writes exceed the limit, flushing all.");
+        flush();
+    }
+    Ok(())
+}
+
+// Writes the files taken from the writer. A failed file is queued again unless it was
+// updated in the meantime, so it is retried with the next batch.
+fn write_batch(files: Vec<(PathBuf, Pending)>) -> usize {
+    let mut written = 0;
+    for (path, file) in files {
+        match write_atomic(&path, &file.content) {
+            Ok(()) => {
+                written += 1;
+                log::debug!("Persist: Wrote {} ({} updates coalesced)", path.display(), file.coalesced);
+            }
+            Err(e) => {
+                log::error!("Persist: Failed to write {}: {}", path.display(), e);
+                if let Ok(mut guard) = WRITER.lock()
+                    && let Some(writer) = guard.as_mut()
+                {
+                    writer.pending.entry(path).or_insert(Pending { queued_at: Instant::now(), ..file });
+                }
+            }
+        }
+    }
+    written
+}
+
+/// Writes every pending file at once, e.g. on shutdown. Returns the number of written files.
+pub fn flush() -> usize {
+    let files = match WRITER.lock() {
+        Ok(mut guard) => guard.as_mut().map(|writer| writer.take_due(true)).unwrap_or_default(),
+        Err(_) => return 0,
+    };
+    write_batch(files)
+}
+
+// --- Writer Task ---
+/// Writes the files whose flush interval elapsed. The files are taken from the queue
+/// before writing, so the SD card latency does not block the tasks queueing updates.
+pub async fn task() -> Result<(), AppError> {
+    log::info!("Starting persistence writer (check every {:?})", CHECK_INTERVAL);
+    let mut ticker = interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let files = WRITER
+            .lock()
+            .map_err(|_| AppError::LockPoisoned)?
+            .as_mut()
+            .map(|writer| writer.take_due(false))
+            .unwrap_or_default();
+        if !files.is_empty() {
+            tokio::task::spawn_blocking(move || write_batch(files))
+                .await
+                .map_err(|e| AppError::Persist(e.to_string()))?;
+        }
+    }
+}
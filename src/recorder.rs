@@ -0,0 +1,143 @@
+// src/recorder.rs
+use crate::{
+    clock,
+    config::RecorderConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+use tokio::time::interval;
+
+// --- Samples ---
+/// One recorded BMS dataset.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    /// Monotonic time in milliseconds, for ordering across clock steps
+    pub monotonic_ms: u128,
+    pub data: BmsData,
+}
+
+// --- Data Recorder ---
+/// Ring buffers of the most recent datasets of every BMS, used to look at the
+/// history leading up to a trip.
+#[derive(Debug)]
+pub struct DataRecorder {
+    capacity: usize,
+    samples: Mutex<BTreeMap<u8, VecDeque<Sample>>>,
+}
+
+impl DataRecorder {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            samples: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Appends a dataset, dropping the oldest one of the BMS if its buffer is full.
+    pub fn record(&self, bms_id: u8, data: BmsData) {
+        let sample = Sample {
+            timestamp_ms: clock::unix_millis(),
+            monotonic_ms: clock::monotonic_millis(),
+            data,
+        };
+        let Ok(mut samples) = self.samples.lock() else {
+            log::error!("Data recorder lock poisoned, dropping sample.");
+            return;
+        };
+        let buffer = samples
+            .entry(bms_id)
+            .or_insert_with(|| VecDeque::with_capacity(self.capacity));
+        if buffer.len() >= self.capacity {
+            buffer.pop_front();
+        }
+        buffer.push_back(sample);
+    }
+
+    /// Copy of the buffered datasets of a BMS, oldest first.
+    pub fn snapshot(&self, bms_id: u8) -> Vec<Sample> {
+        self.samples
+            .lock()
+            .map(|samples| samples.get(&bms_id).map(|buffer| buffer.iter().cloned().collect()))
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+}
+
+// --- CSV Export ---
+// A CSV cell, quoted if it contains a separator, quote or line break
+fn csv_cell(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::Null => return String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => return value.to_string(),
+        // Nested values (pack metadata) stay JSON
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Samples as CSV, one row per sample with a column per BmsData field.
+/// Fields that were not received are empty.
+pub fn to_csv(samples: &[Sample]) -> String {
+    let rows: Vec<(&Sample, serde_json::Map<String, serde_json::Value>)> = samples
+        .iter()
+        .map(|sample| match serde_json::to_value(&sample.data) {
+            Ok(serde_json::Value::Object(fields)) => (sample, fields),
+            _ => (sample, serde_json::Map::new()),
+        })
+        .collect();
+
+    // Every sample has the same fields, the header is taken from the first one
+    let columns: Vec<String> = rows
+        .first()
+        .map(|(_, fields)| fields.keys().cloned().collect())
+        .unwrap_or_default();
+    let mut csv = String::from("timestamp_ms,monotonic_ms");
+    for column in &columns {
+        let _ = write!(csv, ",{}", column);
+    }
+    csv.push('\n');
+    for (sample, fields) in &rows {
+        let _ = write!(csv, "{},{}", sample.timestamp_ms, sample.monotonic_ms);
+        for column in &columns {
+            let cell = fields.get(column).map(csv_cell).unwrap_or_default();
+            let _ = write!(csv, ",{}", cell);
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+// --- Recorder Task ---
+/// Samples the datasets of all BMS into the recorder.
+pub async fn task(
+    config: RecorderConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    recorder: Arc<DataRecorder>,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting data recorder ({} min at {:?})",
+        config.minutes,
+        config.interval()
+    );
+    let mut ticker = interval(config.interval());
+    loop {
+        ticker.tick().await;
+        for (bms_id, bms_data) in &bms {
+            recorder.record(*bms_id, bms_data.get());
+        }
+    }
+}
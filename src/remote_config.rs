@@ -0,0 +1,130 @@
+// src/remote_config.rs
+use crate::{
+    config::{Config, RemoteConfig},
+    error::AppError,
+    persist,
+};
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::{io::Read, path::PathBuf};
+
+// Upper limit of a downloaded file, a configuration is a few kilobytes
+const MAX_DOWNLOAD_BYTES: u64 = 1024 * 1024;
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn public_key(settings: &RemoteConfig) -> Result<VerifyingKey, AppError> {
+    let bytes: [u8; 32] = decode_hex(&settings.public_key)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| AppError::Config("remote.public_key: expected 32 hex-encoded bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| AppError::Config(format!("remote.public_key: {}", e)))
+}
+
+// Checks the detached signature (raw 64 bytes or hex text) of `content`
+fn verify(key: &VerifyingKey, content: &[u8], signature: &[u8]) -> Result<(), AppError> {
+    let bytes: [u8; 64] = match signature.try_into() {
+        Ok(raw) => raw,
+        Err(_) => std::str::from_utf8(signature)
+            .ok()
+            .and_then(decode_hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| AppError::Config("signature is neither 64 raw nor 128 hex bytes".to_string()))?,
+    };
+    key.verify_strict(content, &Signature::from_bytes(&bytes))
+        .map_err(|e| AppError::Config(format!("signature does not verify: {}", e)))
+}
+
+fn signature_path(settings: &RemoteConfig) -> PathBuf {
+    let mut path = settings.cache_path.clone().into_os_string();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn download(agent: &ureq::Agent, url: &str) -> Result<Vec<u8>, AppError> {
+    let response = agent.get(url).call().map_err(|e| AppError::Config(format!("{}: {}", url, e)))?;
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_BYTES)
+        .read_to_end(&mut body)
+        .map_err(|e| AppError::Config(format!("{}: {}", url, e)))?;
+    Ok(body)
+}
+
+// Configuration and signature from the server
+fn fetch(settings: &RemoteConfig) -> Result<(Vec<u8>, Vec<u8>), AppError> {
+    let agent = ureq::AgentBuilder::new().timeout(settings.timeout()).https_only(true).build();
+    let content = download(&agent, &settings.url)?;
+    let signature = download(&agent, &settings.signature_url())?;
+    Ok((content, signature))
+}
+
+// Configuration and signature of the last verified fetch
+fn cached(settings: &RemoteConfig) -> Result<(Vec<u8>, Vec<u8>), AppError> {
+    let read = |path: &PathBuf| std::fs::read(path).map_err(|e| AppError::Config(format!("{}: {}", path.display(), e)));
+    Ok((read(&settings.cache_path)?, read(&signature_path(settings))?))
+}
+
+// The configuration in `content` if its signature verifies and it parses
+fn apply(
+    settings: &RemoteConfig,
+    key: &VerifyingKey,
+    (content, signature): (&[u8], &[u8]),
+    origin: &str,
+    overrides: &[(String, String)],
+) -> Result<Config, AppError> {
+    verify(key, content, signature)?;
+    let text = std::str::from_utf8(content).map_err(|e| AppError::Config(format!("{}: {}", origin, e)))?;
+    let mut config = Config::parse(text, origin, overrides)?;
+    config.remote = settings.clone();
+    Ok(config)
+}
+
+/// Replaces the locally loaded configuration by the remote one (see RemoteConfig). Falls
+/// back to the cached copy if the fetch fails or does not verify, and to `local` if there
+/// is no valid cached copy either.
+pub fn load(local: Config, overrides: &[(String, String)]) -> Result<Config, AppError> {
+    let settings = local.remote.clone();
+    if !settings.url.starts_with("https://") {
+        return Err(AppError::Config(format!("remote.url: '{}' is not an HTTPS URL", settings.url)));
+    }
+    let key = public_key(&settings)?;
+
+    let fetched = fetch(&settings)
+        .and_then(|(content, signature)| {
+            let config = apply(&settings, &key, (&content, &signature), &settings.url, overrides)?;
+            Ok((config, content, signature))
+        });
+    match fetched {
+        Ok((config, content, signature)) => {
+            log::warn!(target: "audit", "Applying the verified remote configuration from {}.", settings.url);
+            let cache = persist::write_atomic(&settings.cache_path, &content)
+                .and_then(|()| persist::write_atomic(&signature_path(&settings), &signature));
+            if let Err(e) = cache {
+                log::error!("Cannot cache the remote configuration in {}: {}", settings.cache_path.display(), e);
+            }
+            return Ok(config);
+        }
+        Err(e) => log::error!("Remote configuration rejected: {}", e),
+    }
+
+    let origin = settings.cache_path.display().to_string();
+    match cached(&settings).and_then(|(content, signature)| apply(&settings, &key, (&content, &signature), &origin, overrides)) {
+        Ok(config) => {
+            log::warn!(target: "audit", "Applying the cached remote configuration from {}.", origin);
+            Ok(config)
+        }
+        Err(e) => {
+            log::error!("No valid cached remote configuration ({}), using the local configuration.", e);
+            Ok(local)
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// src/rules.rs
+use crate::config::{Limits, RulesConfig};
+use crate::data::BmsData;
+use std::collections::BTreeMap;
+
+// --- Severity ---
+/// Graded outcome of a threshold rule, ordered from harmless to critical.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Severity {
+    #[default]
+    Normal = 0,
+    Warning = 1,
+    Derate = 2,
+    Trip = 3,
+}
+
+/// Highest active severity per BMS ID, shared with the inverter clients for derating.
+pub type SeverityMap = BTreeMap<u8, Severity>;
+
+// --- Rules ---
+/// The monitored quantities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    CellVoltageHigh,
+    CellVoltageLow,
+    TemperatureHigh,
+    TemperatureLow,
+    SocLow,
+}
+
+const RULES: [Rule; 5] = [
+    Rule::CellVoltageHigh,
+    Rule::CellVoltageLow,
+    Rule::TemperatureHigh,
+    Rule::TemperatureLow,
+    Rule::SocLow,
+];
+
+impl Rule {
+    // Value the rule looks at, None while the BMS has not reported it yet
+    fn value(self, data: &BmsData) -> Option<u16> {
+        match self {
+            Rule::CellVoltageHigh => data.max_cell_voltage,
+            Rule::CellVoltageLow => data.min_cell_voltage,
+            Rule::TemperatureHigh => data.max_temperature.map(u16::from),
+            Rule::TemperatureLow => data.min_temperature.map(u16::from),
+            Rule::SocLow => data.soc.map(u16::from),
+        }
+    }
+
+    // Whether exceeding means "above the limit" (true) or "below the limit" (false)
+    fn is_upper_limit(self) -> bool {
+        matches!(self, Rule::CellVoltageHigh | Rule::TemperatureHigh)
+    }
+
+    // Highest level whose limit `value` exceeds
+    fn classify(self, value: u16, config: &RulesConfig) -> Severity {
+        let limits = self.limits(config);
+        let exceeds = |limit: Option<u16>| match limit {
+            Some(limit) if self.is_upper_limit() => value >= limit,
+            Some(limit) => value <= limit,
+            None => false,
+        };
+        if exceeds(limits.trip) {
+            Severity::Trip
+        } else if exceeds(limits.derate) {
+            Severity::Derate
+        } else if exceeds(limits.warning) {
+            Severity::Warning
+        } else {
+            Severity::Normal
+        }
+    }
+
+    /// Current severity of this rule for `data`, Normal if rules are disabled or the value is unknown.
+    pub fn severity(self, data: &BmsData, config: &RulesConfig) -> Severity {
+        match self.value(data) {
+            Some(value) if config.enabled => self.classify(value, config),
+            _ => Severity::Normal,
+        }
+    }
+
+    fn limits(self, config: &RulesConfig) -> &Limits {
+        match self {
+            Rule::CellVoltageHigh => &config.cell_voltage_high,
+            Rule::CellVoltageLow => &config.cell_voltage_low,
+            Rule::TemperatureHigh => &config.temperature_high,
+            Rule::TemperatureLow => &config.temperature_low,
+            Rule::SocLow => &config.soc_low,
+        }
+    }
+}
+
+/// A change of the severity of one rule.
+#[derive(Debug, Clone)]
+pub struct RuleEvent {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub previous: Severity,
+    pub value: u16,
+}
+
+// --- Rule Engine ---
+/// Evaluates the configured thresholds of one BMS and reports severity changes.
+pub struct RuleEngine {
+    config: RulesConfig,
+    active: [Severity; RULES.len()],
+}
+
+impl RuleEngine {
+    pub fn new(config: RulesConfig) -> Self {
+        Self {
+            config,
+            active: [Severity::Normal; RULES.len()],
+        }
+    }
+
+    /// Evaluates all rules against `data` and returns the rules whose severity changed.
+    pub fn evaluate(&mut self, data: &BmsData) -> Vec<RuleEvent> {
+        let mut events = Vec::new();
+        if !self.config.enabled {
+            return events;
+        }
+
+        for (index, rule) in RULES.iter().enumerate() {
+            let Some(value) = rule.value(data) else {
+                continue;
+            };
+            let severity = rule.classify(value, &self.config);
+
+            let previous = self.active[index];
+            if severity != previous {
+                self.active[index] = severity;
+                events.push(RuleEvent { rule: *rule, severity, previous, value });
+            }
+        }
+        events
+    }
+
+    /// Highest severity of all rules.
+    pub fn highest(&self) -> Severity {
+        self.active.iter().copied().max().unwrap_or_default()
+    }
+}
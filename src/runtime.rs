@@ -0,0 +1,97 @@
+// src/runtime.rs
+use crate::{
+    config::{RuntimeConfig, RuntimeFlavor},
+    error::AppError,
+};
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Builds the main runtime according to the configuration.
+pub fn build(config: &RuntimeConfig) -> Result<Runtime, AppError> {
+    let mut builder = match config.flavor {
+        RuntimeFlavor::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+            if let Some(worker_threads) = config.worker_threads {
+                builder.worker_threads(worker_threads.max(1));
+            }
+            builder
+        }
+        RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+    };
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads.max(1));
+    }
+    let runtime = builder.enable_all().build().map_err(AppError::Runtime)?;
+    log::info!(
+        "Runtime: {:?}, worker threads {:?}, max blocking threads {:?}",
+        config.flavor,
+        config.worker_threads,
+        config.max_blocking_threads
+    );
+    Ok(runtime)
+}
+
+// Raises the calling thread to SCHED_FIFO and pins it to a core, failures only cost latency
+#[cfg(target_os = "linux")]
+fn tune_current_thread(config: &RuntimeConfig) {
+    if let Some(priority) = config.can_rx_priority {
+        let param = libc::sched_param { sched_priority: priority };
+        // SAFETY: pid 0 addresses the calling thread, `param` outlives the call
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+            log::warn!(
+                "Runtime: Cannot set SCHED_FIFO priority {} for CAN RX: {}",
+                priority,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    if let Some(cpu) = config.can_rx_cpu {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            log::warn!("Runtime: CPU {} for CAN RX is out of range.", cpu);
+            return;
+        }
+        // SAFETY: cpu_set_t is plain data, zeroed is the empty set
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        // SAFETY: the index was checked against the size of the set
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        // SAFETY: pid 0 addresses the calling thread, the size matches `set`
+        if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+            log::warn!(
+                "Runtime: Cannot pin CAN RX to CPU {}: {}",
+                cpu,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tune_current_thread(config: &RuntimeConfig) {
+    if config.can_rx_priority.is_some() || config.can_rx_cpu.is_some() {
+        log::warn!("Runtime: Priority and CPU of the CAN RX thread can only be set on Linux.");
+    }
+}
+
+/// Runtime the CAN receivers are spawned on: the current one, or with `can_rx_thread`
+/// a single-threaded runtime on a dedicated (prioritized, pinned) thread.
+pub fn can_rx_handle(config: &RuntimeConfig) -> Result<Handle, AppError> {
+    if !config.can_rx_thread {
+        return Ok(Handle::current());
+    }
+    let runtime = Builder::new_current_thread().enable_all().build().map_err(AppError::Runtime)?;
+    let handle = runtime.handle().clone();
+    let config = config.clone();
+    std::thread::Builder::new()
+        .name("can-rx".to_string())
+        .spawn(move || {
+            tune_current_thread(&config);
+            log::info!(
+                "Runtime: CAN RX thread started (priority {:?}, CPU {:?})",
+                config.can_rx_priority,
+                config.can_rx_cpu
+            );
+            // Drives the spawned receivers until the process exits
+            runtime.block_on(std::future::pending::<()>());
+        })
+        .map_err(AppError::Runtime)?;
+    Ok(handle)
+}
@@ -0,0 +1,104 @@
+// src/scheduler.rs
+use crate::{
+    SystemCommand,
+    arbiter::{CommandSource, SourcedCommand},
+    config::{SchedulerConfig, Weekday},
+    error::AppError,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+// Indexed by days since the Unix epoch modulo 7, 1970-01-01 was a Thursday
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+];
+
+// An entry with its time parsed to minutes since midnight
+struct Schedule {
+    command: SystemCommand,
+    minute_of_day: i64,
+    days: Vec<Weekday>,
+}
+
+fn parse_time(at: &str) -> Result<i64, AppError> {
+    let invalid = || AppError::Config(format!("Invalid schedule time '{}', expected HH:MM", at));
+    let (hours, minutes) = at.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: i64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+// Local weekday and minute of the day for a Unix timestamp
+fn local_time(unix_seconds: i64, utc_offset_minutes: i32) -> (Weekday, i64) {
+    let local = unix_seconds + i64::from(utc_offset_minutes) * 60;
+    let day = local.div_euclid(SECONDS_PER_DAY);
+    let weekday = WEEKDAYS[day.rem_euclid(7) as usize];
+    (weekday, local.rem_euclid(SECONDS_PER_DAY) / 60)
+}
+
+fn unix_seconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// --- Scheduler Task ---
+/// Sends the configured commands to the arbiter when their time is reached.
+/// Checked once per minute, commands missed while the gateway was not running are not caught up.
+pub async fn task(
+    config: SchedulerConfig,
+    input_tx: std::sync::mpsc::Sender<SourcedCommand>,
+) -> Result<(), AppError> {
+    let schedules = config
+        .entries
+        .iter()
+        .map(|entry| {
+            Ok(Schedule {
+                command: entry.command.clone(),
+                minute_of_day: parse_time(&entry.at)?,
+                days: entry.days.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+    log::info!(
+        "Starting scheduler with {} entries (UTC offset {} min)",
+        schedules.len(),
+        config.utc_offset_minutes
+    );
+
+    loop {
+        // Wake up at the start of every minute
+        let now = unix_seconds();
+        sleep(Duration::from_secs((60 - now.rem_euclid(60)) as u64)).await;
+
+        let (weekday, minute_of_day) = local_time(unix_seconds(), config.utc_offset_minutes);
+        for schedule in &schedules {
+            if schedule.minute_of_day != minute_of_day
+                || !(schedule.days.is_empty() || schedule.days.contains(&weekday))
+            {
+                continue;
+            }
+            log::info!(
+                "Scheduler: Sending {:?} ({:?} {:02}:{:02}).",
+                schedule.command,
+                weekday,
+                minute_of_day / 60,
+                minute_of_day % 60
+            );
+            input_tx
+                .send(SourcedCommand::new(CommandSource::Scheduler, schedule.command.clone()))
+                .map_err(|e| AppError::SendError(format!("Failed to send scheduled command: {}", e)))?;
+        }
+    }
+}
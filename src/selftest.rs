@@ -0,0 +1,160 @@
+// src/selftest.rs
+use crate::{
+    can_sys::{CanInterface, CanSocket, Socket},
+    config::Config,
+    data, gpio,
+    gpio_backend::GpioProvider,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, net::SocketAddr, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+// --- Checks ---
+/// Group of a self-test check, its code is blinked by the red LED when the check fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    Can,
+    Gpio,
+    ModbusBind,
+    Inverter,
+    RegisterMap,
+}
+
+impl CheckKind {
+    pub fn led_code(self) -> u8 {
+        match self {
+            CheckKind::Can => 1,
+            CheckKind::Gpio => 2,
+            CheckKind::ModbusBind => 3,
+            CheckKind::Inverter => 4,
+            CheckKind::RegisterMap => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub kind: CheckKind,
+    /// What was checked, e.g. the interface name or address
+    pub target: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of the startup self-test, served by the HTTP API as /health.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    fn push(&mut self, kind: CheckKind, target: &str, result: Result<String, String>) {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        if passed {
+            log::info!("Self-test: {:?} {} passed ({})", kind, target, detail);
+        } else {
+            log::error!("Self-test: {:?} {} FAILED: {}", kind, target, detail);
+        }
+        self.checks.push(CheckResult { kind, target: target.to_string(), passed, detail });
+    }
+
+    /// LED codes of the failed check groups, in ascending order.
+    pub fn failed_codes(&self) -> Vec<u8> {
+        self.checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.kind.led_code())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+// The interface has to exist and, if configured, run at the expected bitrate
+fn check_can(interface: &str, expected_bitrate: Option<u32>) -> Result<String, String> {
+    CanSocket::open(interface).map_err(|e| format!("cannot open interface: {}", e))?;
+    let Some(expected) = expected_bitrate else {
+        return Ok("interface available".to_string());
+    };
+    let bitrate = CanInterface::open(interface)
+        .map_err(|e| format!("cannot query interface: {}", e))?
+        .bit_rate()
+        .map_err(|e| format!("cannot read bitrate: {}", e))?;
+    match bitrate {
+        Some(bitrate) if bitrate == expected => Ok(format!("bitrate {}", bitrate)),
+        Some(bitrate) => Err(format!("bitrate {}, expected {}", bitrate, expected)),
+        None => Err(format!("bitrate unknown, expected {}", expected)),
+    }
+}
+
+// The listener is dropped right away, the Modbus server binds the port again
+async fn check_bind(addr: &str) -> Result<String, String> {
+    let socket_addr: SocketAddr = addr.parse().map_err(|e| format!("invalid address: {}", e))?;
+    TcpListener::bind(socket_addr)
+        .await
+        .map(|_| "port available".to_string())
+        .map_err(|e| e.to_string())
+}
+
+async fn check_inverter(addr: &str, connect_timeout: Duration) -> Result<String, String> {
+    let socket_addr: SocketAddr = addr.parse().map_err(|e| format!("invalid address: {}", e))?;
+    match timeout(connect_timeout, TcpStream::connect(socket_addr)).await {
+        Ok(Ok(_)) => Ok("reachable".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no connection within {:?}", connect_timeout)),
+    }
+}
+
+// --- Self-Test ---
+/// Runs all startup checks. Failures are logged and reported, they do not stop the checks.
+pub async fn run(config: &Config, gpio: &dyn GpioProvider) -> SelfTestReport {
+    log::info!("Running startup self-test...");
+    let mut report = SelfTestReport::default();
+
+    let mut interfaces = BTreeSet::from([config.can.interface.clone()]);
+    for bms_id in [1, 2] {
+        let bus = config.can.bus(bms_id);
+        interfaces.insert(bus.primary);
+        interfaces.extend(bus.secondary);
+    }
+    for interface in &interfaces {
+        report.push(CheckKind::Can, interface, check_can(interface, config.self_test.can_bitrate));
+    }
+
+    report.push(CheckKind::Gpio, "gpio", gpio::check_pins(gpio, &config.gpio.pins).map_err(|e| e.to_string()));
+
+    if config.self_test.register_map {
+        report.push(CheckKind::RegisterMap, "registers", data::check_register_map());
+    }
+
+    for server in &config.modbus_servers {
+        report.push(CheckKind::ModbusBind, &server.addr, check_bind(&server.addr).await);
+    }
+
+    for inverter in &config.inverters {
+        let result = check_inverter(&inverter.addr, config.self_test.inverter_timeout()).await;
+        report.push(CheckKind::Inverter, &inverter.name, result);
+    }
+
+    report.passed = report.checks.iter().all(|check| check.passed);
+    let failed = report.checks.iter().filter(|check| !check.passed).count();
+    if report.passed {
+        log::info!("Self-test passed ({} checks).", report.checks.len());
+    } else {
+        log::error!(
+            "Self-test failed: {} of {} checks failed (LED codes {:?}).",
+            failed,
+            report.checks.len(),
+            report.failed_codes()
+        );
+    }
+    report
+}
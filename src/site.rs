@@ -0,0 +1,168 @@
+// src/site.rs
+use crate::{
+    config::{InvalidValueConfig, InvalidValuePolicy, SiteMapConfig, WordOrder},
+    data::{self, BmsData, INVALID_REGISTER_VALUE, SharedBmsData},
+};
+use tokio_modbus::prelude::ExceptionCode;
+
+// --- Register Block ---
+// Offsets from SiteMapConfig::start. The measurements combine the packs with valid data,
+// the flags all packs that are not in maintenance, so no fault is hidden by stale data.
+/// SOC of the site (%), weighted by the pack capacities if configured, else the lowest
+pub const SITE_SOC: u16 = 0;
+/// Sum of the pack currents (0.1 A, signed), 32 bits over two registers
+pub const SITE_CURRENT: u16 = 1;
+pub const SITE_CURRENT_WORD2: u16 = 2;
+pub const SITE_MIN_TEMPERATURE: u16 = 3;
+pub const SITE_MAX_TEMPERATURE: u16 = 4;
+pub const SITE_MIN_CELL_VOLTAGE: u16 = 5;
+pub const SITE_MAX_CELL_VOLTAGE: u16 = 6;
+/// Warning and error bytes ORed over the packs
+pub const SITE_WARNING_1: u16 = 7;
+pub const SITE_WARNING_2: u16 = 8;
+pub const SITE_ERROR_1: u16 = 9;
+pub const SITE_ERROR_2: u16 = 10;
+/// Bit n is set while the n-th pack delivers valid data
+pub const SITE_PACKS_VALID: u16 = 11;
+/// Highest rule severity of the packs
+pub const SITE_RULE_SEVERITY: u16 = 12;
+/// Number of registers in the block
+pub const SITE_LEN: u16 = 13;
+
+// Data of a pack counts for the measurements: fresh, plausible and not being serviced
+fn is_valid(data: &BmsData) -> bool {
+    !data.stale.unwrap_or(true) && !data.implausible.unwrap_or(false) && !data.in_maintenance()
+}
+
+// Signed pack current in 0.1 A
+fn current(data: &BmsData) -> Option<i64> {
+    data.pack_current().map(i64::from)
+}
+
+/// The aggregate register block of all packs.
+#[derive(Debug)]
+pub struct SiteMap {
+    start: u16,
+    // Pack and its capacity in Ah, None if not configured
+    packs: Vec<(SharedBmsData, Option<f64>)>,
+}
+
+impl SiteMap {
+    pub fn new(config: &SiteMapConfig, packs: Vec<SharedBmsData>) -> Self {
+        log::info!("Site register block at {} ({} registers) over {} packs", config.start, SITE_LEN, packs.len());
+        let packs: Vec<(SharedBmsData, Option<f64>)> = packs
+            .into_iter()
+            .enumerate()
+            .map(|(i, pack)| (pack, config.capacities_ah.get(i).copied().filter(|capacity| *capacity > 0.0)))
+            .collect();
+        if packs.iter().all(|(_, capacity)| capacity.is_some()) {
+            log::info!("Site SOC weighted by the pack capacities {:?} Ah", config.capacities_ah);
+        } else if !config.capacities_ah.is_empty() {
+            log::warn!(
+                "Site SOC: {} capacities configured for {} packs, using the lowest pack SOC instead.",
+                config.capacities_ah.len(),
+                packs.len()
+            );
+        }
+        Self { start: config.start, packs }
+    }
+
+    /// True if `addr..addr + cnt` lies in the block, ranges only partly inside are rejected.
+    pub fn contains(&self, addr: u16, cnt: u16) -> Result<bool, ExceptionCode> {
+        // Computed in u32, a block at the end of the address space must not overflow
+        let (start, end) = (u32::from(self.start), u32::from(self.start) + u32::from(SITE_LEN));
+        let (first, last) = (u32::from(addr), u32::from(addr) + u32::from(cnt.max(1)) - 1);
+        if last < start || first >= end {
+            Ok(false)
+        } else if first >= start && last < end {
+            Ok(true)
+        } else {
+            Err(ExceptionCode::IllegalDataAddress)
+        }
+    }
+
+    /// Registers `addr..addr + cnt` of the block. Measurements without any valid pack
+    /// are answered according to the invalid value policy of their absolute address.
+    pub fn read(
+        &self,
+        addr: u16,
+        cnt: u16,
+        word_order: WordOrder,
+        invalid_value: &InvalidValueConfig,
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        let packs: Vec<BmsData> = self.packs.iter().map(|(pack, _)| pack.get()).collect();
+        let valid: Vec<&BmsData> = packs.iter().filter(|data| is_valid(data)).collect();
+        (0..cnt)
+            .map(|i| {
+                let address = addr + i;
+                let offset = address - self.start;
+                match self.value(&packs, &valid, offset, word_order) {
+                    Some(value) => Ok(value),
+                    None => match invalid_value.policy_for(address) {
+                        InvalidValuePolicy::Zero => Ok(0),
+                        // Nothing valid to fall back to, the packs may disagree
+                        InvalidValuePolicy::Marker | InvalidValuePolicy::LastKnown => Ok(INVALID_REGISTER_VALUE),
+                        InvalidValuePolicy::Exception => Err(ExceptionCode::GatewayTargetDevice),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    // SOC weighted by capacity over the valid packs, None if a capacity is missing (or no
+    // pack is valid). A mean over mixed packs would misstate the stored energy.
+    fn weighted_soc(&self, packs: &[BmsData]) -> Option<u16> {
+        let mut capacity_total = 0.0;
+        let mut charge_total = 0.0;
+        for ((_, capacity), data) in self.packs.iter().zip(packs) {
+            let capacity = (*capacity)?;
+            if let (true, Some(soc)) = (is_valid(data), data.soc) {
+                capacity_total += capacity;
+                charge_total += capacity * f64::from(soc);
+            }
+        }
+        (capacity_total > 0.0).then(|| (charge_total / capacity_total).round() as u16)
+    }
+
+    // Value at `offset`, None if no pack has a valid measurement for it
+    fn value(&self, packs: &[BmsData], valid: &[&BmsData], offset: u16, word_order: WordOrder) -> Option<u16> {
+        let min = |field: fn(&BmsData) -> Option<u16>| valid.iter().filter_map(|data| field(data)).min();
+        let max = |field: fn(&BmsData) -> Option<u16>| valid.iter().filter_map(|data| field(data)).max();
+        let flags = |field: fn(&BmsData) -> Option<u8>| {
+            packs
+                .iter()
+                .filter(|data| !data.in_maintenance())
+                .filter_map(field)
+                .fold(0, |flags, byte| flags | u16::from(byte))
+        };
+        match offset {
+            SITE_SOC => self.weighted_soc(packs).or_else(|| min(|data| data.soc.map(u16::from))),
+            SITE_CURRENT | SITE_CURRENT_WORD2 => {
+                let currents: Vec<i64> = valid.iter().filter_map(|data| current(data)).collect();
+                if currents.is_empty() {
+                    return None;
+                }
+                let total = currents.iter().sum::<i64>().clamp(i64::from(i32::MIN), i64::from(i32::MAX));
+                Some(data::register_word(total as i32 as u32, offset - SITE_CURRENT, word_order))
+            }
+            SITE_MIN_TEMPERATURE => min(|data| data.min_temperature.map(u16::from)),
+            SITE_MAX_TEMPERATURE => max(|data| data.max_temperature.map(u16::from)),
+            SITE_MIN_CELL_VOLTAGE => min(|data| data.min_cell_voltage),
+            SITE_MAX_CELL_VOLTAGE => max(|data| data.max_cell_voltage),
+            SITE_WARNING_1 => Some(flags(|data| data.warning1)),
+            SITE_WARNING_2 => Some(flags(|data| data.warning2)),
+            SITE_ERROR_1 => Some(flags(|data| data.error1)),
+            SITE_ERROR_2 => Some(flags(|data| data.error2)),
+            SITE_PACKS_VALID => Some(
+                packs
+                    .iter()
+                    .take(16)
+                    .enumerate()
+                    .filter(|(_, data)| is_valid(data))
+                    .fold(0, |mask, (bit, _)| mask | 1 << bit),
+            ),
+            SITE_RULE_SEVERITY => Some(packs.iter().filter_map(|data| data.rule_severity).max().unwrap_or(0)),
+            _ => None,
+        }
+    }
+}
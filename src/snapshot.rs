@@ -0,0 +1,81 @@
+// src/snapshot.rs
+use crate::{
+    config::SnapshotConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    persist::{self, WriteClass},
+};
+use std::collections::BTreeMap;
+use tokio::time::interval;
+
+/// Loads the last persisted BmsData per BMS ID. Restored data is flagged as stale
+/// until the first CAN frame of that BMS arrives.
+pub fn load(config: &SnapshotConfig) -> BTreeMap<u8, BmsData> {
+    let content = match std::fs::read(&config.path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(e) => {
+            log::error!("Failed to read BMS snapshot {}: {}", config.path.display(), e);
+            return BTreeMap::new();
+        }
+    };
+
+    match serde_json::from_slice::<BTreeMap<u8, BmsData>>(&content) {
+        Ok(mut snapshots) => {
+            for data in snapshots.values_mut() {
+                data.stale = Some(true);
+                // Runtime state must not survive a restart
+                data.control_frozen = Some(false);
+            }
+            log::info!(
+                "Restored stale BMS data for IDs {:?} from {}",
+                snapshots.keys().collect::<Vec<_>>(),
+                config.path.display()
+            );
+            snapshots
+        }
+        Err(e) => {
+            log::error!("Failed to parse BMS snapshot {}: {}", config.path.display(), e);
+            BTreeMap::new()
+        }
+    }
+}
+
+fn save(config: &SnapshotConfig, bms: &[(u8, SharedBmsData)]) -> Result<(), AppError> {
+    let snapshots: BTreeMap<u8, BmsData> = bms.iter().map(|(bms_id, bms_data)| (*bms_id, bms_data.get())).collect();
+    let content = serde_json::to_vec(&snapshots).map_err(|e| AppError::Persist(e.to_string()))?;
+    persist::queue(WriteClass::Snapshot, &config.path, content)
+}
+
+// --- Snapshot Task ---
+/// Periodically writes the latest BmsData of every BMS to disk, skipping intervals
+/// in which no dataset changed.
+pub async fn task(
+    config: SnapshotConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting BMS snapshot task ({} every {:?})",
+        config.path.display(),
+        config.interval()
+    );
+    let mut ticker = interval(config.interval());
+    // The first tick completes immediately, skip it to not overwrite the snapshot with startup values
+    ticker.tick().await;
+    let mut receivers: Vec<_> = bms.iter().map(|(_, bms_data)| bms_data.subscribe()).collect();
+
+    loop {
+        ticker.tick().await;
+        let mut changed = false;
+        for rx in &mut receivers {
+            changed |= rx.has_changed().unwrap_or(false);
+            rx.mark_unchanged();
+        }
+        if !changed {
+            continue;
+        }
+        if let Err(e) = save(&config, &bms) {
+            log::error!("Failed to write BMS snapshot: {}", e);
+        }
+    }
+}
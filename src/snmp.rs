@@ -0,0 +1,625 @@
+// src/snmp.rs
+use crate::{
+    config::SnmpConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    rules::Severity,
+};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    ops::Bound,
+    time::Instant,
+};
+use tokio::{net::UdpSocket, time::interval};
+
+// --- Standard Objects ---
+const SYS_DESCR: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 1, 0];
+const SYS_UP_TIME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+const SNMP_TRAP_OID: [u32; 11] = [1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+// --- Gateway Objects ---
+// Below the enterprise OID: .1.<column>.<bms id> holds the BMS values,
+// .2.<trap> are the notification OIDs
+const BMS_TABLE: u32 = 1;
+const COLUMN_STALE: u32 = 15;
+const COLUMN_FAULT: u32 = 16;
+const COLUMN_CORRUPTED_FRAMES: u32 = 17;
+const COLUMN_SEQUENCE_GAPS: u32 = 18;
+const NOTIFICATIONS: u32 = 2;
+const TRAP_FAULT: u32 = 1;
+const TRAP_FAULT_CLEARED: u32 = 2;
+const TRAP_STALE: u32 = 3;
+const TRAP_FRESH: u32 = 4;
+
+// --- BER Tags ---
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_GAUGE: u8 = 0x42;
+const TAG_TIME_TICKS: u8 = 0x43;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+const PDU_GET: u8 = 0xA0;
+const PDU_GET_NEXT: u8 = 0xA1;
+const PDU_RESPONSE: u8 = 0xA2;
+const PDU_SET: u8 = 0xA3;
+const PDU_GET_BULK: u8 = 0xA5;
+const PDU_TRAP_V2: u8 = 0xA7;
+
+const VERSION_1: i64 = 0;
+const VERSION_2C: i64 = 1;
+
+// Error statuses
+const NO_ERROR: i64 = 0;
+const NO_SUCH_NAME: i64 = 2;
+const READ_ONLY: i64 = 4;
+const GEN_ERR: i64 = 5;
+const NOT_WRITABLE: i64 = 17;
+
+// Upper bound for the repetitions of a GetBulk request
+const MAX_BULK_REPETITIONS: i64 = 64;
+
+/// Value of a MIB object.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i64),
+    OctetString(String),
+    Oid(Vec<u32>),
+    Gauge(u32),
+    TimeTicks(u32),
+    Null,
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+// --- BER Encoding ---
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+// Minimal two's complement representation
+fn integer_content(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+// Unsigned application types must not look negative
+fn unsigned_content(value: u32) -> Vec<u8> {
+    integer_content(i64::from(value))
+}
+
+fn oid_content(oid: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (first, rest) = match oid {
+        [a, b, rest @ ..] => (a * 40 + b, rest),
+        [a] => (a * 40, &[][..]),
+        [] => (0, &[][..]),
+    };
+    for arc in std::iter::once(first).chain(rest.iter().copied()) {
+        let mut groups = vec![(arc & 0x7F) as u8];
+        let mut remaining = arc >> 7;
+        while remaining > 0 {
+            groups.push((remaining & 0x7F) as u8 | 0x80);
+            remaining >>= 7;
+        }
+        out.extend(groups.into_iter().rev());
+    }
+    out
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Integer(value) => tlv(TAG_INTEGER, &integer_content(*value)),
+            Value::OctetString(value) => tlv(TAG_OCTET_STRING, value.as_bytes()),
+            Value::Oid(oid) => tlv(TAG_OID, &oid_content(oid)),
+            Value::Gauge(value) => tlv(TAG_GAUGE, &unsigned_content(*value)),
+            Value::TimeTicks(value) => tlv(TAG_TIME_TICKS, &unsigned_content(*value)),
+            Value::Null => tlv(TAG_NULL, &[]),
+            Value::NoSuchObject => tlv(TAG_NO_SUCH_OBJECT, &[]),
+            Value::NoSuchInstance => tlv(TAG_NO_SUCH_INSTANCE, &[]),
+            Value::EndOfMibView => tlv(TAG_END_OF_MIB_VIEW, &[]),
+        }
+    }
+}
+
+fn encode_varbinds(varbinds: &[(Vec<u32>, Value)]) -> Vec<u8> {
+    let content: Vec<u8> = varbinds
+        .iter()
+        .flat_map(|(oid, value)| {
+            let mut varbind = tlv(TAG_OID, &oid_content(oid));
+            varbind.extend(value.encode());
+            tlv(TAG_SEQUENCE, &varbind)
+        })
+        .collect();
+    tlv(TAG_SEQUENCE, &content)
+}
+
+fn encode_message(
+    version: i64,
+    community: &str,
+    pdu_type: u8,
+    request_id: i64,
+    error_status: i64,
+    error_index: i64,
+    varbinds: &[(Vec<u32>, Value)],
+) -> Vec<u8> {
+    let mut pdu = tlv(TAG_INTEGER, &integer_content(request_id));
+    pdu.extend(tlv(TAG_INTEGER, &integer_content(error_status)));
+    pdu.extend(tlv(TAG_INTEGER, &integer_content(error_index)));
+    pdu.extend(encode_varbinds(varbinds));
+
+    let mut message = tlv(TAG_INTEGER, &integer_content(version));
+    message.extend(tlv(TAG_OCTET_STRING, community.as_bytes()));
+    message.extend(tlv(pdu_type, &pdu));
+    tlv(TAG_SEQUENCE, &message)
+}
+
+// --- BER Decoding ---
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    // Next element as (tag, content)
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            let count = usize::from(first & 0x7F);
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count].iter().fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (content, remaining) = rest.split_at(len);
+        self.data = remaining;
+        Some((tag, content))
+    }
+
+    fn read_expected(&mut self, expected: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (tag, content) if tag == expected => Some(content),
+            _ => None,
+        }
+    }
+
+    fn read_integer(&mut self) -> Option<i64> {
+        let content = self.read_expected(TAG_INTEGER)?;
+        if content.is_empty() || content.len() > 8 {
+            return None;
+        }
+        let sign = if content[0] & 0x80 != 0 { -1i64 } else { 0 };
+        Some(content.iter().fold(sign, |acc, b| (acc << 8) | i64::from(*b)))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+fn decode_oid(content: &[u8]) -> Option<Vec<u32>> {
+    let mut arcs = Vec::new();
+    let mut value: u32 = 0;
+    for byte in content {
+        value = value.checked_mul(128)? | u32::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    Some(arcs)
+}
+
+/// A decoded request PDU.
+struct Request {
+    version: i64,
+    community: String,
+    pdu_type: u8,
+    request_id: i64,
+    // Non-repeaters and max-repetitions for GetBulk, error status and index otherwise
+    field1: i64,
+    field2: i64,
+    oids: Vec<Vec<u32>>,
+}
+
+fn decode_request(packet: &[u8]) -> Option<Request> {
+    let mut message = Reader::new(Reader::new(packet).read_expected(TAG_SEQUENCE)?);
+    let version = message.read_integer()?;
+    let community = String::from_utf8_lossy(message.read_expected(TAG_OCTET_STRING)?).into_owned();
+    let (pdu_type, pdu) = message.read()?;
+
+    let mut pdu = Reader::new(pdu);
+    let request_id = pdu.read_integer()?;
+    let field1 = pdu.read_integer()?;
+    let field2 = pdu.read_integer()?;
+    let mut varbinds = Reader::new(pdu.read_expected(TAG_SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !varbinds.is_empty() {
+        let mut varbind = Reader::new(varbinds.read_expected(TAG_SEQUENCE)?);
+        oids.push(decode_oid(varbind.read_expected(TAG_OID)?)?);
+    }
+
+    Some(Request { version, community, pdu_type, request_id, field1, field2, oids })
+}
+
+// --- MIB ---
+type Mib = BTreeMap<Vec<u32>, Value>;
+
+fn is_fault(data: &BmsData) -> bool {
+    data.error1.unwrap_or(0) != 0
+        || data.error2.unwrap_or(0) != 0
+        || data.rule_severity.unwrap_or(0) >= Severity::Trip as u16
+}
+
+// Columns of the BMS table, unreported values are left out
+fn bms_columns(data: &BmsData) -> Vec<(u32, Option<Value>)> {
+    let gauge = |value: Option<u32>| value.map(Value::Gauge);
+    vec![
+        (1, gauge(data.min_cell_voltage.map(u32::from))),
+        (2, gauge(data.max_cell_voltage.map(u32::from))),
+        (3, gauge(data.min_temperature.map(u32::from))),
+        (4, gauge(data.max_temperature.map(u32::from))),
+        (5, gauge(data.soc.map(u32::from))),
+        (6, data.pack_current().map(|current| Value::Integer(i64::from(current)))),
+        (7, gauge(data.pack_voltage())),
+        (8, gauge(data.info.map(u32::from))),
+        (9, gauge(data.warning1.map(u32::from))),
+        (10, gauge(data.warning2.map(u32::from))),
+        (11, gauge(data.error1.map(u32::from))),
+        (12, gauge(data.error2.map(u32::from))),
+        (13, gauge(data.rule_severity.map(u32::from))),
+        (14, gauge(data.command_status.map(u32::from))),
+        (COLUMN_STALE, Some(Value::Integer(i64::from(data.stale.unwrap_or(true))))),
+        (COLUMN_FAULT, Some(Value::Integer(i64::from(is_fault(data))))),
+        (COLUMN_CORRUPTED_FRAMES, gauge(Some(u32::from(data.corrupted_frames.unwrap_or(0))))),
+        (COLUMN_SEQUENCE_GAPS, gauge(Some(u32::from(data.sequence_gaps.unwrap_or(0))))),
+    ]
+}
+
+fn table_oid(base: &[u32], column: u32, bms_id: u8) -> Vec<u32> {
+    [base, &[BMS_TABLE, column, u32::from(bms_id)]].concat()
+}
+
+// Uptime in hundredths of a second, as used by sysUpTime
+fn uptime_ticks(start: Instant) -> u32 {
+    (start.elapsed().as_millis() / 10) as u32
+}
+
+fn build_mib(base: &[u32], start: Instant, bms: &[(u8, SharedBmsData)]) -> Mib {
+    let mut mib = Mib::new();
+    mib.insert(
+        SYS_DESCR.to_vec(),
+        Value::OctetString(format!("CAN Modbus Gateway {}", env!("CARGO_PKG_VERSION"))),
+    );
+    mib.insert(SYS_UP_TIME.to_vec(), Value::TimeTicks(uptime_ticks(start)));
+    for (bms_id, bms_data) in bms {
+        for (column, value) in bms_data.read(bms_columns) {
+            if let Some(value) = value {
+                mib.insert(table_oid(base, column, *bms_id), value);
+            }
+        }
+    }
+    mib
+}
+
+fn next_entry(mib: &Mib, oid: &[u32]) -> Option<(Vec<u32>, Value)> {
+    mib.range::<[u32], _>((Bound::Excluded(oid), Bound::Unbounded))
+        .next()
+        .map(|(oid, value)| (oid.clone(), value.clone()))
+}
+
+// --- Request Handling ---
+// Builds the response to an authenticated request
+fn handle_request(request: &Request, mib: &Mib) -> Vec<u8> {
+    let v1 = request.version == VERSION_1;
+    let mut error_status = NO_ERROR;
+    let mut error_index = 0;
+    let mut varbinds = Vec::new();
+
+    match request.pdu_type {
+        PDU_GET | PDU_GET_NEXT => {
+            for (index, oid) in request.oids.iter().enumerate() {
+                let entry = if request.pdu_type == PDU_GET {
+                    mib.get(oid).map(|value| (oid.clone(), value.clone()))
+                } else {
+                    next_entry(mib, oid)
+                };
+                match entry {
+                    Some(entry) => varbinds.push(entry),
+                    None if v1 => {
+                        error_status = NO_SUCH_NAME;
+                        error_index = index as i64 + 1;
+                        varbinds.push((oid.clone(), Value::Null));
+                    }
+                    None if request.pdu_type == PDU_GET => {
+                        // Known subtree but no instance (e.g. a value the BMS has not reported yet)
+                        let value = if mib.keys().any(|key| key.starts_with(&oid[..oid.len().saturating_sub(1)])) {
+                            Value::NoSuchInstance
+                        } else {
+                            Value::NoSuchObject
+                        };
+                        varbinds.push((oid.clone(), value));
+                    }
+                    None => varbinds.push((oid.clone(), Value::EndOfMibView)),
+                }
+            }
+        }
+        PDU_GET_BULK if !v1 => {
+            let non_repeaters = request.field1.clamp(0, request.oids.len() as i64) as usize;
+            let max_repetitions = request.field2.clamp(0, MAX_BULK_REPETITIONS);
+            let (singles, repeaters) = request.oids.split_at(non_repeaters);
+            for oid in singles {
+                varbinds.push(next_entry(mib, oid).unwrap_or((oid.clone(), Value::EndOfMibView)));
+            }
+            let mut cursors: Vec<Vec<u32>> = repeaters.to_vec();
+            for _ in 0..max_repetitions {
+                if cursors.is_empty() {
+                    break;
+                }
+                for cursor in cursors.iter_mut() {
+                    match next_entry(mib, cursor) {
+                        Some((oid, value)) => {
+                            *cursor = oid.clone();
+                            varbinds.push((oid, value));
+                        }
+                        None => varbinds.push((cursor.clone(), Value::EndOfMibView)),
+                    }
+                }
+                // Stop once every column reached the end of the MIB
+                if cursors.iter().all(|cursor| next_entry(mib, cursor).is_none()) {
+                    break;
+                }
+            }
+        }
+        PDU_SET => {
+            error_status = if v1 { READ_ONLY } else { NOT_WRITABLE };
+            error_index = 1;
+            varbinds = request.oids.iter().map(|oid| (oid.clone(), Value::Null)).collect();
+        }
+        other => {
+            log::debug!("SNMP: Unsupported PDU type {:#04X}", other);
+            error_status = GEN_ERR;
+        }
+    }
+
+    encode_message(
+        request.version,
+        &request.community,
+        PDU_RESPONSE,
+        request.request_id,
+        error_status,
+        error_index,
+        &varbinds,
+    )
+}
+
+// --- Traps ---
+// Trap state of one BMS, to send traps on changes only
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct TrapState {
+    fault: bool,
+    stale: bool,
+}
+
+async fn send_trap(
+    socket: &UdpSocket,
+    config: &SnmpConfig,
+    base: &[u32],
+    start: Instant,
+    trap: u32,
+    bms_id: u8,
+    data: &BmsData,
+) {
+    let mut varbinds = vec![
+        (SYS_UP_TIME.to_vec(), Value::TimeTicks(uptime_ticks(start))),
+        (SNMP_TRAP_OID.to_vec(), Value::Oid([base, &[NOTIFICATIONS, trap]].concat())),
+    ];
+    for (column, value) in bms_columns(data) {
+        if matches!(column, 11 | 12 | COLUMN_STALE | COLUMN_FAULT) {
+            if let Some(value) = value {
+                varbinds.push((table_oid(base, column, bms_id), value));
+            }
+        }
+    }
+    let message = encode_message(VERSION_2C, &config.trap_community, PDU_TRAP_V2, 0, 0, 0, &varbinds);
+
+    for target in &config.trap_targets {
+        if let Err(e) = socket.send_to(&message, target.as_str()).await {
+            log::error!("SNMP: Failed to send trap to {}: {}", target, e);
+        }
+    }
+}
+
+// Sends traps for fault and stale-data changes since the last check
+async fn check_events(
+    socket: &UdpSocket,
+    config: &SnmpConfig,
+    base: &[u32],
+    start: Instant,
+    bms: &[(u8, SharedBmsData)],
+    states: &mut BTreeMap<u8, TrapState>,
+) -> Result<(), AppError> {
+    for (bms_id, bms_data) in bms {
+        let data = bms_data.get();
+        // Changes during maintenance are reported once it ends
+        if data.in_maintenance() {
+            continue;
+        }
+        let state = TrapState { fault: is_fault(&data), stale: data.stale.unwrap_or(true) };
+        let previous = states.insert(*bms_id, state).unwrap_or_default();
+
+        if state.fault != previous.fault {
+            let trap = if state.fault { TRAP_FAULT } else { TRAP_FAULT_CLEARED };
+            log::info!("SNMP: Sending {} trap for BMS {}", if state.fault { "fault" } else { "fault cleared" }, bms_id);
+            send_trap(socket, config, base, start, trap, *bms_id, &data).await;
+        }
+        if state.stale != previous.stale {
+            let trap = if state.stale { TRAP_STALE } else { TRAP_FRESH };
+            log::info!("SNMP: Sending {} trap for BMS {}", if state.stale { "stale" } else { "fresh" }, bms_id);
+            send_trap(socket, config, base, start, trap, *bms_id, &data).await;
+        }
+    }
+    Ok(())
+}
+
+// --- SNMP Agent Task ---
+/// Answers Get/GetNext/GetBulk requests for the gateway and BMS status and sends
+/// SNMPv2c traps when a BMS faults or its data becomes stale.
+pub async fn task(config: SnmpConfig, bms: Vec<(u8, SharedBmsData)>) -> Result<(), AppError> {
+    let base = config.enterprise_arcs()?;
+    let socket_addr: SocketAddr = config
+        .addr
+        .parse()
+        .map_err(|e| AppError::Config(format!("Invalid SNMP address '{}': {}", config.addr, e)))?;
+    let socket = UdpSocket::bind(socket_addr)
+        .await
+        .map_err(|source| AppError::Bind { addr: socket_addr, source })?;
+    log::info!("SNMP agent listening on {} (enterprise OID {})", socket_addr, config.enterprise_oid);
+
+    let start = Instant::now();
+    // BMS start out as fresh and fault free, so a stale restored snapshot raises a trap right away
+    let mut states: BTreeMap<u8, TrapState> = BTreeMap::new();
+    let mut ticker = interval(config.poll_interval());
+    let mut buf = [0u8; 1500];
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if !config.trap_targets.is_empty() {
+                    check_events(&socket, &config, &base, start, &bms, &mut states).await?;
+                }
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (len, peer_addr) = received?;
+                let Some(request) = decode_request(&buf[..len]) else {
+                    log::debug!("SNMP: Dropping malformed packet from {}", peer_addr);
+                    continue;
+                };
+                if !matches!(request.version, VERSION_1 | VERSION_2C) {
+                    log::debug!("SNMP: Dropping unsupported version {} from {}", request.version, peer_addr);
+                    continue;
+                }
+                if request.community != config.community {
+                    log::warn!("SNMP: Wrong community from {}", peer_addr);
+                    continue;
+                }
+
+                let response = handle_request(&request, &build_mib(&base, start, &bms));
+                if let Err(e) = socket.send_to(&response, peer_addr).await {
+                    log::warn!("SNMP: Failed to answer {}: {}", peer_addr, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_use_the_minimal_twos_complement() {
+        for (value, content) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x00, 0x80]),
+            (-1, vec![0xFF]),
+            (-129, vec![0xFF, 0x7F]),
+            (i64::MAX, vec![0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+        ] {
+            assert_eq!(integer_content(value), content, "{}", value);
+            let encoded = tlv(TAG_INTEGER, &content);
+            assert_eq!(Reader::new(&encoded).read_integer(), Some(value));
+        }
+    }
+
+    #[test]
+    fn unsigned_values_do_not_look_negative() {
+        assert_eq!(Value::Gauge(0x8000_0000).encode(), vec![TAG_GAUGE, 5, 0x00, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn oids_round_trip() {
+        let oid = vec![1, 3, 6, 1, 4, 1, 311, 21_000];
+        let content = oid_content(&oid);
+        assert_eq!(content[..7], [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37]);
+        assert_eq!(decode_oid(&content), Some(oid));
+    }
+
+    #[test]
+    fn long_lengths_round_trip() {
+        let content = vec![0xAB; 300];
+        let encoded = tlv(TAG_OCTET_STRING, &content);
+        assert_eq!(encoded[..4], [TAG_OCTET_STRING, 0x82, 0x01, 0x2C]);
+        let mut reader = Reader::new(&encoded);
+        assert_eq!(reader.read(), Some((TAG_OCTET_STRING, &content[..])));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn truncated_elements_are_rejected() {
+        let encoded = tlv(TAG_OCTET_STRING, b"public");
+        assert_eq!(Reader::new(&encoded[..encoded.len() - 1]).read(), None);
+        assert_eq!(Reader::new(&[TAG_OCTET_STRING, 0x85, 0, 0, 0, 0, 1]).read(), None);
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let packet = encode_message(
+            VERSION_2C,
+            "public",
+            PDU_GET_BULK,
+            42,
+            0,
+            10,
+            &[(SYS_DESCR.to_vec(), Value::Null), (SYS_UP_TIME.to_vec(), Value::Null)],
+        );
+        let request = decode_request(&packet).expect("request decodes");
+        assert_eq!(request.version, VERSION_2C);
+        assert_eq!(request.community, "public");
+        assert_eq!(request.pdu_type, PDU_GET_BULK);
+        assert_eq!(request.request_id, 42);
+        assert_eq!((request.field1, request.field2), (0, 10));
+        assert_eq!(request.oids, vec![SYS_DESCR.to_vec(), SYS_UP_TIME.to_vec()]);
+    }
+}
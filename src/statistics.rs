@@ -0,0 +1,230 @@
+// src/statistics.rs
+use crate::{
+    config::StatisticsConfig,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    persist::{self, WriteClass},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    task::JoinSet,
+    time::{interval, timeout},
+};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+// Window of the rolling cell voltage extremes
+const ROLLING_WINDOW: Duration = Duration::from_secs(60);
+
+// --- Energy Counters ---
+/// Charged/discharged charge and energy of one BMS over one (UTC) day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DailyStats {
+    /// Days since 1970-01-01 (UTC)
+    pub day: u64,
+    pub charged_ah: f64,
+    pub discharged_ah: f64,
+    pub charged_wh: f64,
+    pub discharged_wh: f64,
+}
+
+impl DailyStats {
+    fn new(day: u64) -> Self {
+        Self { day, ..Default::default() }
+    }
+
+    // Adds a sample of `current` (A) at `voltage` (V) held for `dt`
+    fn integrate(&mut self, current: f64, voltage: f64, dt: Duration) {
+        let hours = dt.as_secs_f64() / 3600.0;
+        let ah = current * hours;
+        let wh = current * voltage * hours;
+        if current >= 0.0 {
+            self.charged_ah += ah;
+            self.charged_wh += wh;
+        } else {
+            self.discharged_ah -= ah;
+            self.discharged_wh -= wh;
+        }
+    }
+}
+
+/// Counters of the current and the previous day for one BMS.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BmsStatistics {
+    pub today: DailyStats,
+    pub yesterday: Option<DailyStats>,
+}
+
+impl BmsStatistics {
+    // Starts a new day if the date changed since the last sample
+    fn roll_over(&mut self, day: u64) {
+        if self.today.day != day {
+            let previous = std::mem::replace(&mut self.today, DailyStats::new(day));
+            // Keep the previous day only if it really was yesterday
+            self.yesterday = (previous.day + 1 == day).then_some(previous);
+        }
+    }
+}
+
+// --- Rolling Extremes ---
+// Min and max cell voltage samples within ROLLING_WINDOW
+#[derive(Debug, Default)]
+struct RollingExtremes {
+    samples: VecDeque<(Instant, u16, u16)>,
+}
+
+impl RollingExtremes {
+    // Adds a sample and returns the lowest min and highest max cell voltage of the window
+    fn push(&mut self, now: Instant, min: u16, max: u16) -> (u16, u16) {
+        self.samples.push_back((now, min, max));
+        while let Some((at, ..)) = self.samples.front() {
+            if now.duration_since(*at) <= ROLLING_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        let lowest = self.samples.iter().map(|(_, min, _)| *min).min().unwrap_or(min);
+        let highest = self.samples.iter().map(|(_, _, max)| *max).max().unwrap_or(max);
+        (lowest, highest)
+    }
+}
+
+/// Statistics of all BMS, keyed by BMS ID.
+pub type SharedStatistics = Arc<RwLock<BTreeMap<u8, BmsStatistics>>>;
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+// Converts a counter to the 0.1 unit resolution of the statistics registers
+fn to_register(value: f64) -> u16 {
+    (value * 10.0).round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+/// Loads persisted statistics, starting empty if the file is missing or unreadable.
+pub fn load(config: &StatisticsConfig) -> BTreeMap<u8, BmsStatistics> {
+    match std::fs::read(&config.persist_path) {
+        Ok(content) => match serde_json::from_slice(&content) {
+            Ok(stats) => {
+                log::info!("Statistics restored from {}", config.persist_path.display());
+                stats
+            }
+            Err(e) => {
+                log::error!("Failed to parse statistics file {}: {}", config.persist_path.display(), e);
+                BTreeMap::new()
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            log::error!("Failed to read statistics file {}: {}", config.persist_path.display(), e);
+            BTreeMap::new()
+        }
+    }
+}
+
+/// Writes the current counters to the configured file.
+pub fn save(config: &StatisticsConfig, stats: &SharedStatistics) -> Result<(), AppError> {
+    let content = {
+        let stats_guard = stats.read().map_err(|_| AppError::LockPoisoned)?;
+        serde_json::to_vec_pretty(&*stats_guard).map_err(|e| AppError::Persist(e.to_string()))?
+    };
+    persist::queue(WriteClass::Telemetry, &config.persist_path, content)
+}
+
+// --- Statistics Task ---
+// Fields the counters and the rolling extremes are computed from
+fn input_fields(data: &BmsData) -> (Option<i32>, Option<u32>, Option<u16>, Option<u16>, Option<bool>) {
+    (data.pack_current(), data.pack_voltage(), data.min_cell_voltage, data.max_cell_voltage, data.stale)
+}
+
+// Current (A) and total voltage (V) of a dataset, None until both are known
+fn power_sample(data: &BmsData, config: &StatisticsConfig) -> Option<(f64, f64)> {
+    let current = f64::from(data.pack_current()?);
+    let voltage = f64::from(data.pack_voltage()?);
+    Some((current * config.current_scale, voltage * config.voltage_scale))
+}
+
+// Updates the statistics of one BMS whenever one of its input fields changes, and after
+// `sample_interval` without such a change, so the counters roll over while the BMS is silent
+async fn track(config: StatisticsConfig, bms_id: u8, bms_data: SharedBmsData, stats: SharedStatistics) -> Result<(), AppError> {
+    let mut changes = bms_data.changes(input_fields);
+    let mut extremes = RollingExtremes::default();
+    let mut last_sample = Instant::now();
+    // Current and voltage of the last sample, held until now
+    let mut held: Option<(f64, f64)> = None;
+
+    loop {
+        let now = Instant::now();
+        let dt = now.duration_since(last_sample);
+        last_sample = now;
+        // After a long stall (e.g. clock jump, suspended task) don't extrapolate a single sample
+        let dt = if dt > config.sample_interval() * 10 { Duration::ZERO } else { dt };
+        let day = current_day();
+
+        held = bms_data.modify(|data| -> Result<Option<(f64, f64)>, AppError> {
+            let mut stats_guard = stats.write().map_err(|_| AppError::LockPoisoned)?;
+            let entry = stats_guard.entry(bms_id).or_default();
+            entry.roll_over(day);
+            if let Some((current, voltage)) = held {
+                entry.today.integrate(current, voltage, dt);
+            }
+
+            data.charged_ah_today = Some(to_register(entry.today.charged_ah));
+            data.discharged_ah_today = Some(to_register(entry.today.discharged_ah));
+            data.charged_kwh_today = Some(to_register(entry.today.charged_wh / 1000.0));
+            data.discharged_kwh_today = Some(to_register(entry.today.discharged_wh / 1000.0));
+
+            // Stale values would stretch the window beyond the last minute of real data
+            if !data.stale.unwrap_or(true)
+                && let (Some(min), Some(max)) = (data.min_cell_voltage, data.max_cell_voltage)
+            {
+                let (lowest, highest) = extremes.push(now, min, max);
+                data.min_cell_voltage_1min = Some(lowest);
+                data.max_cell_voltage_1min = Some(highest);
+            }
+            Ok(power_sample(data, &config))
+        })?;
+
+        // The counters written above change the dataset too, but not the input fields
+        if let Ok(None) = timeout(config.sample_interval(), changes.next()).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Integrates current and power of every BMS as they change, publishes the daily counters
+/// and the rolling cell voltage extremes into the BMS registers and persists the counters
+/// periodically.
+pub async fn task(
+    config: StatisticsConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    stats: SharedStatistics,
+) -> Result<(), AppError> {
+    log::info!("Starting statistics task (at least every {:?})", config.sample_interval());
+
+    let mut trackers = JoinSet::new();
+    for (bms_id, bms_data) in bms {
+        trackers.spawn(track(config.clone(), bms_id, bms_data, Arc::clone(&stats)));
+    }
+    let mut ticker = interval(config.persist_interval());
+    // The first tick completes at once, nothing to persist yet
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = save(&config, &stats) {
+                    log::error!("Failed to persist statistics: {}", e);
+                }
+            }
+            Some(result) = trackers.join_next() => result??,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// src/storage.rs
+use crate::{
+    archive,
+    config::{StorageAreaConfig, StorageConfig},
+    data::SharedBmsData,
+    error::AppError,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::time::interval;
+
+// --- Free Space ---
+/// Bytes available to unprivileged processes on the filesystem holding `path`.
+#[cfg(unix)]
+pub fn free_bytes(path: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    // SAFETY: statvfs is plain data, it is filled in by the call
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and both pointers outlive the call
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+pub fn free_bytes(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+// --- Pruning ---
+/// Deletes the oldest files in `dir` whose names start with `prefix` until their total
+/// size is within `quota_bytes` and at least `min_free_bytes` are free. The names must
+/// sort oldest first (they start with a timestamp), the newest file is always kept.
+/// Returns the number of deleted files.
+pub fn prune(dir: &Path, prefix: &str, quota_bytes: u64, min_free_bytes: u64) -> io::Result<usize> {
+    let mut files: Vec<(PathBuf, u64)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+            .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?.len())))
+            .collect(),
+        // Nothing written yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    files.sort();
+    let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+    let mut free = if min_free_bytes > 0 { free_bytes(dir)? } else { u64::MAX };
+    let mut deleted = 0;
+    for (path, size) in files.iter().take(files.len().saturating_sub(1)) {
+        if total <= quota_bytes && free >= min_free_bytes {
+            break;
+        }
+        log::info!("Storage: Deleting {} ({} bytes)", path.display(), size);
+        fs::remove_file(path)?;
+        total -= size;
+        free = free.saturating_add(*size);
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+// --- Storage Task ---
+/// Compresses the rotated files of the data directories (black box dumps and configured
+/// areas), enforces their byte quotas and keeps `min_free_bytes` free on their
+/// filesystems. While the last check had to delete data, the storage flag of every BMS
+/// dataset is set.
+pub async fn task(config: StorageConfig, areas: Vec<StorageAreaConfig>, bms: Vec<SharedBmsData>) -> Result<(), AppError> {
+    log::info!(
+        "Starting storage manager for {} data directories (every {:?}, {} bytes kept free)",
+        areas.len(),
+        config.check_interval(),
+        config.min_free_bytes
+    );
+    let mut ticker = interval(config.check_interval());
+    let mut pruning = false;
+
+    loop {
+        ticker.tick().await;
+        let mut deleted = 0;
+        for area in &areas {
+            if config.compress
+                && let Err(e) = archive::compress_rotated(area, config.compression_level)
+            {
+                log::error!("Storage: Cannot compress the files of {}: {}", area.dir.display(), e);
+            }
+            match prune(&area.dir, &area.prefix, area.quota_bytes, config.min_free_bytes) {
+                Ok(count) => deleted += count,
+                Err(e) => log::error!("Storage: Cannot enforce the quota of {}: {}", area.dir.display(), e),
+            }
+        }
+        if deleted > 0 {
+            log::warn!("Storage: Deleted {} files to stay within the quotas.", deleted);
+        }
+        if (deleted > 0) != pruning {
+            pruning = deleted > 0;
+            for bms_data in &bms {
+                bms_data.modify(|data| data.storage_pruned = Some(pruning));
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// src/supervisor.rs
+use crate::{config::SupervisorConfig, error::AppError};
+use serde::Serialize;
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    collections::BTreeMap,
+    future::Future,
+    panic,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::{JoinError, JoinHandle},
+    time::sleep,
+};
+
+// --- Panic Hook ---
+/// Logs panics with their location and a backtrace instead of printing them to stderr,
+/// where they would be lost with the syslog/journald output. The supervisor sees the
+/// panic afterwards as a failed join.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        log::error!(
+            "Panic in thread '{}': {}\n{}",
+            thread.name().unwrap_or("<unnamed>"),
+            info,
+            Backtrace::force_capture()
+        );
+    }));
+}
+
+// Message of a panic payload, panics carry a &str or a String
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+// --- Task Status ---
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Waiting for the restart after a panic
+    Restarting,
+    /// Returned without error
+    Finished,
+    /// Returned an error
+    Failed,
+    /// Panicked and was not restarted
+    Panicked,
+}
+
+/// Diagnostics of one supervised task.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub state: TaskState,
+    pub panics: u64,
+    pub restarts: u32,
+    /// Message of the last panic
+    pub last_panic: Option<String>,
+    /// Error the task returned
+    pub error: Option<String>,
+}
+
+// Aborts the task when the supervising task is aborted, a dropped JoinHandle would detach it
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// --- Supervisor ---
+/// Runs the long-lived tasks, reports their panics and errors and restarts tasks that
+/// panicked. The task states are served by the HTTP API as `/tasks`.
+#[derive(Debug)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    tasks: Mutex<BTreeMap<String, TaskStatus>>,
+    // Fatal task errors (see AppError::is_fatal), they terminate the gateway
+    fatal_tx: UnboundedSender<(String, AppError)>,
+}
+
+impl Supervisor {
+    /// The receiver yields the name and error of every task that failed fatally.
+    pub fn new(config: SupervisorConfig) -> (Arc<Self>, UnboundedReceiver<(String, AppError)>) {
+        let (fatal_tx, fatal_rx) = unbounded_channel();
+        (Arc::new(Self { config, tasks: Mutex::new(BTreeMap::new()), fatal_tx }), fatal_rx)
+    }
+
+    /// Copy of the task states, keyed by task name.
+    pub fn snapshot(&self) -> BTreeMap<String, TaskStatus> {
+        self.tasks.lock().map(|tasks| tasks.clone()).unwrap_or_default()
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskStatus)) {
+        let Ok(mut tasks) = self.tasks.lock() else {
+            log::error!("Supervisor lock poisoned, cannot update the state of task '{}'.", name);
+            return;
+        };
+        let status = tasks.entry(name.to_string()).or_insert(TaskStatus {
+            state: TaskState::Running,
+            panics: 0,
+            restarts: 0,
+            last_panic: None,
+            error: None,
+        });
+        f(status);
+    }
+
+    // Records how a run of the task ended, returns true if the task is to be restarted
+    fn record_exit(&self, name: &str, result: Result<Result<(), AppError>, JoinError>, restartable: bool) -> bool {
+        match result {
+            Ok(Ok(())) => {
+                log::info!("Task '{}' finished.", name);
+                self.update(name, |status| status.state = TaskState::Finished);
+                false
+            }
+            Ok(Err(e)) => {
+                log::error!("Task '{}' failed: {}", name, e);
+                self.update(name, |status| {
+                    status.state = TaskState::Failed;
+                    status.error = Some(e.to_string());
+                });
+                if e.is_fatal() {
+                    // Nobody listens anymore once main is shutting down
+                    let _ = self.fatal_tx.send((name.to_string(), e));
+                }
+                false
+            }
+            Err(e) if e.is_panic() => {
+                let message = panic_message(e.into_panic().as_ref());
+                let mut restart = false;
+                self.update(name, |status| {
+                    status.panics += 1;
+                    status.last_panic = Some(message.clone());
+                    restart = restartable
+                        && self.config.restart_on_panic
+                        && (self.config.max_restarts == 0 || status.restarts < self.config.max_restarts);
+                    status.state = if restart { TaskState::Restarting } else { TaskState::Panicked };
+                });
+                if restart {
+                    log::error!("Task '{}' panicked: {}. Restarting in {:?}.", name, message, self.config.restart_delay());
+                } else {
+                    log::error!("Task '{}' panicked: {}. It is not restarted.", name, message);
+                }
+                restart
+            }
+            // Aborted, only happens at shutdown
+            Err(_) => false,
+        }
+    }
+
+    /// Spawns a task on the current runtime that is created anew by `factory` after a panic.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: &str, factory: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), AppError>> + Send + 'static,
+    {
+        self.spawn_on(&Handle::current(), name, factory)
+    }
+
+    /// Like `spawn`, on the runtime of `handle`.
+    pub fn spawn_on<F, Fut>(self: &Arc<Self>, handle: &Handle, name: &str, factory: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), AppError>> + Send + 'static,
+    {
+        let supervisor = Arc::clone(self);
+        let name = name.to_string();
+        let runtime = handle.clone();
+        self.update(&name, |status| status.state = TaskState::Running);
+        handle.spawn(async move {
+            loop {
+                let mut task = AbortOnDrop(runtime.spawn(factory()));
+                let result = (&mut task.0).await;
+                if !supervisor.record_exit(&name, result, true) {
+                    return;
+                }
+                sleep(supervisor.config.restart_delay()).await;
+                supervisor.update(&name, |status| {
+                    status.restarts += 1;
+                    status.state = TaskState::Running;
+                });
+            }
+        })
+    }
+
+    /// Spawns a task that cannot be restarted (it owns a receiver), its panic is only reported.
+    pub fn spawn_once<Fut>(self: &Arc<Self>, name: &str, future: Fut) -> JoinHandle<()>
+    where
+        Fut: Future<Output = Result<(), AppError>> + Send + 'static,
+    {
+        let supervisor = Arc::clone(self);
+        let name = name.to_string();
+        self.update(&name, |status| status.state = TaskState::Running);
+        tokio::spawn(async move {
+            let mut task = AbortOnDrop(tokio::spawn(future));
+            let result = (&mut task.0).await;
+            supervisor.record_exit(&name, result, false);
+        })
+    }
+}
@@ -0,0 +1,101 @@
+// src/trace.rs
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_modbus::prelude::{ExceptionCode, Request, Response};
+
+// --- Trace Entry ---
+/// One Modbus request/response pair as seen by the gateway's servers.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    /// Listen address of the server that handled the request
+    pub server: SocketAddr,
+    pub client: SocketAddr,
+    pub function: u8,
+    /// First register/coil address of the request, if it has one
+    pub address: Option<u16>,
+    pub request: String,
+    pub response: String,
+}
+
+// --- Protocol Trace ---
+/// Ring buffer of the most recent Modbus transactions, used to debug interop problems.
+#[derive(Debug)]
+pub struct ProtocolTrace {
+    capacity: usize,
+    entries: Mutex<VecDeque<TraceEntry>>,
+}
+
+// Start address of the request, for filtering the trace by register
+fn request_address(req: &Request<'_>) -> Option<u16> {
+    match req {
+        Request::ReadCoils(addr, _)
+        | Request::ReadDiscreteInputs(addr, _)
+        | Request::WriteSingleCoil(addr, _)
+        | Request::WriteMultipleCoils(addr, _)
+        | Request::ReadInputRegisters(addr, _)
+        | Request::ReadHoldingRegisters(addr, _)
+        | Request::WriteSingleRegister(addr, _)
+        | Request::WriteMultipleRegisters(addr, _)
+        | Request::MaskWriteRegister(addr, _, _)
+        | Request::ReadWriteMultipleRegisters(addr, _, _, _) => Some(*addr),
+        _ => None,
+    }
+}
+
+impl ProtocolTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        }
+    }
+
+    /// Appends a transaction, dropping the oldest one if the buffer is full.
+    pub fn record(
+        &self,
+        server: SocketAddr,
+        client: SocketAddr,
+        req: &Request<'_>,
+        result: &Result<Response, ExceptionCode>,
+    ) {
+        let entry = TraceEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            server,
+            client,
+            function: req.function_code().value(),
+            address: request_address(req),
+            request: format!("{:?}", req),
+            response: match result {
+                Ok(response) => format!("{:?}", response),
+                Err(exception) => format!("Exception({:?})", exception),
+            },
+        };
+
+        let Ok(mut entries) = self.entries.lock() else {
+            log::error!("Protocol trace lock poisoned, dropping entry.");
+            return;
+        };
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Copy of all buffered transactions, oldest first.
+    pub fn snapshot(&self) -> Vec<TraceEntry> {
+        self.entries
+            .lock()
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
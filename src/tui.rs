@@ -0,0 +1,237 @@
+// src/tui.rs
+use crate::{
+    clock,
+    config::{Config, DisplayConfig, Language},
+    connections::{ConnectionState, InverterStatus},
+    data::BmsData,
+    display,
+    error::AppError,
+    i18n::{self, Text},
+    logging::LogEvent,
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, Row, Table},
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+// How long to wait for a key press before redrawing
+const INPUT_POLL: Duration = Duration::from_millis(250);
+
+// --- HTTP API Client ---
+// GET of a JSON endpoint, the API closes the connection after every response
+fn get<T: DeserializeOwned>(addr: SocketAddr, path: &str) -> Result<T, String> {
+    let mut stream =
+        TcpStream::connect_timeout(&addr, REQUEST_TIMEOUT).map_err(|e| format!("cannot connect to {}: {}", addr, e))?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).map_err(|e| e.to_string())?;
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|e| format!("{}: {}", path, e))?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| format!("{}: malformed response", path))?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.starts_with("HTTP/1.1 200") {
+        return Err(format!("{}: {}", path, status));
+    }
+    serde_json::from_str(body).map_err(|e| format!("{}: {}", path, e))
+}
+
+// The API of the gateway on this host, a wildcard listen address is reached via loopback
+fn api_addr(config: &Config) -> Result<SocketAddr, AppError> {
+    let mut addr: SocketAddr = config
+        .http
+        .addr
+        .parse()
+        .map_err(|e| AppError::Config(format!("Invalid HTTP address '{}': {}", config.http.addr, e)))?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    Ok(addr)
+}
+
+// --- Dashboard ---
+struct Dashboard {
+    addr: SocketAddr,
+    scales: DisplayConfig,
+    language: Language,
+    bms: BTreeMap<u8, BmsData>,
+    inverters: Vec<InverterStatus>,
+    events: Vec<LogEvent>,
+    // Why the last refresh failed, the previous values stay on screen
+    error: Option<String>,
+    refreshed_at: Option<Instant>,
+}
+
+impl Dashboard {
+    fn refresh(&mut self) {
+        self.refreshed_at = Some(Instant::now());
+        let result = (|| {
+            self.bms = get(self.addr, "/bms")?;
+            self.inverters = get(self.addr, "/inverters")?;
+            self.events = get(self.addr, "/events")?;
+            Ok::<_, String>(())
+        })();
+        self.error = result.err();
+    }
+
+    fn bms_table(&self) -> Table<'_> {
+        let format_value = |value: Option<f64>, unit: &str| match value {
+            Some(value) => format!("{:.1} {}", value, unit),
+            None => format!("-- {}", unit),
+        };
+        let hex = |byte: Option<u8>| byte.map(|b| format!("{:02X}", b)).unwrap_or_else(|| "--".to_string());
+        let rows = self.bms.iter().map(|(bms_id, data)| {
+            let (state, color) = if data.in_maintenance() {
+                (Text::Maintenance, Color::Yellow)
+            } else if data.stale.unwrap_or(true) {
+                (Text::Stale, Color::Red)
+            } else if data.error1.unwrap_or(0) != 0 || data.error2.unwrap_or(0) != 0 {
+                (Text::Error, Color::Red)
+            } else {
+                (Text::Ok, Color::Green)
+            };
+            Row::new(vec![
+                bms_id.to_string(),
+                data.soc.map(|soc| format!("{} %", soc)).unwrap_or_else(|| "-- %".to_string()),
+                format_value(display::pack_voltage(data, &self.scales), "V"),
+                format_value(display::pack_current(data, &self.scales), "A"),
+                display::severity_name(data.rule_severity, self.language).to_string(),
+                format!("{}{}", hex(data.error1), hex(data.error2)),
+                format!("{}{}", hex(data.warning1), hex(data.warning2)),
+                i18n::text(self.language, state).to_string(),
+            ])
+            .style(Style::default().fg(color))
+        });
+        Table::new(
+            rows,
+            [
+                Constraint::Length(5),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(12),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Min(11),
+            ],
+        )
+        .header(Row::new(["BMS", "SOC", "Voltage", "Current", "Rules", "Errors", "Warnings", "State"]).bold())
+        .block(Block::bordered().title(" BMS "))
+    }
+
+    fn inverter_table(&self, now_ms: u128) -> Table<'_> {
+        let rows = self.inverters.iter().map(|inverter| {
+            let color = match inverter.state {
+                ConnectionState::Connected => Color::Green,
+                ConnectionState::Reconnecting => Color::Yellow,
+                ConnectionState::Down => Color::Red,
+            };
+            Row::new(vec![
+                inverter.name.clone(),
+                format!("{:?}", inverter.state),
+                format!("{} s", now_ms.saturating_sub(inverter.since_ms) / 1000),
+            ])
+            .style(Style::default().fg(color))
+        });
+        Table::new(rows, [Constraint::Min(16), Constraint::Length(14), Constraint::Length(12)])
+            .header(Row::new(["Inverter", "State", "For"]).bold())
+            .block(Block::bordered().title(" Inverters "))
+    }
+
+    fn event_list(&self, now_ms: u128) -> List<'_> {
+        // Newest first
+        let items = self.events.iter().rev().map(|event| {
+            let color = match event.level.as_str() {
+                "ERROR" => Color::Red,
+                "WARN" => Color::Yellow,
+                _ => Color::default(),
+            };
+            ListItem::new(format!(
+                "{:>6} s ago  {:<5} {}: {}",
+                now_ms.saturating_sub(event.timestamp_ms) / 1000,
+                event.level,
+                event.target,
+                event.message
+            ))
+            .style(Style::default().fg(color))
+        });
+        List::new(items).block(Block::bordered().title(" Recent events "))
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let now_ms = clock::unix_millis();
+        let [bms_area, inverter_area, event_area, status_area] = Layout::vertical([
+            Constraint::Length(self.bms.len() as u16 + 3),
+            Constraint::Length(self.inverters.len() as u16 + 3),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(self.bms_table(), bms_area);
+        frame.render_widget(self.inverter_table(now_ms), inverter_area);
+        frame.render_widget(self.event_list(now_ms), event_area);
+        let status = match &self.error {
+            Some(error) => Line::from(format!(" q: quit | {} | {}", self.addr, error)).red(),
+            None => Line::from(format!(" q: quit | {}", self.addr)),
+        };
+        frame.render_widget(status, status_area);
+    }
+}
+
+fn run_dashboard(terminal: &mut DefaultTerminal, dashboard: &mut Dashboard) -> std::io::Result<()> {
+    loop {
+        if dashboard.refreshed_at.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL) {
+            dashboard.refresh();
+        }
+        terminal.draw(|frame| dashboard.render(frame))?;
+        if event::poll(INPUT_POLL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+        {
+            return Ok(());
+        }
+    }
+}
+
+// --- Entry Point ---
+/// Terminal dashboard of the gateway running on this host (`tui` subcommand), for
+/// technicians logged in over SSH. Shows the BMS values, the inverter connections and
+/// the recent warnings and commands, read from the HTTP API once a second.
+pub fn run(config: &Config) -> Result<(), AppError> {
+    if !config.http.enabled {
+        return Err(AppError::Config("The dashboard reads the HTTP API, enable [http]".to_string()));
+    }
+    let mut dashboard = Dashboard {
+        addr: api_addr(config)?,
+        scales: config.display.clone(),
+        language: config.language,
+        bms: BTreeMap::new(),
+        inverters: Vec::new(),
+        events: Vec::new(),
+        error: None,
+        refreshed_at: None,
+    };
+    let mut terminal = ratatui::try_init().map_err(AppError::Runtime)?;
+    let result = run_dashboard(&mut terminal, &mut dashboard);
+    // Give the terminal back even if drawing failed
+    ratatui::restore();
+    result.map_err(AppError::Runtime)
+}
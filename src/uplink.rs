@@ -0,0 +1,264 @@
+// src/uplink.rs
+use crate::{
+    clock,
+    config::UplinkConfig,
+    connections::ConnectionRegistry,
+    control_mode::ControlMode,
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    log_output,
+    supervisor::{Supervisor, TaskState},
+    version::{self, BuildInfo},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+// --- Heartbeat ---
+/// State of one BMS in the heartbeat.
+#[derive(Debug, Clone, Serialize)]
+pub struct BmsSummary {
+    pub id: u8,
+    pub soc: Option<u8>,
+    pub error1: u8,
+    pub error2: u8,
+    pub stale: bool,
+    pub maintenance: bool,
+}
+
+/// Compact status of the site, POSTed as JSON to the central server.
+#[derive(Debug, Clone, Serialize)]
+pub struct Heartbeat {
+    pub site: String,
+    /// Unix time in milliseconds
+    pub timestamp_ms: u128,
+    pub version: BuildInfo,
+    /// "on" or "off" after the last accepted command, "unknown" before the first one
+    pub state: &'static str,
+    pub control_mode: ControlMode,
+    pub bms: Vec<BmsSummary>,
+    /// Names of the inverters that are down
+    pub inverters_down: Vec<String>,
+    /// Names of the tasks that are not running
+    pub tasks_down: Vec<String>,
+}
+
+fn bms_summary(bms_id: u8, data: &BmsData) -> BmsSummary {
+    BmsSummary {
+        id: bms_id,
+        soc: data.soc,
+        error1: data.error1.unwrap_or(0),
+        error2: data.error2.unwrap_or(0),
+        stale: data.stale.unwrap_or(true),
+        maintenance: data.in_maintenance(),
+    }
+}
+
+fn heartbeat(site: &str, bms: &[(u8, SharedBmsData)], connections: &ConnectionRegistry, supervisor: &Supervisor) -> Heartbeat {
+    let datasets: Vec<(u8, BmsData)> = bms.iter().map(|(bms_id, bms_data)| (*bms_id, bms_data.get())).collect();
+    // The arbiter writes the last command into every dataset
+    let state = match datasets.first().and_then(|(_, data)| data.last_command) {
+        Some(1) => "off",
+        Some(2) => "on",
+        _ => "unknown",
+    };
+    Heartbeat {
+        site: site.to_string(),
+        timestamp_ms: clock::unix_millis(),
+        version: version::build_info(),
+        state,
+        control_mode: datasets.first().map(|(_, data)| data.control_mode()).unwrap_or_default(),
+        bms: datasets.iter().map(|(bms_id, data)| bms_summary(*bms_id, data)).collect(),
+        inverters_down: connections.down(),
+        tasks_down: supervisor
+            .snapshot()
+            .into_iter()
+            .filter(|(_, status)| status.state != TaskState::Running)
+            .map(|(name, _)| name)
+            .collect(),
+    }
+}
+
+// --- Delta Encoding ---
+// JSON merge patch (RFC 7396) turning `old` into `new`, None if they are equal. Arrays
+// are replaced as a whole.
+fn merge_patch(old: &Value, new: &Value) -> Option<Value> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return (old != new).then(|| new.clone());
+    };
+    let mut patch = Map::new();
+    for (key, value) in new {
+        let changed = match old.get(key) {
+            Some(previous) => merge_patch(previous, value),
+            None => Some(value.clone()),
+        };
+        if let Some(changed) = changed {
+            patch.insert(key.clone(), changed);
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        patch.insert(key.clone(), Value::Null);
+    }
+    (!patch.is_empty()).then_some(Value::Object(patch))
+}
+
+fn encode_error(e: serde_json::Error) -> AppError {
+    AppError::Config(format!("Heartbeat: {}", e))
+}
+
+// A heartbeat with all fields and `"full": true`
+fn encode_full(heartbeat: &Heartbeat) -> Result<Vec<u8>, AppError> {
+    let mut message = serde_json::to_value(heartbeat).map_err(encode_error)?;
+    if let Value::Object(fields) = &mut message {
+        fields.insert("full".to_string(), Value::Bool(true));
+    }
+    serde_json::to_vec(&message).map_err(encode_error)
+}
+
+// Encodes the heartbeats as sent: full ones carry `"full": true` and all fields, the
+// others site, timestamp and the merge patch of the previous heartbeat in `patch`
+struct Encoder {
+    full_interval: Option<Duration>,
+    // Previous heartbeat without site and timestamp, None if the next one has to be full
+    previous: Option<Value>,
+    last_full: Instant,
+}
+
+impl Encoder {
+    fn new(full_interval: Option<Duration>) -> Self {
+        Self { full_interval, previous: None, last_full: Instant::now() }
+    }
+
+    fn encode(&mut self, heartbeat: &Heartbeat) -> Result<Vec<u8>, AppError> {
+        let mut state = serde_json::to_value(heartbeat).map_err(encode_error)?;
+        if let Value::Object(fields) = &mut state {
+            fields.remove("site");
+            fields.remove("timestamp_ms");
+        }
+        let full_due = self.last_full.elapsed() >= self.full_interval.unwrap_or_default();
+        let body = match self.previous.as_ref().filter(|_| !full_due) {
+            Some(previous) => serde_json::to_vec(&serde_json::json!({
+                "site": heartbeat.site,
+                "timestamp_ms": heartbeat.timestamp_ms,
+                "full": false,
+                "patch": merge_patch(previous, &state).unwrap_or_else(|| Value::Object(Map::new())),
+            }))
+            .map_err(encode_error)?,
+            None => {
+                self.last_full = Instant::now();
+                encode_full(heartbeat)?
+            }
+        };
+        self.previous = Some(state);
+        Ok(body)
+    }
+}
+
+// --- Uplink Task ---
+/// Sends a heartbeat to the central server every `interval_minutes`. Heartbeats that
+/// cannot be delivered are buffered (at most `buffer_len`, the oldest are dropped) and
+/// sent oldest first, retried every `retry_delay_ms` until the server is reachable again.
+/// With `full_snapshot_minutes` only the changes are sent in between the full heartbeats.
+pub async fn task(
+    config: UplinkConfig,
+    bms: Vec<(u8, SharedBmsData)>,
+    connections: Arc<ConnectionRegistry>,
+    supervisor: Arc<Supervisor>,
+) -> Result<(), AppError> {
+    let site = if config.site_id.is_empty() { log_output::hostname() } else { config.site_id.clone() };
+    log::info!(
+        "Starting fleet uplink to {} as '{}' (every {:?})",
+        config.url,
+        site,
+        config.interval()
+    );
+    let agent = ureq::AgentBuilder::new().timeout(config.timeout()).build();
+    let mut encoder = Encoder::new(config.full_snapshot_interval());
+    // Each heartbeat is kept next to its body, to send it in full once it becomes the oldest
+    let mut buffer: VecDeque<(Heartbeat, Vec<u8>)> = VecDeque::new();
+    let mut next_heartbeat = Instant::now();
+    let mut online = true;
+
+    loop {
+        if Instant::now() >= next_heartbeat {
+            next_heartbeat += config.interval();
+            let heartbeat = heartbeat(&site, &bms, &connections, &supervisor);
+            let body = encoder.encode(&heartbeat)?;
+            buffer.push_back((heartbeat, body));
+            if buffer.len() > config.buffer_len.max(1) {
+                buffer.pop_front();
+                // The patch of the new oldest one refers to the dropped one, the server
+                // gets it in full instead
+                if let Some((heartbeat, body)) = buffer.front_mut() {
+                    *body = encode_full(heartbeat)?;
+                }
+                log::warn!("Fleet uplink: Buffer full, dropping the oldest heartbeat.");
+            }
+        }
+
+        // Oldest first, so the server receives them in order
+        while let Some(body) = buffer.front().map(|(_, body)| body.clone()) {
+            let (agent, url) = (agent.clone(), config.url.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                agent.post(&url).set("Content-Type", "application/json").send_bytes(&body).map(|_| ())
+            })
+            .await?;
+            match result {
+                Ok(()) => {
+                    buffer.pop_front();
+                    if !online {
+                        online = true;
+                        log::info!("Fleet uplink: {} reachable again, sending the buffered heartbeats.", config.url);
+                    }
+                }
+                Err(e) => {
+                    if online {
+                        online = false;
+                        log::warn!("Fleet uplink: Cannot reach {}, buffering heartbeats: {}", config.url, e);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let until_heartbeat = next_heartbeat.saturating_duration_since(Instant::now());
+        sleep(if buffer.is_empty() { until_heartbeat } else { until_heartbeat.min(config.retry_delay()) }).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn equal_values_need_no_patch() {
+        let value = json!({"state": "on", "bms": [{"id": 1}]});
+        assert_eq!(merge_patch(&value, &value), None);
+    }
+
+    #[test]
+    fn patch_contains_only_the_changes() {
+        let old = json!({"state": "on", "control_mode": "auto", "version": {"major": 1, "minor": 2}});
+        let new = json!({"state": "off", "control_mode": "auto", "version": {"major": 1, "minor": 3}});
+        assert_eq!(merge_patch(&old, &new), Some(json!({"state": "off", "version": {"minor": 3}})));
+    }
+
+    #[test]
+    fn removed_keys_are_null_and_arrays_replaced() {
+        let old = json!({"tasks_down": ["snmp"], "extra": 1});
+        let new = json!({"tasks_down": ["snmp", "grpc"]});
+        assert_eq!(merge_patch(&old, &new), Some(json!({"tasks_down": ["snmp", "grpc"], "extra": null})));
+    }
+
+    #[test]
+    fn other_values_are_replaced_as_a_whole() {
+        assert_eq!(merge_patch(&json!(1), &json!({"a": 1})), Some(json!({"a": 1})));
+        assert_eq!(merge_patch(&json!("x"), &json!("x")), None);
+    }
+}
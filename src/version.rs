@@ -0,0 +1,46 @@
+// src/version.rs
+use serde::Serialize;
+
+/// Version of the gateway (semver from Cargo.toml).
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Abbreviated commit hash, "-dirty" if built with local changes, "unknown" without git.
+pub const GIT_HASH: &str = env!("GATEWAY_GIT_HASH");
+/// Unix time of the build.
+pub const BUILD_TIMESTAMP: &str = env!("GATEWAY_BUILD_TIMESTAMP");
+
+/// Build information, as served by the HTTP /version endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_timestamp: u64,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_hash: GIT_HASH,
+        build_timestamp: BUILD_TIMESTAMP.parse().unwrap_or(0),
+    }
+}
+
+/// Major, minor and patch version (pre-release suffixes are dropped).
+pub fn semver() -> [u16; 3] {
+    let mut parts = VERSION
+        .split(['.', '-', '+'])
+        .map(|part| part.parse().unwrap_or(0));
+    [(); 3].map(|_| parts.next().unwrap_or(0))
+}
+
+/// First eight hex digits of the commit hash, 0 if unknown.
+pub fn git_hash_value() -> u32 {
+    GIT_HASH
+        .get(..8)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .unwrap_or(0)
+}
+
+/// One line summary for the startup log.
+pub fn describe() -> String {
+    format!("v{} ({}, built {})", VERSION, GIT_HASH, BUILD_TIMESTAMP)
+}
@@ -0,0 +1,178 @@
+// src/victron.rs
+use crate::{
+    can,
+    can_sys::{CanFrame, EmbeddedFrame, Frame, Socket, StandardId},
+    can_tx::{TxLane, TxPriority},
+    config::{RulesConfig, VictronConfig},
+    data::{BmsData, SharedBmsData},
+    error::AppError,
+    rules::{Rule, Severity},
+};
+use std::sync::Arc;
+use tokio::time::interval;
+
+// --- Victron/Pylontech CAN-BMS Protocol ---
+// All values are little endian
+const ID_LIMITS: u16 = 0x351;
+const ID_SOC: u16 = 0x355;
+const ID_MEASUREMENTS: u16 = 0x356;
+const ID_ALARMS: u16 = 0x35A;
+
+// Each flag of 0x35A is two bits wide
+const FLAG_ACTIVE: u8 = 0b01;
+const FLAG_OK: u8 = 0b10;
+
+// (byte, bit) of the alarm flags in 0x35A, the warnings use the same layout 4 bytes later
+const FLAG_GENERAL: (usize, u8) = (0, 0);
+const FLAG_HIGH_VOLTAGE: (usize, u8) = (0, 2);
+const FLAG_LOW_VOLTAGE: (usize, u8) = (0, 4);
+const FLAG_HIGH_TEMPERATURE: (usize, u8) = (0, 6);
+const FLAG_LOW_TEMPERATURE: (usize, u8) = (1, 0);
+
+fn frame(id: u16, data: &[u8]) -> Result<CanFrame, AppError> {
+    StandardId::new(id)
+        .and_then(|standard_id| CanFrame::new(standard_id, data))
+        .ok_or(AppError::UnsupportedCanId(u32::from(id)))
+}
+
+// Rounds to the integer range of the message field, saturating instead of wrapping
+fn to_i16(value: f64) -> i16 {
+    value.round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+}
+
+fn to_u16(value: f64) -> u16 {
+    value.round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+// Severity of the general flag, derived from the BMS warning and error bytes
+fn general_severity(data: &BmsData) -> Severity {
+    let any_set = |bytes: [Option<u8>; 2]| bytes.iter().any(|byte| byte.unwrap_or(0) != 0);
+    if any_set([data.error1, data.error2]) {
+        Severity::Trip
+    } else if any_set([data.warning1, data.warning2]) {
+        Severity::Warning
+    } else {
+        Severity::Normal
+    }
+}
+
+// 0x35A: a trip raises the alarm flag, warning and derate levels raise the warning flag
+fn alarm_data(flags: &[((usize, u8), Severity)]) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for &((byte, bit), severity) in flags {
+        let alarm = if severity == Severity::Trip { FLAG_ACTIVE } else { FLAG_OK };
+        let warning = if matches!(severity, Severity::Warning | Severity::Derate) {
+            FLAG_ACTIVE
+        } else {
+            FLAG_OK
+        };
+        bytes[byte] |= alarm << bit;
+        bytes[byte + 4] |= warning << bit;
+    }
+    bytes
+}
+
+// Builds the CAN-BMS messages as (ID, payload), None while no fresh data is available.
+// The data goes stale once the BMS stops sending (see can::rx_task).
+fn messages(data: &BmsData, config: &VictronConfig, rules: &RulesConfig) -> Option<[(u16, Vec<u8>); 4]> {
+    if data.stale.unwrap_or(true) {
+        return None;
+    }
+    let voltage = f64::from(data.pack_voltage()?) * config.voltage_scale;
+    let current = f64::from(data.pack_current()?) * config.current_scale;
+    let temperature = f64::from(data.max_temperature?) + config.temperature_offset;
+    let soc = u16::from(data.soc?);
+
+    let flags = [
+        (FLAG_GENERAL, general_severity(data)),
+        (FLAG_HIGH_VOLTAGE, Rule::CellVoltageHigh.severity(data, rules)),
+        (FLAG_LOW_VOLTAGE, Rule::CellVoltageLow.severity(data, rules)),
+        (FLAG_HIGH_TEMPERATURE, Rule::TemperatureHigh.severity(data, rules)),
+        (FLAG_LOW_TEMPERATURE, Rule::TemperatureLow.severity(data, rules)),
+    ];
+    let highest = flags
+        .iter()
+        .map(|(_, severity)| *severity)
+        .chain([Rule::SocLow.severity(data, rules)])
+        .max()
+        .unwrap_or_default();
+    // Stop charging/discharging on a trip, reduce the limits while derating
+    let current_factor = match highest {
+        Severity::Trip => 0.0,
+        Severity::Derate => config.derate_factor,
+        Severity::Normal | Severity::Warning => 1.0,
+    };
+
+    let mut limits = [0u8; 8];
+    limits[0..2].copy_from_slice(&to_u16(config.charge_voltage_limit * 10.0).to_le_bytes());
+    limits[2..4].copy_from_slice(&to_i16(config.charge_current_limit * current_factor * 10.0).to_le_bytes());
+    limits[4..6].copy_from_slice(&to_i16(config.discharge_current_limit * current_factor * 10.0).to_le_bytes());
+    limits[6..8].copy_from_slice(&to_u16(config.discharge_voltage_limit * 10.0).to_le_bytes());
+
+    let mut state = [0u8; 4];
+    state[0..2].copy_from_slice(&soc.to_le_bytes());
+    state[2..4].copy_from_slice(&config.state_of_health.to_le_bytes());
+
+    let mut measurements = [0u8; 6];
+    measurements[0..2].copy_from_slice(&to_i16(voltage * 100.0).to_le_bytes());
+    measurements[2..4].copy_from_slice(&to_i16(current * 10.0).to_le_bytes());
+    measurements[4..6].copy_from_slice(&to_i16(temperature * 10.0).to_le_bytes());
+
+    Some([
+        (ID_LIMITS, limits.to_vec()),
+        (ID_SOC, state.to_vec()),
+        (ID_MEASUREMENTS, measurements.to_vec()),
+        (ID_ALARMS, alarm_data(&flags).to_vec()),
+    ])
+}
+
+// --- Victron Output Task ---
+/// Periodically sends the data of one BMS as Victron/Pylontech CAN-BMS messages, so a
+/// Victron GX device can use the gateway as its battery monitor.
+pub async fn task(
+    can_if: &str,
+    config: VictronConfig,
+    rules: RulesConfig,
+    bms_data: SharedBmsData,
+    tx: Arc<TxLane>,
+) -> Result<(), AppError> {
+    log::info!(
+        "Starting Victron CAN-BMS output for BMS {} on {} (every {:?})",
+        config.bms_id,
+        can_if,
+        config.interval()
+    );
+    let socket = can::open_socket(can_if)?;
+    let mut ticker = interval(config.interval());
+    let mut sending = false;
+
+    loop {
+        ticker.tick().await;
+        let messages = bms_data.read(|data| messages(data, &config, &rules));
+
+        match messages {
+            Some(messages) => {
+                if !sending {
+                    log::info!("Victron: Sending CAN-BMS messages of BMS {}.", config.bms_id);
+                    sending = true;
+                }
+                for (id, payload) in &messages {
+                    let frame = frame(*id, payload)?;
+                    tx.acquire(TxPriority::Normal, &frame).await;
+                    if let Err(e) = socket.write_frame(&frame) {
+                        log::error!("Victron: Failed to send frame {:#X}: {}", frame.raw_id(), e);
+                    }
+                }
+            }
+            None if sending => {
+                // Going silent lets the GX device detect the lost battery and stop the inverters
+                log::warn!(
+                    "Victron: No fresh data of BMS {}, pausing CAN-BMS messages.",
+                    config.bms_id
+                );
+                sending = false;
+            }
+            None => {}
+        }
+    }
+}